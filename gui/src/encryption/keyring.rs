@@ -0,0 +1,149 @@
+use chirp_protocol::EncryptedKey;
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
+use rsa::{Oaep, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+
+/// The RSA private keys this device used before rotating its key. Messages stay encrypted with the
+/// key that was current when they were sent, so the old keys are needed to read the older messages
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Keyring {
+    // {Key ID: PKCS#1 DER of the private key}
+    old_keys: HashMap<u64, Vec<u8>>,
+}
+
+impl Keyring {
+    /// Keeps a key that got replaced by a rotation
+    pub fn add_old_key(&mut self, key_id: u64, private_key: &RsaPrivateKey) {
+        let key_der = private_key.to_pkcs1_der().unwrap();
+        self.old_keys.insert(key_id, key_der.as_bytes().to_vec());
+    }
+
+    /// Decrypts the AES key with the RSA key it was encrypted with. Keys from before the key IDs
+    /// existed carry no usable ID so every key is tried in that case
+    pub fn decrypt_key(
+        &self,
+        current_key: &RsaPrivateKey,
+        encrypted_key: &EncryptedKey,
+    ) -> Option<Vec<u8>> {
+        let decrypt = |private_key: &RsaPrivateKey| {
+            let padding = Oaep::new::<sha2::Sha256>();
+            private_key
+                .decrypt(padding, &encrypted_key.encrypted_key)
+                .ok()
+        };
+
+        if let Some(private_key) = self.old_key(encrypted_key.key_id) {
+            if let Some(aes_key) = decrypt(&private_key) {
+                return Some(aes_key);
+            }
+        }
+
+        if let Some(aes_key) = decrypt(current_key) {
+            return Some(aes_key);
+        }
+
+        self.old_keys
+            .keys()
+            .filter(|key_id| **key_id != encrypted_key.key_id)
+            .filter_map(|key_id| self.old_key(*key_id))
+            .find_map(|private_key| decrypt(&private_key))
+    }
+
+    fn old_key(&self, key_id: u64) -> Option<RsaPrivateKey> {
+        let key_der = self.old_keys.get(&key_id)?;
+        match RsaPrivateKey::from_pkcs1_der(key_der) {
+            Ok(private_key) => Some(private_key),
+            Err(e) => {
+                error!("Failed to read the old RSA key {key_id}. Reason: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+    use rsa::RsaPublicKey;
+
+    /// Small keys keep the tests fast
+    fn new_key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut OsRng, 1024).unwrap()
+    }
+
+    fn encrypt_for(private_key: &RsaPrivateKey, key_id: u64, aes_key: &[u8]) -> EncryptedKey {
+        let padding = Oaep::new::<sha2::Sha256>();
+        let encrypted_key = RsaPublicKey::from(private_key)
+            .encrypt(&mut OsRng, padding, aes_key)
+            .unwrap();
+        EncryptedKey::new(1, encrypted_key).update_key_id(key_id)
+    }
+
+    #[test]
+    fn old_keys_are_found_by_key_id() {
+        let (first_key, second_key, current_key) = (new_key(), new_key(), new_key());
+        let mut keyring = Keyring::default();
+        keyring.add_old_key(1, &first_key);
+        keyring.add_old_key(2, &second_key);
+
+        assert_eq!(keyring.old_key(1).unwrap(), first_key);
+        assert_eq!(keyring.old_key(2).unwrap(), second_key);
+
+        let encrypted_key = encrypt_for(&first_key, 1, b"first aes key");
+        assert_eq!(
+            keyring.decrypt_key(&current_key, &encrypted_key).unwrap(),
+            b"first aes key"
+        );
+
+        let encrypted_key = encrypt_for(&current_key, 3, b"current aes key");
+        assert_eq!(
+            keyring.decrypt_key(&current_key, &encrypted_key).unwrap(),
+            b"current aes key"
+        );
+    }
+
+    #[test]
+    fn keys_without_a_usable_id_try_every_key() {
+        let (old_key, current_key) = (new_key(), new_key());
+        let mut keyring = Keyring::default();
+        keyring.add_old_key(1, &old_key);
+
+        let encrypted_key = encrypt_for(&old_key, 0, b"aes key");
+        assert_eq!(
+            keyring.decrypt_key(&current_key, &encrypted_key).unwrap(),
+            b"aes key"
+        );
+    }
+
+    #[test]
+    fn unknown_key_id_is_not_found() {
+        let (old_key, current_key, other_key) = (new_key(), new_key(), new_key());
+        let mut keyring = Keyring::default();
+        keyring.add_old_key(1, &old_key);
+
+        assert!(keyring.old_key(5).is_none());
+
+        let encrypted_key = encrypt_for(&other_key, 5, b"aes key");
+        assert!(keyring.decrypt_key(&current_key, &encrypted_key).is_none());
+    }
+
+    #[test]
+    fn keyring_survives_saving() {
+        let (old_key, current_key) = (new_key(), new_key());
+        let mut keyring = Keyring::default();
+        keyring.add_old_key(7, &old_key);
+
+        let saved = serde_json::to_vec(&keyring).unwrap();
+        let keyring = serde_json::from_slice::<Keyring>(&saved).unwrap();
+
+        assert_eq!(keyring.old_key(7).unwrap(), old_key);
+        let encrypted_key = encrypt_for(&old_key, 7, b"aes key");
+        assert_eq!(
+            keyring.decrypt_key(&current_key, &encrypted_key).unwrap(),
+            b"aes key"
+        );
+    }
+}
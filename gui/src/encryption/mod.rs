@@ -1,3 +1,7 @@
 pub mod handler;
+pub mod keyring;
+pub mod ratchet;
 
 pub use handler::*;
+pub use keyring::*;
+pub use ratchet::*;
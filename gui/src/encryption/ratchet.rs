@@ -0,0 +1,1054 @@
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
+use chirp_protocol::{
+    EncryptedKey, InitialHeader, MessageData, OneTimePreKey, PreKeyBundle, PreKeyUpload,
+    RatchetHeader,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use tracing::{error, info};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The most message keys of a chain that are kept for messages arriving out of order
+const MAX_SKIP: u32 = 1000;
+
+/// The most sessions kept with a single device. Older sessions are kept for messages that are still arriving
+const MAX_SESSIONS: usize = 5;
+
+/// X3DH keys of this device and the double ratchet sessions with every other device
+#[derive(Serialize, Deserialize)]
+pub struct RatchetStore {
+    identity_secret: [u8; 32],
+    signing_secret: [u8; 32],
+    signed_prekey_id: u64,
+    signed_prekey_secret: [u8; 32],
+    signed_prekey_signature: Vec<u8>,
+    // One-time prekeys that were published and not used yet. {Key ID: Secret}
+    one_time_prekeys: HashMap<u64, [u8; 32]>,
+    next_prekey_id: u64,
+    // {"User ID:Device ID": Sessions with the newest first}
+    sessions: HashMap<String, Vec<RatchetSession>>,
+    // Identity keys of the other devices the sessions were started with. {"User ID:Device ID": Keys}
+    #[serde(default)]
+    identities: HashMap<String, DeviceIdentity>,
+    // Identity keys of the devices of the verified users. Sessions with other keys are refused.
+    // Not saved as the verified users are saved separately
+    #[serde(skip)]
+    pinned: HashMap<String, DeviceIdentity>,
+    // AES keys of the messages that were not saved in the message cache yet. Ratchet keys can only
+    // be used once so these are needed to decrypt the messages again after a crash.
+    // {Chat ID: {Message number: Key}}
+    #[serde(default)]
+    unwrapped_keys: HashMap<u64, HashMap<u64, UnwrappedKey>>,
+}
+
+/// The long-term keys a device starts ratchet sessions with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub identity_key: Vec<u8>,
+    pub signing_key: Vec<u8>,
+}
+
+/// The double ratchet state of a single session with a device
+#[derive(Clone, Serialize, Deserialize)]
+struct RatchetSession {
+    root_key: [u8; 32],
+    sending_secret: [u8; 32],
+    remote_key: Option<[u8; 32]>,
+    sending_chain: Option<[u8; 32]>,
+    receiving_chain: Option<[u8; 32]>,
+    sent_count: u32,
+    received_count: u32,
+    previous_count: u32,
+    skipped_keys: Vec<SkippedKey>,
+    // Identity key of the initiator followed by the identity key of the responder
+    associated_data: Vec<u8>,
+    // Sent with every message till the other device replies
+    pending_initial: Option<InitialHeader>,
+    // Ephemeral key the other device started this session with. None if this device started it
+    remote_ephemeral: Option<Vec<u8>>,
+}
+
+/// AES key of a message unwrapped with a ratchet key
+#[derive(Clone, Serialize, Deserialize)]
+struct UnwrappedKey {
+    encrypted_key: Vec<u8>,
+    aes_key: Vec<u8>,
+}
+
+/// Message key of a message that has not arrived yet
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    ratchet_key: [u8; 32],
+    message_index: u32,
+    message_key: [u8; 32],
+}
+
+impl RatchetStore {
+    /// Creates the identity, signing and signed prekey of this device
+    pub fn generate() -> Self {
+        info!("Generating new ratchet identity keys");
+        let identity_secret = StaticSecret::random_from_rng(OsRng);
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let signed_prekey_secret = StaticSecret::random_from_rng(OsRng);
+
+        let mut signed_data = PublicKey::from(&identity_secret).as_bytes().to_vec();
+        signed_data.extend(PublicKey::from(&signed_prekey_secret).as_bytes());
+        let signature = signing_key.sign(&signed_data);
+
+        RatchetStore {
+            identity_secret: identity_secret.to_bytes(),
+            signing_secret: signing_key.to_bytes(),
+            signed_prekey_id: 1,
+            signed_prekey_secret: signed_prekey_secret.to_bytes(),
+            signed_prekey_signature: signature.to_bytes().to_vec(),
+            one_time_prekeys: HashMap::new(),
+            next_prekey_id: 1,
+            sessions: HashMap::new(),
+            identities: HashMap::new(),
+            pinned: HashMap::new(),
+            unwrapped_keys: HashMap::new(),
+        }
+    }
+
+    /// Creates new one-time prekeys and returns the public part to publish
+    pub fn generate_one_time_prekeys(&mut self, count: usize) -> Vec<OneTimePreKey> {
+        (0..count)
+            .map(|_| {
+                let key_id = self.next_prekey_id;
+                self.next_prekey_id += 1;
+
+                let secret = StaticSecret::random_from_rng(OsRng);
+                let public_key = PublicKey::from(&secret).as_bytes().to_vec();
+                self.one_time_prekeys.insert(key_id, secret.to_bytes());
+                OneTimePreKey::new(key_id, public_key)
+            })
+            .collect()
+    }
+
+    /// The public keys of this device to publish along with the given one-time prekeys
+    pub fn prekey_upload(&self, one_time_prekeys: Vec<OneTimePreKey>) -> PreKeyUpload {
+        PreKeyUpload::new(
+            self.identity_public().to_vec(),
+            self.signing_public().to_vec(),
+            self.signed_prekey_id,
+            public_key_of(&self.signed_prekey_secret).to_vec(),
+            self.signed_prekey_signature.clone(),
+            one_time_prekeys,
+        )
+    }
+
+    pub fn has_session(&self, user_id: u64, device_id: u64) -> bool {
+        self.sessions
+            .get(&session_id(user_id, device_id))
+            .is_some_and(|sessions| !sessions.is_empty())
+    }
+
+    /// Starts a new session with the device of the bundle with X3DH. False if the signature does
+    /// not match or the identity keys are not the verified ones
+    pub fn start_session(&mut self, bundle: &PreKeyBundle) -> bool {
+        let (Some(remote_identity), Some(signed_prekey)) =
+            (to_key(&bundle.identity_key), to_key(&bundle.signed_prekey))
+        else {
+            error!("Prekey bundle of device {} is malformed", bundle.device_id);
+            return false;
+        };
+
+        if !verify_signature(bundle) {
+            error!(
+                "Signed prekey of device {} of User ID {} has an invalid signature",
+                bundle.device_id, bundle.user_id
+            );
+            return false;
+        }
+
+        let identity = DeviceIdentity {
+            identity_key: bundle.identity_key.to_owned(),
+            signing_key: bundle.signing_key.to_owned(),
+        };
+        if self.conflicts_with_pin(bundle.user_id, bundle.device_id, &identity) {
+            error!(
+                "Identity keys of device {} of User ID {} do not match the verified ones",
+                bundle.device_id, bundle.user_id
+            );
+            return false;
+        }
+
+        let ephemeral_secret = StaticSecret::random_from_rng(OsRng).to_bytes();
+
+        let mut key_material = vec![0xFF; 32];
+        key_material.extend(diffie_hellman(&self.identity_secret, &signed_prekey));
+        key_material.extend(diffie_hellman(&ephemeral_secret, &remote_identity));
+        key_material.extend(diffie_hellman(&ephemeral_secret, &signed_prekey));
+
+        let mut one_time_prekey_id = None;
+        if let Some(prekey) = &bundle.one_time_prekey {
+            if let Some(one_time_prekey) = to_key(&prekey.public_key) {
+                key_material.extend(diffie_hellman(&ephemeral_secret, &one_time_prekey));
+                one_time_prekey_id = Some(prekey.key_id);
+            }
+        }
+        let shared_secret = kdf_x3dh(&key_material);
+
+        let mut associated_data = self.identity_public().to_vec();
+        associated_data.extend(&bundle.identity_key);
+
+        // The signed prekey is the first ratchet key of the other device
+        let sending_secret = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, sending_chain) = kdf_root(
+            &shared_secret,
+            &diffie_hellman(&sending_secret, &signed_prekey),
+        );
+
+        let initial = InitialHeader::new(
+            self.identity_public().to_vec(),
+            public_key_of(&ephemeral_secret).to_vec(),
+            bundle.signed_prekey_id,
+            one_time_prekey_id,
+        )
+        .update_signing_key(self.signing_public().to_vec());
+
+        let session = RatchetSession {
+            root_key,
+            sending_secret,
+            remote_key: Some(signed_prekey),
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_count: 0,
+            skipped_keys: Vec::new(),
+            associated_data,
+            pending_initial: Some(initial),
+            remote_ephemeral: None,
+        };
+
+        info!(
+            "Started a ratchet session with device {} of User ID {}",
+            bundle.device_id, bundle.user_id
+        );
+        let session_id = session_id(bundle.user_id, bundle.device_id);
+        self.identities.insert(session_id.to_owned(), identity);
+        self.add_session(session_id, session);
+        true
+    }
+
+    /// Encrypts the AES key of a message for the device with the newest session. None if there is no session
+    pub fn encrypt_key(
+        &mut self,
+        user_id: u64,
+        device_id: u64,
+        own_device: u64,
+        aes_key: &[u8],
+    ) -> Option<EncryptedKey> {
+        let session = self
+            .sessions
+            .get_mut(&session_id(user_id, device_id))?
+            .first_mut()?;
+
+        let (encrypted_key, header) = session.encrypt(own_device, aes_key)?;
+        Some(EncryptedKey::new(device_id, encrypted_key).update_ratchet_header(Some(header)))
+    }
+
+    /// Unwraps the AES key the ratchet session encrypted for this device. The key is kept till the
+    /// message is cached. None if the message key was not encrypted with a ratchet or was already used
+    pub fn message_key(
+        &mut self,
+        chat_id: u64,
+        message_data: &MessageData,
+        device_id: u64,
+    ) -> Option<Vec<u8>> {
+        let device_key = message_data
+            .device_keys
+            .as_ref()?
+            .iter()
+            .find(|key| key.device_id == device_id)?;
+
+        let message_number = message_data.message_number;
+        let unwrapped = self
+            .unwrapped_keys
+            .get(&chat_id)
+            .and_then(|keys| keys.get(&message_number));
+
+        // Edits of the message are encrypted with a new ratchet key
+        if let Some(unwrapped) = unwrapped {
+            if unwrapped.encrypted_key == device_key.encrypted_key {
+                return Some(unwrapped.aes_key.clone());
+            }
+        }
+
+        let aes_key = self.decrypt_key(message_data.from_user, device_key)?;
+        self.unwrapped_keys.entry(chat_id).or_default().insert(
+            message_number,
+            UnwrappedKey {
+                encrypted_key: device_key.encrypted_key.clone(),
+                aes_key: aes_key.clone(),
+            },
+        );
+        Some(aes_key)
+    }
+
+    /// Drops the unwrapped keys of the messages that were saved in the message cache
+    pub fn forget_unwrapped(&mut self, chat_id: u64, message_numbers: impl Iterator<Item = u64>) {
+        let Some(keys) = self.unwrapped_keys.get_mut(&chat_id) else {
+            return;
+        };
+
+        for message_number in message_numbers {
+            keys.remove(&message_number);
+        }
+
+        if keys.is_empty() {
+            self.unwrapped_keys.remove(&chat_id);
+        }
+    }
+
+    /// Decrypts a ratchet encrypted AES key sent by a device of the user. Starts a new session if
+    /// the key was sent with an initial header this device has not seen yet
+    pub fn decrypt_key(&mut self, sender_user: u64, device_key: &EncryptedKey) -> Option<Vec<u8>> {
+        let header = device_key.ratchet_header.as_ref()?;
+        let session_id = session_id(sender_user, header.sender_device);
+
+        let sessions = self.sessions.entry(session_id.to_owned()).or_default();
+        for index in 0..sessions.len() {
+            // Only a session that decrypted the key successfully gets updated
+            let mut session = sessions[index].clone();
+            if let Some(aes_key) = session.decrypt(header, &device_key.encrypted_key) {
+                sessions.remove(index);
+                sessions.insert(0, session);
+                return Some(aes_key);
+            }
+        }
+
+        let initial = header.initial.as_ref()?;
+        let already_started = sessions
+            .iter()
+            .any(|session| session.remote_ephemeral.as_ref() == Some(&initial.ephemeral_key));
+        if already_started {
+            error!("Failed to decrypt a message key from {session_id}");
+            return None;
+        }
+
+        let identity = DeviceIdentity {
+            identity_key: initial.identity_key.to_owned(),
+            signing_key: initial.signing_key.to_owned(),
+        };
+        if self.conflicts_with_pin(sender_user, header.sender_device, &identity) {
+            error!("Identity keys of {session_id} do not match the verified ones");
+            return None;
+        }
+
+        let mut session = self.respond_session(initial)?;
+        let Some(aes_key) = session.decrypt(header, &device_key.encrypted_key) else {
+            error!("Failed to decrypt the first message key from {session_id}");
+            return None;
+        };
+
+        // One-time prekeys are never used twice
+        if let Some(key_id) = initial.one_time_prekey_id {
+            self.one_time_prekeys.remove(&key_id);
+        }
+
+        info!("Started a ratchet session with {session_id} from a received message");
+        self.identities.insert(session_id.to_owned(), identity);
+        self.add_session(session_id, session);
+        Some(aes_key)
+    }
+
+    /// Creates the session another device started with the prekeys of this device
+    fn respond_session(&self, initial: &InitialHeader) -> Option<RatchetSession> {
+        if initial.signed_prekey_id != self.signed_prekey_id {
+            error!(
+                "Session started with unknown signed prekey {}",
+                initial.signed_prekey_id
+            );
+            return None;
+        }
+
+        let remote_identity = to_key(&initial.identity_key)?;
+        let ephemeral_key = to_key(&initial.ephemeral_key)?;
+
+        let mut key_material = vec![0xFF; 32];
+        key_material.extend(diffie_hellman(&self.signed_prekey_secret, &remote_identity));
+        key_material.extend(diffie_hellman(&self.identity_secret, &ephemeral_key));
+        key_material.extend(diffie_hellman(&self.signed_prekey_secret, &ephemeral_key));
+
+        if let Some(key_id) = initial.one_time_prekey_id {
+            let Some(one_time_prekey) = self.one_time_prekeys.get(&key_id) else {
+                error!("Session started with unknown one-time prekey {key_id}");
+                return None;
+            };
+            key_material.extend(diffie_hellman(one_time_prekey, &ephemeral_key));
+        }
+
+        let mut associated_data = initial.identity_key.to_owned();
+        associated_data.extend(self.identity_public());
+
+        Some(RatchetSession {
+            root_key: kdf_x3dh(&key_material),
+            sending_secret: self.signed_prekey_secret,
+            remote_key: None,
+            sending_chain: None,
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_count: 0,
+            skipped_keys: Vec::new(),
+            associated_data,
+            pending_initial: None,
+            remote_ephemeral: Some(initial.ephemeral_key.to_owned()),
+        })
+    }
+
+    /// The keys of this device the other devices start sessions with
+    pub fn own_identity(&self) -> DeviceIdentity {
+        DeviceIdentity {
+            identity_key: self.identity_public().to_vec(),
+            signing_key: self.signing_public().to_vec(),
+        }
+    }
+
+    /// Identity keys of every device of the user a session was started with or that got pinned.
+    /// {Device ID: Keys}
+    pub fn identities_of(&self, user_id: u64) -> HashMap<u64, DeviceIdentity> {
+        // No session can be started with other keys than the pinned ones
+        self.pinned
+            .iter()
+            .chain(self.identities.iter())
+            .filter_map(|(session_id, identity)| {
+                let (session_user, device_id) = session_id.split_once(':')?;
+                if session_user.parse::<u64>().ok()? != user_id {
+                    return None;
+                }
+                Some((device_id.parse().ok()?, identity.to_owned()))
+            })
+            .collect()
+    }
+
+    /// Only allows sessions with the given identity keys for the devices of the user.
+    /// An empty list removes the pinned keys of the user
+    pub fn pin_identities(&mut self, user_id: u64, identities: &HashMap<u64, DeviceIdentity>) {
+        self.pinned
+            .retain(|session_id, _| !session_id.starts_with(&format!("{user_id}:")));
+        for (device_id, identity) in identities {
+            self.pinned
+                .insert(session_id(user_id, *device_id), identity.to_owned());
+        }
+    }
+
+    /// Whether the device has other identity keys pinned than the given ones
+    pub fn conflicts_with_pin(
+        &self,
+        user_id: u64,
+        device_id: u64,
+        identity: &DeviceIdentity,
+    ) -> bool {
+        self.pinned
+            .get(&session_id(user_id, device_id))
+            .is_some_and(|pinned| pinned != identity)
+    }
+
+    fn add_session(&mut self, session_id: String, session: RatchetSession) {
+        let sessions = self.sessions.entry(session_id).or_default();
+        sessions.insert(0, session);
+        sessions.truncate(MAX_SESSIONS);
+    }
+
+    fn identity_public(&self) -> [u8; 32] {
+        public_key_of(&self.identity_secret)
+    }
+
+    fn signing_public(&self) -> [u8; 32] {
+        SigningKey::from_bytes(&self.signing_secret)
+            .verifying_key()
+            .to_bytes()
+    }
+}
+
+impl RatchetSession {
+    /// Encrypts the AES key with the next sending message key. None if the other device has not replied yet
+    fn encrypt(&mut self, own_device: u64, aes_key: &[u8]) -> Option<(Vec<u8>, RatchetHeader)> {
+        let (next_chain, message_key) = kdf_chain(&self.sending_chain?);
+        self.sending_chain = Some(next_chain);
+
+        let header = RatchetHeader::new(
+            own_device,
+            public_key_of(&self.sending_secret).to_vec(),
+            self.previous_count,
+            self.sent_count,
+            self.pending_initial.clone(),
+        );
+        self.sent_count += 1;
+
+        let encrypted_key = seal(&message_key, &self.header_data(&header), aes_key);
+        Some((encrypted_key, header))
+    }
+
+    fn decrypt(&mut self, header: &RatchetHeader, encrypted_key: &[u8]) -> Option<Vec<u8>> {
+        let ratchet_key = to_key(&header.ratchet_key)?;
+        let header_data = self.header_data(header);
+
+        let skipped_index = self.skipped_keys.iter().position(|skipped| {
+            skipped.ratchet_key == ratchet_key && skipped.message_index == header.message_index
+        });
+
+        let aes_key = if let Some(index) = skipped_index {
+            let skipped = self.skipped_keys.remove(index);
+            open(&skipped.message_key, &header_data, encrypted_key)?
+        } else {
+            // A new ratchet key means the other device received the earlier messages and replied
+            if self.remote_key != Some(ratchet_key) {
+                self.skip_message_keys(header.previous_count)?;
+                self.ratchet_step(ratchet_key);
+            }
+            self.skip_message_keys(header.message_index)?;
+
+            let (next_chain, message_key) = kdf_chain(&self.receiving_chain?);
+            self.receiving_chain = Some(next_chain);
+            self.received_count += 1;
+            open(&message_key, &header_data, encrypted_key)?
+        };
+
+        // The other device has the session once it sends something with it
+        self.pending_initial = None;
+        Some(aes_key)
+    }
+
+    /// Saves the message keys till the given index of the current receiving chain
+    fn skip_message_keys(&mut self, until: u32) -> Option<()> {
+        if until > self.received_count + MAX_SKIP {
+            error!("Too many message keys to skip in a ratchet session");
+            return None;
+        }
+
+        if let (Some(mut chain_key), Some(ratchet_key)) = (self.receiving_chain, self.remote_key) {
+            while self.received_count < until {
+                let (next_chain, message_key) = kdf_chain(&chain_key);
+                self.skipped_keys.push(SkippedKey {
+                    ratchet_key,
+                    message_index: self.received_count,
+                    message_key,
+                });
+                chain_key = next_chain;
+                self.received_count += 1;
+            }
+            self.receiving_chain = Some(chain_key);
+
+            let total_skipped = self.skipped_keys.len();
+            if total_skipped > MAX_SKIP as usize {
+                self.skipped_keys.drain(..total_skipped - MAX_SKIP as usize);
+            }
+        }
+        Some(())
+    }
+
+    /// Starts new receiving and sending chains with the new ratchet key of the other device
+    fn ratchet_step(&mut self, ratchet_key: [u8; 32]) {
+        self.previous_count = self.sent_count;
+        self.sent_count = 0;
+        self.received_count = 0;
+        self.remote_key = Some(ratchet_key);
+
+        let (root_key, receiving_chain) = kdf_root(
+            &self.root_key,
+            &diffie_hellman(&self.sending_secret, &ratchet_key),
+        );
+        self.receiving_chain = Some(receiving_chain);
+
+        self.sending_secret = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, sending_chain) = kdf_root(
+            &root_key,
+            &diffie_hellman(&self.sending_secret, &ratchet_key),
+        );
+        self.root_key = root_key;
+        self.sending_chain = Some(sending_chain);
+    }
+
+    /// The data every encrypted key is authenticated with
+    fn header_data(&self, header: &RatchetHeader) -> Vec<u8> {
+        let mut header_data = self.associated_data.to_owned();
+        header_data.extend(&header.ratchet_key);
+        header_data.extend(header.previous_count.to_be_bytes());
+        header_data.extend(header.message_index.to_be_bytes());
+        header_data.extend(header.sender_device.to_be_bytes());
+        header_data
+    }
+}
+
+fn session_id(user_id: u64, device_id: u64) -> String {
+    format!("{user_id}:{device_id}")
+}
+
+fn to_key(key: &[u8]) -> Option<[u8; 32]> {
+    key.try_into().ok()
+}
+
+fn public_key_of(secret: &[u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(*secret)).to_bytes()
+}
+
+fn diffie_hellman(secret: &[u8; 32], public_key: &[u8; 32]) -> [u8; 32] {
+    StaticSecret::from(*secret)
+        .diffie_hellman(&PublicKey::from(*public_key))
+        .to_bytes()
+}
+
+/// Whether the signed prekey of the bundle was signed by the signing key of the device
+fn verify_signature(bundle: &PreKeyBundle) -> bool {
+    let Ok(signing_key) = <[u8; 32]>::try_from(bundle.signing_key.as_slice()) else {
+        return false;
+    };
+    let Ok(signature) = <[u8; 64]>::try_from(bundle.signature.as_slice()) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&signing_key) else {
+        return false;
+    };
+
+    let mut signed_data = bundle.identity_key.to_owned();
+    signed_data.extend(&bundle.signed_prekey);
+    verifying_key
+        .verify(&signed_data, &Signature::from_bytes(&signature))
+        .is_ok()
+}
+
+fn kdf_x3dh(key_material: &[u8]) -> [u8; 32] {
+    let mut shared_secret = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32][..]), key_material)
+        .expand(b"chirp-x3dh", &mut shared_secret)
+        .unwrap();
+    shared_secret
+}
+
+/// Derives the next root key and a new chain key from a DH output
+fn kdf_root(root_key: &[u8; 32], dh_output: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut output = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(&root_key[..]), dh_output)
+        .expand(b"chirp-ratchet", &mut output)
+        .unwrap();
+
+    let (root_key, chain_key) = output.split_at(32);
+    (root_key.try_into().unwrap(), chain_key.try_into().unwrap())
+}
+
+/// Derives the next chain key and the message key from a chain key
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let derive = |constant: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key).unwrap();
+        mac.update(&[constant]);
+        mac.finalize().into_bytes().into()
+    };
+    (derive(0x02), derive(0x01))
+}
+
+/// Encrypts with the message key. The output starts with the nonce
+fn seal(message_key: &[u8; 32], header_data: &[u8], to_encrypt: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(message_key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: to_encrypt,
+        aad: header_data,
+    };
+
+    let mut encrypted_data = nonce.to_vec();
+    encrypted_data.extend(cipher.encrypt(&nonce, payload).unwrap());
+    encrypted_data
+}
+
+fn open(message_key: &[u8; 32], header_data: &[u8], encrypted_data: &[u8]) -> Option<Vec<u8>> {
+    if encrypted_data.len() < 12 {
+        return None;
+    }
+
+    let (nonce, encrypted_data) = encrypted_data.split_at(12);
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(message_key));
+    let payload = Payload {
+        msg: encrypted_data,
+        aad: header_data,
+    };
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), payload)
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: (u64, u64) = (1, 10);
+    const BOB: (u64, u64) = (2, 20);
+
+    /// The bundle the server would hand out for the device of the store
+    fn bundle_of(store: &mut RatchetStore, (user_id, device_id): (u64, u64)) -> PreKeyBundle {
+        let one_time_prekeys = store.generate_one_time_prekeys(1);
+        let upload = store.prekey_upload(one_time_prekeys);
+
+        PreKeyBundle {
+            user_id,
+            device_id,
+            identity_key: upload.identity_key,
+            signing_key: upload.signing_key,
+            signed_prekey_id: upload.signed_prekey_id,
+            signed_prekey: upload.signed_prekey,
+            signature: upload.signature,
+            one_time_prekey: upload.one_time_prekeys.into_iter().next(),
+        }
+    }
+
+    fn send(
+        store: &mut RatchetStore,
+        from: (u64, u64),
+        to: (u64, u64),
+        aes_key: &[u8],
+    ) -> EncryptedKey {
+        store.encrypt_key(to.0, to.1, from.1, aes_key).unwrap()
+    }
+
+    /// Alice starts a session with Bob and Bob receives the first key
+    fn started_sessions() -> (RatchetStore, RatchetStore) {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+
+        assert!(alice.start_session(&bundle_of(&mut bob, BOB)));
+        let first_key = send(&mut alice, ALICE, BOB, b"first");
+        assert_eq!(bob.decrypt_key(ALICE.0, &first_key).unwrap(), b"first");
+        (alice, bob)
+    }
+
+    #[test]
+    fn x3dh_agrees_on_the_shared_key() {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+        let bundle = bundle_of(&mut bob, BOB);
+        let one_time_prekey_id = bundle.one_time_prekey.as_ref().unwrap().key_id;
+
+        assert!(alice.start_session(&bundle));
+        let encrypted_key = send(&mut alice, ALICE, BOB, b"aes key");
+        let initial = encrypted_key
+            .ratchet_header
+            .as_ref()
+            .unwrap()
+            .initial
+            .clone()
+            .unwrap();
+
+        let bob_session = bob.respond_session(&initial).unwrap();
+        let alice_session = &alice.sessions[&session_id(BOB.0, BOB.1)][0];
+        assert_eq!(bob_session.associated_data, alice_session.associated_data);
+
+        // Alice derived her first sending chain from the shared secret and the signed prekey of Bob
+        let (_, alice_chain) = kdf_root(
+            &bob_session.root_key,
+            &diffie_hellman(
+                &bob.signed_prekey_secret,
+                &public_key_of(&alice_session.sending_secret),
+            ),
+        );
+        let (_, first_message_key) = kdf_chain(&alice_chain);
+        let header_data = bob_session.header_data(encrypted_key.ratchet_header.as_ref().unwrap());
+        assert_eq!(
+            open(
+                &first_message_key,
+                &header_data,
+                &encrypted_key.encrypted_key
+            )
+            .unwrap(),
+            b"aes key"
+        );
+
+        assert_eq!(
+            bob.decrypt_key(ALICE.0, &encrypted_key).unwrap(),
+            b"aes key"
+        );
+        assert!(!bob.one_time_prekeys.contains_key(&one_time_prekey_id));
+        assert!(bob.has_session(ALICE.0, ALICE.1));
+    }
+
+    #[test]
+    fn messages_in_order() {
+        let (mut alice, mut bob) = started_sessions();
+
+        for round in 0..3u8 {
+            for index in 0..3u8 {
+                let key = [round, index, 0];
+                let encrypted_key = send(&mut bob, BOB, ALICE, &key);
+                assert_eq!(alice.decrypt_key(BOB.0, &encrypted_key).unwrap(), key);
+            }
+            for index in 0..3u8 {
+                let key = [round, index, 1];
+                let encrypted_key = send(&mut alice, ALICE, BOB, &key);
+                assert_eq!(bob.decrypt_key(ALICE.0, &encrypted_key).unwrap(), key);
+            }
+        }
+
+        // The initial header is only sent till the other device replies
+        let encrypted_key = send(&mut alice, ALICE, BOB, b"later");
+        assert!(encrypted_key.ratchet_header.unwrap().initial.is_none());
+    }
+
+    #[test]
+    fn messages_out_of_order() {
+        let (mut alice, mut bob) = started_sessions();
+        let reply = send(&mut bob, BOB, ALICE, b"reply");
+        assert_eq!(alice.decrypt_key(BOB.0, &reply).unwrap(), b"reply");
+
+        let keys: Vec<EncryptedKey> = (0..4u8)
+            .map(|index| send(&mut alice, ALICE, BOB, &[index]))
+            .collect();
+
+        assert_eq!(bob.decrypt_key(ALICE.0, &keys[3]).unwrap(), [3]);
+        assert_eq!(bob.decrypt_key(ALICE.0, &keys[1]).unwrap(), [1]);
+        assert_eq!(bob.decrypt_key(ALICE.0, &keys[0]).unwrap(), [0]);
+        assert_eq!(bob.decrypt_key(ALICE.0, &keys[2]).unwrap(), [2]);
+    }
+
+    #[test]
+    fn messages_from_an_earlier_chain() {
+        let (mut alice, mut bob) = started_sessions();
+        let late_key = send(&mut alice, ALICE, BOB, b"late");
+
+        // Bob replies and Alice moves on to a new sending chain before the late key arrives
+        let reply = send(&mut bob, BOB, ALICE, b"reply");
+        assert_eq!(alice.decrypt_key(BOB.0, &reply).unwrap(), b"reply");
+        let new_key = send(&mut alice, ALICE, BOB, b"new");
+
+        assert_eq!(bob.decrypt_key(ALICE.0, &new_key).unwrap(), b"new");
+        assert_eq!(bob.decrypt_key(ALICE.0, &late_key).unwrap(), b"late");
+    }
+
+    #[test]
+    fn skipped_keys_are_used_once() {
+        let (mut alice, mut bob) = started_sessions();
+
+        let skipped = send(&mut alice, ALICE, BOB, b"skipped");
+        let received = send(&mut alice, ALICE, BOB, b"received");
+        assert_eq!(bob.decrypt_key(ALICE.0, &received).unwrap(), b"received");
+
+        let session = &bob.sessions[&session_id(ALICE.0, ALICE.1)][0];
+        assert_eq!(session.skipped_keys.len(), 1);
+
+        assert_eq!(bob.decrypt_key(ALICE.0, &skipped).unwrap(), b"skipped");
+        assert!(bob.decrypt_key(ALICE.0, &skipped).is_none());
+        assert!(bob.decrypt_key(ALICE.0, &received).is_none());
+
+        let session = &bob.sessions[&session_id(ALICE.0, ALICE.1)][0];
+        assert!(session.skipped_keys.is_empty());
+    }
+
+    #[test]
+    fn skipping_is_limited() {
+        let (mut alice, mut bob) = started_sessions();
+
+        // Index MAX_SKIP + 1 of the chain. Bob already received index 0
+        let keys: Vec<EncryptedKey> = (0..MAX_SKIP + 1)
+            .map(|_| send(&mut alice, ALICE, BOB, b"key"))
+            .collect();
+        let last_key = send(&mut alice, ALICE, BOB, b"too far");
+        assert!(bob.decrypt_key(ALICE.0, &last_key).is_none());
+
+        // Skipping exactly MAX_SKIP keys still works and every skipped key is kept
+        assert_eq!(
+            bob.decrypt_key(ALICE.0, &keys[MAX_SKIP as usize]).unwrap(),
+            b"key"
+        );
+        let session = &bob.sessions[&session_id(ALICE.0, ALICE.1)][0];
+        assert_eq!(session.skipped_keys.len(), MAX_SKIP as usize);
+        assert_eq!(bob.decrypt_key(ALICE.0, &keys[0]).unwrap(), b"key");
+    }
+
+    #[test]
+    fn too_far_first_message_is_rejected() {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+        assert!(alice.start_session(&bundle_of(&mut bob, BOB)));
+
+        for _ in 0..MAX_SKIP + 1 {
+            send(&mut alice, ALICE, BOB, b"lost");
+        }
+        let too_far = send(&mut alice, ALICE, BOB, b"too far");
+
+        assert!(bob.decrypt_key(ALICE.0, &too_far).is_none());
+        assert!(!bob.has_session(ALICE.0, ALICE.1));
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+        let bundle = bundle_of(&mut bob, BOB);
+
+        let mut tampered_signature = bundle.clone();
+        tampered_signature.signature[0] ^= 1;
+        assert!(!alice.start_session(&tampered_signature));
+
+        let mut tampered_prekey = bundle.clone();
+        tampered_prekey.signed_prekey = public_key_of(&[7; 32]).to_vec();
+        assert!(!alice.start_session(&tampered_prekey));
+
+        // A signature made with another signing key than the bundle has
+        let mut other_signer = bundle.clone();
+        other_signer.signing_key = SigningKey::generate(&mut OsRng)
+            .verifying_key()
+            .to_bytes()
+            .to_vec();
+        assert!(!alice.start_session(&other_signer));
+
+        let mut malformed = bundle.clone();
+        malformed.signature.truncate(10);
+        assert!(!alice.start_session(&malformed));
+
+        assert!(!alice.has_session(BOB.0, BOB.1));
+        assert!(alice.start_session(&bundle));
+    }
+
+    #[test]
+    fn tampered_key_is_rejected() {
+        let (mut alice, mut bob) = started_sessions();
+
+        let mut encrypted_key = send(&mut alice, ALICE, BOB, b"key");
+        let last = encrypted_key.encrypted_key.len() - 1;
+        encrypted_key.encrypted_key[last] ^= 1;
+        assert!(bob.decrypt_key(ALICE.0, &encrypted_key).is_none());
+
+        // The header is authenticated as well
+        let mut encrypted_key = send(&mut alice, ALICE, BOB, b"key");
+        encrypted_key.ratchet_header.as_mut().unwrap().sender_device = 99;
+        assert!(bob.decrypt_key(ALICE.0, &encrypted_key).is_none());
+    }
+
+    #[test]
+    fn restarting_a_session() {
+        let (mut alice, mut bob) = started_sessions();
+        let old_key = send(&mut alice, ALICE, BOB, b"old");
+
+        // Alice starts over, for example after losing the reply of Bob
+        assert!(alice.start_session(&bundle_of(&mut bob, BOB)));
+        let new_key = send(&mut alice, ALICE, BOB, b"new");
+        assert!(new_key.ratchet_header.as_ref().unwrap().initial.is_some());
+
+        assert_eq!(bob.decrypt_key(ALICE.0, &new_key).unwrap(), b"new");
+        assert_eq!(bob.sessions[&session_id(ALICE.0, ALICE.1)].len(), 2);
+
+        // Keys of the older session can still be read
+        assert_eq!(bob.decrypt_key(ALICE.0, &old_key).unwrap(), b"old");
+
+        // The same initial header does not start another session
+        assert!(bob.decrypt_key(ALICE.0, &new_key).is_none());
+        assert_eq!(bob.sessions[&session_id(ALICE.0, ALICE.1)].len(), 2);
+
+        let reply = send(&mut bob, BOB, ALICE, b"reply");
+        assert_eq!(alice.decrypt_key(BOB.0, &reply).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn sessions_are_limited() {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+
+        for _ in 0..MAX_SESSIONS + 2 {
+            assert!(alice.start_session(&bundle_of(&mut bob, BOB)));
+        }
+        assert_eq!(
+            alice.sessions[&session_id(BOB.0, BOB.1)].len(),
+            MAX_SESSIONS
+        );
+    }
+
+    #[test]
+    fn identities_are_recorded_on_both_sides() {
+        let (alice, bob) = started_sessions();
+
+        assert_eq!(alice.identities_of(BOB.0)[&BOB.1], bob.own_identity());
+        assert_eq!(bob.identities_of(ALICE.0)[&ALICE.1], alice.own_identity());
+        assert!(alice.identities_of(ALICE.0).is_empty());
+
+        // Pinned keys count before a session exists
+        let mut carol = RatchetStore::generate();
+        let pins = HashMap::from([(BOB.1, bob.own_identity())]);
+        carol.pin_identities(BOB.0, &pins);
+        assert_eq!(carol.identities_of(BOB.0), pins);
+    }
+
+    #[test]
+    fn other_identity_than_the_pinned_one_is_refused() {
+        let mut alice = RatchetStore::generate();
+        let mut bob = RatchetStore::generate();
+        let mut server = RatchetStore::generate();
+
+        let pins = HashMap::from([(BOB.1, bob.own_identity())]);
+        alice.pin_identities(BOB.0, &pins);
+
+        // The server hands out its own keys in place of the keys of Bob
+        assert!(!alice.start_session(&bundle_of(&mut server, BOB)));
+        assert!(!alice.has_session(BOB.0, BOB.1));
+        assert!(alice.start_session(&bundle_of(&mut bob, BOB)));
+
+        // A session started by the server in the name of Bob is refused as well
+        let mut impostor = RatchetStore::generate();
+        assert!(impostor.start_session(&bundle_of(&mut alice, ALICE)));
+        let forged_key = send(&mut impostor, BOB, ALICE, b"forged");
+        assert!(alice.decrypt_key(BOB.0, &forged_key).is_none());
+
+        alice.pin_identities(BOB.0, &HashMap::new());
+        assert_eq!(alice.decrypt_key(BOB.0, &forged_key).unwrap(), b"forged");
+    }
+
+    #[test]
+    fn sessions_survive_saving() {
+        let (mut alice, bob) = started_sessions();
+
+        let saved = serde_json::to_vec(&bob).unwrap();
+        let mut bob = serde_json::from_slice::<RatchetStore>(&saved).unwrap();
+
+        let encrypted_key = send(&mut alice, ALICE, BOB, b"after restart");
+        assert_eq!(
+            bob.decrypt_key(ALICE.0, &encrypted_key).unwrap(),
+            b"after restart"
+        );
+
+        let reply = send(&mut bob, BOB, ALICE, b"reply");
+        assert_eq!(alice.decrypt_key(BOB.0, &reply).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn unwrapped_keys_survive_a_crash() {
+        let (mut alice, mut bob) = started_sessions();
+        let mut message = MessageData::new_incomplete(String::new(), ALICE.0, BOB.0);
+        message.message_number = 7;
+        message.device_keys = Some(vec![send(&mut alice, ALICE, BOB, b"not cached")]);
+
+        assert_eq!(
+            bob.message_key(ALICE.0, &message, BOB.1).unwrap(),
+            b"not cached"
+        );
+
+        // The message was not cached before the crash
+        let saved = serde_json::to_vec(&bob).unwrap();
+        let mut bob = serde_json::from_slice::<RatchetStore>(&saved).unwrap();
+        assert_eq!(
+            bob.message_key(ALICE.0, &message, BOB.1).unwrap(),
+            b"not cached"
+        );
+
+        bob.forget_unwrapped(ALICE.0, [7].into_iter());
+        assert!(bob.message_key(ALICE.0, &message, BOB.1).is_none());
+    }
+
+    #[test]
+    fn edits_are_not_read_with_the_unwrapped_key() {
+        let (mut alice, mut bob) = started_sessions();
+        let mut message = MessageData::new_incomplete(String::new(), ALICE.0, BOB.0);
+        message.message_number = 7;
+        message.device_keys = Some(vec![send(&mut alice, ALICE, BOB, b"message")]);
+        bob.message_key(ALICE.0, &message, BOB.1).unwrap();
+
+        message.device_keys = Some(vec![send(&mut alice, ALICE, BOB, b"edit")]);
+        assert_eq!(bob.message_key(ALICE.0, &message, BOB.1).unwrap(), b"edit");
+    }
+}
@@ -10,10 +10,11 @@ use adw::prelude::*;
 use adw::Application;
 use dotenvy::dotenv;
 use gdk::Display;
-use gio::resources_register_include;
-use glib::ExitCode;
+use gio::{resources_register_include, Settings};
+use glib::{clone, ExitCode};
 use gtk::{gdk, gio, glib, CssProvider, STYLE_PROVIDER_PRIORITY_THEME};
 use tracing::info;
+use user::UserPrompt;
 use window::Window;
 
 const APP_ID: &str = "com.github.therustypickle.chirp";
@@ -33,9 +34,19 @@ fn main() -> ExitCode {
 }
 
 fn build_ui(app: &Application) {
-    let window = Window::new(app);
-    window.set_icon_name(Some("chirp"));
-    window.present();
+    let location = Settings::new(APP_ID).string("location").to_string();
+
+    // The main window is created once the saved private key is unlocked
+    let prompt = UserPrompt::new("Unlock").unlock_keys(
+        app,
+        location,
+        clone!(@weak app => move |passphrase, saved_keys| {
+            let window = Window::new(&app, passphrase, saved_keys);
+            window.set_icon_name(Some("chirp"));
+            window.present();
+        }),
+    );
+    prompt.present();
 }
 
 fn load_css() {
@@ -1,26 +1,31 @@
 mod imp {
     use adw::prelude::*;
     use adw::subclass::prelude::*;
+    use chirp_protocol::Attachment;
+    use gdk::Paintable;
     use glib::{derived_properties, object_subclass, Properties};
-    use gtk::glib;
+    use gtk::{gdk, glib};
     use std::cell::{Cell, OnceCell, RefCell};
 
+    use super::ReceiptState;
     use crate::message::MessageRow;
     use crate::user::UserObject;
 
     #[derive(Properties, Default)]
     #[properties(wrapper_type = super::MessageObject)]
     pub struct MessageObject {
+        // Replaced when the message gets edited
         #[property(get, set)]
-        pub message: OnceCell<String>,
+        pub message: RefCell<String>,
         #[property(get, set)]
         pub is_send: OnceCell<bool>,
         #[property(get, set)]
         pub sent_from: OnceCell<UserObject>,
         #[property(get, set)]
         pub sent_to: OnceCell<UserObject>,
+        // Replaced with the server time once the sent message gets saved
         #[property(get, set)]
-        pub message_timing: OnceCell<String>,
+        pub message_timing: RefCell<String>,
         #[property(get, set)]
         pub message_number: OnceCell<u64>,
         #[property(get, set)]
@@ -29,6 +34,37 @@ mod imp {
         pub must_process: Cell<bool>,
         #[property(get, set)]
         pub show_initial_message: Cell<bool>,
+        #[property(get, set, builder(ReceiptState::default()))]
+        pub receipt: Cell<ReceiptState>,
+        #[property(get, set)]
+        pub edited: Cell<bool>,
+        // Number of the message this one replies to. 0 if it is not a reply
+        #[property(get, set)]
+        pub reply_to: Cell<u64>,
+        // Set once the replied message is loaded
+        #[property(get, set, nullable)]
+        pub replied_message: RefCell<Option<super::MessageObject>>,
+        // Reactions in the order they were added. (User ID, Emoji)
+        pub reactions: RefCell<Vec<(u64, String)>>,
+        // Decrypted name of the attached file. Empty if there is no attachment
+        #[property(get, set)]
+        pub attachment_name: RefCell<String>,
+        // Set once the attachment is downloaded and decrypted
+        #[property(get, set)]
+        pub attachment_ready: Cell<bool>,
+        #[property(get, set)]
+        pub attachment_downloading: Cell<bool>,
+        // Preview of the attachment if it is an image
+        #[property(get, set, nullable)]
+        pub attachment_image: RefCell<Option<Paintable>>,
+        pub attachment: RefCell<Option<Attachment>>,
+        // The AES key the attachment was encrypted with
+        pub attachment_key: RefCell<Vec<u8>>,
+        pub attachment_data: RefCell<Option<Vec<u8>>>,
+        // The UTC time the server saved the message at. Kept for the local cache
+        pub created_at: RefCell<String>,
+        // The AES key the message text was encrypted with
+        pub used_aes_key: RefCell<Vec<u8>>,
     }
 
     #[object_subclass]
@@ -41,11 +77,35 @@ mod imp {
     impl ObjectImpl for MessageObject {}
 }
 
-use glib::{wrapper, Object};
-use gtk::glib;
+use adw::subclass::prelude::*;
+use chirp_protocol::{Attachment, Reaction, ReceiptStatus};
+use gdk::{Paintable, Texture};
+use glib::{wrapper, Bytes, Enum, Object};
+use gtk::{gdk, gio, glib};
+use tracing::error;
 
+use crate::encryption::decrypt_bytes;
 use crate::user::UserObject;
 
+/// How far a sent message got on the receiver side
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Enum)]
+#[enum_type(name = "ReceiptState")]
+pub enum ReceiptState {
+    #[default]
+    Sent,
+    Delivered,
+    Read,
+}
+
+impl From<ReceiptStatus> for ReceiptState {
+    fn from(status: ReceiptStatus) -> Self {
+        match status {
+            ReceiptStatus::Delivered => ReceiptState::Delivered,
+            ReceiptStatus::Read => ReceiptState::Read,
+        }
+    }
+}
+
 wrapper! {
     pub struct MessageObject(ObjectSubclass<imp::MessageObject>);
 }
@@ -76,15 +136,172 @@ impl MessageObject {
         obj
     }
 
+    /// Updates the receipt state. A receipt never goes back to an earlier state
+    pub fn update_receipt(&self, status: ReceiptStatus) {
+        let state = ReceiptState::from(status);
+        if state > self.receipt() {
+            self.set_receipt(state);
+        }
+    }
+
+    /// Shows the new text of an edited message
+    pub fn update_text(&self, message: String) {
+        self.set_message(message);
+        self.set_edited(true);
+    }
+
+    /// Replaces every reaction with the synced ones
+    pub fn set_reactions(&self, reactions: Vec<Reaction>) {
+        let reactions = reactions
+            .into_iter()
+            .map(|reaction| (reaction.user_id, reaction.emoji))
+            .collect();
+        self.imp().reactions.replace(reactions);
+        self.show_reactions();
+    }
+
+    /// Sets or removes the reaction of a user. A user can only have one reaction
+    pub fn set_reaction(&self, user_id: u64, emoji: Option<String>) {
+        {
+            let mut reactions = self.imp().reactions.borrow_mut();
+            reactions.retain(|(id, _)| *id != user_id);
+            if let Some(emoji) = emoji {
+                reactions.push((user_id, emoji));
+            }
+        }
+        self.show_reactions();
+    }
+
+    pub fn user_reaction(&self, user_id: u64) -> Option<String> {
+        self.imp()
+            .reactions
+            .borrow()
+            .iter()
+            .find(|(id, _)| *id == user_id)
+            .map(|(_, emoji)| emoji.to_owned())
+    }
+
+    /// Every used emoji with the number of users that reacted with it
+    pub fn reaction_counts(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for (_, emoji) in self.imp().reactions.borrow().iter() {
+            match counts.iter_mut().find(|(counted, _)| counted == emoji) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((emoji.to_owned(), 1)),
+            }
+        }
+        counts
+    }
+
+    fn show_reactions(&self) {
+        if let Some(row) = self.target_row() {
+            row.show_reactions();
+        }
+    }
+
+    /// Every reaction of the message in the order they were added
+    pub fn reactions(&self) -> Vec<Reaction> {
+        self.imp()
+            .reactions
+            .borrow()
+            .iter()
+            .map(|(user_id, emoji)| Reaction::new(*user_id, emoji.to_owned()))
+            .collect()
+    }
+
+    /// Saves the server time and the AES key of the message so it can be cached
+    pub fn set_cache_data(&self, created_at: String, used_aes_key: Vec<u8>) {
+        self.imp().created_at.replace(created_at);
+        self.imp().used_aes_key.replace(used_aes_key);
+    }
+
+    /// Sets the AES key the message gets encrypted with before it is sent
+    pub fn set_used_aes_key(&self, used_aes_key: Vec<u8>) {
+        self.imp().used_aes_key.replace(used_aes_key);
+    }
+
+    pub fn created_at(&self) -> String {
+        self.imp().created_at.borrow().clone()
+    }
+
+    pub fn used_aes_key(&self) -> Vec<u8> {
+        self.imp().used_aes_key.borrow().clone()
+    }
+
+    /// Saves the attachment of the message and decrypts its name with the AES key of the message
+    pub fn set_attachment(&self, attachment: Attachment, aes_key: Vec<u8>) {
+        let name = decrypt_bytes(&aes_key, &attachment.file_name, &attachment.name_nonce)
+            .and_then(|name| String::from_utf8(name).ok());
+
+        let Some(name) = name else {
+            error!("Failed to decrypt the attachment name");
+            self.set_attachment_name("Attachment could not be decrypted");
+            return;
+        };
+
+        self.set_attachment_name(name);
+        self.imp().attachment.replace(Some(attachment));
+        self.imp().attachment_key.replace(aes_key);
+    }
+
+    pub fn attachment(&self) -> Option<Attachment> {
+        self.imp().attachment.borrow().clone()
+    }
+
+    /// The attachment was only saved if it could be decrypted
+    pub fn has_attachment(&self) -> bool {
+        self.imp().attachment.borrow().is_some()
+    }
+
+    /// Whether the attachment should be shown as an image going by the file name
+    pub fn attachment_is_image(&self) -> bool {
+        let (content_type, _) = gio::content_type_guess(Some(self.attachment_name()), &[]);
+        gio::content_type_get_mime_type(&content_type)
+            .is_some_and(|mime_type| mime_type.starts_with("image/"))
+    }
+
+    /// Decrypts the downloaded attachment file and shows it if it is an image
+    pub fn attachment_downloaded(&self, encrypted_data: Vec<u8>) {
+        let Some(attachment) = self.attachment() else {
+            return;
+        };
+
+        let key = self.imp().attachment_key.borrow().clone();
+        match decrypt_bytes(&key, &encrypted_data, &attachment.file_nonce) {
+            Some(data) => self.set_attachment_data(data),
+            None => error!("Failed to decrypt the attachment file"),
+        }
+    }
+
+    /// Saves the decrypted attachment file
+    pub fn set_attachment_data(&self, data: Vec<u8>) {
+        if self.attachment_is_image() {
+            match Texture::from_bytes(&Bytes::from(&data)) {
+                Ok(texture) => self.set_attachment_image(Some(Paintable::from(texture))),
+                Err(e) => error!("Failed to load the attached image. Reason: {e}"),
+            }
+        }
+        self.imp().attachment_data.replace(Some(data));
+        self.set_attachment_ready(true);
+    }
+
+    pub fn attachment_data(&self) -> Option<Vec<u8>> {
+        self.imp().attachment_data.borrow().clone()
+    }
+
     /// Sets the status of whether this needs to be processed. Utilized
     /// by MessageRow to determine whether to show the spinner
     pub fn to_process(self, state: bool) -> Self {
         self.set_must_process(state);
         if let Some(row) = self.target_row() {
             if state {
-                row.disable_delete_message()
+                row.disable_delete_message();
+                row.disable_edit_message();
             } else {
-                row.enable_delete_message()
+                row.enable_delete_message();
+                if self.is_send() {
+                    row.enable_edit_message();
+                }
             }
         };
         self
@@ -3,7 +3,10 @@ mod imp {
     use adw::Avatar;
     use glib::subclass::InitializingObject;
     use glib::{object_subclass, Binding};
-    use gtk::{glib, Box, Button, CompositeTemplate, Label, PopoverMenu, Revealer, Spinner};
+    use gtk::{
+        glib, Box, Button, CompositeTemplate, EmojiChooser, Image, Label, Picture, Popover,
+        PopoverMenu, Revealer, Spinner,
+    };
     use std::cell::RefCell;
 
     use crate::message::MessageObject;
@@ -35,6 +38,32 @@ mod imp {
         pub message_time: TemplateChild<Label>,
         #[template_child]
         pub processing_spinner: TemplateChild<Spinner>,
+        #[template_child]
+        pub receipt_status: TemplateChild<Label>,
+        #[template_child]
+        pub edited_button: TemplateChild<Button>,
+        #[template_child]
+        pub history_popover: TemplateChild<Popover>,
+        #[template_child]
+        pub history_text: TemplateChild<Label>,
+        #[template_child]
+        pub reply_quote: TemplateChild<Button>,
+        #[template_child]
+        pub reply_sender: TemplateChild<Label>,
+        #[template_child]
+        pub reply_text: TemplateChild<Label>,
+        #[template_child]
+        pub reaction_bar: TemplateChild<Box>,
+        #[template_child]
+        pub reaction_chooser: TemplateChild<EmojiChooser>,
+        #[template_child]
+        pub attachment_image: TemplateChild<Picture>,
+        #[template_child]
+        pub attachment_button: TemplateChild<Button>,
+        #[template_child]
+        pub attachment_icon: TemplateChild<Image>,
+        #[template_child]
+        pub attachment_label: TemplateChild<Label>,
         pub bindings: RefCell<Vec<Binding>>,
         pub message_data: RefCell<Option<MessageObject>>,
     }
@@ -53,6 +82,24 @@ mod imp {
             klass.install_action("message-row.delete", None, move |row, _, _| {
                 row.delete_message()
             });
+            klass.install_action("message-row.react", None, move |row, _, _| {
+                row.imp().reaction_chooser.popup()
+            });
+            klass.install_action("message-row.reply", None, move |row, _, _| {
+                row.reply_to_message()
+            });
+            klass.install_action("message-row.show-reply", None, move |row, _, _| {
+                row.show_replied_message()
+            });
+            klass.install_action("message-row.edit", None, move |row, _, _| {
+                row.edit_message()
+            });
+            klass.install_action("message-row.history", None, move |row, _, _| {
+                row.get_edit_history()
+            });
+            klass.install_action("message-row.attachment", None, move |row, _, _| {
+                row.open_attachment()
+            });
         }
 
         fn instance_init(obj: &InitializingObject<Self>) {
@@ -69,17 +116,20 @@ mod imp {
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
+use gdk::Paintable;
 use gdk::{Cursor, Rectangle};
+use gio::{Cancellable, FileCreateFlags};
 use glib::{clone, timeout_add_local_once, wrapper, Object};
 use gtk::{
-    gdk, glib, Accessible, Box, Buildable, ConstraintTarget, GestureClick, Orientable,
-    RevealerTransitionType, Widget,
+    gdk, gio, glib, Accessible, Box, Buildable, Button, ConstraintTarget, FileDialog, GestureClick,
+    Orientable, RevealerTransitionType, Widget,
 };
 use std::time::Duration;
-use tracing::info;
+use tracing::{error, info};
 
-use crate::message::MessageObject;
-use crate::user::UserProfile;
+use crate::message::{MessageObject, ReceiptState};
+use crate::user::{UserObject, UserProfile, UserPrompt};
+use crate::utils::format_size;
 use crate::window::Window;
 use crate::ws::RequestType;
 
@@ -114,13 +164,27 @@ impl MessageRow {
 
         self.imp().message_data.replace(Some(object.clone()));
 
-        if object.must_process() {
+        // Group members can only delete their own messages
+        if object.must_process() || (!object.is_send() && object.sent_to().is_group()) {
             self.disable_delete_message()
         } else {
             self.enable_delete_message()
         }
 
+        // Only the sender can edit a message
+        if object.is_send() && !object.must_process() {
+            self.enable_edit_message()
+        } else {
+            self.disable_edit_message()
+        }
+
+        self.action_set_enabled(
+            "message-row.attachment",
+            !object.attachment_name().is_empty(),
+        );
+
         self.bind();
+        self.show_reactions();
         self.connect_button_signals(window);
 
         // The transition must start after it gets added to the view
@@ -135,7 +199,13 @@ impl MessageRow {
     }
 
     pub fn new_empty() -> Self {
-        Object::builder().build()
+        let obj: MessageRow = Object::builder().build();
+        obj.imp().reaction_chooser.connect_emoji_picked(
+            clone!(@weak obj as row => move |_, emoji| {
+                row.react(emoji);
+            }),
+        );
+        obj
     }
 
     pub fn stop_signals(&self) {
@@ -213,6 +283,11 @@ impl MessageRow {
             .sync_create()
             .build();
 
+        let edited_binding = message_object
+            .bind_property("edited", &self.imp().edited_button.get(), "visible")
+            .sync_create()
+            .build();
+
         let message_timing_binding = message_object
             .bind_property("message-timing", &message_time, "label")
             .sync_create()
@@ -317,6 +392,138 @@ impl MessageRow {
         bindings.push(spinner_visible_binding);
         bindings.push(spinner_spinning_binding);
         bindings.push(message_timing_binding);
+        bindings.push(edited_binding);
+
+        // Captions are optional for attachments
+        let message_visible_binding = message_object
+            .bind_property("message", &message, "visible")
+            .transform_to(|_, text: String| Some(!text.is_empty()))
+            .sync_create()
+            .build();
+
+        let attachment_image = self.imp().attachment_image.get();
+        let attachment_image_binding = message_object
+            .bind_property("attachment-image", &attachment_image, "paintable")
+            .sync_create()
+            .build();
+
+        let attachment_image_visible_binding = message_object
+            .bind_property("attachment-image", &attachment_image, "visible")
+            .transform_to(|_, image: Option<Paintable>| Some(image.is_some()))
+            .sync_create()
+            .build();
+
+        let attachment_button_binding = message_object
+            .bind_property(
+                "attachment-name",
+                &self.imp().attachment_button.get(),
+                "visible",
+            )
+            .transform_to(|_, name: String| Some(!name.is_empty()))
+            .sync_create()
+            .build();
+
+        let attachment_size = message_object
+            .attachment()
+            .map_or(String::new(), |attachment| format_size(attachment.size));
+        let attachment_label_binding = message_object
+            .bind_property(
+                "attachment-name",
+                &self.imp().attachment_label.get(),
+                "label",
+            )
+            .transform_to(move |_, name: String| {
+                if attachment_size.is_empty() {
+                    Some(name)
+                } else {
+                    Some(format!("{name} ({attachment_size})"))
+                }
+            })
+            .sync_create()
+            .build();
+
+        let attachment_icon_binding = message_object
+            .bind_property(
+                "attachment-ready",
+                &self.imp().attachment_icon.get(),
+                "icon-name",
+            )
+            .transform_to(|_, ready: bool| {
+                if ready {
+                    Some("document-save-symbolic")
+                } else {
+                    Some("folder-download-symbolic")
+                }
+            })
+            .sync_create()
+            .build();
+
+        bindings.push(message_visible_binding);
+        bindings.push(attachment_image_binding);
+        bindings.push(attachment_image_visible_binding);
+        bindings.push(attachment_button_binding);
+        bindings.push(attachment_label_binding);
+        bindings.push(attachment_icon_binding);
+
+        // The quote waits for the replied message if it is not loaded yet
+        let reply_quote = self.imp().reply_quote.get();
+        reply_quote.set_visible(message_object.reply_to() != 0);
+        if message_object.reply_to() != 0 {
+            let reply_sender_binding = message_object
+                .bind_property("replied-message", &self.imp().reply_sender.get(), "label")
+                .transform_to(|_, replied_message: Option<MessageObject>| {
+                    Some(
+                        replied_message.map_or(String::new(), |message| message.sent_from().name()),
+                    )
+                })
+                .sync_create()
+                .build();
+
+            let reply_text_binding = message_object
+                .bind_property("replied-message", &self.imp().reply_text.get(), "label")
+                .transform_to(|_, replied_message: Option<MessageObject>| {
+                    Some(
+                        replied_message
+                            .map_or(String::from("Loading the replied message"), |message| {
+                                message.message()
+                            }),
+                    )
+                })
+                .sync_create()
+                .build();
+
+            bindings.push(reply_sender_binding);
+            bindings.push(reply_text_binding);
+        }
+
+        // Only direct messages sent by the owner get receipts
+        let receipt_status = self.imp().receipt_status.get();
+        if is_sent && !message_object.sent_to().is_group() {
+            let receipt_binding = message_object
+                .bind_property("receipt", &receipt_status, "label")
+                .transform_to(|_, receipt: ReceiptState| {
+                    let label = match receipt {
+                        ReceiptState::Sent => "Sent",
+                        ReceiptState::Delivered => "Delivered",
+                        ReceiptState::Read => "Read",
+                    };
+                    Some(label.to_string())
+                })
+                .sync_create()
+                .build();
+
+            // The receipt is shown once the spinner is gone
+            let receipt_visible_binding = message_object
+                .bind_property("must-process", &receipt_status, "visible")
+                .invert_boolean()
+                .sync_create()
+                .build();
+
+            bindings.push(receipt_binding);
+            bindings.push(receipt_visible_binding);
+        } else {
+            receipt_status.set_visible(false);
+        }
     }
 
     fn connect_button_signals(&self, window: &Window) {
@@ -357,12 +564,14 @@ impl MessageRow {
         info!("Deleting a message from the UI");
         let message_data = self.imp().message_data.borrow().clone().unwrap();
 
-        let other_user =
-            if message_data.sent_from().user_id() == message_data.sent_from().owner_id() {
-                message_data.sent_to()
-            } else {
-                message_data.sent_from()
-            };
+        let other_user = if message_data.sent_from().user_id()
+            == message_data.sent_from().owner_id()
+            || message_data.sent_to().is_group()
+        {
+            message_data.sent_to()
+        } else {
+            message_data.sent_from()
+        };
 
         let message_number = message_data.message_number();
 
@@ -378,6 +587,157 @@ impl MessageRow {
             .to_process(true);
     }
 
+    /// The chat the message belongs to
+    fn message_chat(&self) -> UserObject {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+
+        if message_data.is_send() || message_data.sent_to().is_group() {
+            message_data.sent_to()
+        } else {
+            message_data.sent_from()
+        }
+    }
+
+    fn reply_to_message(&self) {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+
+        // Messages without a number from the server can not be replied to yet
+        if message_data.must_process() {
+            return;
+        }
+
+        let window: Window = self.root().and_downcast().unwrap();
+        window.reply_to_message(&message_data);
+    }
+
+    fn show_replied_message(&self) {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+        let window: Window = self.root().and_downcast().unwrap();
+        window.scroll_to_message(self.message_chat(), message_data.reply_to());
+    }
+
+    /// Adds the reaction of the owner. Reacting with the current reaction removes it
+    fn react(&self, emoji: &str) {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+
+        // Messages without a number from the server can not get reactions yet
+        if message_data.must_process() {
+            return;
+        }
+
+        let chat = self.message_chat();
+        let owner_id = chat.owner_id();
+        let message_number = message_data.message_number();
+
+        if message_data.user_reaction(owner_id).as_deref() == Some(emoji) {
+            info!("Removing reaction from message number {}", message_number);
+            message_data.set_reaction(owner_id, None);
+            chat.add_to_queue(RequestType::RemoveReaction(message_number));
+        } else {
+            info!("Reacting to message number {}", message_number);
+            message_data.set_reaction(owner_id, Some(emoji.to_string()));
+            chat.add_to_queue(RequestType::AddReaction(message_number, emoji.to_string()));
+        }
+    }
+
+    /// Rebuilds the reaction bar. Clicking on a reaction reacts with the same emoji
+    pub fn show_reactions(&self) {
+        let reaction_bar = self.imp().reaction_bar.get();
+        while let Some(child) = reaction_bar.first_child() {
+            reaction_bar.remove(&child);
+        }
+
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+        let own_reaction = message_data.user_reaction(self.message_chat().owner_id());
+        let reaction_counts = message_data.reaction_counts();
+
+        reaction_bar.set_visible(!reaction_counts.is_empty());
+
+        for (emoji, count) in reaction_counts {
+            let button = Button::builder()
+                .label(format!("{emoji} {count}"))
+                .css_classes(["flat", "reaction"])
+                .can_focus(false)
+                .build();
+
+            if own_reaction.as_deref() == Some(emoji.as_str()) {
+                button.add_css_class("own-reaction");
+            }
+
+            button.connect_clicked(clone!(@weak self as row => move |_| {
+                row.react(&emoji);
+            }));
+            reaction_bar.append(&button);
+        }
+    }
+
+    fn edit_message(&self) {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+        let window: Window = self.root().and_downcast().unwrap();
+        let prompt = UserPrompt::new("Edit").edit_message(&window, &message_data);
+        prompt.present();
+    }
+
+    fn get_edit_history(&self) {
+        info!("Getting the edit history of a message");
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+        self.message_chat()
+            .add_to_queue(RequestType::GetEditHistory(message_data));
+    }
+
+    /// Shows the earlier versions of the message below it
+    pub fn show_edit_history(&self, history: &str) {
+        self.imp().history_text.set_label(history);
+        self.imp().history_popover.popup();
+    }
+
+    /// Downloads the attachment if it is not downloaded yet, otherwise asks where to save it
+    fn open_attachment(&self) {
+        let message_data = self.imp().message_data.borrow().clone().unwrap();
+
+        if !message_data.attachment_ready() {
+            self.message_chat().download_attachment(&message_data);
+            return;
+        }
+
+        let window: Window = self.root().and_downcast().unwrap();
+        let dialog = FileDialog::builder()
+            .title("Save Attachment")
+            .initial_name(message_data.attachment_name())
+            .modal(true)
+            .build();
+
+        dialog.save(
+            Some(&window),
+            None::<&Cancellable>,
+            clone!(@weak window, @weak message_data => move |result| {
+                let Ok(file) = result else {
+                    return;
+                };
+                let Some(data) = message_data.attachment_data() else {
+                    return;
+                };
+
+                file.replace_contents_async(
+                    data,
+                    None,
+                    false,
+                    FileCreateFlags::REPLACE_DESTINATION,
+                    None::<&Cancellable>,
+                    clone!(@weak window => move |result| {
+                        match result {
+                            Ok(_) => window.show_toast("Attachment saved"),
+                            Err((_, e)) => {
+                                error!("Failed to save the attachment. Reason: {e}");
+                                window.show_toast("Failed to save the attachment");
+                            }
+                        }
+                    }),
+                );
+            }),
+        );
+    }
+
     fn copy_message(&self) {
         info!("Copying message text to clipboard");
         let text = self.imp().message_data.borrow().clone().unwrap().message();
@@ -391,4 +751,12 @@ impl MessageRow {
     pub fn disable_delete_message(&self) {
         self.action_set_enabled("message-row.delete", false);
     }
+
+    pub fn enable_edit_message(&self) {
+        self.action_set_enabled("message-row.edit", true);
+    }
+
+    pub fn disable_edit_message(&self) {
+        self.action_set_enabled("message-row.edit", false);
+    }
 }
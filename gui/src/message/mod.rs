@@ -1,5 +1,5 @@
 pub mod message_object;
 pub mod message_row;
 
-pub use message_object::MessageObject;
+pub use message_object::{MessageObject, ReceiptState};
 pub use message_row::MessageRow;
@@ -0,0 +1,135 @@
+use chirp_protocol::{FullUserData, UserIDs};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::encryption::{
+    decrypt_bytes, derive_passphrase_key, encrypt_bytes, Keyring, VerifiedKeys,
+};
+
+/// Length of the random salt the backup key is derived with
+const SALT_LENGTH: usize = 16;
+
+/// Everything needed to restore the account of the owner on another device
+#[derive(Serialize, Deserialize)]
+pub struct AccountBackup {
+    pub user_ids: UserIDs,
+    pub public_key: String,
+    // PKCS#8 private key encrypted with the same passphrase as the backup
+    pub private_key: String,
+    // The saved user list. The owner is always the first one
+    pub users: Vec<FullUserData>,
+    // {User ID: Keys the user was verified with}
+    pub verified_users: HashMap<u64, VerifiedKeys>,
+    // The private keys used before the key was rotated. Needed for the older messages
+    #[serde(default)]
+    pub keyring: Keyring,
+}
+
+impl AccountBackup {
+    /// Encrypts the backup with a key derived from the passphrase.
+    /// Starts with the salt and the nonce followed by the encrypted data
+    pub fn encrypt(&self, passphrase: &str) -> Vec<u8> {
+        let mut salt = vec![0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+
+        let backup_key = derive_passphrase_key(passphrase, &salt);
+        let (encrypted_data, nonce) =
+            encrypt_bytes(&backup_key, &serde_json::to_vec(self).unwrap());
+
+        let mut backup_data = salt;
+        backup_data.extend(nonce);
+        backup_data.extend(encrypted_data);
+        backup_data
+    }
+
+    /// None if the passphrase is wrong or the data is not a backup
+    pub fn decrypt(backup_data: &[u8], passphrase: &str) -> Option<Self> {
+        if backup_data.len() < SALT_LENGTH + 12 {
+            error!("The backup is too short to be valid");
+            return None;
+        }
+
+        let (salt, encrypted_data) = backup_data.split_at(SALT_LENGTH);
+        let (nonce, encrypted_data) = encrypted_data.split_at(12);
+
+        let backup_key = derive_passphrase_key(passphrase, salt);
+        let decrypted_data = decrypt_bytes(&backup_key, encrypted_data, nonce)?;
+
+        match serde_json::from_slice(&decrypted_data) {
+            Ok(backup) => Some(backup),
+            Err(e) => {
+                error!("Failed to read the decrypted backup. Reason: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::DeviceIdentity;
+    use rsa::RsaPrivateKey;
+
+    fn new_backup() -> AccountBackup {
+        let mut keyring = Keyring::default();
+        keyring.add_old_key(3, &RsaPrivateKey::new(&mut OsRng, 1024).unwrap());
+
+        let identity = DeviceIdentity {
+            identity_key: vec![1; 32],
+            signing_key: vec![2; 32],
+        };
+        let verified_keys = VerifiedKeys {
+            key_digest: vec![3; 32],
+            identities: HashMap::from([(20, identity)]),
+        };
+
+        AccountBackup {
+            user_ids: UserIDs::new(1, String::from("token"), 10),
+            public_key: String::from("public key"),
+            private_key: String::from("private key"),
+            users: vec![FullUserData::new(2, String::from("Bob"), None, Vec::new())],
+            verified_users: HashMap::from([(2, verified_keys)]),
+            keyring,
+        }
+    }
+
+    #[test]
+    fn backup_survives_encrypting() {
+        let backup = new_backup();
+        let backup_data = backup.encrypt("passphrase");
+
+        let decrypted = AccountBackup::decrypt(&backup_data, "passphrase").unwrap();
+        assert_eq!(
+            serde_json::to_vec(&decrypted).unwrap(),
+            serde_json::to_vec(&backup).unwrap()
+        );
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let backup_data = new_backup().encrypt("passphrase");
+        assert!(AccountBackup::decrypt(&backup_data, "other passphrase").is_none());
+    }
+
+    #[test]
+    fn damaged_backup_is_rejected() {
+        let backup_data = new_backup().encrypt("passphrase");
+
+        let truncated = &backup_data[..backup_data.len() - 1];
+        assert!(AccountBackup::decrypt(truncated, "passphrase").is_none());
+        assert!(AccountBackup::decrypt(&backup_data[..SALT_LENGTH + 4], "passphrase").is_none());
+        assert!(AccountBackup::decrypt(&[], "passphrase").is_none());
+
+        let mut corrupted = backup_data.clone();
+        corrupted[SALT_LENGTH + 20] ^= 1;
+        assert!(AccountBackup::decrypt(&corrupted, "passphrase").is_none());
+
+        let mut wrong_salt = backup_data;
+        wrong_salt[0] ^= 1;
+        assert!(AccountBackup::decrypt(&wrong_salt, "passphrase").is_none());
+    }
+}
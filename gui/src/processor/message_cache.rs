@@ -0,0 +1,101 @@
+use adw::subclass::prelude::*;
+use chirp_protocol::{Attachment, Reaction, ReceiptStatus};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::encryption::{read_encrypted_file, save_encrypted_file};
+use crate::message::{MessageObject, ReceiptState};
+use crate::ws::DecryptedMessageData;
+
+/// A decrypted message saved in the local cache with the state it had when it was saved
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedMessage {
+    pub message_number: u64,
+    pub created_at: String,
+    pub from_user: u64,
+    pub to_user: u64,
+    pub message: String,
+    pub used_aes_key: Vec<u8>,
+    pub receipt: Option<ReceiptStatus>,
+    pub edited: bool,
+    pub reply_to: Option<u64>,
+    pub reactions: Vec<Reaction>,
+    pub attachment: Option<Attachment>,
+}
+
+impl CachedMessage {
+    /// None if the message was not saved by the server yet
+    pub fn from_message(message: &MessageObject) -> Option<Self> {
+        if message.must_process() {
+            return None;
+        }
+        let message_number = message.imp().message_number.get().copied()?;
+
+        let receipt = match message.receipt() {
+            ReceiptState::Sent => None,
+            ReceiptState::Delivered => Some(ReceiptStatus::Delivered),
+            ReceiptState::Read => Some(ReceiptStatus::Read),
+        };
+
+        let reply_to = match message.reply_to() {
+            0 => None,
+            number => Some(number),
+        };
+
+        Some(CachedMessage {
+            message_number,
+            created_at: message.created_at(),
+            from_user: message.sent_from().user_id(),
+            to_user: message.sent_to().user_id(),
+            message: message.message(),
+            used_aes_key: message.used_aes_key(),
+            receipt,
+            edited: message.edited(),
+            reply_to,
+            reactions: message.reactions(),
+            attachment: message.attachment(),
+        })
+    }
+
+    pub fn into_decrypted(self) -> DecryptedMessageData {
+        DecryptedMessageData {
+            created_at: self.created_at,
+            from_user: self.from_user,
+            to_user: self.to_user,
+            message: Some(self.message),
+            message_number: self.message_number,
+            used_aes_key: self.used_aes_key,
+            receipt: self.receipt,
+            // Only whether the message was edited is shown
+            edited_at: self.edited.then(String::new),
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            attachment: self.attachment,
+        }
+    }
+}
+
+/// Encrypts the messages with the cache key and saves them in the given file. False if it could not be saved
+pub fn save_chat_cache(cache_path: &str, cache_key: &[u8], messages: &[CachedMessage]) -> bool {
+    let to_save = serde_json::to_vec(messages).unwrap();
+    save_encrypted_file(cache_path, cache_key, &to_save)
+}
+
+/// Reads and decrypts the cached messages of a chat. None if there is no cache or it can not be decrypted
+pub fn load_chat_cache(cache_path: &str, cache_key: &[u8]) -> Option<Vec<CachedMessage>> {
+    let decrypted_data = read_encrypted_file(cache_path, cache_key)?;
+
+    match serde_json::from_slice::<Vec<CachedMessage>>(&decrypted_data) {
+        Ok(messages) => {
+            info!(
+                "Loaded {} cached messages from {cache_path}",
+                messages.len()
+            );
+            Some(messages)
+        }
+        Err(e) => {
+            error!("Failed to read the message cache {cache_path}. Reason: {e}");
+            None
+        }
+    }
+}
@@ -5,7 +5,7 @@ mod imp {
     use glib::{derived_properties, object_subclass, Properties, SignalHandlerId};
     use gtk::{glib, NoSelection, SignalListItemFactory};
     use std::cell::{Cell, OnceCell, RefCell};
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     use crate::message::MessageObject;
     use crate::user::UserObject;
@@ -14,6 +14,10 @@ mod imp {
     #[properties(wrapper_type = super::MessageRenderer)]
     pub struct MessageRenderer {
         pub saved_messages: RefCell<HashMap<u64, MessageObject>>,
+        // Replies that are waiting for the replied message to load. {Replied number: Replies}
+        pub pending_replies: RefCell<HashMap<u64, Vec<MessageObject>>>,
+        // Replied message numbers that were requested from the server
+        pub fetching_replies: RefCell<HashSet<u64>>,
         #[property(get, set)]
         pub message_liststore: OnceCell<ListStore>,
         #[property(get, set)]
@@ -24,6 +28,12 @@ mod imp {
         pub synced_till: Cell<u64>,
         #[property(get, set)]
         pub shown_till: Cell<u64>,
+        // The message number after the newest shown message. 0 if the newest message is shown
+        #[property(get, set)]
+        pub shown_upto: Cell<u64>,
+        // Message to show once the ongoing sync is done. 0 if there is none
+        #[property(get, set)]
+        pub jump_target: Cell<u64>,
         #[property(get, set)]
         pub belongs_to: OnceCell<UserObject>,
         #[property(get, set)]
@@ -53,6 +63,7 @@ use gtk::{glib, ListItem, NoSelection, SignalListItemFactory};
 use tracing::debug;
 
 use crate::message::{MessageObject, MessageRow};
+use crate::processor::CachedMessage;
 use crate::user::UserObject;
 use crate::ws::RequestType;
 
@@ -75,6 +86,8 @@ impl MessageRenderer {
             .property("selection-model", selection_model)
             .property("belongs-to", belongs_to)
             .property("became-inactive", false)
+            .property("shown-upto", 0_u64)
+            .property("jump-target", 0_u64)
             .build();
 
         obj.start_factory();
@@ -143,7 +156,65 @@ impl MessageRenderer {
         self.imp()
             .saved_messages
             .borrow_mut()
-            .insert(number, message);
+            .insert(number, message.clone());
+        self.set_replied_message(&message, number);
+    }
+
+    /// Links a reply with the message it replies to. If that message is not loaded, the reply waits for it
+    pub fn resolve_reply(&self, message: &MessageObject) {
+        let reply_to = message.reply_to();
+        if let Some(replied_message) = self.get_message(reply_to) {
+            message.set_replied_message(Some(replied_message));
+        } else {
+            self.imp()
+                .pending_replies
+                .borrow_mut()
+                .entry(reply_to)
+                .or_default()
+                .push(message.clone());
+        }
+    }
+
+    /// Gives the replied message to every reply that was waiting for it
+    pub fn set_replied_message(&self, message: &MessageObject, number: u64) {
+        let replies = self.imp().pending_replies.borrow_mut().remove(&number);
+        for reply in replies.unwrap_or_default() {
+            reply.set_replied_message(Some(message.clone()));
+        }
+        self.imp().fetching_replies.borrow_mut().remove(&number);
+    }
+
+    /// Replied message numbers that are not loaded and were not requested yet
+    pub fn replies_to_fetch(&self) -> Vec<u64> {
+        let mut fetching = self.imp().fetching_replies.borrow_mut();
+        let to_fetch: Vec<u64> = self
+            .imp()
+            .pending_replies
+            .borrow()
+            .keys()
+            .filter(|number| !fetching.contains(number))
+            .copied()
+            .collect();
+        fetching.extend(to_fetch.iter().copied());
+        to_fetch
+    }
+
+    /// Adds the saved messages older than the shown ones to the ListStore till the given number
+    pub fn show_till(&self, number: u64) {
+        let message_list = self.message_liststore();
+        let saved_messages = self.imp().saved_messages.borrow();
+
+        for num in (number..self.shown_till()).rev() {
+            if let Some(message_object) = saved_messages.get(&num) {
+                message_list.insert(0, message_object);
+            }
+        }
+        self.set_shown_till(number);
+    }
+
+    /// Gets a saved message with the given number
+    pub fn get_message(&self, number: u64) -> Option<MessageObject> {
+        self.imp().saved_messages.borrow().get(&number).cloned()
     }
 
     /// Clears the user's Message ListStore. Used before another user is set as active
@@ -152,6 +223,7 @@ impl MessageRenderer {
             self.set_became_inactive(true)
         }
         self.set_shown_till(self.message_number());
+        self.set_shown_upto(0);
         self.message_liststore().remove_all();
     }
 
@@ -172,6 +244,11 @@ impl MessageRenderer {
         if let Some(start_from) = start_from {
             ended_at = start_from;
         }
+        if ended_at > self.message_number() {
+            self.set_shown_upto(0);
+        } else {
+            self.set_shown_upto(ended_at);
+        }
 
         for num in (final_num..ended_at).rev() {
             if let Some(message_object) = saved_messages.get(&num) {
@@ -212,6 +289,61 @@ impl MessageRenderer {
         }
     }
 
+    /// The number of the newest saved message. 0 if nothing is saved
+    pub fn newest_saved(&self) -> u64 {
+        self.imp()
+            .saved_messages
+            .borrow()
+            .keys()
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every saved message that can be cached, oldest first
+    pub fn cached_messages(&self) -> Vec<CachedMessage> {
+        let saved_messages = self.imp().saved_messages.borrow();
+        let mut numbers: Vec<&u64> = saved_messages.keys().collect();
+        numbers.sort();
+
+        numbers
+            .into_iter()
+            .filter_map(|number| CachedMessage::from_message(&saved_messages[number]))
+            .collect()
+    }
+
+    /// Whether the newest message is in the ListStore. New messages are only added if it is
+    pub fn is_showing_latest(&self) -> bool {
+        self.shown_upto() == 0
+    }
+
+    /// Adds the saved messages newer than the shown ones to the ListStore
+    pub fn load_newer_items(&self) {
+        if self.is_showing_latest() {
+            return;
+        }
+
+        let message_list = self.message_liststore();
+        let saved_messages = self.imp().saved_messages.borrow();
+
+        let mut total_to_add = 50;
+        let mut shown_upto = self.shown_upto();
+
+        while shown_upto <= self.message_number() && total_to_add > 0 {
+            if let Some(message_object) = saved_messages.get(&shown_upto) {
+                message_list.append(message_object);
+                total_to_add -= 1;
+            }
+            shown_upto += 1;
+        }
+
+        if shown_upto > self.message_number() {
+            self.set_shown_upto(0);
+        } else {
+            self.set_shown_upto(shown_upto);
+        }
+    }
+
     pub fn delete_item(&self, message_number: &u64) {
         self.imp()
             .saved_messages
@@ -1,3 +1,9 @@
+pub mod account_backup;
+pub mod message_cache;
 pub mod message_renderer;
+pub mod search_index;
 
+pub use account_backup::AccountBackup;
+pub use message_cache::{load_chat_cache, save_chat_cache, CachedMessage};
 pub use message_renderer::MessageRenderer;
+pub use search_index::{SearchIndex, SearchResult};
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ws::DecryptedMessageData;
+
+/// The most results a single search returns
+const MAX_RESULTS: usize = 50;
+
+/// A message that matched a search
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub chat_id: u64,
+    pub message_number: u64,
+    pub text: String,
+}
+
+/// Word index of the decrypted messages of every chat. It only lives in memory
+#[derive(Default)]
+pub struct SearchIndex {
+    // {Word: {(Chat ID, Message number)}}
+    words: HashMap<String, HashSet<(u64, u64)>>,
+    // {(Chat ID, Message number): Message text}
+    messages: HashMap<(u64, u64), String>,
+}
+
+impl SearchIndex {
+    /// Adds a decrypted message of the given chat. Messages that could not be decrypted are skipped
+    pub fn add_decrypted(&mut self, chat_id: u64, message_data: &DecryptedMessageData) {
+        if message_data.message_number == 0 {
+            return;
+        }
+
+        if let Some(text) = &message_data.message {
+            self.add_message(chat_id, message_data.message_number, text);
+        }
+    }
+
+    /// Adds the text of a message. An already indexed message gets its old text replaced
+    pub fn add_message(&mut self, chat_id: u64, message_number: u64, text: &str) {
+        self.remove_message(chat_id, message_number);
+
+        let key = (chat_id, message_number);
+        for word in split_words(text) {
+            self.words.entry(word).or_default().insert(key);
+        }
+        self.messages.insert(key, text.to_string());
+    }
+
+    pub fn remove_message(&mut self, chat_id: u64, message_number: u64) {
+        let key = (chat_id, message_number);
+        let Some(text) = self.messages.remove(&key) else {
+            return;
+        };
+
+        for word in split_words(&text) {
+            if let Some(keys) = self.words.get_mut(&word) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Finds the messages that have a word starting with every word of the query. Newest messages come first
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let mut matched: Option<HashSet<(u64, u64)>> = None;
+
+        for query_word in split_words(query) {
+            let word_matches: HashSet<(u64, u64)> = self
+                .words
+                .iter()
+                .filter(|(word, _)| word.starts_with(&query_word))
+                .flat_map(|(_, keys)| keys.iter().copied())
+                .collect();
+
+            matched = Some(match matched {
+                Some(previous) => previous.intersection(&word_matches).copied().collect(),
+                None => word_matches,
+            });
+        }
+
+        let mut keys: Vec<(u64, u64)> = matched.unwrap_or_default().into_iter().collect();
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        keys.into_iter()
+            .take(MAX_RESULTS)
+            .map(|(chat_id, message_number)| SearchResult {
+                chat_id,
+                message_number,
+                text: self.messages[&(chat_id, message_number)].to_owned(),
+            })
+            .collect()
+    }
+}
+
+/// Lowercase words of a text without the punctuation
+fn split_words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chirp_protocol::MessageData;
+
+    fn decrypted(message_number: u64, text: Option<&str>) -> DecryptedMessageData {
+        let mut message_data = MessageData::new_incomplete(String::new(), 1, 2);
+        message_data.message_number = message_number;
+
+        match text {
+            Some(text) => DecryptedMessageData::new(message_data, text.to_string(), Vec::new()),
+            None => DecryptedMessageData::new_incomplete(message_data),
+        }
+    }
+
+    fn found(search_index: &SearchIndex, query: &str) -> Vec<(u64, u64)> {
+        search_index
+            .search(query)
+            .into_iter()
+            .map(|result| (result.chat_id, result.message_number))
+            .collect()
+    }
+
+    #[test]
+    fn text_is_split_into_lowercase_words() {
+        let words = split_words("Hello, WORLD! it's 2pm...");
+        let expected = ["hello", "world", "it", "s", "2pm"];
+
+        assert_eq!(words.len(), expected.len());
+        assert!(expected.iter().all(|word| words.contains(*word)));
+        assert!(split_words(" ,.!? ").is_empty());
+    }
+
+    #[test]
+    fn search_ignores_the_case() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_message(1, 1, "Meeting at the Office");
+
+        assert_eq!(found(&search_index, "office"), [(1, 1)]);
+        assert_eq!(found(&search_index, "MEETING"), [(1, 1)]);
+        assert_eq!(found(&search_index, "oFF"), [(1, 1)]);
+    }
+
+    #[test]
+    fn every_query_word_must_match() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_message(1, 1, "lunch tomorrow");
+        search_index.add_message(1, 2, "lunch today");
+
+        assert_eq!(found(&search_index, "lunch tom"), [(1, 1)]);
+        assert_eq!(found(&search_index, "lunch"), [(1, 2), (1, 1)]);
+        assert!(found(&search_index, "dinner").is_empty());
+        assert!(found(&search_index, "").is_empty());
+    }
+
+    #[test]
+    fn results_come_from_every_chat() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_message(1, 5, "the report is done");
+        search_index.add_message(2, 5, "send me the report");
+        search_index.add_message(3, 9, "report ready");
+        search_index.add_message(3, 10, "nothing here");
+
+        // Newest message numbers first
+        assert_eq!(found(&search_index, "report"), [(3, 9), (1, 5), (2, 5)]);
+
+        let results = search_index.search("send");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].text, "send me the report");
+    }
+
+    #[test]
+    fn only_decrypted_messages_are_added() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_decrypted(1, &decrypted(1, Some("secret plans")));
+        search_index.add_decrypted(1, &decrypted(2, None));
+        search_index.add_decrypted(1, &decrypted(0, Some("secret unsaved")));
+
+        assert_eq!(found(&search_index, "secret"), [(1, 1)]);
+    }
+
+    #[test]
+    fn edited_messages_lose_the_old_words() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_message(1, 1, "see you on monday");
+        search_index.add_decrypted(1, &decrypted(1, Some("see you on tuesday")));
+
+        assert!(found(&search_index, "monday").is_empty());
+        assert_eq!(found(&search_index, "tuesday"), [(1, 1)]);
+        assert_eq!(search_index.search("see")[0].text, "see you on tuesday");
+        assert!(!search_index.words.contains_key("monday"));
+    }
+
+    #[test]
+    fn deleted_messages_are_not_found() {
+        let mut search_index = SearchIndex::default();
+        search_index.add_message(1, 1, "delete this");
+        search_index.add_message(2, 1, "keep this");
+
+        search_index.remove_message(1, 1);
+        assert!(found(&search_index, "delete").is_empty());
+        assert_eq!(found(&search_index, "this"), [(2, 1)]);
+
+        // Removing an unknown message changes nothing
+        search_index.remove_message(5, 5);
+        assert_eq!(found(&search_index, "keep"), [(2, 1)]);
+    }
+}
@@ -0,0 +1,182 @@
+mod imp {
+    use adw::subclass::prelude::*;
+    use adw::Avatar;
+    use glib::subclass::InitializingObject;
+    use glib::{object_subclass, Binding};
+    use gtk::{glib, Box, CompositeTemplate, Label, Popover, PopoverMenu, Revealer};
+    use std::cell::{Cell, OnceCell, RefCell};
+
+    use crate::user::UserObject;
+
+    #[derive(Default, CompositeTemplate)]
+    #[template(resource = "/com/github/therustypickle/chirp/group_row.xml")]
+    pub struct GroupRow {
+        #[template_child]
+        pub group_revealer: TemplateChild<Revealer>,
+        #[template_child]
+        pub group_avatar: TemplateChild<Avatar>,
+        #[template_child]
+        pub group_popover: TemplateChild<Popover>,
+        #[template_child]
+        pub popover_label: TemplateChild<Label>,
+        #[template_child]
+        pub group_menu: TemplateChild<PopoverMenu>,
+        pub popover_visible: Cell<bool>,
+        pub bindings: RefCell<Vec<Binding>>,
+        pub group_data: OnceCell<UserObject>,
+    }
+
+    #[object_subclass]
+    impl ObjectSubclass for GroupRow {
+        const NAME: &'static str = "GroupRow";
+        type Type = super::GroupRow;
+        type ParentType = Box;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+            klass.install_action("group-row.add-member", None, move |row, _, _| {
+                row.add_member()
+            });
+            klass.install_action("group-row.leave", None, move |row, _, _| row.leave_group());
+        }
+
+        fn instance_init(obj: &InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for GroupRow {}
+
+    impl WidgetImpl for GroupRow {}
+
+    impl BoxImpl for GroupRow {}
+}
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use gdk::{Cursor, Rectangle};
+use glib::{clone, timeout_add_local_once, wrapper, Object};
+use gtk::{
+    gdk, glib, Accessible, Box, Buildable, ConstraintTarget, EventControllerMotion, GestureClick,
+    Orientable, Widget,
+};
+use std::time::Duration;
+use tracing::info;
+
+use crate::user::{UserObject, UserPrompt};
+use crate::window::Window;
+use crate::ws::RequestType;
+
+wrapper! {
+    pub struct GroupRow(ObjectSubclass<imp::GroupRow>)
+    @extends Box, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Orientable;
+}
+
+impl GroupRow {
+    pub fn new(object: UserObject) -> Self {
+        let row: GroupRow = Object::builder().build();
+        row.imp().popover_visible.set(false);
+
+        let motion = EventControllerMotion::new();
+        row.imp().group_avatar.get().add_controller(motion.clone());
+
+        let new_cursor = Cursor::builder().name("pointer").build();
+        row.imp().group_avatar.set_cursor(Some(&new_cursor));
+
+        let row_clone = row.clone();
+        motion.connect_enter(move |_, _, _| {
+            if !row_clone.imp().popover_visible.get() {
+                let popover = row_clone.imp().group_popover.get();
+                let position = row_clone
+                    .compute_bounds(&row_clone.imp().group_avatar.get())
+                    .unwrap();
+                let group_data = row_clone.imp().group_data.get().unwrap();
+
+                // The owner is not saved as a group member
+                let popover_text = format!(
+                    "{}\n{} members",
+                    group_data.name(),
+                    group_data.group_members().len() + 1
+                );
+
+                let x_position = position.x() as i32 + 45;
+                let y_position = position.y() as i32 + 25;
+
+                let position = Rectangle::new(x_position, y_position, -1, -1);
+
+                popover.set_pointing_to(Some(&position));
+                row_clone.imp().popover_label.set_label(&popover_text);
+
+                popover.set_visible(true);
+                row_clone.imp().popover_visible.set(true);
+            }
+        });
+
+        motion.connect_leave(clone!(@weak row => move |_| {
+            if row.imp().popover_visible.get() {
+                row.imp().group_popover.get().set_visible(false);
+                row.imp().popover_visible.set(false);
+            }
+        }));
+
+        let gesture = GestureClick::new();
+        gesture.set_button(3);
+        row.imp().group_avatar.add_controller(gesture.clone());
+
+        gesture.connect_pressed(clone!(@weak row => move |_, _, x_position, y_position|{
+            let popover = row.imp().group_menu.get();
+            let position = Rectangle::new(x_position as i32, y_position as i32 + 10, -1, -1);
+            popover.set_pointing_to(Some(&position));
+            popover.set_visible(true);
+        }));
+
+        row.imp().group_data.set(object).unwrap();
+        row.bind();
+
+        // The transition must start after it gets added to the ListBox thus a small timer
+        let revealer = row.imp().group_revealer.get();
+        timeout_add_local_once(Duration::from_millis(50), move || {
+            revealer.set_reveal_child(true);
+        });
+
+        row
+    }
+
+    pub fn stop_signals(&self) {
+        for binding in self.imp().bindings.take() {
+            binding.unbind();
+        }
+    }
+
+    pub fn bind(&self) {
+        let mut bindings = self.imp().bindings.borrow_mut();
+        let group_avatar = self.imp().group_avatar.get();
+
+        let group_object = self.imp().group_data.get().unwrap();
+
+        let avatar_text_binding = group_object
+            .bind_property("name", &group_avatar, "text")
+            .sync_create()
+            .build();
+
+        bindings.push(avatar_text_binding);
+    }
+
+    fn add_member(&self) {
+        info!("Opening prompt to add a group member");
+        let root = self.root().unwrap();
+        let main_window = root.downcast_ref::<Window>().unwrap();
+        let group_data = self.imp().group_data.get().unwrap();
+
+        let prompt = UserPrompt::new("Add").add_group_member(main_window, group_data);
+        prompt.present();
+    }
+
+    /// The group row gets removed once the server confirms the owner left
+    fn leave_group(&self) {
+        info!("Leaving a group");
+        let group_data = self.imp().group_data.get().unwrap();
+        group_data.add_to_queue(RequestType::RemoveGroupMember(group_data.owner_id()));
+    }
+}
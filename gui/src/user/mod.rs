@@ -1,8 +1,10 @@
+pub mod group_row;
 pub mod user_object;
 pub mod user_profile;
 pub mod user_prompt;
 pub mod user_row;
 
+pub use group_row::GroupRow;
 pub use user_object::UserObject;
 pub use user_profile::UserProfile;
 pub use user_prompt::UserPrompt;
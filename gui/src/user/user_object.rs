@@ -4,10 +4,11 @@ mod imp {
     use gdk::Paintable;
     use glib::once_cell::sync::Lazy;
     use glib::subclass::Signal;
-    use glib::{derived_properties, object_subclass, Properties, SignalHandlerId};
+    use glib::{derived_properties, object_subclass, Properties, SignalHandlerId, SourceId};
     use gtk::{gdk, glib};
     use rsa::{RsaPrivateKey, RsaPublicKey};
     use std::cell::{Cell, OnceCell, RefCell};
+    use std::collections::{HashMap, HashSet};
     use std::sync::Mutex;
 
     use crate::processor::MessageRenderer;
@@ -37,17 +38,64 @@ mod imp {
         #[property(get, set)]
         pub owner_id: Cell<u64>,
         #[property(get, set)]
-        pub user_token: OnceCell<String>,
+        pub user_token: RefCell<String>,
+        #[property(get, set)]
+        pub device_id: Cell<u64>,
+        // Groups use the group ID as the user ID
+        #[property(get, set)]
+        pub is_group: Cell<bool>,
+        #[property(get, set)]
+        pub group_creator: Cell<u64>,
+        // Every member of this group except the owner. {User ID: UserObject}
+        pub group_members: RefCell<HashMap<u64, super::UserObject>>,
         #[property(get, set)]
         pub main_window: OnceCell<Window>,
         #[property(get, set)]
         pub renderer: OnceCell<MessageRenderer>,
-        pub rsa_public: OnceCell<RsaPublicKey>,
-        pub rsa_private: OnceCell<RsaPrivateKey>,
-        pub receiver_rsa_public: OnceCell<RsaPublicKey>,
+        // Replaced when the key of this device gets rotated
+        pub rsa_public: RefCell<Option<RsaPublicKey>>,
+        pub rsa_private: RefCell<Option<RsaPrivateKey>>,
+        // RSA public keys of every device of this user. {Device ID: Key}
+        pub device_keys: RefCell<HashMap<u64, RsaPublicKey>>,
+        // ID of the current key of every device of this user. {Device ID: Key ID}
+        pub key_ids: RefCell<HashMap<u64, u64>>,
         pub aes_key: OnceCell<Vec<u8>>,
         pub receiver_aes_key: RefCell<Option<Vec<u8>>>,
+        // Whether the prekey bundles were asked for since the last reconnect or new device
+        pub prekeys_requested: Cell<bool>,
+        // The prekey bundles were asked for and no reply was received yet
+        pub bundles_pending: Cell<bool>,
+        // Devices that had no prekeys published when the bundles were received.
+        // Only these get the AES key of direct messages encrypted with RSA
+        pub prekeyless_devices: RefCell<HashSet<u64>>,
+        // Devices no session could be started with. Direct messages fail till the bundles are asked for again
+        pub failed_devices: RefCell<HashSet<u64>>,
+        // Whether the owner was told messages to a device of this user are not forward secret
+        pub downgrade_warned: Cell<bool>,
+        // Requests waiting for the prekey bundles to start the missing sessions
+        pub waiting_requests: RefCell<Vec<RequestType>>,
         pub signal_ids: RefCell<Vec<SignalHandlerId>>,
+        // The highest message number received from this user and the ones the receipts were sent for
+        pub last_received: Cell<u64>,
+        pub last_delivered: Cell<u64>,
+        pub last_read: Cell<u64>,
+        pub receipt_scheduled: Cell<bool>,
+        // Monotonic time the last typing signal was sent to this user
+        pub last_typing_sent: Cell<i64>,
+        #[property(get, set)]
+        pub is_typing: Cell<bool>,
+        pub typing_timeout: RefCell<Option<SourceId>>,
+        #[property(get, set)]
+        pub online: Cell<bool>,
+        // Online or the last time the user was seen. Empty if unknown
+        #[property(get, set)]
+        pub presence: RefCell<String>,
+        // Whether the owner compared the safety number with this user and the keys did not change since
+        #[property(get, set)]
+        pub verified: Cell<bool>,
+        // The keys of this user changed after they were verified
+        #[property(get, set)]
+        pub key_changed: Cell<bool>,
     }
 
     #[object_subclass]
@@ -77,33 +125,57 @@ mod imp {
 }
 
 use adw::prelude::*;
+use chirp_protocol::{
+    Attachment, AttachmentChunk, DeleteMessage, DeletedMessageData, DeviceKey, EditHistoryRequest,
+    EncryptedKey, ErrorCode, FullUserData, GroupData, GroupMember, ImageUpdate, MessageAck,
+    MessageData, MessageReaction, MessageReceipt, MessageSyncData, MessageSyncRequest, NameUpdate,
+    NewGroup, PreKeyBundle, Presence, ReceiptStatus, ResponseFrame, ServerResponse, UnreadChat,
+    UserIDs, ATTACHMENT_CHUNK_SIZE,
+};
+use chrono::Local;
 use gdk::{gdk_pixbuf, Paintable, Texture};
 use gdk_pixbuf::{InterpType, PixbufLoader};
 use gio::subclass::prelude::ObjectSubclassIsExt;
 use gio::{spawn_blocking, ListStore};
 use glib::{
-    clone, closure_local, timeout_add_local_once, Bytes, ControlFlow, MainContext, Object,
-    Priority, Receiver,
+    clone, closure_local, idle_add_local_once, monotonic_time, timeout_add_local_once, Bytes,
+    ControlFlow, MainContext, Object, Priority, Receiver,
 };
 use gtk::{gdk, glib, NoSelection, SignalListItemFactory};
 use rsa::{RsaPrivateKey, RsaPublicKey};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::encryption::{
-    decrypt_message, decrypt_message_chunk, encrypt_message, generate_new_aes_key,
-    generate_new_rsa_keys,
+    decrypt_message, decrypt_message_chunk, encrypt_aes_key, encrypt_bytes, encrypt_message,
+    generate_new_aes_key, generate_new_rsa_keys, key_digest, read_rsa_public_from_string,
+    safety_number, stringify_rsa_public, DeviceIdentity, VerifiedKeys,
 };
 use crate::message::MessageObject;
 use crate::processor::MessageRenderer;
-use crate::utils::{generate_random_avatar_link, get_avatar, get_random_color};
-use crate::window::Window;
-use crate::ws::{
-    DecryptedMessageData, DeleteMessage, DeletedMessageData, FullUserData, ImageUpdate,
-    MessageData, MessageSyncData, MessageSyncRequest, NameUpdate, RequestType, UserIDs, WSObject,
+use crate::utils::{
+    generate_random_avatar_link, get_avatar, get_created_at_timing, get_random_color,
+    parse_server_time,
 };
+use crate::window::Window;
+use crate::ws::{DecryptedMessageData, RequestFailure, RequestType, WSObject};
+
+/// One-time prekeys get replenished once fewer than this are left on the server
+const MIN_PREKEYS: u64 = 20;
+
+/// Number of one-time prekeys published at once
+const NEW_PREKEYS: usize = 50;
+
+/// Why the AES key of a direct message could not be encrypted for every device
+enum MissingSession {
+    // The prekey bundle of a device is on the way
+    Pending,
+    // No session could be started with a device
+    Failed,
+}
 
 glib::wrapper! {
     pub struct UserObject(ObjectSubclass<imp::UserObject>);
@@ -117,7 +189,7 @@ impl UserObject {
         user_id: Option<u64>,
         user_token: Option<String>,
         window: Window,
-        receiving_rsa_public_key: Option<RsaPublicKey>,
+        devices: Option<Vec<DeviceKey>>,
         saved_keys: Option<(RsaPublicKey, RsaPrivateKey)>,
     ) -> Self {
         let random_color = get_random_color(color_to_ignore);
@@ -141,24 +213,27 @@ impl UserObject {
 
         // Will only be some in case of owner object and with some data saved
         if let Some((public_key, private_key)) = saved_keys {
-            obj.imp().rsa_public.set(public_key).unwrap();
-            obj.imp().rsa_private.set(private_key).unwrap();
+            obj.set_rsa_keys(public_key, private_key);
         }
 
         // Will always be when a new user is getting added
         // Will only be Some for owner object when the data is saved
         // In case not saved, creates a new RSA pair in thread
-        if let Some(key) = receiving_rsa_public_key {
-            obj.imp().receiver_rsa_public.set(key).unwrap();
+        if let Some(devices) = devices {
+            obj.set_devices(devices);
         } else {
             let (sender, receiver) = MainContext::channel(Priority::default());
 
             receiver.attach(None, clone!(@weak obj as user_object, @weak window => @default-return ControlFlow::Break, move |(public_key, private_key): (RsaPublicKey, RsaPrivateKey)| {
-                user_object.imp().rsa_public.set(public_key.clone()).unwrap();
-                user_object.imp().rsa_private.set(private_key).unwrap();
-                user_object.imp().receiver_rsa_public.set(public_key).unwrap();
+                user_object.set_rsa_keys(public_key.clone(), private_key);
+                user_object.imp().device_keys.borrow_mut().insert(0, public_key);
                 window.save_rsa_keys();
-                user_object.add_queue_to_first(RequestType::CreateNewUser);
+                // An existing user without saved keys is on a new device
+                if user_object.user_id() == 0 {
+                    user_object.add_queue_to_first(RequestType::CreateNewUser);
+                } else if user_object.user_ws().authenticated() {
+                    user_object.add_to_queue(RequestType::RegisterDevice);
+                }
                 ControlFlow::Break
             }));
             thread::spawn(move || sender.send(generate_new_rsa_keys()));
@@ -167,9 +242,6 @@ impl UserObject {
         // Each object will have its own aes key for encrypting when sending messages
         obj.imp().aes_key.set(generate_new_aes_key()).unwrap();
         obj.start_signals();
-
-        let ws = WSObject::new();
-        obj.set_user_ws(ws);
         obj.check_image_link(image_link, false);
         obj
     }
@@ -183,8 +255,8 @@ impl UserObject {
             closure_local!(
                 move |from: UserObject, error_message: String, image_link: String| {
                     if error_message.is_empty() && !image_link.is_empty() {
-                        let image_data = ImageUpdate::new_json(Some(image_link), from.user_token());
-                        from.user_ws().image_link_updated(&image_data);
+                        let image_data = ImageUpdate::new(from.user_id(), Some(image_link));
+                        from.user_ws().image_link_updated(image_data);
                     }
                 }
             ),
@@ -317,101 +389,230 @@ impl UserObject {
         let mut highest_index: u64 = 0;
         let mut connection_lost = false;
 
+        // Other users share the owner connection so they must wait for the owner to get authenticated
+        let can_process = self.is_owner() || user_ws.authenticated();
+
         for task in queue_list {
-            if user_ws.ws_conn().is_some() {
+            if user_ws.ws_conn().is_some() && can_process {
                 debug!("starting processing {task:#?}");
                 match task {
                     RequestType::ReconnectUser => {
-                        let id_data = UserIDs::new_json(self.user_id(), self.user_token());
-                        user_ws.reconnect_user(id_data);
+                        let callback = clone!(@weak self as user_object => move |result: Result<FullUserData, RequestFailure>| {
+                            match result {
+                                Ok(user_data) => user_object.reconnect_success(user_data),
+                                Err(e) => user_object.request_failed(e),
+                            }
+                        });
+                        if self.is_owner() {
+                            let id_data =
+                                UserIDs::new(self.user_id(), self.user_token(), self.device_id());
+                            user_ws.reconnect_user(id_data, callback);
+                        } else {
+                            user_ws.open_chat(self.user_id(), callback);
+                        }
                     }
                     RequestType::CreateNewUser => {
-                        let user_data = FullUserData::new(self).to_json();
-                        user_ws.create_new_user(user_data);
+                        let user_data = self.to_user_data();
+                        user_ws.create_new_user(
+                            user_data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(id_data) => user_object.update_user_id(id_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
                     }
                     RequestType::SendMessage(message_data, msg_obj) => {
                         let message_text = msg_obj.message();
-                        let new_number = self.message_number() + 1;
-
-                        self.renderer().set_message_number(new_number);
-                        msg_obj.set_message_number(new_number);
 
-                        let aes_key = self.imp().aes_key.get().unwrap().clone();
-
-                        let rsa_public = self.imp().rsa_public.get().unwrap();
-                        let receiver_rsa_public = self.imp().receiver_rsa_public.get().unwrap();
-
-                        // This side's message encrypted with this side's rsa key
-                        let (sender_message, sender_key, sender_nonce) =
-                            encrypt_message(aes_key.clone(), rsa_public, &message_text);
-
-                        // The receiver side's is encrypted with the rsa key from the receiver's side
-                        // The receiver side is where this message is getting sent
-                        let (receiver_message, receiver_key, receiver_nonce) =
-                            encrypt_message(aes_key, receiver_rsa_public, &message_text);
-
-                        let data = message_data
-                            .update_message(
-                                sender_message,
-                                receiver_message,
-                                sender_key,
-                                receiver_key,
-                                sender_nonce,
-                                receiver_nonce,
-                            )
-                            .update_token(self.user_token())
-                            .update_message_number(new_number)
-                            .to_json();
-
-                        user_ws.send_text_message(&data);
-                        msg_obj.to_process(false);
-
-                        self.main_window()
-                            .imp()
-                            .message_numbers
-                            .borrow_mut()
-                            .get_mut(&self.user_id())
-                            .unwrap()
-                            .insert(new_number);
+                        // The server picks the message number, the message is shown as pending till then
+                        let callback = clone!(@weak self as user_object, @strong msg_obj => move |result: Result<MessageAck, RequestFailure>| {
+                            match result {
+                                Ok(ack) => user_object.message_sent(ack, msg_obj),
+                                Err(e) => {
+                                    user_object.remove_unsent_message(&msg_obj);
+                                    user_object.request_failed(e);
+                                }
+                            }
+                        });
+
+                        let aes_key = &self.message_aes_key(&msg_obj);
+                        msg_obj.set_used_aes_key(aes_key.clone());
+
+                        // Every device of every member gets its own copy of the AES key
+                        if self.is_group() {
+                            let (message, nonce) = encrypt_message(aes_key, &message_text);
+                            let device_keys = encrypt_aes_key(
+                                aes_key,
+                                &self.message_devices(),
+                                &self.message_key_ids(),
+                            );
 
-                        //self.main_window().scroll_to_bottom(self.clone());
+                            let data =
+                                message_data.update_group_message(message, device_keys, nonce);
+                            user_ws.send_group_message(data, callback);
+                        } else {
+                            // Every device of both sides gets its own copy of the AES key
+                            match self.direct_message_keys(aes_key) {
+                                Ok(device_keys) => {
+                                    let (sender_message, sender_nonce) =
+                                        encrypt_message(aes_key, &message_text);
+                                    let (receiver_message, receiver_nonce) =
+                                        encrypt_message(aes_key, &message_text);
+
+                                    let data = message_data.update_message(
+                                        sender_message,
+                                        receiver_message,
+                                        device_keys,
+                                        sender_nonce,
+                                        receiver_nonce,
+                                    );
+
+                                    user_ws.send_text_message(data, callback);
+                                }
+                                Err(MissingSession::Pending) => {
+                                    self.imp()
+                                        .waiting_requests
+                                        .borrow_mut()
+                                        .push(RequestType::SendMessage(message_data, msg_obj));
+                                }
+                                Err(MissingSession::Failed) => {
+                                    self.remove_unsent_message(&msg_obj);
+                                    self.session_failed();
+                                }
+                            }
+                        }
                     }
                     RequestType::ImageUpdated(link) => {
                         self.check_image_link(link.to_owned(), true);
                         if link.is_none() {
-                            let image_data = ImageUpdate::new_json(link, self.user_token());
-                            user_ws.image_link_updated(&image_data);
+                            let image_data = ImageUpdate::new(self.user_id(), link);
+                            user_ws.image_link_updated(image_data);
                         }
                     }
                     RequestType::NameUpdated(name) => {
                         self.set_name(name.to_owned());
-                        let name_data = NameUpdate::new_json(name.to_string(), self.user_token());
-                        user_ws.name_updated(&name_data)
+                        let name_data = NameUpdate::new(self.user_id(), name.to_string());
+                        user_ws.name_updated(name_data)
                     }
                     RequestType::GetUserData(id) => {
-                        let user_data = UserIDs::new_json(id.to_owned(), self.user_token());
-                        user_ws.get_user_data(&user_data)
+                        user_ws.get_user_data(
+                            id,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(user_data) => user_object.user_data_received(user_data),
+                                    Err(e) => {
+                                        // Stop the user prompt from waiting for the reply
+                                        user_object.emit_by_name::<()>("user-exists", &[&false]);
+                                        user_object.request_failed(e);
+                                    }
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::RegisterDevice => {
+                        let (rsa_public, _) = self.rsa_keys().unwrap();
+                        let device_key =
+                            DeviceKey::new(self.user_id(), 0, stringify_rsa_public(&rsa_public));
+                        user_ws.register_device(
+                            device_key,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(device_id) => user_object.device_registered(device_id),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::PublishPreKeys(total_new) => {
+                        if let Some(ratchet_store) = self.main_window().ratchet_store() {
+                            let prekey_data = {
+                                let mut ratchet_store = ratchet_store.lock().unwrap();
+                                let one_time_prekeys =
+                                    ratchet_store.generate_one_time_prekeys(total_new);
+                                ratchet_store.prekey_upload(one_time_prekeys)
+                            };
+                            self.main_window().save_ratchet_store();
+
+                            user_ws.publish_prekeys(
+                                prekey_data,
+                                clone!(@weak self as user_object => move |result| {
+                                    match result {
+                                        Ok(remaining) => user_object.prekeys_published(remaining),
+                                        Err(e) => user_object.request_failed(e),
+                                    }
+                                }),
+                            );
+                        }
+                    }
+                    RequestType::GetPreKeyBundles => {
+                        user_ws.get_prekey_bundles(
+                            self.user_id(),
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(bundles) => user_object.prekey_bundles_received(bundles),
+                                    Err(e) => {
+                                        user_object.bundles_failed();
+                                        user_object.request_failed(e);
+                                    }
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::GetSenderData(id) => {
+                        user_ws.get_user_data(
+                            id,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(user_data) => user_object.add_new_user(user_data, true),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
                     }
                     RequestType::GetLastMessageNumber(user) => {
-                        let data = UserIDs::new_json(user.user_id(), self.user_token());
-                        user_ws.selection_update(data)
+                        user_ws.selection_update(
+                            user.user_id(),
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(number) => user_object.message_number_received(number),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
                     }
                     RequestType::SyncMessage(start_at, end_at) => {
                         info!(
                             "Sending request to sync message from {} {}",
                             start_at, end_at
                         );
-                        let data = MessageSyncRequest::new_json(
-                            self.user_id(),
-                            start_at,
-                            end_at,
-                            self.user_token(),
+                        let data = MessageSyncRequest::new(self.user_id(), start_at, end_at);
+                        user_ws.sync_message(
+                            data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(chat_data) => user_object.messages_synced(chat_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
                         );
-                        user_ws.sync_message(data)
+                    }
+                    RequestType::MessageReceipt(status, number) => {
+                        let receipt = MessageReceipt::new(self.user_id(), number, status);
+                        user_ws.message_receipt(receipt);
+                    }
+                    RequestType::AddReaction(number, emoji) => {
+                        let data = MessageReaction::new(self.user_id(), number, emoji);
+                        user_ws.add_reaction(data);
+                    }
+                    RequestType::RemoveReaction(number) => {
+                        let data = MessageReaction::new(self.user_id(), number, String::new());
+                        user_ws.remove_reaction(data);
                     }
                     RequestType::DeleteMessage(user_id, number) => {
                         self.remove_message(number, false);
-                        let data = DeleteMessage::new_json(user_id, number, self.user_token());
+                        let data = DeleteMessage::new(user_id, number);
                         user_ws.delete_message(data);
 
                         self.main_window()
@@ -428,13 +629,185 @@ impl UserObject {
                             "Sending request to sync deleted message from {} {}",
                             start_at, end_at
                         );
-                        let data = MessageSyncRequest::new_json(
+                        let data = MessageSyncRequest::new(self.user_id(), start_at, end_at);
+                        user_ws.sync_deleted_message(
+                            data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(deleted_data) => user_object.deleted_messages_synced(deleted_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::EditMessage(message_number, message_text, msg_obj) => {
+                        let callback = clone!(@weak self as user_object, @strong msg_obj, @strong message_text => move |result: Result<MessageData, RequestFailure>| {
+                            match result {
+                                Ok(_) => {
+                                    user_object
+                                        .main_window()
+                                        .search_index()
+                                        .lock()
+                                        .unwrap()
+                                        .add_message(user_object.user_id(), msg_obj.message_number(), &message_text);
+                                    msg_obj.update_text(message_text);
+                                    msg_obj.to_process(false);
+                                }
+                                Err(e) => {
+                                    msg_obj.to_process(false);
+                                    user_object.request_failed(e);
+                                }
+                            }
+                        });
+
+                        // Edits keep the key of the message so devices that decrypted the message can decrypt the edit
+                        let aes_key = &self.message_aes_key(&msg_obj);
+                        let message_data = MessageData::new_incomplete(
+                            Local::now().to_string(),
+                            self.owner_id(),
+                            self.user_id(),
+                        )
+                        .update_message_number(message_number);
+
+                        // The edit is encrypted for every device the same way a new message is
+                        if self.is_group() {
+                            let device_keys = encrypt_aes_key(
+                                aes_key,
+                                &self.message_devices(),
+                                &self.message_key_ids(),
+                            );
+                            let (message, nonce) = encrypt_message(aes_key, &message_text);
+                            let data =
+                                message_data.update_group_message(message, device_keys, nonce);
+                            user_ws.edit_message(data, callback);
+                        } else {
+                            match self.direct_message_keys(aes_key) {
+                                Ok(device_keys) => {
+                                    let (sender_message, sender_nonce) =
+                                        encrypt_message(aes_key, &message_text);
+                                    let (receiver_message, receiver_nonce) =
+                                        encrypt_message(aes_key, &message_text);
+                                    let data = message_data.update_message(
+                                        sender_message,
+                                        receiver_message,
+                                        device_keys,
+                                        sender_nonce,
+                                        receiver_nonce,
+                                    );
+                                    user_ws.edit_message(data, callback);
+                                }
+                                Err(MissingSession::Pending) => {
+                                    self.imp().waiting_requests.borrow_mut().push(
+                                        RequestType::EditMessage(
+                                            message_number,
+                                            message_text,
+                                            msg_obj,
+                                        ),
+                                    );
+                                }
+                                Err(MissingSession::Failed) => {
+                                    msg_obj.to_process(false);
+                                    self.session_failed();
+                                }
+                            }
+                        }
+                    }
+                    RequestType::GetRepliedMessage(message_number) => {
+                        let data = MessageSyncRequest::new(
+                            self.user_id(),
+                            message_number.saturating_sub(1),
+                            message_number,
+                        );
+                        user_ws.sync_message(
+                            data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(chat_data) => user_object.replied_message_received(chat_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::SyncEditedMessage(start_at, end_at) => {
+                        info!(
+                            "Sending request to sync edited message from {} {}",
+                            start_at, end_at
+                        );
+                        let data = MessageSyncRequest::new(self.user_id(), start_at, end_at);
+                        user_ws.sync_edited_message(
+                            data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(edited_data) => user_object.edited_messages_synced(edited_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::GetEditHistory(msg_obj) => {
+                        let data =
+                            EditHistoryRequest::new(self.user_id(), msg_obj.message_number());
+                        user_ws.get_edit_history(
+                            data,
+                            clone!(@weak self as user_object, @strong msg_obj => move |result| {
+                                match result {
+                                    Ok(history) => user_object.edit_history_received(history, msg_obj),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::CreateGroup(group_name) => {
+                        let group_data = NewGroup::new(group_name, Vec::new());
+                        user_ws.create_group(
+                            group_data,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(group_data) => user_object.group_received(group_data),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
+                        );
+                    }
+                    RequestType::GetGroups => {
+                        user_ws.get_groups(clone!(@weak self as user_object => move |result| {
+                            match result {
+                                Ok(groups) => user_object.groups_received(groups),
+                                Err(e) => user_object.request_failed(e),
+                            }
+                        }));
+                    }
+                    RequestType::AddGroupMember(user_id) => {
+                        let member_data = GroupMember::new(self.user_id(), user_id);
+                        user_ws.add_group_member(member_data);
+                    }
+                    RequestType::RemoveGroupMember(user_id) => {
+                        let member_data = GroupMember::new(self.user_id(), user_id);
+                        user_ws.remove_group_member(member_data);
+                    }
+                    RequestType::RotateToken => {
+                        user_ws.rotate_token(clone!(@weak self as user_object => move |result| {
+                            match result {
+                                Ok(id_data) => user_object.token_rotated(id_data),
+                                Err(e) => user_object.request_failed(e),
+                            }
+                        }));
+                    }
+                    RequestType::RotateKey(public_key, private_key) => {
+                        let device_key = DeviceKey::new(
                             self.user_id(),
-                            start_at,
-                            end_at,
-                            self.user_token(),
+                            self.device_id(),
+                            stringify_rsa_public(&public_key),
+                        );
+                        user_ws.rotate_key(
+                            device_key,
+                            clone!(@weak self as user_object => move |result| {
+                                match result {
+                                    Ok(device_key) => user_object.key_rotated(device_key, public_key, private_key),
+                                    Err(e) => user_object.request_failed(e),
+                                }
+                            }),
                         );
-                        user_ws.sync_deleted_message(data)
                     }
                 }
                 highest_index += 1;
@@ -445,7 +818,7 @@ impl UserObject {
                     }
                 }
             } else {
-                info!("Connection lost. Stopping processing request");
+                info!("Connection lost or not authenticated yet. Stopping processing request");
                 connection_lost = true;
                 break;
             }
@@ -484,6 +857,12 @@ impl UserObject {
     /// Tries to find the given message ID and remove it from the message list.
     /// If the list changed while the UI was being updated, does a recursive call to remove it
     pub fn remove_message(&self, target_number: u64, is_recursive: bool) {
+        self.main_window()
+            .search_index()
+            .lock()
+            .unwrap()
+            .remove_message(self.user_id(), target_number);
+
         let total_len = self.messages().n_items();
 
         // Try to reduce the number of iterations required. If bigger than total length then the message is likely to be
@@ -560,7 +939,8 @@ impl UserObject {
         false
     }
 
-    /// Waits for the websocket connection to be established and calls the function to start listening to messages
+    /// Waits for the websocket connection to be established and calls the function to start listening to messages.
+    /// Only called for the owner as every other user shares the owner connection
     pub fn handle_ws(&self) {
         let user_object = self.clone();
         let user_ws = self.user_ws();
@@ -595,194 +975,1579 @@ impl UserObject {
         }
 
         let id = user_ws.ws_conn().unwrap().connect_message(
-            clone!(@weak self as user_object => move |_ws, _s, bytes| {
+            clone!(@weak self as user_object, @weak user_ws => move |_ws, _s, bytes| {
                 let byte_slice = bytes.to_vec();
                 let text = String::from_utf8(byte_slice).unwrap();
 
-                if text.starts_with('/') {
-                    let splitted_data: Vec<&str> = text.splitn(2, ' ').collect();
-                    match splitted_data[0] {
-                        "/reconnect-success" => {
-                            let user_data = FullUserData::from_json(splitted_data[1]);
-                            user_object.set_name(user_data.user_name);
-                            user_object.check_image_link(user_data.image_link, false);
-                            window.save_user_list();
-                            // It must be set to zero to ensure the server sends every single message from the server
-                            // If from the other side a message gets deleted
-                            // while this client is on but not connected it would mean this client would not receive
-                            // the deletion event. So we have to check every single message the server has to ensure
-                            // nothing is missed
-                            let synced_till = user_object.renderer().synced_till();
-                            let message_number = user_object.renderer().message_number();
-                            user_object.add_to_queue(RequestType::SyncDeletedMessage(synced_till, message_number));
-                            user_object.renderer().set_message_number(0);
-                            user_object.renderer().set_is_syncing(false);
-                            user_object.add_queue_to_first(RequestType::GetLastMessageNumber(user_object.clone()));
+                let frame = match ResponseFrame::from_json(&text) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        error!("Failed to parse WS response. Reason: {e}");
+                        return;
+                    }
+                };
+
+                // Replies to requests are handled by the callback of the request
+                let Some(response) = user_ws.resolve_response(frame) else {
+                    return;
+                };
+
+                // Broadcasts name the chat they belong to with the other user's ID
+                match response {
+                    // Errors of requests that do not wait for a reply
+                    ServerResponse::Error(error_data) => {
+                        user_object.request_failed(RequestFailure::Server(error_data));
+                    }
+                    ServerResponse::ImageUpdated(image_data) => {
+                        if let Some(target_user) = window.find_user(image_data.user_id) {
+                            target_user.check_image_link(image_data.image_link, false);
                         }
-                        "/update-user-id" => {
-                            let id_data = UserIDs::from_json(splitted_data[1]);
-                            user_object.set_user_id(id_data.user_id);
-                            user_object.set_user_token(id_data.user_token);
-                            user_object.set_owner_id(id_data.user_id);
-                            window.save_user_data();
-                            window.imp()
-                                .message_numbers
-                                .borrow_mut()
-                                .insert(id_data.user_id, HashSet::new());
-                            window.save_user_list();
-                            user_object.process_queue(None);
+                    }
+                    ServerResponse::NameUpdated(name_data) => {
+                        if let Some(target_user) = window.find_user(name_data.user_id) {
+                            target_user.set_name(name_data.new_name);
                         }
-                        "/image-updated" => {
-                            let image_data = ImageUpdate::new_from_json(splitted_data[1]);
-                            user_object.check_image_link(image_data.image_link, false);
+                    }
+                    ServerResponse::DeleteMessage(deletion_data) => {
+                        if let Some(target_user) = window.find_user(deletion_data.user_id) {
+                            target_user.remove_message(deletion_data.message_number, false);
                         }
-                        "/name-updated" => user_object.set_name(splitted_data[1]),
-                        "/message-number" => {
-                            let message_number = splitted_data[1].parse::<u64>().unwrap();
-                            info!(
-                                "Current message_number number {}, gotten number {}",
-                                user_object.message_number(),
-                                message_number
-                            );
-                            let total_to_load = 200;
-
-                            if message_number > user_object.message_number() {
-                                let sync_target = if message_number > total_to_load {
-                                    message_number - total_to_load
-                                } else {
-                                    0
-                                };
-                                user_object.renderer().set_message_number(message_number);
-                                // Syncing must happen before any pending message sent or deletion is performed
-                                user_object.add_queue_to_first(RequestType::SyncMessage(
-                                    sync_target,
-                                    message_number,
-                                ));
+                    }
+                    ServerResponse::Message(message_data) => {
+                        let owner_id = user_object.user_id();
+                        let is_group_message =
+                            message_data.from_user != owner_id && message_data.to_user != owner_id;
+
+                        // Messages sent from other devices of the owner and group messages
+                        // belong to the receiver's chat
+                        let other_user = if is_group_message || message_data.from_user == owner_id {
+                            message_data.to_user
+                        } else {
+                            message_data.from_user
+                        };
+
+                        if let Some(target_user) = window.find_user(other_user) {
+                            target_user.message_received(message_data);
+                        } else if is_group_message {
+                            info!("Message received in a group that was not added. Getting the groups");
+                            user_object.add_to_queue(RequestType::GetGroups);
+                        } else {
+                            // The message gets synced once the user is added and the chat is opened
+                            info!("Message received from a user that was not added. Getting the user data");
+                            user_object.add_to_queue(RequestType::GetSenderData(other_user));
+                        }
+                    }
+                    ServerResponse::EditMessage(message_data) => {
+                        let owner_id = user_object.user_id();
+                        let is_group_message =
+                            message_data.from_user != owner_id && message_data.to_user != owner_id;
+
+                        // Edits are routed to the chat the same way messages are
+                        let other_user = if is_group_message || message_data.from_user == owner_id {
+                            message_data.to_user
+                        } else {
+                            message_data.from_user
+                        };
 
-                            } else {
-                                user_object.process_queue(None);
+                        if let Some(target_user) = window.find_user(other_user) {
+                            target_user.message_edited(message_data);
+                        }
+                    }
+                    ServerResponse::Typing(user_id) => {
+                        if let Some(target_user) = window.find_user(user_id) {
+                            target_user.typing_received();
+                        }
+                    }
+                    ServerResponse::Presence(presence) => {
+                        if let Some(target_user) = window.find_user(presence.user_id) {
+                            target_user.presence_received(presence);
+                        }
+                    }
+                    ServerResponse::ReactionAdded(reaction) => {
+                        if let Some(target_user) = window.find_user(reaction.user_id) {
+                            target_user.reaction_received(reaction, true);
+                        }
+                    }
+                    ServerResponse::ReactionRemoved(reaction) => {
+                        if let Some(target_user) = window.find_user(reaction.user_id) {
+                            target_user.reaction_received(reaction, false);
+                        }
+                    }
+                    ServerResponse::DeviceAdded(device_key) => {
+                        if let Some(target_user) = window.find_user(device_key.user_id) {
+                            target_user.add_device(device_key.clone());
+                            target_user.forget_bundles();
+                        }
+
+                        // Group members that were not added as a user only exist in the groups
+                        for group in window.groups() {
+                            if let Some(member) = group.group_member(device_key.user_id) {
+                                member.add_device(device_key.clone());
                             }
                         }
-                        "/sync-message" => {
-                            if user_object.syncing() {
-                                return;
+                    }
+                    ServerResponse::KeyRotated(device_key) => {
+                        info!(
+                            "Device {} of User ID {} rotated its key",
+                            device_key.device_id, device_key.user_id
+                        );
+                        if let Some(target_user) = window.find_user(device_key.user_id) {
+                            target_user.add_device(device_key.clone());
+                        }
+
+                        for group in window.groups() {
+                            if let Some(member) = group.group_member(device_key.user_id) {
+                                member.add_device(device_key.clone());
                             }
-                            user_object.renderer().set_is_syncing(true);
+                        }
+                    }
+                    ServerResponse::GroupUpdated(group_data) => {
+                        user_object.group_received(group_data);
+                    }
+                    ServerResponse::MessageReceipt(receipt) => {
+                        if let Some(target_user) = window.find_user(receipt.user_id) {
+                            target_user.receipt_received(receipt);
+                        }
+                    }
+                    ServerResponse::UnreadChats(unread_chats) => {
+                        user_object.unread_chats_received(unread_chats);
+                    }
+                    ServerResponse::GroupRemoved(group_id) => {
+                        info!("Removed from group {group_id}");
+                        window.delete_user(group_id);
+                    }
+                    response => {
+                        info!("Received a reply that no request is waiting for. Dismissing {response:?}");
+                    }
+                }
+            }),
+        );
+        self.user_ws().set_signal_id(id);
+    }
+
+    /// Decrypts and shows a message that was received from this user
+    fn message_received(&self, message_data: MessageData) {
+        let window = self.main_window();
+        self.typing_stopped();
+        let (_, rsa_private_key) = self.rsa_keys().unwrap();
+        let owner_id = self.owner_id();
+
+        let old_aes_key = self.imp().receiver_aes_key.borrow().clone();
+        let ratchet_key = self.ratchet_message_key(&message_data);
+        let used_ratchet = ratchet_key.is_some();
+        let decrypted_data = decrypt_message(
+            message_data,
+            &old_aes_key,
+            ratchet_key,
+            &rsa_private_key,
+            &window.keyring(),
+            owner_id,
+            self.device_id(),
+        );
+
+        self.imp()
+            .receiver_aes_key
+            .replace(Some(decrypted_data.used_aes_key.clone()));
+        window
+            .search_index()
+            .lock()
+            .unwrap()
+            .add_decrypted(self.user_id(), &decrypted_data);
+        let message_object = window.receive_message(decrypted_data, self.clone(), true);
+
+        if let Some(object) = message_object {
+            object.set_show_initial_message(false)
+        }
+        window.scroll_to_bottom(self.clone(), true);
 
-                            let owner_id = user_object.owner_id();
-                            let chat_data = MessageSyncData::from_json(splitted_data[1]);
+        // The ratchet key can not be unwrapped again so the message is cached right away
+        if used_ratchet {
+            window.save_message_cache(self);
+        }
+    }
 
-                            let rsa_private_key = user_object.imp().rsa_private.get().unwrap().clone();
+    /// Decrypts the new text of an edited message and shows it if the message was loaded.
+    /// Messages that were not synced yet arrive with the edited text
+    fn message_edited(&self, message_data: MessageData) {
+        let Some(message) = self.renderer().get_message(message_data.message_number) else {
+            return;
+        };
+        let (_, rsa_private_key) = self.rsa_keys().unwrap();
 
-                            let (sender, receiver) = MainContext::channel(Priority::default());
+        // Edits are encrypted with the key of the message so the ratchet is only needed if the
+        // message could not be decrypted before
+        let message_key = message.used_aes_key();
+        let (old_aes_key, ratchet_key) = if message_key.is_empty() {
+            (None, self.ratchet_message_key(&message_data))
+        } else {
+            (Some(message_key), None)
+        };
 
-                            receiver.attach(None, clone!(
-                                @weak user_object, @weak window => @default-return ControlFlow::Break,
-                                move |(message_data, completed): (Vec<DecryptedMessageData>, bool)| {
-                                for message in message_data {
-                                    if message.message_number == 0 {
-                                        continue;
-                                    }
-                                    window.receive_message(message, user_object.clone(), false);
-                                }
+        let decrypted_data = decrypt_message(
+            message_data,
+            &old_aes_key,
+            ratchet_key,
+            &rsa_private_key,
+            &self.main_window().keyring(),
+            self.owner_id(),
+            self.device_id(),
+        );
 
-                                if completed {
-                                    user_object.renderer().set_is_syncing(false);
-                                    user_object.renderer().set_became_inactive(false);
-                                    return ControlFlow::Break
-                                }
-                                ControlFlow::Continue
-                            }));
-
-                            let old_aes_key = user_object.imp().receiver_aes_key.borrow().clone();
-                            let existing_numbers = window
-                                .imp()
-                                .message_numbers
-                                .borrow_mut()
-                                .get(&user_object.user_id())
-                                .unwrap().clone();
-                            thread::spawn(move || {
-                                decrypt_message_chunk(
-                                    sender,
-                                    old_aes_key,
-                                    chat_data.message_data,
-                                    &rsa_private_key,
-                                    owner_id,
-                                    existing_numbers
-                                )
-                            });
-                            user_object.process_queue(None);
-                        }
-                        "/sync-deleted-message" => {
-                            let deleted_numbers = DeletedMessageData::from_json(splitted_data[1]);
-                            let mut existing_numbers = window
-                                .imp()
-                                .message_numbers
-                                .borrow_mut()
-                                .get_mut(&user_object.user_id())
-                                .unwrap()
-                                .clone();
-                            for num in deleted_numbers.message_numbers {
-                                let message_exists = existing_numbers.remove(&num);
-
-                                if message_exists {
-                                    user_object.remove_message(num, false);
-                                    user_object.renderer().delete_item(&num);
-                                }
-                            }
-                            user_object.process_queue(None);
-                        }
-                        "/delete-message" => {
-                            let deletion_data = DeleteMessage::from_json(splitted_data[1]);
-                            user_object.remove_message(deletion_data.message_number, false);
-                        }
-                        "/message" => {
-                            let message_data = MessageData::from_json(splitted_data[1]);
+        if decrypted_data.used_aes_key.is_empty() {
+            info!(
+                "Edit of message number {} could not be decrypted",
+                decrypted_data.message_number
+            );
+            return;
+        }
 
-                            let rsa_private_key = user_object.imp().rsa_private.get().unwrap();
-                            let owner_id = user_object.owner_id();
+        self.main_window()
+            .search_index()
+            .lock()
+            .unwrap()
+            .add_decrypted(self.user_id(), &decrypted_data);
 
-                            let old_aes_key = user_object.imp().receiver_aes_key.borrow().clone();
-                            let decrypted_data =
-                                decrypt_message(message_data, &old_aes_key, rsa_private_key, owner_id);
+        info!(
+            "Message number {} was edited",
+            decrypted_data.message_number
+        );
+        message.set_used_aes_key(decrypted_data.used_aes_key);
+        message.update_text(decrypted_data.message.unwrap());
+    }
 
-                            user_object.imp().receiver_aes_key.replace(Some(decrypted_data.used_aes_key.clone()));
-                            let message_object = window.receive_message(decrypted_data, user_object.clone(), true);
+    /// Shows a reaction on a loaded message. Messages that are not loaded get it when synced
+    fn reaction_received(&self, reaction: MessageReaction, added: bool) {
+        if let Some(message) = self.renderer().get_message(reaction.message_number) {
+            let emoji = added.then_some(reaction.emoji);
+            message.set_reaction(reaction.reacted_by, emoji);
+        }
+    }
 
-                            if let Some(object) = message_object {
-                                object.set_show_initial_message(false)
-                            }
-                            window.scroll_to_bottom(user_object, true);
-                        }
-                        "/get-user-data" | "/new-user-message" => {
-                            let user_data = FullUserData::from_json(splitted_data[1]);
-
-                            if splitted_data[0] == "/get-user-data" {
-                                if user_data.user_id != 0 {
-                                    user_object.emit_by_name::<()>("user-exists", &[&true]);
-                                } else {
-                                    user_object.emit_by_name::<()>("user-exists", &[&false]);
-                                    return;
-                                }
-                            }
+    /// Asks for the replied messages that are not loaded so the replies can show them
+    pub fn fetch_replied_messages(&self) {
+        for message_number in self.renderer().replies_to_fetch() {
+            info!("Getting replied message number {}", message_number);
+            self.add_to_queue(RequestType::GetRepliedMessage(message_number));
+        }
+    }
 
-                            if window.find_user(user_data.user_id).is_some() {
-                                user_object.check_image_link(user_data.image_link, false);
-                                info!("User {} has already been added. Dismissing the request", user_data.user_id);
-                                return;
-                            }
+    /// Shows a fetched replied message in the replies waiting for it
+    fn replied_message_received(&self, chat_data: MessageSyncData) {
+        let window = self.main_window();
+        let (_, rsa_private_key) = self.rsa_keys().unwrap();
+        let keyring = window.keyring();
+        let old_aes_key = self.imp().receiver_aes_key.borrow().clone();
+
+        // Nothing is received if the replied message was deleted
+        for message_data in chat_data.message_data {
+            let ratchet_key = self.ratchet_message_key(&message_data);
+            let decrypted_data = decrypt_message(
+                message_data,
+                &old_aes_key,
+                ratchet_key,
+                &rsa_private_key,
+                &keyring,
+                self.owner_id(),
+                self.device_id(),
+            );
+            let message = window.quoted_message(&decrypted_data, self);
+            self.renderer()
+                .set_replied_message(&message, decrypted_data.message_number);
+        }
+    }
 
-                            let new_user = window.create_user(user_data);
-                            if splitted_data[0] == "/new-user-message" {
-                                window.add_pending_avatar_css(new_user);
-                            }
-                        },
-                        _ => {}
+    /// Shows the decrypted earlier versions of a message below the message
+    fn edit_history_received(&self, history: Vec<MessageData>, message: MessageObject) {
+        let (_, rsa_private_key) = self.rsa_keys().unwrap();
+        let keyring = self.main_window().keyring();
+        // Earlier versions are encrypted with the key of the message
+        let message_key = Some(message.used_aes_key());
+
+        let versions: Vec<String> = history
+            .into_iter()
+            .map(|message_data| {
+                let decrypted_data = decrypt_message(
+                    message_data,
+                    &message_key,
+                    None,
+                    &rsa_private_key,
+                    &keyring,
+                    self.owner_id(),
+                    self.device_id(),
+                );
+                let written_at = parse_server_time(&decrypted_data.created_at);
+                format!(
+                    "{}: {}",
+                    get_created_at_timing(&written_at),
+                    decrypted_data.message.unwrap()
+                )
+            })
+            .collect();
+
+        let history_text = if versions.is_empty() {
+            String::from("No earlier versions found")
+        } else {
+            versions.join("\n")
+        };
+
+        if let Some(row) = message.target_row() {
+            row.show_edit_history(&history_text);
+        }
+    }
+
+    /// Logs and shows the reason a request failed and continues with the queue if possible
+    fn request_failed(&self, failure: RequestFailure) {
+        let window = self.main_window();
+
+        match failure {
+            RequestFailure::Server(error_data) => {
+                error!(
+                    "Request failed with {:?}. Reason: {}",
+                    error_data.code, error_data.message
+                );
+                window.show_toast(&error_data.message);
+                match error_data.code {
+                    // Every other request will fail the same way with an invalid token
+                    ErrorCode::InvalidToken => {}
+                    // The saved device is not known by the server. Reconnect as a new device
+                    ErrorCode::UnknownDevice => {
+                        self.set_device_id(0);
+                        window.save_user_data();
+                        self.add_queue_to_first(RequestType::ReconnectUser);
                     }
+                    _ => self.process_queue(None),
                 }
-            }),
+            }
+            RequestFailure::UnexpectedResponse(response) => {
+                error!("Received an unexpected reply: {response:?}");
+                self.process_queue(None);
+            }
+            RequestFailure::TimedOut => {
+                window.show_toast("The server did not reply in time");
+                self.process_queue(None);
+            }
+            // The queue gets processed again once the connection is reestablished
+            RequestFailure::ConnectionLost => info!("Connection lost before receiving a reply"),
+        }
+    }
+
+    fn reconnect_success(&self, user_data: FullUserData) {
+        self.set_name(user_data.user_name);
+        self.check_image_link(user_data.image_link, false);
+        self.set_devices(user_data.devices);
+        self.main_window().save_user_list();
+
+        self.forget_bundles();
+        self.send_waiting_requests();
+
+        if self.is_owner() {
+            self.user_ws().set_authenticated(true);
+            self.main_window().open_chats();
+            self.add_to_queue(RequestType::GetGroups);
+
+            // The RSA keys might still be getting generated. In that case it gets registered afterwards
+            if self.device_id() == 0 && self.rsa_keys().is_some() {
+                self.add_to_queue(RequestType::RegisterDevice);
+            } else if self.device_id() != 0 {
+                self.add_to_queue(RequestType::PublishPreKeys(0));
+            }
+        }
+
+        self.fetch_missing_sessions();
+        self.start_sync();
+    }
+
+    /// Starts syncing messages, deleted and edited messages of this chat with the server
+    pub fn start_sync(&self) {
+        // It must be set to zero to ensure the server sends every single message from the server
+        // If from the other side a message gets deleted
+        // while this client is on but not connected it would mean this client would not receive
+        // the deletion event. So we have to check every single message the server has to ensure
+        // nothing is missed
+        let synced_till = self.renderer().synced_till();
+        let message_number = self.renderer().message_number();
+        self.add_to_queue(RequestType::SyncDeletedMessage(synced_till, message_number));
+        self.add_to_queue(RequestType::SyncEditedMessage(synced_till, message_number));
+        self.renderer().set_message_number(0);
+        self.renderer().set_is_syncing(false);
+        self.add_queue_to_first(RequestType::GetLastMessageNumber(self.clone()));
+    }
+
+    fn update_user_id(&self, id_data: UserIDs) {
+        let window = self.main_window();
+
+        self.set_user_id(id_data.user_id);
+        self.set_user_token(id_data.user_token);
+        self.set_owner_id(id_data.user_id);
+        self.set_own_device(id_data.device_id);
+        self.user_ws().set_authenticated(true);
+        window.save_user_data();
+        window
+            .imp()
+            .message_numbers
+            .borrow_mut()
+            .insert(id_data.user_id, HashSet::new());
+        window.save_user_list();
+        self.add_to_queue(RequestType::PublishPreKeys(0));
+        self.process_queue(None);
+    }
+
+    /// Saves the ID the server gave to this device
+    fn device_registered(&self, device_id: u64) {
+        info!("Device registered with ID {device_id}");
+        self.set_own_device(device_id);
+        self.main_window().save_user_data();
+        self.add_to_queue(RequestType::PublishPreKeys(0));
+        self.process_queue(None);
+    }
+
+    /// Sets the device ID of this client and replaces the key of this device
+    fn set_own_device(&self, device_id: u64) {
+        let (rsa_public, _) = self.rsa_keys().unwrap();
+        let mut device_keys = self.imp().device_keys.borrow_mut();
+
+        device_keys.remove(&self.device_id());
+        device_keys.insert(device_id, rsa_public);
+        drop(device_keys);
+
+        self.set_device_id(device_id);
+    }
+
+    /// Replaces the saved device keys of this user
+    fn set_devices(&self, devices: Vec<DeviceKey>) {
+        let key_ids = devices
+            .iter()
+            .map(|device| (device.device_id, device.key_id))
+            .collect();
+        let device_keys = devices
+            .into_iter()
+            .map(|device| {
+                (
+                    device.device_id,
+                    read_rsa_public_from_string(device.rsa_public_key),
+                )
+            })
+            .collect();
+        self.imp().device_keys.replace(device_keys);
+        self.imp().key_ids.replace(key_ids);
+        self.check_verified_keys();
+    }
+
+    /// Saves a newly registered device of this user
+    fn add_device(&self, device: DeviceKey) {
+        info!(
+            "New device {} added for User ID {}",
+            device.device_id, device.user_id
         );
-        self.user_ws().set_signal_id(id);
+        let rsa_public = read_rsa_public_from_string(device.rsa_public_key);
+        self.imp()
+            .device_keys
+            .borrow_mut()
+            .insert(device.device_id, rsa_public);
+        self.imp()
+            .key_ids
+            .borrow_mut()
+            .insert(device.device_id, device.key_id);
+        self.check_verified_keys();
+    }
+
+    /// Digest of the device keys of this user the safety number is made from
+    pub fn key_digest(&self) -> Vec<u8> {
+        key_digest(
+            self.user_id(),
+            &self.imp().device_keys.borrow(),
+            &self.device_identities(),
+        )
+    }
+
+    /// Identity keys of the devices of this user that are known from the ratchet sessions. {Device ID: Keys}
+    fn device_identities(&self) -> HashMap<u64, DeviceIdentity> {
+        let Some(ratchet_store) = self.main_window().ratchet_store() else {
+            return HashMap::new();
+        };
+        let ratchet_store = ratchet_store.lock().unwrap();
+
+        let mut identities = ratchet_store.identities_of(self.user_id());
+        if self.is_owner() {
+            identities.insert(self.device_id(), ratchet_store.own_identity());
+        }
+        identities
+    }
+
+    /// Whether the identity keys of every device of this user are known
+    fn has_every_identity(&self) -> bool {
+        let identities = self.device_identities();
+        self.imp()
+            .device_keys
+            .borrow()
+            .keys()
+            .all(|device_id| identities.contains_key(device_id))
+    }
+
+    /// The safety number of the owner and this user. None if the keys of a device of either side
+    /// are not known yet
+    pub fn safety_number(&self) -> Option<String> {
+        let owner = self.main_window().get_chatting_from();
+        if self.imp().device_keys.borrow().is_empty() || owner.imp().device_keys.borrow().is_empty()
+        {
+            return None;
+        }
+
+        // The identity keys are known once a session exists with the device
+        if !self.has_every_identity() || !owner.has_every_identity() {
+            self.fetch_missing_sessions();
+            owner.fetch_missing_sessions();
+            return None;
+        }
+        Some(safety_number(&owner.key_digest(), &self.key_digest()))
+    }
+
+    /// Saves the current keys of this user as verified and pins the identity keys of every device
+    /// or forgets the verified keys
+    pub fn mark_verified(&self, verified: bool) {
+        let verified_keys = verified.then(|| VerifiedKeys {
+            key_digest: self.key_digest(),
+            identities: self.device_identities(),
+        });
+        info!("Setting User ID {} verified as {verified}", self.user_id());
+
+        self.main_window()
+            .save_verified_key(self.user_id(), verified_keys);
+        self.set_verified(verified);
+        self.set_key_changed(false);
+    }
+
+    /// Compares the device keys of this user with the keys that were verified
+    fn check_verified_keys(&self) {
+        // Groups get created without any device keys
+        if self.is_owner() || self.is_group() || self.imp().device_keys.borrow().is_empty() {
+            return;
+        }
+
+        let Some(verified_keys) = self.main_window().verified_key(self.user_id()) else {
+            self.set_verified(false);
+            return;
+        };
+
+        if verified_keys.key_digest == self.key_digest() {
+            self.set_verified(true);
+            self.set_key_changed(false);
+        } else if !self.key_changed() {
+            error!(
+                "Keys of User ID {} changed after they were verified",
+                self.user_id()
+            );
+            self.set_verified(false);
+            self.set_key_changed(true);
+            self.main_window().show_toast(&format!(
+                "The keys of {} changed. Verify the safety number again",
+                self.name()
+            ));
+        }
+    }
+
+    /// Every device a message to this chat must be readable on. The owner devices and the devices
+    /// of this user or every member of this group
+    fn message_devices(&self) -> Vec<(u64, RsaPublicKey)> {
+        let owner = self.main_window().get_chatting_from();
+        let mut devices = owner.imp().device_keys.borrow().clone();
+
+        let (rsa_public, _) = self.rsa_keys().unwrap();
+        devices.insert(self.device_id(), rsa_public);
+
+        if self.is_group() {
+            for member in self.imp().group_members.borrow().values() {
+                devices.extend(member.imp().device_keys.borrow().clone());
+            }
+        } else {
+            devices.extend(self.imp().device_keys.borrow().clone());
+        }
+        devices.into_iter().collect()
+    }
+
+    /// The current key ID of every device a message to this chat gets encrypted for. {Device ID: Key ID}
+    fn message_key_ids(&self) -> HashMap<u64, u64> {
+        let owner = self.main_window().get_chatting_from();
+        let mut key_ids = owner.imp().key_ids.borrow().clone();
+
+        if self.is_group() {
+            for member in self.imp().group_members.borrow().values() {
+                key_ids.extend(member.imp().key_ids.borrow().clone());
+            }
+        } else {
+            key_ids.extend(self.imp().key_ids.borrow().clone());
+        }
+        key_ids
+    }
+
+    /// The AES key a message to this chat gets encrypted with. Groups use the key of the chat while
+    /// every direct message gets a new key so a leaked key only exposes a single message
+    fn message_aes_key(&self, message: &MessageObject) -> Vec<u8> {
+        let used_aes_key = message.used_aes_key();
+        if !used_aes_key.is_empty() {
+            used_aes_key
+        } else if self.is_group() {
+            self.imp().aes_key.get().unwrap().clone()
+        } else {
+            generate_new_aes_key()
+        }
+    }
+
+    /// Encrypts the AES key of a direct message for every device of the owner and this user.
+    /// Devices with a ratchet session get it encrypted with the ratchet. Only the devices that
+    /// published no prekeys get it encrypted with their RSA key. Fails if a session is missing
+    /// with any other device. The sending device keeps the decrypted message in the message cache instead
+    fn direct_message_keys(&self, aes_key: &[u8]) -> Result<Vec<EncryptedKey>, MissingSession> {
+        let window = self.main_window();
+        let owner = window.get_chatting_from();
+        let own_device = self.device_id();
+        let ratchet_store = window.ratchet_store();
+
+        let mut added_devices = HashSet::from([own_device]);
+        let mut ratchet_devices = Vec::new();
+        let mut rsa_devices = Vec::new();
+        let mut downgraded_users = Vec::new();
+        let mut missing_session = None;
+
+        // Nothing is encrypted till every device is known to be usable so no ratchet key gets wasted
+        for user in [owner, self.clone()] {
+            let devices = user.imp().device_keys.borrow().clone();
+            for (device_id, rsa_public) in devices {
+                if !added_devices.insert(device_id) {
+                    continue;
+                }
+
+                let has_session = ratchet_store.as_ref().is_some_and(|ratchet_store| {
+                    ratchet_store
+                        .lock()
+                        .unwrap()
+                        .has_session(user.user_id(), device_id)
+                });
+
+                if has_session {
+                    ratchet_devices.push((user.clone(), device_id, rsa_public));
+                } else if user.imp().prekeyless_devices.borrow().contains(&device_id) {
+                    rsa_devices.push((device_id, rsa_public));
+                    downgraded_users.push(user.clone());
+                } else if user.imp().failed_devices.borrow().contains(&device_id) {
+                    error!(
+                        "No session could be started with device {device_id} of User ID {}",
+                        user.user_id()
+                    );
+                    missing_session = Some(MissingSession::Failed);
+                } else if missing_session.is_none() {
+                    info!(
+                        "Waiting for the prekey bundle of device {device_id} of User ID {}",
+                        user.user_id()
+                    );
+                    user.request_bundles();
+                    missing_session = Some(MissingSession::Pending);
+                }
+            }
+        }
+
+        if let Some(missing_session) = missing_session {
+            return Err(missing_session);
+        }
+
+        let mut device_keys = Vec::new();
+        if let Some(ratchet_store) = &ratchet_store {
+            let mut ratchet_store = ratchet_store.lock().unwrap();
+            for (user, device_id, rsa_public) in ratchet_devices {
+                match ratchet_store.encrypt_key(user.user_id(), device_id, own_device, aes_key) {
+                    Some(key) => device_keys.push(key),
+                    // A session started by the other device can not send before it received a message
+                    None => {
+                        rsa_devices.push((device_id, rsa_public));
+                        downgraded_users.push(user);
+                    }
+                }
+            }
+        }
+
+        for user in downgraded_users {
+            user.warn_downgrade();
+        }
+
+        // The server needs at least one key. Only happens when chatting with the owner on a single device
+        if device_keys.is_empty() && rsa_devices.is_empty() {
+            let (rsa_public, _) = self.rsa_keys().unwrap();
+            rsa_devices.push((own_device, rsa_public));
+        }
+
+        device_keys.extend(encrypt_aes_key(
+            aes_key,
+            &rsa_devices,
+            &self.message_key_ids(),
+        ));
+        window.save_ratchet_store();
+        Ok(device_keys)
+    }
+
+    /// Tells the owner once that direct messages to a device of this user are not forward secret
+    fn warn_downgrade(&self) {
+        if self.imp().downgrade_warned.replace(true) {
+            return;
+        }
+
+        error!(
+            "Messages to a device of User ID {} are encrypted without a ratchet session",
+            self.user_id()
+        );
+        self.main_window().show_toast(&format!(
+            "A device of {} published no prekeys. Messages to it are not forward secret",
+            self.name()
+        ));
+    }
+
+    /// Tells the owner a message could not be encrypted for every device
+    fn session_failed(&self) {
+        self.main_window().show_toast(&format!(
+            "No secure session could be started with every device of {}. The message was not sent",
+            self.name()
+        ));
+    }
+
+    /// Asks for the prekey bundles of this user unless they are on the way already
+    fn request_bundles(&self) {
+        if self.imp().bundles_pending.replace(true) {
+            return;
+        }
+        self.imp().prekeys_requested.set(true);
+
+        // Can be called while the queue of this user is being processed
+        idle_add_local_once(clone!(@weak self as user_object => move || {
+            user_object.add_to_queue(RequestType::GetPreKeyBundles);
+        }));
+    }
+
+    /// Forgets which devices had no usable prekeys so the bundles get asked for again
+    fn forget_bundles(&self) {
+        self.imp().prekeys_requested.set(false);
+        self.imp().bundles_pending.set(false);
+        self.imp().prekeyless_devices.borrow_mut().clear();
+        self.imp().failed_devices.borrow_mut().clear();
+        self.imp().downgrade_warned.set(false);
+    }
+
+    /// Queues the requests that were waiting for the prekey bundles again
+    pub fn send_waiting_requests(&self) {
+        let waiting_requests = self.imp().waiting_requests.take();
+        for request in waiting_requests {
+            self.add_to_queue(request);
+        }
+    }
+
+    /// No session can be started with the devices without one till the bundles are asked for again
+    fn bundles_failed(&self) {
+        self.imp().bundles_pending.set(false);
+        let device_ids = self.imp().device_keys.borrow().keys().copied().collect();
+        self.imp().failed_devices.replace(device_ids);
+        self.main_window().send_waiting_requests();
+    }
+
+    /// Unwraps the AES key the ratchet session with the sending device encrypted for this device
+    fn ratchet_message_key(&self, message_data: &MessageData) -> Option<Vec<u8>> {
+        let window = self.main_window();
+        let ratchet_store = window.ratchet_store()?;
+
+        let aes_key = ratchet_store.lock().unwrap().message_key(
+            self.user_id(),
+            message_data,
+            self.device_id(),
+        );
+
+        if aes_key.is_some() {
+            window.save_ratchet_store();
+        }
+        aes_key
+    }
+
+    /// Asks for the prekey bundles of this user if a device has no ratchet session yet
+    pub fn fetch_missing_sessions(&self) {
+        if self.is_group() || self.imp().prekeys_requested.get() || self.device_id() == 0 {
+            return;
+        }
+
+        let Some(ratchet_store) = self.main_window().ratchet_store() else {
+            return;
+        };
+
+        let missing_session = {
+            let ratchet_store = ratchet_store.lock().unwrap();
+            self.imp().device_keys.borrow().keys().any(|device_id| {
+                *device_id != self.device_id()
+                    && !ratchet_store.has_session(self.user_id(), *device_id)
+            })
+        };
+
+        if missing_session {
+            self.request_bundles();
+        }
+    }
+
+    /// Publishes more one-time prekeys once most of them were used by other devices
+    fn prekeys_published(&self, remaining: u64) {
+        info!("{remaining} one-time prekeys left on the server");
+        if remaining < MIN_PREKEYS {
+            self.add_to_queue(RequestType::PublishPreKeys(NEW_PREKEYS));
+        }
+    }
+
+    /// Starts a ratchet session with every device of this user that does not have one yet
+    fn prekey_bundles_received(&self, bundles: Vec<PreKeyBundle>) {
+        let window = self.main_window();
+        let Some(ratchet_store) = window.ratchet_store() else {
+            self.bundles_failed();
+            return;
+        };
+
+        // Devices missing from the reply have no prekeys published
+        let bundle_devices: HashSet<u64> = bundles.iter().map(|bundle| bundle.device_id).collect();
+        let prekeyless_devices = self
+            .imp()
+            .device_keys
+            .borrow()
+            .keys()
+            .filter(|device_id| !bundle_devices.contains(device_id))
+            .copied()
+            .collect();
+
+        let mut refused_devices = Vec::new();
+        {
+            let mut ratchet_store = ratchet_store.lock().unwrap();
+            for bundle in bundles {
+                let is_own_device =
+                    bundle.user_id == self.owner_id() && bundle.device_id == self.device_id();
+                if is_own_device || ratchet_store.has_session(bundle.user_id, bundle.device_id) {
+                    continue;
+                }
+
+                // The server could hand out its own keys in the name of a verified user
+                let identity = DeviceIdentity {
+                    identity_key: bundle.identity_key.to_owned(),
+                    signing_key: bundle.signing_key.to_owned(),
+                };
+                if ratchet_store.conflicts_with_pin(bundle.user_id, bundle.device_id, &identity) {
+                    refused_devices.push(bundle.device_id);
+                    continue;
+                }
+
+                if !ratchet_store.start_session(&bundle) {
+                    self.imp()
+                        .failed_devices
+                        .borrow_mut()
+                        .insert(bundle.device_id);
+                }
+            }
+        }
+        window.save_ratchet_store();
+
+        self.imp().bundles_pending.set(false);
+        self.imp().prekeyless_devices.replace(prekeyless_devices);
+        self.imp()
+            .failed_devices
+            .borrow_mut()
+            .extend(refused_devices.iter().copied());
+
+        if !refused_devices.is_empty() {
+            error!(
+                "Identity keys of devices {refused_devices:?} of User ID {} do not match the verified ones",
+                self.user_id()
+            );
+            self.set_verified(false);
+            self.set_key_changed(true);
+            window.show_toast(&format!(
+                "A device of {} sent other keys than the verified ones. No secure session was started with it",
+                self.name()
+            ));
+        }
+
+        window.send_waiting_requests();
+    }
+
+    /// Creates the group or updates it if it was added already
+    fn group_received(&self, group_data: GroupData) {
+        let window = self.main_window();
+
+        if let Some(group) = window.find_user(group_data.group_id) {
+            group.set_group_data(group_data);
+        } else {
+            window.create_group(group_data);
+        }
+    }
+
+    /// Adds or updates every group the owner is a member of and removes the rest
+    fn groups_received(&self, groups: Vec<GroupData>) {
+        let window = self.main_window();
+        let group_ids: HashSet<u64> = groups.iter().map(|group| group.group_id).collect();
+
+        for group in window.groups() {
+            if !group_ids.contains(&group.user_id()) {
+                info!("No longer a member of group {}", group.user_id());
+                window.delete_user(group.user_id());
+            }
+        }
+
+        for group_data in groups {
+            self.group_received(group_data);
+        }
+    }
+
+    /// Updates the name and the members of this group
+    pub fn set_group_data(&self, group_data: GroupData) {
+        let window = self.main_window();
+        let owner = window.get_chatting_from();
+
+        self.set_name(group_data.group_name);
+        self.set_group_creator(group_data.created_by);
+
+        let mut members = HashMap::new();
+        for member_data in group_data.members {
+            if member_data.user_id == owner.user_id() {
+                continue;
+            }
+
+            // Members that were added as a user share the same object
+            let member = if let Some(user) = window.find_user(member_data.user_id) {
+                user.set_devices(member_data.devices);
+                user
+            } else if let Some(member) = self.group_member(member_data.user_id) {
+                member.set_name(member_data.user_name);
+                member.set_devices(member_data.devices);
+                member
+            } else {
+                UserObject::new(
+                    &member_data.user_name,
+                    member_data.image_link,
+                    Some(&owner.name_color()),
+                    Some(member_data.user_id),
+                    None,
+                    window.clone(),
+                    Some(member_data.devices),
+                    None,
+                )
+            };
+            members.insert(member.user_id(), member);
+        }
+        self.imp().group_members.replace(members);
+    }
+
+    /// Get a member of this group
+    pub fn group_member(&self, user_id: u64) -> Option<UserObject> {
+        self.imp().group_members.borrow().get(&user_id).cloned()
+    }
+
+    /// Every member of this group except the owner
+    pub fn group_members(&self) -> Vec<UserObject> {
+        self.imp()
+            .group_members
+            .borrow()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Gives the pending message the number and the time the server saved it with
+    fn message_sent(&self, ack: MessageAck, message: MessageObject) {
+        let window = self.main_window();
+        info!(
+            "Message saved with number {} for chat {}",
+            ack.message_number, ack.to_user
+        );
+
+        // The message might have been synced before the reply arrived
+        let already_added = !window
+            .imp()
+            .message_numbers
+            .borrow_mut()
+            .get_mut(&self.user_id())
+            .unwrap()
+            .insert(ack.message_number);
+
+        if already_added {
+            self.remove_unsent_message(&message);
+            return;
+        }
+
+        if self.message_number() < ack.message_number {
+            self.renderer().set_message_number(ack.message_number);
+        }
+
+        let created_at = parse_server_time(&ack.created_at);
+        message.set_cache_data(ack.created_at.to_owned(), message.used_aes_key());
+        message.set_message_number(ack.message_number);
+        message.set_message_timing(get_created_at_timing(&created_at));
+        self.renderer()
+            .save_message(message.clone(), ack.message_number);
+        window.search_index().lock().unwrap().add_message(
+            self.user_id(),
+            ack.message_number,
+            &message.message(),
+        );
+        message.to_process(false);
+
+        // Other devices do not encrypt the key for the sending device so the message is cached right away
+        if !self.is_group() {
+            window.save_message_cache(self);
+        }
+    }
+
+    /// Saves the number of a message received from this user and sends the receipts for it
+    pub fn message_arrived(&self, message_number: u64) {
+        if message_number > self.imp().last_received.get() {
+            self.imp().last_received.set(message_number);
+            self.schedule_receipts();
+        }
+    }
+
+    /// Lets this user know the owner is typing. Sent at most once every 3 seconds
+    pub fn send_typing(&self) {
+        let user_ws = self.user_ws();
+        if self.is_group() || user_ws.ws_conn().is_none() || !user_ws.authenticated() {
+            return;
+        }
+
+        let current_time = monotonic_time();
+        if current_time - self.imp().last_typing_sent.get() < 3_000_000 {
+            return;
+        }
+        self.imp().last_typing_sent.set(current_time);
+        user_ws.typing(self.user_id());
+    }
+
+    /// Shows this user as typing till no new typing signal arrives for 5 seconds
+    fn typing_received(&self) {
+        self.set_is_typing(true);
+
+        if let Some(source_id) = self.imp().typing_timeout.take() {
+            source_id.remove();
+        }
+
+        let source_id = timeout_add_local_once(
+            Duration::from_secs(5),
+            clone!(@weak self as user_object => move || {
+                user_object.imp().typing_timeout.replace(None);
+                user_object.set_is_typing(false);
+            }),
+        );
+        self.imp().typing_timeout.replace(Some(source_id));
+    }
+
+    /// Stops showing this user as typing once the message arrives
+    fn typing_stopped(&self) {
+        if let Some(source_id) = self.imp().typing_timeout.take() {
+            source_id.remove();
+        }
+        self.set_is_typing(false);
+    }
+
+    /// Updates whether this user is online and when the user was last seen
+    fn presence_received(&self, presence: Presence) {
+        let presence_text = if presence.online {
+            "Online".to_string()
+        } else if let Some(last_seen_at) = presence.last_seen_at {
+            let last_seen_at = parse_server_time(&last_seen_at);
+            format!("Last seen {}", get_created_at_timing(&last_seen_at))
+        } else {
+            String::new()
+        };
+
+        self.set_online(presence.online);
+        self.set_presence(presence_text);
+    }
+
+    /// Sends the receipts after a short delay so a batch of synced messages needs a single receipt
+    pub fn schedule_receipts(&self) {
+        if self.is_group() || self.imp().receipt_scheduled.get() {
+            return;
+        }
+        self.imp().receipt_scheduled.set(true);
+
+        let user_object = self.clone();
+        timeout_add_local_once(Duration::from_millis(500), move || {
+            user_object.imp().receipt_scheduled.set(false);
+            user_object.send_receipts();
+        });
+    }
+
+    /// Messages count as read only if the chat is open in an active window
+    fn send_receipts(&self) {
+        let window = self.main_window();
+        let last_received = self.imp().last_received.get();
+
+        let is_viewing = window.is_active() && &window.get_chatting_with() == self;
+
+        if is_viewing && last_received > self.imp().last_read.get() {
+            self.imp().last_read.set(last_received);
+            self.imp().last_delivered.set(last_received);
+            self.add_to_queue(RequestType::MessageReceipt(
+                ReceiptStatus::Read,
+                last_received,
+            ));
+        } else if last_received > self.imp().last_delivered.get() {
+            self.imp().last_delivered.set(last_received);
+            self.add_to_queue(RequestType::MessageReceipt(
+                ReceiptStatus::Delivered,
+                last_received,
+            ));
+        }
+    }
+
+    /// Updates every message the owner sent to this user up to the receipt number
+    fn receipt_received(&self, receipt: MessageReceipt) {
+        info!(
+            "User ID {} marked messages till {} as {:?}",
+            receipt.user_id, receipt.message_number, receipt.status
+        );
+        let saved_messages = self.renderer().imp().saved_messages.borrow().clone();
+        for (number, message) in saved_messages {
+            if number <= receipt.message_number && message.is_send() {
+                message.update_receipt(receipt.status);
+            }
+        }
+    }
+
+    /// Removes a message that never got a number from the server
+    fn remove_unsent_message(&self, message: &MessageObject) {
+        let position = self
+            .messages()
+            .iter::<MessageObject>()
+            .position(|msg_obj| msg_obj.ok().as_ref() == Some(message));
+
+        if let Some(index) = position {
+            self.messages().remove(index as u32);
+        }
+    }
+
+    /// Encrypts the file with the AES key of the message and uploads it chunk by chunk.
+    /// The message gets sent with the attachment once every chunk is saved
+    pub fn upload_attachment(
+        &self,
+        message_data: MessageData,
+        message: MessageObject,
+        file_name: String,
+        file_data: Vec<u8>,
+    ) {
+        let user_ws = self.user_ws();
+        if user_ws.ws_conn().is_none() || !user_ws.authenticated() {
+            self.main_window()
+                .show_toast("Attachments can only be sent while connected");
+            self.remove_unsent_message(&message);
+            return;
+        }
+
+        // The message gets sent with the same key so the attachment can be decrypted with it
+        let aes_key = self.message_aes_key(&message);
+        message.set_used_aes_key(aes_key.clone());
+        let (encrypted_file, file_nonce) = encrypt_bytes(&aes_key, &file_data);
+        let (encrypted_name, name_nonce) = encrypt_bytes(&aes_key, file_name.as_bytes());
+
+        info!(
+            "Uploading attachment of {} bytes to chat {}",
+            encrypted_file.len(),
+            self.user_id()
+        );
+        let attachment = Attachment::new(
+            encrypted_file.len() as u64,
+            encrypted_name,
+            name_nonce,
+            file_nonce,
+        );
+
+        user_ws.start_upload(
+            attachment.clone(),
+            clone!(@weak self as user_object => move |result| {
+                match result {
+                    Ok(attachment_id) => {
+                        let attachment = attachment.update_id(attachment_id);
+                        message.set_attachment(attachment.clone(), aes_key);
+                        user_object.upload_next_chunk(message_data, message, attachment, Rc::new(encrypted_file), 0);
+                    }
+                    Err(e) => {
+                        user_object.remove_unsent_message(&message);
+                        user_object.request_failed(e);
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Sends the chunk starting at the offset. Chunks are sent one at a time so they are saved in order
+    fn upload_next_chunk(
+        &self,
+        message_data: MessageData,
+        message: MessageObject,
+        attachment: Attachment,
+        encrypted_file: Rc<Vec<u8>>,
+        offset: usize,
+    ) {
+        if offset >= encrypted_file.len() {
+            info!("Attachment {} uploaded", attachment.attachment_id);
+            let message_data = message_data.update_attachment(Some(attachment));
+            self.add_to_queue(RequestType::SendMessage(message_data, message));
+            return;
+        }
+
+        let end = (offset + ATTACHMENT_CHUNK_SIZE).min(encrypted_file.len());
+        let chunk = AttachmentChunk::new(
+            attachment.attachment_id.to_owned(),
+            offset as u64,
+            encrypted_file[offset..end].to_vec(),
+        );
+
+        self.user_ws().upload_chunk(
+            chunk,
+            clone!(@weak self as user_object => move |result| {
+                match result {
+                    Ok(uploaded_size) => user_object.upload_next_chunk(message_data, message, attachment, encrypted_file, uploaded_size as usize),
+                    Err(e) => {
+                        user_object.remove_unsent_message(&message);
+                        user_object.request_failed(e);
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Downloads the attachment of the message chunk by chunk and decrypts it once every chunk arrives
+    pub fn download_attachment(&self, message: &MessageObject) {
+        let Some(attachment) = message.attachment() else {
+            return;
+        };
+
+        if message.attachment_ready() || message.attachment_downloading() {
+            return;
+        }
+
+        let user_ws = self.user_ws();
+        if user_ws.ws_conn().is_none() || !user_ws.authenticated() {
+            info!("Not connected. Attachment download skipped");
+            return;
+        }
+
+        info!("Downloading attachment {}", attachment.attachment_id);
+        message.set_attachment_downloading(true);
+        let encrypted_file = Vec::with_capacity(attachment.size as usize);
+        self.download_next_chunk(message.clone(), attachment, encrypted_file);
+    }
+
+    fn download_next_chunk(
+        &self,
+        message: MessageObject,
+        attachment: Attachment,
+        mut encrypted_file: Vec<u8>,
+    ) {
+        let chunk_request = AttachmentChunk::new(
+            attachment.attachment_id.to_owned(),
+            encrypted_file.len() as u64,
+            Vec::new(),
+        );
+
+        self.user_ws().get_attachment_chunk(
+            chunk_request,
+            clone!(@weak self as user_object => move |result| {
+                match result {
+                    Ok(chunk) => {
+                        encrypted_file.extend(chunk.data.iter());
+
+                        // An empty chunk means the end of the file was reached
+                        if chunk.data.is_empty() || encrypted_file.len() as u64 >= attachment.size {
+                            message.set_attachment_downloading(false);
+                            message.attachment_downloaded(encrypted_file);
+                        } else {
+                            user_object.download_next_chunk(message, attachment, encrypted_file);
+                        }
+                    }
+                    Err(e) => {
+                        message.set_attachment_downloading(false);
+                        user_object.request_failed(e);
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Saves the newly issued token. The old token no longer works after this
+    fn token_rotated(&self, id_data: UserIDs) {
+        let window = self.main_window();
+
+        info!("Token rotated for User ID {}", id_data.user_id);
+        self.set_user_token(id_data.user_token);
+        window.save_user_data();
+        window.show_toast("A new token was issued");
+        self.process_queue(None);
+    }
+
+    /// Generates a new RSA key pair for this device in a thread and asks the server to replace
+    /// the public key of this device with it
+    pub fn rotate_rsa_key(&self) {
+        let window = self.main_window();
+        if self.device_id() == 0 || self.rsa_keys().is_none() {
+            window.show_toast("This device is not registered yet");
+            return;
+        }
+
+        let (sender, receiver) = MainContext::channel(Priority::default());
+
+        receiver.attach(None, clone!(@weak self as user_object => @default-return ControlFlow::Break, move |(public_key, private_key): (RsaPublicKey, RsaPrivateKey)| {
+            user_object.add_to_queue(RequestType::RotateKey(public_key, private_key));
+            ControlFlow::Break
+        }));
+        thread::spawn(move || sender.send(generate_new_rsa_keys()));
+    }
+
+    /// Switches every chat to the new keys once the server saved the new public key of this device
+    fn key_rotated(
+        &self,
+        device_key: DeviceKey,
+        public_key: RsaPublicKey,
+        private_key: RsaPrivateKey,
+    ) {
+        let window = self.main_window();
+
+        info!(
+            "Key of Device ID {} rotated to Key ID {}",
+            device_key.device_id, device_key.key_id
+        );
+        let old_key_id = self
+            .imp()
+            .key_ids
+            .borrow()
+            .get(&self.device_id())
+            .copied()
+            .unwrap_or_default();
+
+        window.replace_rsa_keys(public_key, private_key, old_key_id);
+        self.add_device(device_key);
+        window.show_toast("The RSA key of this device was rotated");
+        self.process_queue(None);
+    }
+
+    fn message_number_received(&self, message_number: u64) {
+        info!(
+            "Current message_number number {}, gotten number {}",
+            self.message_number(),
+            message_number
+        );
+        let total_to_load = 200;
+
+        if message_number > self.message_number() {
+            // Only the messages newer than the ones loaded from the cache are needed
+            let newest_saved = self.renderer().newest_saved();
+            let sync_target = if newest_saved != 0 {
+                newest_saved + 1
+            } else if message_number > total_to_load {
+                message_number - total_to_load
+            } else {
+                0
+            };
+            self.renderer().set_message_number(message_number);
+
+            if sync_target > message_number {
+                self.process_queue(None);
+                return;
+            }
+            // Syncing must happen before any pending message sent or deletion is performed
+            self.add_queue_to_first(RequestType::SyncMessage(sync_target, message_number));
+        } else {
+            self.process_queue(None);
+        }
+    }
+
+    fn messages_synced(&self, chat_data: MessageSyncData) {
+        if self.syncing() {
+            return;
+        }
+
+        // Every message after the cached ones might have been deleted
+        if chat_data.message_data.is_empty() {
+            self.process_queue(None);
+            return;
+        }
+        self.renderer().set_is_syncing(true);
+
+        let window = self.main_window();
+        let owner_id = self.owner_id();
+        let device_id = self.device_id();
+
+        let (_, rsa_private_key) = self.rsa_keys().unwrap();
+        let keyring = window.keyring();
+
+        let (sender, receiver) = MainContext::channel(Priority::default());
+
+        receiver.attach(
+            None,
+            clone!(
+                @weak self as user_object, @weak window => @default-return ControlFlow::Break,
+                move |(message_data, completed): (Vec<DecryptedMessageData>, bool)| {
+                for message in message_data {
+                    if message.message_number == 0 {
+                        continue;
+                    }
+                    window.receive_message(message, user_object.clone(), false);
+                }
+
+                if completed {
+                    user_object.renderer().set_is_syncing(false);
+                    user_object.renderer().set_became_inactive(false);
+                    user_object.fetch_replied_messages();
+                    window.save_message_cache(&user_object);
+                    window.save_ratchet_store();
+
+                    // A search result was waiting for this sync
+                    let jump_target = user_object.renderer().jump_target();
+                    if jump_target != 0 {
+                        user_object.renderer().set_jump_target(0);
+                        window.show_found_message(user_object.clone(), jump_target);
+                    }
+                    return ControlFlow::Break
+                }
+                ControlFlow::Continue
+            }),
+        );
+
+        let old_aes_key = self.imp().receiver_aes_key.borrow().clone();
+        let existing_numbers = window
+            .imp()
+            .message_numbers
+            .borrow_mut()
+            .get(&self.user_id())
+            .unwrap()
+            .clone();
+        let search_index = window.search_index();
+        let ratchet_store = window.ratchet_store();
+        let chat_id = self.user_id();
+        thread::spawn(move || {
+            decrypt_message_chunk(
+                sender,
+                old_aes_key,
+                chat_data.message_data,
+                &rsa_private_key,
+                keyring,
+                owner_id,
+                device_id,
+                existing_numbers,
+                search_index,
+                ratchet_store,
+                chat_id,
+            )
+        });
+        self.process_queue(None);
+    }
+
+    fn deleted_messages_synced(&self, deleted_numbers: DeletedMessageData) {
+        let mut existing_numbers = self
+            .main_window()
+            .imp()
+            .message_numbers
+            .borrow_mut()
+            .get_mut(&self.user_id())
+            .unwrap()
+            .clone();
+        for num in deleted_numbers.message_numbers {
+            let message_exists = existing_numbers.remove(&num);
+
+            if message_exists {
+                self.remove_message(num, false);
+                self.renderer().delete_item(&num);
+            }
+        }
+        self.process_queue(None);
+    }
+
+    fn edited_messages_synced(&self, edited_data: MessageSyncData) {
+        for message_data in edited_data.message_data {
+            self.message_edited(message_data);
+        }
+        self.process_queue(None);
+    }
+
+    fn user_data_received(&self, user_data: FullUserData) {
+        if user_data.user_id != 0 {
+            self.emit_by_name::<()>("user-exists", &[&true]);
+        } else {
+            self.emit_by_name::<()>("user-exists", &[&false]);
+            return;
+        }
+        self.add_new_user(user_data, false);
+    }
+
+    /// Creates a new UserObject with the given data if it was not added already
+    /// Adds the senders that were not added yet and marks every chat with unread messages
+    fn unread_chats_received(&self, unread_chats: Vec<UnreadChat>) {
+        let window = self.main_window();
+        let total_unread: u64 = unread_chats.iter().map(|chat| chat.unread_count).sum();
+
+        for chat in unread_chats {
+            info!(
+                "{} unread messages from User ID {}",
+                chat.unread_count, chat.user_data.user_id
+            );
+            if let Some(target_user) = window.find_user(chat.user_data.user_id) {
+                if target_user != window.get_chatting_with() {
+                    window.add_pending_avatar_css(target_user);
+                }
+            } else {
+                self.add_new_user(chat.user_data, true);
+            }
+        }
+
+        window.show_toast(&format!(
+            "{total_unread} new messages received while offline"
+        ));
+    }
+
+    fn add_new_user(&self, user_data: FullUserData, from_message: bool) {
+        let window = self.main_window();
+
+        if window.find_user(user_data.user_id).is_some() {
+            self.check_image_link(user_data.image_link, false);
+            info!(
+                "User {} has already been added. Dismissing the request",
+                user_data.user_id
+            );
+            return;
+        }
+
+        let new_user = window.create_user(user_data);
+        if from_message {
+            window.add_pending_avatar_css(new_user);
+        }
+    }
+
+    /// Gathers the data of this UserObject in a sendable format
+    pub fn to_user_data(&self) -> FullUserData {
+        let devices = self
+            .imp()
+            .device_keys
+            .borrow()
+            .iter()
+            .map(|(device_id, key)| {
+                let key_id = self.imp().key_ids.borrow().get(device_id).copied();
+                DeviceKey::new(self.user_id(), *device_id, stringify_rsa_public(key))
+                    .update_key_id(key_id.unwrap_or_default())
+            })
+            .collect();
+
+        FullUserData::new(self.user_id(), self.name(), self.image_link(), devices)
+    }
+
+    /// The RSA keys of this device. None until they are read or generated
+    pub fn rsa_keys(&self) -> Option<(RsaPublicKey, RsaPrivateKey)> {
+        let public_key = self.imp().rsa_public.borrow().clone()?;
+        let private_key = self.imp().rsa_private.borrow().clone()?;
+        Some((public_key, private_key))
+    }
+
+    pub fn set_rsa_keys(&self, public_key: RsaPublicKey, private_key: RsaPrivateKey) {
+        self.imp().rsa_public.replace(Some(public_key));
+        self.imp().rsa_private.replace(Some(private_key));
+    }
+
+    /// Whether this UserObject belongs to the owner of the client
+    pub fn is_owner(&self) -> bool {
+        self.user_id() == self.owner_id()
     }
 
     pub fn factory(&self) -> SignalListItemFactory {
@@ -38,6 +38,26 @@ mod imp {
         #[template_child]
         pub image_link_delete: TemplateChild<Button>,
         #[template_child]
+        pub presence_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub safety_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub safety_warning: TemplateChild<Image>,
+        #[template_child]
+        pub safety_copy: TemplateChild<Button>,
+        #[template_child]
+        pub safety_verify: TemplateChild<Button>,
+        #[template_child]
+        pub passphrase_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub passphrase_edit: TemplateChild<Button>,
+        #[template_child]
+        pub backup_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub backup_import: TemplateChild<Button>,
+        #[template_child]
+        pub backup_export: TemplateChild<Button>,
+        #[template_child]
         pub conn_row: TemplateChild<ActionRow>,
         #[template_child]
         pub conn_switch: TemplateChild<Switch>,
@@ -82,13 +102,15 @@ mod imp {
 use adw::prelude::*;
 use adw::subclass::prelude::*;
 use adw::Toast;
+use gio::{Cancellable, FileCreateFlags};
 use glib::{clone, closure_local, timeout_add_seconds_local_once, wrapper, Object};
 use gtk::{
-    glib, Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager, Widget, Window,
+    gio, glib, Accessible, Buildable, ConstraintTarget, FileDialog, Native, Root, ShortcutManager,
+    Widget, Window,
 };
 use soup::WebsocketConnection;
 use std::env;
-use tracing::info;
+use tracing::{error, info};
 
 use crate::user::{UserObject, UserPrompt};
 use crate::window;
@@ -168,6 +190,7 @@ impl UserProfile {
         let user_data = self.imp().user_data.get().unwrap();
         let image_link_delete_button = self.imp().image_link_delete.get();
         let image_link_copy_button = self.imp().image_link_copy.get();
+        let presence_row = self.imp().presence_row.get();
         let conn_switch = self.imp().conn_switch.get();
         let conn_reload = self.imp().conn_reload.get();
         let conn_timer = self.imp().conn_timer.get();
@@ -233,6 +256,17 @@ impl UserProfile {
             .sync_create()
             .build();
 
+        let presence_subtitle_binding = user_data
+            .bind_property("presence", &presence_row, "subtitle")
+            .sync_create()
+            .build();
+
+        let presence_visible_binding = user_data
+            .bind_property("presence", &presence_row, "visible")
+            .transform_to(|_, presence: String| Some((!presence.is_empty()).to_value()))
+            .sync_create()
+            .build();
+
         let conn_status_binding = user_data
             .user_ws()
             .bind_property("ws-conn", &conn_switch, "active")
@@ -286,6 +320,8 @@ impl UserProfile {
         bindings.push(image_link_subtitle_binding);
         bindings.push(image_delete_biding);
         bindings.push(image_copy_biding);
+        bindings.push(presence_subtitle_binding);
+        bindings.push(presence_visible_binding);
         bindings.push(conn_status_binding);
         bindings.push(conn_reload_binding);
         bindings.push(conn_timer_label_binding);
@@ -297,17 +333,50 @@ impl UserProfile {
         self.imp().image_link_edit.set_visible(false);
         self.imp().image_link_reload.set_visible(false);
         self.imp().image_link_delete.set_visible(false);
+        self.imp().passphrase_row.set_visible(false);
+        self.imp().backup_row.set_visible(false);
         self.imp().conn_row.set_visible(false);
 
         let user_data = self.imp().user_data.get().unwrap();
 
+        let safety_row = self.imp().safety_row.get();
+        safety_row.set_visible(true);
+        match user_data.safety_number() {
+            Some(safety_number) => safety_row.set_subtitle(&safety_number),
+            None => {
+                safety_row.set_subtitle("The keys of this user are not received yet");
+                self.imp().safety_copy.set_sensitive(false);
+                self.imp().safety_verify.set_sensitive(false);
+            }
+        }
+
+        let safety_warning_binding = user_data
+            .bind_property("key-changed", &self.imp().safety_warning.get(), "visible")
+            .sync_create()
+            .build();
+
+        let safety_verify_binding = user_data
+            .bind_property("verified", &self.imp().safety_verify.get(), "label")
+            .transform_to(|_, verified: bool| {
+                if verified {
+                    Some("Unverify")
+                } else {
+                    Some("Verify")
+                }
+            })
+            .sync_create()
+            .build();
+
         let title_binding = user_data
             .bind_property("name", self, "title")
             .transform_to(|_, name: String| Some(format!("Profile - {}", name)))
             .sync_create()
             .build();
 
-        self.imp().bindings.borrow_mut().push(title_binding);
+        let mut bindings = self.imp().bindings.borrow_mut();
+        bindings.push(title_binding);
+        bindings.push(safety_warning_binding);
+        bindings.push(safety_verify_binding);
     }
 
     fn connect_button_signals(&self, window: &window::Window) {
@@ -319,6 +388,11 @@ impl UserProfile {
         let image_link_delete = self.imp().image_link_delete.get();
         let conn_reload = self.imp().conn_reload.get();
         let name_copy = self.imp().name_copy.get();
+        let passphrase_edit = self.imp().passphrase_edit.get();
+        let backup_import = self.imp().backup_import.get();
+        let backup_export = self.imp().backup_export.get();
+        let safety_copy = self.imp().safety_copy.get();
+        let safety_verify = self.imp().safety_verify.get();
 
         name_edit.connect_clicked(clone!(@weak self as profile => move |_| {
             info!("Opening prompt to get new name");
@@ -334,6 +408,48 @@ impl UserProfile {
             prompt.present();
         }));
 
+        passphrase_edit.connect_clicked(clone!(@weak self as profile, @weak window => move |_| {
+            info!("Opening prompt to change the passphrase");
+            let prompt = UserPrompt::new("Confirm").change_passphrase(&profile, &window);
+            prompt.present();
+        }));
+
+        backup_export.connect_clicked(clone!(@weak self as profile, @weak window => move |_| {
+            let dialog = FileDialog::builder()
+                .title("Export Account")
+                .initial_name("chirp_backup.bin")
+                .modal(true)
+                .build();
+
+            dialog.save(
+                Some(&profile),
+                None::<&Cancellable>,
+                clone!(@weak profile, @weak window => move |result| {
+                    if let Ok(file) = result {
+                        let prompt = UserPrompt::new("Export").export_account(&profile, &window, file);
+                        prompt.present();
+                    }
+                }),
+            );
+        }));
+
+        backup_import.connect_clicked(clone!(@weak self as profile, @weak window => move |_| {
+            let dialog = FileDialog::builder()
+                .title("Import Account")
+                .modal(true)
+                .build();
+
+            dialog.open(
+                Some(&profile),
+                None::<&Cancellable>,
+                clone!(@weak profile, @weak window => move |result| {
+                    if let Ok(file) = result {
+                        profile.import_account(&window, file);
+                    }
+                }),
+            );
+        }));
+
         id_copy.connect_clicked(clone!(@weak self as profile => move |_| {
             let text = profile.imp().id_row.get().subtitle().unwrap();
             info!("Copying User ID {text} to clipboard.");
@@ -411,5 +527,95 @@ impl UserProfile {
                 .build();
             toast_overlay.add_toast(toast);
         }));
+
+        safety_copy.connect_clicked(clone!(@weak self as profile => move |_| {
+            let text = profile.imp().safety_row.get().subtitle().unwrap();
+            info!("Copying safety number to clipboard.");
+
+            profile.clipboard().set(&text);
+
+            let toast_overlay = profile.imp().toast_overlay.get();
+            let toast = Toast::builder()
+                .title("Safety number has been copied to clipboard")
+                .timeout(1)
+                .build();
+            toast_overlay.add_toast(toast);
+        }));
+
+        safety_verify.connect_clicked(clone!(@weak self as profile => move |_| {
+            let user_data = profile.imp().user_data.get().unwrap();
+            let verified = !user_data.verified();
+            user_data.mark_verified(verified);
+
+            let title = if verified {
+                format!("{} has been marked as verified", user_data.name())
+            } else {
+                format!("{} is no longer verified", user_data.name())
+            };
+
+            let toast_overlay = profile.imp().toast_overlay.get();
+            let toast = Toast::builder().title(title).timeout(1).build();
+            toast_overlay.add_toast(toast);
+        }));
+    }
+    /// Encrypts the account backup with the chosen passphrase and saves it in the chosen file
+    pub fn export_account(&self, window: &window::Window, file: gio::File, passphrase: &str) {
+        let toast_overlay = self.imp().toast_overlay.get();
+
+        let Some(backup) = window.account_backup(passphrase) else {
+            let toast = Toast::builder()
+                .title("The account is not created yet")
+                .timeout(2)
+                .build();
+            toast_overlay.add_toast(toast);
+            return;
+        };
+
+        info!("Exporting the account backup");
+        let backup_data = backup.encrypt(passphrase);
+
+        file.replace_contents_async(
+            backup_data,
+            None,
+            false,
+            FileCreateFlags::REPLACE_DESTINATION,
+            None::<&Cancellable>,
+            move |result| {
+                let title = match result {
+                    Ok(_) => {
+                        "Account exported. It can be imported with the passphrase of the backup"
+                    }
+                    Err((_, e)) => {
+                        error!("Failed to save the account backup. Reason: {e}");
+                        "Failed to save the account backup"
+                    }
+                };
+                let toast = Toast::builder().title(title).timeout(2).build();
+                toast_overlay.add_toast(toast);
+            },
+        );
+    }
+
+    /// Reads the chosen backup file and asks for its passphrase
+    fn import_account(&self, window: &window::Window, file: gio::File) {
+        file.load_contents_async(
+            None::<&Cancellable>,
+            clone!(@weak self as profile, @weak window => move |result| {
+                match result {
+                    Ok((backup_data, _)) => {
+                        let prompt = UserPrompt::new("Import").import_account(&profile, &window, backup_data.to_vec());
+                        prompt.present();
+                    }
+                    Err(e) => {
+                        error!("Failed to read the chosen backup. Reason: {e}");
+                        let toast = Toast::builder()
+                            .title("Failed to read the chosen file")
+                            .timeout(2)
+                            .build();
+                        profile.imp().toast_overlay.add_toast(toast);
+                    }
+                }
+            }),
+        );
     }
 }
@@ -15,6 +15,9 @@ mod imp {
         pub prompt_text: TemplateChild<Label>,
         #[template_child]
         pub user_entry: TemplateChild<Entry>,
+        // Only shown when a new passphrase is taken
+        #[template_child]
+        pub repeat_entry: TemplateChild<Entry>,
         #[template_child]
         pub confirm_button: TemplateChild<Button>,
         #[template_child]
@@ -58,13 +61,18 @@ mod imp {
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
-use adw::Toast;
+use adw::{Application, Toast};
 use glib::{clone, closure_local, wrapper, Object};
 use gtk::{
-    glib, Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager, Widget, Window,
+    gio, glib, Accessible, Buildable, ConstraintTarget, InputPurpose, Native, Root,
+    ShortcutManager, Widget, Window,
 };
+use rsa::{RsaPrivateKey, RsaPublicKey};
 use tracing::{error, info};
 
+use crate::encryption::{read_rsa_keys_from_file, saved_keys_state, SavedKeys};
+use crate::message::MessageObject;
+use crate::processor::AccountBackup;
 use crate::user::{UserObject, UserProfile};
 use crate::window;
 use crate::ws::RequestType;
@@ -108,8 +116,8 @@ impl UserPrompt {
         }
     }
 
-    /// Bind the GtkEntry to work with an image link
-    fn bind_image_link(&self) {
+    /// Bind the GtkEntry to ensure the input is not empty
+    fn bind_not_empty(&self) {
         self.imp()
             .user_entry
             .connect_changed(clone!(@weak self as prompt => move |entry| {
@@ -224,6 +232,88 @@ impl UserPrompt {
         self
     }
 
+    /// Open prompt prefilled with the text of a sent message to edit it
+    pub fn edit_message(self, window: &window::Window, message: &MessageObject) -> Self {
+        self.bind_not_empty();
+        self.set_transient_for(Some(window));
+        self.set_modal(true);
+
+        let entry = self.imp().user_entry.get();
+        entry.set_placeholder_text(Some("Message"));
+        entry.set_text(&message.message());
+        self.imp()
+            .prompt_text
+            .set_label("Enter the new message text");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak message => move |_| {
+                let entry_data = prompt.imp().user_entry.text().to_string();
+                if entry_data != message.message() {
+                    let message_number = message.message_number();
+                    info!("Editing message number {}", message_number);
+                    message.sent_to().add_to_queue(RequestType::EditMessage(
+                        message_number,
+                        entry_data,
+                        message.clone().to_process(true),
+                    ));
+                }
+                prompt.close()
+            }),
+        );
+
+        self
+    }
+
+    /// Open prompt to take a name for creating a new group
+    pub fn create_group(self, window: &window::Window) -> Self {
+        self.bind_name();
+        self.set_transient_for(Some(window));
+        self.set_modal(true);
+
+        self.imp()
+            .user_entry
+            .get()
+            .set_placeholder_text(Some("Group Name"));
+        self.imp()
+            .prompt_text
+            .set_label("Enter the name of the new group");
+
+        self.imp().confirm_button.connect_clicked(clone!(@weak self as prompt, @weak window => move |_| {
+            let entry_data = prompt.imp().user_entry.text();
+            info!("Creating a new group named {}", entry_data);
+            window.get_chatting_from().add_to_queue(RequestType::CreateGroup(entry_data.to_string()));
+            prompt.close()
+        }));
+
+        self
+    }
+
+    /// Open prompt to handle number input for adding a member to a group
+    pub fn add_group_member(self, window: &window::Window, group_data: &UserObject) -> Self {
+        self.bind_number();
+        self.set_transient_for(Some(window));
+        self.set_modal(true);
+
+        self.imp()
+            .user_entry
+            .get()
+            .set_placeholder_text(Some("User ID"));
+        self.imp()
+            .prompt_text
+            .set_label("Enter the User ID you want to add");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak group_data => move |_| {
+                let entry_data = prompt.imp().user_entry.text();
+                info!("Adding {} to group {}", entry_data, group_data.user_id());
+                group_data.add_to_queue(RequestType::AddGroupMember(entry_data.parse().unwrap()));
+                prompt.close()
+            }),
+        );
+
+        self
+    }
+
     /// Open prompt to take a new name for the user
     pub fn edit_name(self, profile: &UserProfile, user_data: &UserObject) -> Self {
         self.bind_name();
@@ -291,7 +381,7 @@ impl UserPrompt {
                 .push(image_modified_signal);
         }
 
-        self.bind_image_link();
+        self.bind_not_empty();
         self.set_transient_for(Some(profile));
         self.set_modal(true);
 
@@ -322,6 +412,233 @@ impl UserPrompt {
         self
     }
 
+    /// Hide the entered text for taking passphrases. Confirming stays disabled until something is entered
+    fn hide_input(&self) {
+        self.imp().confirm_button.set_sensitive(false);
+
+        let entry = self.imp().user_entry.get();
+        entry.set_visibility(false);
+        entry.set_input_purpose(InputPurpose::Password);
+        entry.set_placeholder_text(Some("Passphrase"));
+    }
+
+    /// Show the second entry the new passphrase must be repeated in
+    fn ask_repeat(&self) {
+        let entry = self.imp().repeat_entry.get();
+        entry.set_visible(true);
+        entry.set_visibility(false);
+        entry.set_input_purpose(InputPurpose::Password);
+        entry.set_placeholder_text(Some("Repeat the passphrase"));
+        entry.add_css_class("blue-entry");
+
+        entry.connect_changed(clone!(@weak self as prompt => move |_| {
+            prompt.imp().error_text.set_label("");
+        }));
+        entry.connect_activate(clone!(@weak self as prompt => move |_| {
+            let confirm_button = prompt.imp().confirm_button.get();
+
+            if confirm_button.is_sensitive() {
+                confirm_button.emit_clicked()
+            }
+        }));
+    }
+
+    /// Whether the passphrase was repeated correctly. Shows an error otherwise
+    fn repeat_matches(&self) -> bool {
+        if self.imp().repeat_entry.text() == self.imp().user_entry.text() {
+            return true;
+        }
+
+        self.imp()
+            .error_text
+            .set_label("Error: The passphrases do not match");
+        false
+    }
+
+    /// Open prompt to take the passphrase of the saved private key before the main window is created.
+    /// A new passphrase is taken if there is no key or the key was saved unencrypted
+    pub fn unlock_keys<F>(self, app: &Application, location: String, on_unlock: F) -> Self
+    where
+        F: Fn(String, Option<(RsaPublicKey, RsaPrivateKey)>) + 'static,
+    {
+        self.bind_not_empty();
+        self.hide_input();
+        self.set_application(Some(app));
+
+        let key_state = saved_keys_state(&location);
+        let prompt_text = match key_state {
+            SavedKeys::Missing => "Enter a passphrase to protect your keys",
+            SavedKeys::Plaintext => "Enter a passphrase to encrypt your saved keys",
+            SavedKeys::Encrypted => "Enter your passphrase to unlock your keys",
+        };
+        self.imp().prompt_text.set_label(prompt_text);
+
+        // A mistyped new passphrase would lock the keys forever
+        let new_passphrase = !matches!(key_state, SavedKeys::Encrypted);
+        if new_passphrase {
+            self.ask_repeat();
+        }
+
+        self.imp()
+            .confirm_button
+            .connect_clicked(clone!(@weak self as prompt => move |_| {
+                if new_passphrase && !prompt.repeat_matches() {
+                    return;
+                }
+                let passphrase = prompt.imp().user_entry.text().to_string();
+
+                let saved_keys = match key_state {
+                    SavedKeys::Missing => None,
+                    SavedKeys::Plaintext => match read_rsa_keys_from_file(location.clone(), &passphrase) {
+                        Ok(keys) => Some(keys),
+                        Err(e) => {
+                            error!("Failed to read the saved RSA keys. Reason: {e}");
+                            None
+                        }
+                    },
+                    SavedKeys::Encrypted => match read_rsa_keys_from_file(location.clone(), &passphrase) {
+                        Ok(keys) => Some(keys),
+                        Err(e) => {
+                            error!("Failed to unlock the saved RSA keys. Reason: {e}");
+                            prompt.imp().error_text.set_label("Error: Wrong passphrase");
+                            return;
+                        }
+                    },
+                };
+
+                info!("RSA keys unlocked");
+                on_unlock(passphrase, saved_keys);
+                prompt.close()
+            }));
+
+        self
+    }
+
+    /// Open prompt to confirm the current passphrase before taking the new one
+    pub fn change_passphrase(self, profile: &UserProfile, window: &window::Window) -> Self {
+        self.bind_not_empty();
+        self.hide_input();
+        self.set_transient_for(Some(profile));
+        self.set_modal(true);
+
+        self.imp()
+            .prompt_text
+            .set_label("Enter your current passphrase");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak profile, @weak window => move |_| {
+                let entry_data = prompt.imp().user_entry.text();
+                if entry_data != window.passphrase() {
+                    prompt.imp().error_text.set_label("Error: Wrong passphrase");
+                    return;
+                }
+
+                prompt.close();
+                let new_prompt = UserPrompt::new("Confirm").new_passphrase(&profile, &window);
+                new_prompt.present();
+            }),
+        );
+
+        self
+    }
+
+    /// Open prompt to take the new passphrase the private key gets encrypted with
+    fn new_passphrase(self, profile: &UserProfile, window: &window::Window) -> Self {
+        self.bind_not_empty();
+        self.hide_input();
+        self.set_transient_for(Some(profile));
+        self.set_modal(true);
+
+        self.imp().prompt_text.set_label("Enter the new passphrase");
+        self.ask_repeat();
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak profile, @weak window => move |_| {
+                if !prompt.repeat_matches() {
+                    return;
+                }
+                let entry_data = prompt.imp().user_entry.text();
+                window.change_passphrase(entry_data.to_string());
+
+                let over_lay = profile.imp().toast_overlay.get();
+                let toast = Toast::builder()
+                    .title("Passphrase has been changed")
+                    .timeout(1)
+                    .build();
+                over_lay.add_toast(toast);
+                prompt.close()
+            }),
+        );
+
+        self
+    }
+
+    /// Open prompt to take the passphrase the account backup gets encrypted with before exporting it.
+    /// The same passphrase unlocks the keys on the device the backup is imported on
+    pub fn export_account(
+        self,
+        profile: &UserProfile,
+        window: &window::Window,
+        file: gio::File,
+    ) -> Self {
+        self.bind_not_empty();
+        self.hide_input();
+        self.ask_repeat();
+        self.set_transient_for(Some(profile));
+        self.set_modal(true);
+
+        self.imp()
+            .prompt_text
+            .set_label("Enter a passphrase for the backup");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak profile, @weak window, @strong file => move |_| {
+                if !prompt.repeat_matches() {
+                    return;
+                }
+                let entry_data = prompt.imp().user_entry.text();
+
+                prompt.close();
+                profile.export_account(&window, file.clone(), &entry_data);
+            }),
+        );
+
+        self
+    }
+
+    /// Open prompt to take the passphrase of a backup and import the account in it
+    pub fn import_account(
+        self,
+        profile: &UserProfile,
+        window: &window::Window,
+        backup_data: Vec<u8>,
+    ) -> Self {
+        self.bind_not_empty();
+        self.hide_input();
+        self.set_transient_for(Some(profile));
+        self.set_modal(true);
+
+        self.imp()
+            .prompt_text
+            .set_label("Enter the passphrase of the backup");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak profile, @weak window => move |_| {
+                let entry_data = prompt.imp().user_entry.text();
+                let Some(backup) = AccountBackup::decrypt(&backup_data, &entry_data) else {
+                    prompt.imp().error_text.set_label("Error: Wrong passphrase or invalid backup");
+                    return;
+                };
+
+                prompt.close();
+                profile.close();
+                window.import_account(backup, entry_data.to_string());
+            }),
+        );
+
+        self
+    }
+
     /// Disable prompt buttons
     fn set_buttons_insensitive(&self) {
         self.imp().confirm_button.set_sensitive(false);
@@ -21,6 +21,8 @@ mod imp {
         pub popover_label: TemplateChild<Label>,
         #[template_child]
         pub user_menu: TemplateChild<PopoverMenu>,
+        #[template_child]
+        pub presence_dot: TemplateChild<Box>,
         pub popover_visible: Cell<bool>,
         pub bindings: RefCell<Vec<Binding>>,
         pub user_data: OnceCell<UserObject>,
@@ -90,7 +92,13 @@ impl UserRow {
                 let position = row_clone
                     .compute_bounds(&row_clone.imp().user_avatar.get())
                     .unwrap();
-                let popover_text = row_clone.imp().user_data.get().unwrap().name();
+                let user_data = row_clone.imp().user_data.get().unwrap();
+                let presence = user_data.presence();
+                let popover_text = if presence.is_empty() {
+                    user_data.name()
+                } else {
+                    format!("{}\n{}", user_data.name(), presence)
+                };
 
                 let x_position = position.x() as i32 + 45;
                 let y_position = position.y() as i32 + 25;
@@ -164,8 +172,14 @@ impl UserRow {
             .sync_create()
             .build();
 
+        let presence_dot_binding = user_object
+            .bind_property("online", &self.imp().presence_dot.get(), "visible")
+            .sync_create()
+            .build();
+
         bindings.push(avatar_image_binding);
         bindings.push(avatar_text_binding);
+        bindings.push(presence_dot_binding);
     }
 
     fn view_profile(&self) {
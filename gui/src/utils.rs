@@ -1,4 +1,4 @@
-use chrono::{Local, NaiveDateTime};
+use chrono::{Local, NaiveDateTime, TimeZone};
 use gio::Cancellable;
 use gtk::glib::Bytes;
 use rand::Rng;
@@ -101,6 +101,12 @@ pub fn get_random_color(to_ignore: Option<&str>) -> &str {
     colors_vector[selected_index]
 }
 
+/// Converts the UTC time the server sends to the Local time
+pub fn parse_server_time(created_at: &str) -> NaiveDateTime {
+    let parsed_naive = NaiveDateTime::parse_from_str(created_at, "%Y-%m-%d %H:%M:%S%.3f").unwrap();
+    Local.from_utc_datetime(&parsed_naive).naive_local()
+}
+
 /// Compare the current date with the given date to determine how the time should be shown in the UI
 // target_date is always in Local time
 pub fn get_created_at_timing(target_date: &NaiveDateTime) -> String {
@@ -118,3 +124,14 @@ pub fn get_created_at_timing(target_date: &NaiveDateTime) -> String {
         format!("{} {}", naive_date, naive_time)
     }
 }
+
+/// Formats a size in bytes to be shown in the UI
+pub fn format_size(size: u64) -> String {
+    if size < 1024 {
+        format!("{size} B")
+    } else if size < 1024 * 1024 {
+        format!("{:.1} KB", size as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", size as f64 / 1024.0 / 1024.0)
+    }
+}
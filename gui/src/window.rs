@@ -1,17 +1,21 @@
 mod imp {
     use adw::subclass::prelude::*;
-    use adw::ApplicationWindow;
+    use adw::{ApplicationWindow, Banner, ToastOverlay, WindowTitle};
     use gio::{ListStore, Settings};
     use glib::subclass::InitializingObject;
     use glib::{object_subclass, Binding, Propagation};
     use gtk::{
         gio, glib, Button, CompositeTemplate, EmojiChooser, Label, ListBox, ListView, Revealer,
-        ScrolledWindow, Stack, TextView,
+        ScrolledWindow, SearchBar, SearchEntry, Stack, TextView,
     };
     use std::cell::{Cell, OnceCell, RefCell};
     use std::collections::{HashMap, HashSet};
     use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
 
+    use crate::encryption::{Keyring, RatchetStore};
+    use crate::message::MessageObject;
+    use crate::processor::{SearchIndex, SearchResult};
     use crate::user::UserObject;
 
     #[derive(CompositeTemplate, Default)]
@@ -32,15 +36,39 @@ mod imp {
         #[template_child]
         pub new_chat: TemplateChild<Button>,
         #[template_child]
+        pub new_group: TemplateChild<Button>,
+        #[template_child]
         pub placeholder: TemplateChild<Label>,
         #[template_child]
         pub entry_revealer: TemplateChild<Revealer>,
         #[template_child]
+        pub attach_button: TemplateChild<Button>,
+        #[template_child]
         pub emoji_button: TemplateChild<Button>,
         #[template_child]
         pub emoji_chooser: TemplateChild<EmojiChooser>,
         #[template_child]
         pub message_scroller: TemplateChild<ScrolledWindow>,
+        #[template_child]
+        pub key_warning: TemplateChild<Banner>,
+        #[template_child]
+        pub toast_overlay: TemplateChild<ToastOverlay>,
+        #[template_child]
+        pub window_title: TemplateChild<WindowTitle>,
+        #[template_child]
+        pub reply_revealer: TemplateChild<Revealer>,
+        #[template_child]
+        pub reply_label: TemplateChild<Label>,
+        #[template_child]
+        pub cancel_reply: TemplateChild<Button>,
+        #[template_child]
+        pub search_bar: TemplateChild<SearchBar>,
+        #[template_child]
+        pub search_entry: TemplateChild<SearchEntry>,
+        #[template_child]
+        pub search_scroller: TemplateChild<ScrolledWindow>,
+        #[template_child]
+        pub search_results: TemplateChild<ListBox>,
         pub users: OnceCell<ListStore>,
         pub chatting_with: Rc<RefCell<Option<UserObject>>>,
         pub own_profile: Rc<RefCell<Option<UserObject>>>,
@@ -48,6 +76,18 @@ mod imp {
         pub bindings: RefCell<Vec<Binding>>,
         pub settings: OnceCell<Settings>,
         pub message_numbers: RefCell<HashMap<u64, HashSet<u64>>>,
+        // The message the next sent message replies to
+        pub replying_to: RefCell<Option<MessageObject>>,
+        // Decrypted text of every loaded message. Shared with the decryption threads
+        pub search_index: Arc<Mutex<SearchIndex>>,
+        // Results of the last search in the order they are shown
+        pub found_messages: RefCell<Vec<SearchResult>>,
+        // X3DH keys and ratchet sessions of this device. Shared with the decryption threads
+        pub ratchet_store: OnceCell<Arc<Mutex<RatchetStore>>>,
+        // RSA private keys this device used before rotating. Read from the disk the first time
+        pub keyring: RefCell<Option<Keyring>>,
+        // The passphrase the saved private key is encrypted with
+        pub passphrase: RefCell<String>,
     }
 
     #[object_subclass]
@@ -71,7 +111,6 @@ mod imp {
             let obj = self.obj();
             obj.setup_settings();
             obj.setup_callbacks();
-            obj.setup_users();
             obj.setup_actions();
         }
     }
@@ -80,6 +119,8 @@ mod imp {
         /// On window close save all existing user data to gschema
         fn close_request(&self) -> Propagation {
             self.obj().save_user_list();
+            self.obj().save_message_caches();
+            self.obj().save_ratchet_store();
             Propagation::Proceed
         }
     }
@@ -93,27 +134,35 @@ mod imp {
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
-use adw::Application;
-use chrono::{Local, NaiveDateTime, TimeZone};
-use gio::{ActionGroup, ActionMap, ListStore, Settings, SimpleAction};
+use adw::{ActionRow, Application, Avatar, MessageDialog, Toast};
+use chirp_protocol::{FullUserData, GroupData, MessageData, UserIDs, MAX_ATTACHMENT_SIZE};
+use chrono::Local;
+use gio::{ActionGroup, ActionMap, Cancellable, ListStore, Settings, SimpleAction};
 use glib::{clone, timeout_add_local_once, wrapper, Object};
 use gtk::{
-    gio, glib, Accessible, ApplicationWindow, Buildable, ConstraintTarget, ListBox, ListBoxRow,
-    ListScrollFlags, Native, PositionType, Root, ShortcutManager, Widget,
+    gio, glib, Accessible, ApplicationWindow, Buildable, ConstraintTarget, FileDialog, ListBox,
+    ListBoxRow, ListScrollFlags, Native, PositionType, Root, ShortcutManager, Widget,
 };
 use rsa::{RsaPrivateKey, RsaPublicKey};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::{debug, error, info};
 
-use crate::encryption::{read_rsa_keys_from_file, read_rsa_public_from_string, stringify_rsa_keys};
+use crate::encryption::{
+    derive_cache_key, read_encrypted_file, read_rsa_keys_from_file, save_encrypted_file,
+    save_rsa_keys_to_file, stringify_rsa_keys, Keyring, RatchetStore, VerifiedKeys,
+};
 use crate::message::MessageObject;
-use crate::user::{UserObject, UserProfile, UserPrompt, UserRow};
-use crate::utils::{generate_random_avatar_link, get_created_at_timing};
-use crate::ws::{DecryptedMessageData, FullUserData, MessageData, RequestType, UserIDs};
+use crate::processor::{
+    load_chat_cache, save_chat_cache, AccountBackup, SearchIndex, SearchResult,
+};
+use crate::user::{GroupRow, UserObject, UserProfile, UserPrompt, UserRow};
+use crate::utils::{generate_random_avatar_link, get_created_at_timing, parse_server_time};
+use crate::ws::{DecryptedMessageData, RequestType, WSObject};
 use crate::APP_ID;
 
 wrapper! {
@@ -124,8 +173,17 @@ wrapper! {
 }
 
 impl Window {
-    pub fn new(app: &Application) -> Self {
-        Object::builder().property("application", app).build()
+    /// Create the main window with the passphrase the private key is encrypted with and the
+    /// unlocked RSA keys if any were saved
+    pub fn new(
+        app: &Application,
+        passphrase: String,
+        saved_keys: Option<(RsaPublicKey, RsaPrivateKey)>,
+    ) -> Self {
+        let obj: Window = Object::builder().property("application", app).build();
+        obj.imp().passphrase.replace(passphrase);
+        obj.setup_users(saved_keys);
+        obj
     }
 
     fn setup_callbacks(&self) {
@@ -157,8 +215,16 @@ impl Window {
                 window.grab_focus();
                 window.bind();
                 window.scroll_to_bottom(window.get_chatting_with(), false);
+                window.get_chatting_with().schedule_receipts();
             }));
 
+        // Received messages of the selected chat get read once the window is focused again
+        self.connect_is_active_notify(|window| {
+            if window.is_active() {
+                window.get_chatting_with().schedule_receipts();
+            }
+        });
+
         // The event on New Chat button clicked
         imp.new_chat
             .connect_clicked(clone!(@weak self as window => move |_| {
@@ -166,12 +232,25 @@ impl Window {
                 prompt.present();
             }));
 
+        // The event on New Group button clicked
+        imp.new_group
+            .connect_clicked(clone!(@weak self as window => move |_| {
+                let prompt = UserPrompt::new("Create").create_group(&window);
+                prompt.present();
+            }));
+
         // The event on Profile button clicked
         imp.my_profile
             .connect_clicked(clone!(@weak self as window => move |_| {
                 UserProfile::new(window.get_chatting_from(), &window);
             }));
 
+        // Opens the profile of the selected user to compare the safety number again
+        imp.key_warning
+            .connect_button_clicked(clone!(@weak self as window => move |_| {
+                UserProfile::new(window.get_chatting_with(), &window);
+            }));
+
         // The event on the send button beside the textview
         imp.send_button
             .connect_clicked(clone!(@weak self as window => move |_| {
@@ -190,6 +269,9 @@ impl Window {
                     window.imp().send_button.set_sensitive(false);
                 } else {
                     window.imp().send_button.set_sensitive(should_be_enabled);
+                    if should_be_enabled {
+                        window.get_chatting_with().send_typing();
+                    }
                 }
 
                 if should_be_enabled {
@@ -207,6 +289,21 @@ impl Window {
             window.imp().entry_revealer.set_reveal_child(true);
         });
 
+        // The window title is shown in the header bar
+        self.bind_property("title", &imp.window_title.get(), "title")
+            .sync_create()
+            .build();
+
+        imp.cancel_reply
+            .connect_clicked(clone!(@weak self as window => move |_| {
+                window.cancel_reply();
+            }));
+
+        imp.attach_button
+            .connect_clicked(clone!(@weak self as window => move |_| {
+                window.choose_attachment();
+            }));
+
         // Set emoji chooser to visible on click
         imp.emoji_button
             .connect_clicked(clone!(@weak self as window => move |_| {
@@ -227,8 +324,31 @@ impl Window {
         vadjustment.connect_value_changed(clone!(@weak self as window => move |adjustment| {
             if adjustment.value() == adjustment.lower() {
                 window.get_chatting_with().renderer().load_more_items()
+            } else if adjustment.value() + adjustment.page_size() >= adjustment.upper() {
+                window.get_chatting_with().renderer().load_newer_items()
             }
         }));
+
+        imp.search_bar.connect_entry(&imp.search_entry.get());
+
+        imp.search_entry
+            .connect_search_changed(clone!(@weak self as window => move |entry| {
+                window.search_messages(&entry.text());
+            }));
+
+        imp.search_results
+            .connect_row_activated(clone!(@weak self as window => move |_, row| {
+                let result = window
+                    .imp()
+                    .found_messages
+                    .borrow()
+                    .get(row.index() as usize)
+                    .cloned();
+
+                if let Some(result) = result {
+                    window.open_search_result(result);
+                }
+            }));
     }
 
     fn setup_actions(&self) {
@@ -248,7 +368,7 @@ impl Window {
     }
 
     /// Setup owner profile and bind User model
-    fn setup_users(&self) {
+    fn setup_users(&self, saved_keys: Option<(RsaPublicKey, RsaPrivateKey)>) {
         let users = ListStore::new::<UserObject>();
         self.imp().users.set(users).expect("Could not set users");
         self.imp().last_selected_user.set(0);
@@ -260,15 +380,18 @@ impl Window {
         user_list.bind_model(
             Some(user_store),
             clone!(@weak self as window => @default-panic, move |obj| {
-                let user_object = obj.downcast_ref().unwrap();
-                let row = window.get_user_row(user_object, "user-inactive");
+                let user_object: &UserObject = obj.downcast_ref().unwrap();
+                let row = if user_object.is_group() {
+                    window.get_group_row(user_object, "user-inactive")
+                } else {
+                    window.get_user_row(user_object, "user-inactive")
+                };
                 row.upcast()
             }),
         );
 
         info!("Setting own profile");
         let saved_user_id = self.check_saved_data();
-        let saved_keys = self.check_saved_keys();
         let data: UserObject = self.create_owner(saved_user_id.clone(), saved_keys.clone());
 
         self.imp().own_profile.replace(Some(data.clone()));
@@ -330,7 +453,12 @@ impl Window {
 
         info!("Saving new user id info on {}", user_data_path);
         let owner_id = self.get_chatting_from();
-        let id_data = UserIDs::new_json(owner_id.user_id(), owner_id.user_token());
+        let id_data = UserIDs::new(
+            owner_id.user_id(),
+            owner_id.user_token(),
+            owner_id.device_id(),
+        )
+        .to_json();
 
         let mut file = File::create(user_data_path).unwrap();
         file.write_all(id_data.as_bytes()).unwrap();
@@ -349,30 +477,13 @@ impl Window {
             file.read_to_string(&mut file_contents)
                 .expect("Failed to read file");
 
-            let id_data = UserIDs::from_json(&file_contents);
-            info!("Saved user data found");
-            return Some(id_data);
-        }
-
-        info!("Failed to find any previously saved user data");
-        None
-    }
-
-    /// Check if any existing rsa keys are available
-    fn check_saved_keys(&self) -> Option<(RsaPublicKey, RsaPrivateKey)> {
-        info!("Checking for saved RSA keys");
-        let saving_location = self.settings().string("location");
-        let public_location = format!("{}public_key.pem", saving_location);
-        let private_location = format!("{}private_key.pem", saving_location);
-
-        if fs::metadata(public_location).is_ok() && fs::metadata(private_location).is_ok() {
-            let existing_keys = read_rsa_keys_from_file(saving_location.to_string());
-            if let Ok((public, private)) = existing_keys {
-                info!("Saved RSA Keys found");
-                return Some((public, private));
+            if let Ok(id_data) = UserIDs::from_json(&file_contents) {
+                info!("Saved user data found");
+                return Some(id_data);
             }
         }
 
+        info!("Failed to find any previously saved user data");
         None
     }
 
@@ -394,13 +505,18 @@ impl Window {
         let user_list = self.get_users_liststore();
         for user_data in user_list.iter() {
             let user_object: UserObject = user_data.unwrap();
+
+            // Groups are received from the server after reconnecting
+            if user_object.is_group() {
+                continue;
+            }
             debug!(
                 "Saving user object {} {} {:?}",
                 user_object.user_id(),
                 user_object.name(),
                 user_object.image_link()
             );
-            let user_data = FullUserData::new(&user_object).empty_token();
+            let user_data = user_object.to_user_data();
             save_list.push(user_data)
         }
 
@@ -408,27 +524,176 @@ impl Window {
         self.settings().set_string("users", &to_save).unwrap();
     }
 
+    /// Key digests of every verified user. {User ID: Key digest}
+    fn verified_keys(&self) -> HashMap<u64, VerifiedKeys> {
+        let verified_users = self.settings().string("verified-users");
+        if verified_users.is_empty() {
+            return HashMap::new();
+        }
+
+        // Users verified before the identity keys were part of the safety number must be verified again
+        serde_json::from_str(&verified_users).unwrap_or_else(|e| {
+            error!("Failed to read the verified users. Reason: {e}");
+            HashMap::new()
+        })
+    }
+
+    /// The keys the given user was verified with
+    pub fn verified_key(&self, user_id: u64) -> Option<VerifiedKeys> {
+        self.verified_keys().remove(&user_id)
+    }
+
+    /// Save the verified keys of a user and pin the identity keys of the devices.
+    /// None removes the user from the verified users
+    pub fn save_verified_key(&self, user_id: u64, keys: Option<VerifiedKeys>) {
+        let mut verified_keys = self.verified_keys();
+        let identities = keys
+            .as_ref()
+            .map(|keys| keys.identities.to_owned())
+            .unwrap_or_default();
+
+        if let Some(keys) = keys {
+            verified_keys.insert(user_id, keys);
+        } else {
+            verified_keys.remove(&user_id);
+        }
+
+        if let Some(ratchet_store) = self.ratchet_store() {
+            ratchet_store
+                .lock()
+                .unwrap()
+                .pin_identities(user_id, &identities);
+        }
+
+        let to_save = serde_json::to_string(&verified_keys).unwrap();
+        self.settings()
+            .set_string("verified-users", &to_save)
+            .unwrap();
+    }
+
     /// Save the RSA keys to the saved location
     pub fn save_rsa_keys(&self) {
         info!("Starting saving RSA keys");
         let saving_location = self.settings().string("location").to_string();
         let owner_data = self.get_chatting_from();
 
-        let public_key = owner_data.imp().rsa_public.get().unwrap();
-        let private_key = owner_data.imp().rsa_private.get().unwrap();
+        let (public_key, private_key) = owner_data.rsa_keys().unwrap();
 
-        let (public_string, private_string) = stringify_rsa_keys(public_key, private_key);
+        let (public_string, private_string) =
+            stringify_rsa_keys(&public_key, &private_key, &self.imp().passphrase.borrow());
 
         if fs::metadata(&saving_location).is_ok() {
-            let public_location = format!("{}public_key.pem", saving_location);
-            let private_location = format!("{}private_key.pem", saving_location);
+            if let Err(e) = save_rsa_keys_to_file(&saving_location, &public_string, &private_string)
+            {
+                error!("Failed to save the RSA keys. Reason: {e}");
+                self.show_toast(&format!("Failed to save the RSA keys. Reason: {e}"));
+            }
+        }
+    }
+
+    pub fn passphrase(&self) -> String {
+        self.imp().passphrase.borrow().clone()
+    }
+
+    /// Encrypt the saved private key with a new passphrase
+    pub fn change_passphrase(&self, new_passphrase: String) {
+        info!("Changing the passphrase of the private key");
+        self.imp().passphrase.replace(new_passphrase);
+        self.save_rsa_keys();
+    }
 
-            let mut file = File::create(public_location).unwrap();
-            file.write_all(public_string.as_bytes()).unwrap();
+    /// Gathers the IDs, the keys and the saved users of the owner for exporting. The private key is
+    /// encrypted with the passphrase of the backup. None if the owner is not created on the server yet
+    pub fn account_backup(&self, passphrase: &str) -> Option<AccountBackup> {
+        let owner = self.get_chatting_from();
+        let (public_key, private_key) = owner.rsa_keys()?;
 
-            let mut file = File::create(private_location).unwrap();
-            file.write_all(private_string.as_bytes()).unwrap();
+        if owner.user_id() == 0 {
+            return None;
         }
+
+        let (public_string, private_string) =
+            stringify_rsa_keys(&public_key, &private_key, passphrase);
+
+        self.save_user_list();
+
+        Some(AccountBackup {
+            user_ids: UserIDs::new(owner.user_id(), owner.user_token(), owner.device_id()),
+            public_key: public_string,
+            private_key: private_string,
+            users: self.get_saved_user_data(),
+            verified_users: self.verified_keys(),
+            keyring: self.keyring(),
+        })
+    }
+
+    /// Tells the app is closing and replaces the saved owner data with the backup. The backup gets
+    /// used after the next start
+    pub fn import_account(&self, backup: AccountBackup, passphrase: String) {
+        let dialog = MessageDialog::new(
+            Some(self),
+            Some("Account Imported"),
+            Some("Chirp will close now. Open it again to use the imported account"),
+        );
+        dialog.add_response("close", "Close");
+        dialog.set_close_response("close");
+
+        // Saved right before quitting so nothing of the current owner gets saved over the backup.
+        // Quitting through the application skips the saving on close
+        dialog.choose(
+            None::<&Cancellable>,
+            clone!(@weak self as window => move |_| {
+                if let Err(e) = window.save_imported_account(backup, &passphrase) {
+                    error!("Failed to import the account. Reason: {e}");
+                    window.show_toast(&format!("Failed to import the account. Reason: {e}"));
+                    return;
+                }
+                if let Some(app) = window.application() {
+                    app.quit();
+                }
+            }),
+        );
+    }
+
+    /// Replaces the saved IDs, keys and users with the ones in the backup
+    fn save_imported_account(&self, backup: AccountBackup, passphrase: &str) -> Result<(), String> {
+        info!(
+            "Importing the account of User ID {}",
+            backup.user_ids.user_id
+        );
+        let saving_location = self.settings().string("location");
+        fs::create_dir_all(&saving_location).map_err(|e| e.to_string())?;
+
+        // Registered as a new device so it does not share the ratchet sessions of the exported device
+        let id_data = UserIDs::new(backup.user_ids.user_id, backup.user_ids.user_token, 0);
+        let user_data_path = format!("{}user_data.json", saving_location);
+        fs::write(user_data_path, id_data.to_json()).map_err(|e| e.to_string())?;
+
+        save_rsa_keys_to_file(&saving_location, &backup.public_key, &backup.private_key)
+            .map_err(|e| e.to_string())?;
+
+        // The ratchet sessions belong to the previous keys. The keyring is saved with the key
+        // derived from the imported private key
+        let _ = fs::remove_file(self.ratchet_store_path());
+        let (_, private_key) = read_rsa_keys_from_file(saving_location.to_string(), passphrase)
+            .map_err(|e| e.to_string())?;
+        let keyring = serde_json::to_vec(&backup.keyring).unwrap();
+        if !save_encrypted_file(
+            &self.keyring_path(),
+            &derive_cache_key(&private_key),
+            &keyring,
+        ) {
+            return Err(String::from("The keyring could not be saved"));
+        }
+
+        let users = serde_json::to_string(&backup.users).unwrap();
+        self.settings().set_string("users", &users).unwrap();
+
+        let verified_users = serde_json::to_string(&backup.verified_users).unwrap();
+        self.settings()
+            .set_string("verified-users", &verified_users)
+            .unwrap();
+        Ok(())
     }
 
     /// Bind the main window header bar's title to the selected chat
@@ -440,12 +705,44 @@ impl Window {
             .transform_to(|_, name: String| Some(format!("Chirp - {}", name)))
             .sync_create()
             .build();
+
+        let typing_binding = chatting_with
+            .bind_property("is-typing", &self.imp().window_title.get(), "subtitle")
+            .transform_to(|binding, is_typing: bool| {
+                let user = binding.source().and_downcast::<UserObject>()?;
+                if is_typing {
+                    Some(format!("{} is typing…", user.name()))
+                } else {
+                    Some(String::new())
+                }
+            })
+            .sync_create()
+            .build();
+
+        let key_warning_binding = chatting_with
+            .bind_property("key-changed", &self.imp().key_warning.get(), "revealed")
+            .sync_create()
+            .build();
+
+        let key_warning_title_binding = chatting_with
+            .bind_property("name", &self.imp().key_warning.get(), "title")
+            .transform_to(|_, name: String| {
+                Some(format!(
+                    "The keys of {name} changed after they were verified. Compare the safety number again"
+                ))
+            })
+            .sync_create()
+            .build();
+
         bindings.push(title_binding);
+        bindings.push(typing_binding);
+        bindings.push(key_warning_binding);
+        bindings.push(key_warning_title_binding);
     }
 
-    /// Disconnect the last header bar title binding
+    /// Disconnect the header bar bindings of the last selected chat
     fn remove_last_binding(&self) {
-        if let Some(binding) = self.imp().bindings.borrow_mut().pop() {
+        for binding in self.imp().bindings.take() {
             binding.unbind();
         }
     }
@@ -473,6 +770,7 @@ impl Window {
         if let Some(old_user) = previous_user {
             old_user.renderer().user_inactive()
         }
+        self.cancel_reply();
 
         let message_factory = user.factory();
         let selection_model = user.selection_model();
@@ -513,9 +811,6 @@ impl Window {
 
     /// Send the text on the Textview as a message
     fn send_message(&self) {
-        if self.get_chatting_with().user_id() == 0 {
-            return;
-        }
         let buffer = self.imp().message_entry.buffer();
         let content = buffer
             .text(&buffer.start_iter(), &buffer.end_iter(), true)
@@ -527,9 +822,109 @@ impl Window {
             return;
         }
 
+        if content == "/rotate-token" {
+            buffer.set_text("");
+            self.get_chatting_from()
+                .add_to_queue(RequestType::RotateToken);
+            return;
+        }
+
+        if content == "/rotate-key" {
+            buffer.set_text("");
+            self.get_chatting_from().rotate_rsa_key();
+            return;
+        }
+
+        if self.get_chatting_with().user_id() == 0 {
+            return;
+        }
+
+        let receiver = self.get_chatting_with();
+        let (message, send_message_data) = self.create_sent_message(content);
+
+        // Receiver gets the queue because the message number the server gives belongs to the receiver chat
+        receiver.add_to_queue(RequestType::SendMessage(send_message_data, message.clone()));
+        self.scroll_to_bottom(receiver, true);
+    }
+
+    /// Opens a file chooser to send the chosen file to the selected chat
+    fn choose_attachment(&self) {
+        if self.get_chatting_with().user_id() == 0 {
+            return;
+        }
+
+        let dialog = FileDialog::builder()
+            .title("Send a File")
+            .modal(true)
+            .build();
+
+        dialog.open(
+            Some(self),
+            None::<&Cancellable>,
+            clone!(@weak self as window => move |result| {
+                if let Ok(file) = result {
+                    window.load_attachment(file);
+                }
+            }),
+        );
+    }
+
+    fn load_attachment(&self, file: gio::File) {
+        let file_name = file.basename().map_or(String::from("file"), |name| {
+            name.to_string_lossy().to_string()
+        });
+
+        file.load_contents_async(
+            None::<&Cancellable>,
+            clone!(@weak self as window => move |result| {
+                match result {
+                    Ok((file_data, _)) => window.send_attachment(file_name, file_data.to_vec()),
+                    Err(e) => {
+                        error!("Failed to read the chosen file. Reason: {e}");
+                        window.show_toast("Failed to read the chosen file");
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Sends the file to the selected chat with the typed text as the caption
+    fn send_attachment(&self, file_name: String, file_data: Vec<u8>) {
+        // The file grows by the 16 byte authentication tag once encrypted
+        if file_data.len() as u64 + 16 > MAX_ATTACHMENT_SIZE {
+            self.show_toast(&format!(
+                "Files larger than {} MB can not be sent",
+                MAX_ATTACHMENT_SIZE / 1024 / 1024
+            ));
+            return;
+        }
+
+        let buffer = self.imp().message_entry.buffer();
+        let content = buffer
+            .text(&buffer.start_iter(), &buffer.end_iter(), true)
+            .trim()
+            .to_string();
+
+        let receiver = self.get_chatting_with();
+        let (message, send_message_data) = self.create_sent_message(content);
+        message.set_attachment_name(file_name.to_owned());
+        message.set_attachment_data(file_data.clone());
+
+        receiver.upload_attachment(send_message_data, message, file_name, file_data);
+        self.scroll_to_bottom(receiver, true);
+    }
+
+    /// Adds a new message of the owner to the selected chat and clears the Textview.
+    /// Returns the message with the data to send it with
+    fn create_sent_message(&self, content: String) -> (MessageObject, MessageData) {
+        let buffer = self.imp().message_entry.buffer();
         let sender = self.get_chatting_from();
         let receiver = self.get_chatting_with();
 
+        // Devices without a ratchet session get the key encrypted with RSA till a session is started
+        sender.fetch_missing_sessions();
+        receiver.fetch_missing_sessions();
+
         let receiver_id = receiver.user_id();
         let current_time = Local::now();
         let created_at = current_time.to_string();
@@ -546,17 +941,29 @@ impl Window {
         )
         .to_process(true);
         message.set_show_initial_message(false);
+
+        // The reply bar is cleared when another chat gets selected so it belongs to this chat
+        let reply_to = self.imp().replying_to.take().map(|replied_message| {
+            message.set_reply_to(replied_message.message_number());
+            message.set_replied_message(Some(replied_message.clone()));
+            replied_message.message_number()
+        });
+        self.imp().reply_revealer.set_reveal_child(false);
+
+        // The new message goes below the newest message so older search results get closed
+        if !receiver.renderer().is_showing_latest() {
+            receiver.renderer().user_active(None);
+        }
+
         self.chatting_with_messages().append(&message);
 
         buffer.set_text("");
 
         let send_message_data =
-            MessageData::new_incomplete(created_at, self.get_owner_id(), receiver_id);
+            MessageData::new_incomplete(created_at, self.get_owner_id(), receiver_id)
+                .update_reply_to(reply_to);
 
-        // Receiver gets the queue because the receiver saves the message number variable
-        // if it was sender, it would send the message number of owner_id@owner_id group which is invalid
-        receiver.add_to_queue(RequestType::SendMessage(send_message_data, message.clone()));
-        self.scroll_to_bottom(receiver, true);
+        (message, send_message_data)
     }
 
     /// Gets called when a message is received or when syncing previous message data
@@ -593,32 +1000,37 @@ impl Window {
                 .set_message_number(other_user.message_number() + 1);
         }
 
-        let (sender, receiver, is_send) =
-            if self.get_chatting_from().user_id() == message_data.from_user {
-                (self.get_chatting_from(), other_user.clone(), true)
-            } else {
-                (other_user.clone(), self.get_chatting_from(), false)
-            };
+        let (message, is_send) = self.create_message_object(&message_data, &other_user);
 
-        // The server sends the time in UTC
-        let parsed_naive =
-            NaiveDateTime::parse_from_str(&message_data.created_at, "%Y-%m-%d %H:%M:%S%.3f")
-                .unwrap()
-                .and_utc()
-                .naive_utc();
+        if let Some(receipt) = message_data.receipt {
+            message.update_receipt(receipt);
+        }
+        message.set_edited(message_data.edited_at.is_some());
+        message.set_reactions(message_data.reactions);
 
-        let created_at = Local.from_utc_datetime(&parsed_naive).naive_local();
+        if let Some(attachment) = message_data.attachment {
+            message.set_attachment(attachment, message_data.used_aes_key.clone());
 
-        let message_timing = get_created_at_timing(&created_at);
+            // Images are downloaded right away to show a preview, other files when asked for
+            if message.has_attachment() && message.attachment_is_image() {
+                other_user.download_attachment(&message);
+            }
+        }
 
-        let message = MessageObject::new(
-            message_data.message.unwrap(),
-            is_send,
-            sender.clone(),
-            receiver.clone(),
-            message_timing,
-            Some(message_data.message_number),
-        );
+        if let Some(reply_to) = message_data.reply_to {
+            message.set_reply_to(reply_to);
+            other_user.renderer().resolve_reply(&message);
+
+            // Replies found while syncing get fetched once the sync is done
+            if !other_user.renderer().is_syncing() {
+                other_user.fetch_replied_messages();
+            }
+        }
+
+        // Direct messages from the other user get delivered and read receipts
+        if !is_send && !other_user.is_group() {
+            other_user.message_arrived(message_data.message_number);
+        }
 
         other_user
             .renderer()
@@ -633,20 +1045,212 @@ impl Window {
                 {
                     other_user.messages().insert(0, &message);
                 }
-            } else {
+            } else if other_user.renderer().is_showing_latest() {
                 other_user.messages().append(&message);
             }
         }
 
         // Pending message color should not be added when syncing messages
-        if add_css {
-            let target_user = if is_send { receiver } else { sender };
+        if add_css && other_user != self.get_chatting_with() {
+            self.add_pending_avatar_css(other_user)
+        }
+        Some(message)
+    }
 
-            if target_user != self.get_chatting_with() {
-                self.add_pending_avatar_css(target_user)
+    /// Creates the MessageObject of a decrypted message of the given chat. Also returns whether the owner sent it
+    fn create_message_object(
+        &self,
+        message_data: &DecryptedMessageData,
+        other_user: &UserObject,
+    ) -> (MessageObject, bool) {
+        // Group messages are sent to the group by one of the members
+        let (sender, receiver, is_send) =
+            if self.get_chatting_from().user_id() == message_data.from_user {
+                (self.get_chatting_from(), other_user.clone(), true)
+            } else if other_user.is_group() {
+                let sender = other_user
+                    .group_member(message_data.from_user)
+                    .unwrap_or(other_user.clone());
+                (sender, other_user.clone(), false)
+            } else {
+                (other_user.clone(), self.get_chatting_from(), false)
+            };
+
+        // The server sends the time in UTC
+        let created_at = parse_server_time(&message_data.created_at);
+
+        let message_timing = get_created_at_timing(&created_at);
+
+        let message = MessageObject::new(
+            message_data.message.clone().unwrap(),
+            is_send,
+            sender,
+            receiver,
+            message_timing,
+            Some(message_data.message_number),
+        );
+        message.set_cache_data(
+            message_data.created_at.to_owned(),
+            message_data.used_aes_key.clone(),
+        );
+        (message, is_send)
+    }
+
+    /// Creates the MessageObject of a replied message that is only shown as a quote
+    pub fn quoted_message(
+        &self,
+        message_data: &DecryptedMessageData,
+        other_user: &UserObject,
+    ) -> MessageObject {
+        self.create_message_object(message_data, other_user).0
+    }
+
+    /// Shows the message the next sent message will reply to above the Textview
+    pub fn reply_to_message(&self, message: &MessageObject) {
+        info!("Replying to message number {}", message.message_number());
+        self.imp().reply_label.set_label(&format!(
+            "{}: {}",
+            message.sent_from().name(),
+            message.message()
+        ));
+        self.imp().replying_to.replace(Some(message.clone()));
+        self.imp().reply_revealer.set_reveal_child(true);
+        self.imp().message_entry.grab_focus();
+    }
+
+    /// Stops replying to the selected message
+    fn cancel_reply(&self) {
+        self.imp().replying_to.replace(None);
+        self.imp().reply_revealer.set_reveal_child(false);
+    }
+
+    /// Scrolls to a message of the selected chat, loading the older saved messages if needed
+    pub fn scroll_to_message(&self, other_user: UserObject, message_number: u64) {
+        if other_user != self.get_chatting_with() {
+            return;
+        }
+
+        let renderer = other_user.renderer();
+        if renderer.get_message(message_number).is_none() {
+            self.show_toast("The replied message is not loaded yet");
+            return;
+        }
+
+        if message_number < renderer.shown_till() {
+            renderer.show_till(message_number);
+        }
+
+        self.focus_message(&other_user, message_number);
+    }
+
+    /// Searches the decrypted messages of every chat and lists the results below the search bar
+    fn search_messages(&self, query: &str) {
+        let results_list = self.imp().search_results.get();
+        while let Some(child) = results_list.first_child() {
+            results_list.remove(&child);
+        }
+
+        let results = if query.trim().is_empty() {
+            Vec::new()
+        } else {
+            self.imp().search_index.lock().unwrap().search(query)
+        };
+
+        for result in results.iter() {
+            let chat_name = self
+                .find_user(result.chat_id)
+                .map_or(String::from("Unknown chat"), |chat| chat.name());
+
+            let row = ActionRow::builder()
+                .title(glib::markup_escape_text(&chat_name))
+                .subtitle(glib::markup_escape_text(&result.text))
+                .subtitle_lines(2)
+                .activatable(true)
+                .build();
+            results_list.append(&row);
+        }
+
+        self.imp().search_scroller.set_visible(!results.is_empty());
+        self.imp().found_messages.replace(results);
+    }
+
+    /// Selects the chat of the search result and shows the found message
+    fn open_search_result(&self, result: SearchResult) {
+        let Some(chat) = self.find_user(result.chat_id) else {
+            return;
+        };
+
+        info!(
+            "Opening message number {} of chat {}",
+            result.message_number, result.chat_id
+        );
+        self.imp().search_bar.set_search_mode(false);
+
+        if chat != self.get_chatting_with() {
+            let index = self
+                .get_users_liststore()
+                .iter::<UserObject>()
+                .position(|user| user.map_or(false, |user| user == chat));
+
+            if let Some(index) = index {
+                self.get_user_list()
+                    .row_at_index(index as i32)
+                    .unwrap()
+                    .activate();
             }
         }
-        Some(message)
+
+        // The selected chat reloads its messages after a short timeout
+        timeout_add_local_once(
+            Duration::from_millis(200),
+            clone!(@weak self as window => move || {
+                window.show_found_message(chat, result.message_number);
+            }),
+        );
+    }
+
+    /// Loads the saved messages around the found message and scrolls to it.
+    /// Messages that are not saved yet are synced first
+    pub fn show_found_message(&self, chat: UserObject, message_number: u64) {
+        if chat != self.get_chatting_with() {
+            return;
+        }
+
+        let renderer = chat.renderer();
+        if renderer.get_message(message_number).is_none() {
+            if renderer.is_syncing() || message_number >= renderer.synced_till() {
+                self.show_toast("The message is not loaded yet");
+                return;
+            }
+
+            renderer.set_jump_target(message_number);
+            chat.add_to_queue(RequestType::SyncMessage(
+                message_number,
+                renderer.synced_till(),
+            ));
+            return;
+        }
+
+        // A few newer messages are shown below the found message
+        renderer.user_active(Some(message_number + 10));
+        self.focus_message(&chat, message_number);
+    }
+
+    /// Scrolls to a message that is in the ListStore of the selected chat
+    fn focus_message(&self, other_user: &UserObject, message_number: u64) {
+        let model = other_user.messages();
+        let position = (0..model.n_items()).find(|index| {
+            model
+                .item(*index)
+                .and_downcast::<MessageObject>()
+                .map_or(false, |message| message.message_number() == message_number)
+        });
+
+        if let Some(position) = position {
+            self.imp()
+                .message_list
+                .scroll_to(position, ListScrollFlags::FOCUS, None);
+        }
     }
 
     /// Create a row using a UserObject for the ListBox
@@ -661,19 +1265,47 @@ impl Window {
             .build()
     }
 
+    fn get_group_row(&self, data: &UserObject, css_name: &str) -> ListBoxRow {
+        let group_row = GroupRow::new(data.clone());
+        group_row.imp().group_avatar.add_css_class(css_name);
+        ListBoxRow::builder()
+            .child(&group_row)
+            .activatable(true)
+            .selectable(false)
+            .can_focus(false)
+            .build()
+    }
+
     /// Used during the startup of the app. Called only once to create the owner profile
     fn create_owner(
         &self,
         id_data: Option<UserIDs>,
         key_data: Option<(RsaPublicKey, RsaPrivateKey)>,
     ) -> UserObject {
-        let data_exists = id_data.is_some() && key_data.is_some();
-
-        let user_data = if data_exists {
+        let user_data = if let (Some(data), Some((public_key, private_key))) = (&id_data, key_data)
+        {
             info!("Saved user data found");
 
-            let (public_key, private_key) = key_data.unwrap();
-            let data = id_data.unwrap();
+            self.imp()
+                .message_numbers
+                .borrow_mut()
+                .insert(data.user_id, HashSet::new());
+            // The device keys are received from the server after reconnecting
+            let user_data = UserObject::new(
+                "Me",
+                None,
+                None,
+                Some(data.user_id),
+                Some(data.user_token.to_owned()),
+                self.clone(),
+                Some(Vec::new()),
+                Some((public_key, private_key)),
+            );
+            user_data.set_device_id(data.device_id);
+            user_data
+        } else if let Some(data) = id_data {
+            info!("Saved user data found without RSA keys. Adding as a new device");
+
             self.imp()
                 .message_numbers
                 .borrow_mut()
@@ -685,8 +1317,8 @@ impl Window {
                 Some(data.user_id),
                 Some(data.user_token),
                 self.clone(),
-                Some(public_key.clone()),
-                Some((public_key, private_key)),
+                None,
+                None,
             )
         } else {
             UserObject::new(
@@ -701,6 +1333,8 @@ impl Window {
             )
         };
 
+        // Every other user shares the owner connection
+        user_data.set_user_ws(WSObject::new());
         user_data.handle_ws();
         self.get_users_liststore().append(&user_data);
 
@@ -715,7 +1349,6 @@ impl Window {
             user_data.user_name, user_data.user_id
         );
 
-        let rsa_public_key = read_rsa_public_from_string(user_data.rsa_public_key);
         let new_user_data = UserObject::new(
             &user_data.user_name,
             user_data.image_link,
@@ -723,37 +1356,104 @@ impl Window {
             Some(user_data.user_id),
             None,
             self.clone(),
-            Some(rsa_public_key),
+            Some(user_data.devices),
             None,
         );
 
+        let user_ws = self.share_owner_data(&new_user_data);
+        self.get_users_liststore().append(&new_user_data);
+        self.save_user_list();
+        self.imp()
+            .message_numbers
+            .borrow_mut()
+            .insert(new_user_data.user_id(), HashSet::new());
+        self.load_message_cache(&new_user_data);
+
+        // Otherwise the chat gets opened once the owner is authenticated
+        if user_ws.authenticated() {
+            new_user_data.add_queue_to_first(RequestType::ReconnectUser);
+        }
+        new_user_data
+    }
+
+    /// Create a new group with the given data and add it to the user list
+    pub fn create_group(&self, group_data: GroupData) -> UserObject {
+        info!(
+            "Creating new group with name: {}, id: {}",
+            group_data.group_name, group_data.group_id
+        );
+
+        let new_group = UserObject::new(
+            &group_data.group_name,
+            None,
+            Some(&self.get_owner_name_color()),
+            Some(group_data.group_id),
+            None,
+            self.clone(),
+            Some(Vec::new()),
+            None,
+        );
+        new_group.set_is_group(true);
+        new_group.set_group_data(group_data);
+
+        let user_ws = self.share_owner_data(&new_group);
+        self.get_users_liststore().append(&new_group);
+        self.imp()
+            .message_numbers
+            .borrow_mut()
+            .insert(new_group.user_id(), HashSet::new());
+        self.load_message_cache(&new_group);
+
+        // Groups have nothing to open on the server, syncing can start right away
+        if user_ws.authenticated() {
+            new_group.start_sync();
+        }
+        new_group
+    }
+
+    /// Binds the owner data that every other UserObject uses and shares the owner connection.
+    /// Returns the shared connection
+    fn share_owner_data(&self, user_object: &UserObject) -> WSObject {
         // Every single user in the UserList of the client will have the owner User ID for reference
         // In case of connection  issues, bind is used so when the owner gets the data, all users will too.
         let chatting_from = self.get_chatting_from();
         chatting_from
-            .bind_property("user-id", &new_user_data, "owner-id")
+            .bind_property("user-id", user_object, "owner-id")
+            .sync_create()
+            .build();
+
+        chatting_from
+            .bind_property("user-token", user_object, "user-token")
             .sync_create()
             .build();
 
         chatting_from
-            .bind_property("user-token", &new_user_data, "user-token")
+            .bind_property("device-id", user_object, "device-id")
             .sync_create()
             .build();
 
-        let public_key = chatting_from.imp().rsa_public.get().unwrap().clone();
-        let private_key = chatting_from.imp().rsa_private.get().unwrap().clone();
+        let (public_key, private_key) = chatting_from.rsa_keys().unwrap();
+        user_object.set_rsa_keys(public_key, private_key);
 
-        new_user_data.imp().rsa_public.set(public_key).unwrap();
-        new_user_data.imp().rsa_private.set(private_key).unwrap();
+        let user_ws = chatting_from.user_ws();
+        user_object.set_user_ws(user_ws.clone());
+        user_ws
+    }
 
-        new_user_data.handle_ws();
-        self.get_users_liststore().append(&new_user_data);
-        self.save_user_list();
-        self.imp()
-            .message_numbers
-            .borrow_mut()
-            .insert(new_user_data.user_id(), HashSet::new());
-        new_user_data
+    /// Get every group the owner is a member of
+    pub fn groups(&self) -> Vec<UserObject> {
+        self.get_users_liststore()
+            .iter::<UserObject>()
+            .map(|user_data| user_data.unwrap())
+            .filter(|user_data| user_data.is_group())
+            .collect()
+    }
+
+    /// Sends the messages that were waiting for the prekey bundles of a user again
+    pub fn send_waiting_requests(&self) {
+        for chat in self.get_users_liststore().iter::<UserObject>() {
+            chat.unwrap().send_waiting_requests();
+        }
     }
 
     /// Get the users ListBox
@@ -771,21 +1471,34 @@ impl Window {
         self.imp().message_entry.grab_focus();
     }
 
+    /// Get the avatar and the UserObject of a row that holds either a UserRow or a GroupRow
+    fn get_row_data(&self, row_data: &ListBoxRow) -> (Avatar, UserObject) {
+        let child = row_data.child().unwrap();
+        if let Some(group_row) = child.downcast_ref::<GroupRow>() {
+            let group_data = group_row.imp().group_data.get().unwrap().clone();
+            (group_row.imp().group_avatar.get(), group_data)
+        } else {
+            let user_row: UserRow = child.downcast().unwrap();
+            let user_data = user_row.imp().user_data.get().unwrap().clone();
+            (user_row.imp().user_avatar.get(), user_data)
+        }
+    }
+
     fn remove_selected_avatar_css(&self, index: i32, listbox: &ListBox) {
         if let Some(row_data) = listbox.row_at_index(index) {
-            let user_row: UserRow = row_data.child().unwrap().downcast().unwrap();
-            user_row.imp().user_avatar.remove_css_class("user-selected");
-            user_row.imp().user_avatar.add_css_class("user-inactive");
-            user_row.imp().user_avatar.remove_css_class("user-pending");
+            let (avatar, _) = self.get_row_data(&row_data);
+            avatar.remove_css_class("user-selected");
+            avatar.add_css_class("user-inactive");
+            avatar.remove_css_class("user-pending");
         }
     }
 
     fn add_selected_avatar_css(&self, index: i32, listbox: &ListBox) {
         if let Some(row_data) = listbox.row_at_index(index) {
-            let user_row: UserRow = row_data.child().unwrap().downcast().unwrap();
-            user_row.imp().user_avatar.add_css_class("user-selected");
-            user_row.imp().user_avatar.remove_css_class("user-inactive");
-            user_row.imp().user_avatar.remove_css_class("user-pending");
+            let (avatar, _) = self.get_row_data(&row_data);
+            avatar.add_css_class("user-selected");
+            avatar.remove_css_class("user-inactive");
+            avatar.remove_css_class("user-pending");
         }
     }
 
@@ -795,25 +1508,40 @@ impl Window {
 
         for index in 0..total_user {
             if let Some(row_data) = listbox.row_at_index(index) {
-                let user_row: UserRow = row_data.child().unwrap().downcast().unwrap();
-                if user_row.imp().user_data.get().unwrap() == &target_user {
-                    user_row.imp().user_avatar.remove_css_class("user-selected");
-                    user_row.imp().user_avatar.remove_css_class("user-inactive");
-                    user_row.imp().user_avatar.add_css_class("user-pending");
+                let (avatar, user_data) = self.get_row_data(&row_data);
+                if user_data == target_user {
+                    avatar.remove_css_class("user-selected");
+                    avatar.remove_css_class("user-inactive");
+                    avatar.add_css_class("user-pending");
                     break;
                 }
             }
         }
     }
 
-    /// Iters through every UserObject and tries to reconnect to the WebSocket server
+    /// Shows a short notification at the bottom of the window
+    pub fn show_toast(&self, message: &str) {
+        let toast = Toast::builder().title(message).timeout(5).build();
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
+    /// Tries to reconnect to the WebSocket server with the connection every UserObject shares
     pub fn reload_user_ws(&self) {
         info!("Reloading websocket connection");
+        self.get_chatting_from().user_ws().reload_manually();
+    }
+
+    /// Opens the chat of every UserObject except the owner after the owner gets authenticated
+    pub fn open_chats(&self) {
         let user_list = self.get_users_liststore();
 
         for user_data in user_list.iter() {
             let user_data: UserObject = user_data.unwrap();
-            user_data.user_ws().reload_manually();
+            if user_data.is_group() {
+                user_data.start_sync();
+            } else if !user_data.is_owner() {
+                user_data.add_queue_to_first(RequestType::ReconnectUser);
+            }
         }
     }
 
@@ -841,15 +1569,232 @@ impl Window {
                 }
                 user_data.stop_signals();
                 self.get_users_liststore().remove(index as u32);
-                user_data
-                    .user_ws()
-                    .emit_by_name::<()>("stop-processing", &[&true]);
                 self.save_user_list();
                 break;
             }
         }
     }
 
+    /// The key the local message cache is encrypted with. None if the owner has no RSA keys yet
+    fn message_cache_key(&self) -> Option<Vec<u8>> {
+        let owner = self.get_chatting_from();
+        let (_, private_key) = owner.rsa_keys()?;
+        Some(derive_cache_key(&private_key))
+    }
+
+    /// Location of the cache file of a chat. Groups and users are saved separately as their IDs can be the same
+    fn message_cache_path(&self, chat: &UserObject) -> String {
+        let saving_location = self.settings().string("location");
+        let chat_type = if chat.is_group() { "group" } else { "user" };
+        format!(
+            "{}message_cache/{}_{}.bin",
+            saving_location,
+            chat_type,
+            chat.user_id()
+        )
+    }
+
+    /// Loads the cached messages of a chat so only the newer messages get synced from the server
+    fn load_message_cache(&self, chat: &UserObject) {
+        let Some(cache_key) = self.message_cache_key() else {
+            return;
+        };
+
+        let Some(cached_messages) = load_chat_cache(&self.message_cache_path(chat), &cache_key)
+        else {
+            return;
+        };
+
+        let (Some(oldest), Some(newest)) = (cached_messages.first(), cached_messages.last()) else {
+            return;
+        };
+        let (oldest, newest) = (oldest.message_number, newest.message_number);
+
+        for cached_message in cached_messages {
+            let message_data = cached_message.into_decrypted();
+            self.search_index()
+                .lock()
+                .unwrap()
+                .add_decrypted(chat.user_id(), &message_data);
+            self.receive_message(message_data, chat.clone(), false);
+        }
+
+        chat.renderer().set_message_number(newest);
+        chat.renderer().set_synced_till(oldest);
+    }
+
+    /// Saves the loaded messages of a chat in the local cache
+    pub fn save_message_cache(&self, chat: &UserObject) {
+        let Some(cache_key) = self.message_cache_key() else {
+            return;
+        };
+
+        let cached_messages = chat.renderer().cached_messages();
+        if cached_messages.is_empty() {
+            return;
+        }
+
+        info!(
+            "Saving {} messages of chat {} to the cache",
+            cached_messages.len(),
+            chat.user_id()
+        );
+        let saved = save_chat_cache(&self.message_cache_path(chat), &cache_key, &cached_messages);
+
+        // The ratchet keys of the cached messages are not needed to decrypt them again anymore
+        if !saved {
+            return;
+        }
+        if let Some(ratchet_store) = self.imp().ratchet_store.get() {
+            ratchet_store.lock().unwrap().forget_unwrapped(
+                chat.user_id(),
+                cached_messages.iter().map(|message| message.message_number),
+            );
+            self.save_ratchet_store();
+        }
+    }
+
+    /// Saves the loaded messages of every chat in the local cache
+    fn save_message_caches(&self) {
+        for chat in self.get_users_liststore().iter::<UserObject>() {
+            let chat = chat.unwrap();
+            if !chat.is_owner() {
+                self.save_message_cache(&chat);
+            }
+        }
+    }
+
+    /// Location of the X3DH keys and ratchet sessions of this device
+    fn ratchet_store_path(&self) -> String {
+        let saving_location = self.settings().string("location");
+        format!("{}ratchet_store.bin", saving_location)
+    }
+
+    /// The X3DH keys and ratchet sessions of this device. Read from the disk or created the first
+    /// time. None if the owner has no RSA keys yet
+    pub fn ratchet_store(&self) -> Option<Arc<Mutex<RatchetStore>>> {
+        if let Some(ratchet_store) = self.imp().ratchet_store.get() {
+            return Some(ratchet_store.clone());
+        }
+
+        let cache_key = self.message_cache_key()?;
+        let saved_store =
+            read_encrypted_file(&self.ratchet_store_path(), &cache_key).and_then(|data| {
+                match serde_json::from_slice::<RatchetStore>(&data) {
+                    Ok(ratchet_store) => Some(ratchet_store),
+                    Err(e) => {
+                        error!("Failed to read the ratchet store. Reason: {e}");
+                        None
+                    }
+                }
+            });
+
+        let is_new = saved_store.is_none();
+        let mut saved_store = saved_store.unwrap_or_else(RatchetStore::generate);
+        for (user_id, verified_keys) in self.verified_keys() {
+            saved_store.pin_identities(user_id, &verified_keys.identities);
+        }
+
+        let ratchet_store = self
+            .imp()
+            .ratchet_store
+            .get_or_init(|| Arc::new(Mutex::new(saved_store)))
+            .clone();
+
+        if is_new {
+            self.save_ratchet_store();
+        }
+        Some(ratchet_store)
+    }
+
+    /// Saves the ratchet store encrypted with the same key as the message cache
+    pub fn save_ratchet_store(&self) {
+        let (Some(ratchet_store), Some(cache_key)) =
+            (self.imp().ratchet_store.get(), self.message_cache_key())
+        else {
+            return;
+        };
+
+        let to_save = serde_json::to_vec(&*ratchet_store.lock().unwrap()).unwrap();
+        save_encrypted_file(&self.ratchet_store_path(), &cache_key, &to_save);
+    }
+
+    /// Location of the RSA private keys this device used before rotating
+    fn keyring_path(&self) -> String {
+        let saving_location = self.settings().string("location");
+        format!("{}keyring.bin", saving_location)
+    }
+
+    /// The RSA private keys this device used before rotating. Read from the disk the first time
+    pub fn keyring(&self) -> Keyring {
+        if let Some(keyring) = self.imp().keyring.borrow().clone() {
+            return keyring;
+        }
+
+        let Some(cache_key) = self.message_cache_key() else {
+            return Keyring::default();
+        };
+        let keyring = read_encrypted_file(&self.keyring_path(), &cache_key)
+            .and_then(|data| match serde_json::from_slice::<Keyring>(&data) {
+                Ok(keyring) => Some(keyring),
+                Err(e) => {
+                    error!("Failed to read the keyring. Reason: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        self.imp().keyring.replace(Some(keyring.clone()));
+        keyring
+    }
+
+    fn save_keyring(&self) {
+        let (Some(keyring), Some(cache_key)) = (
+            self.imp().keyring.borrow().clone(),
+            self.message_cache_key(),
+        ) else {
+            return;
+        };
+
+        let to_save = serde_json::to_vec(&keyring).unwrap();
+        save_encrypted_file(&self.keyring_path(), &cache_key, &to_save);
+    }
+
+    /// Switches every chat to the rotated RSA keys of this device. The replaced private key goes
+    /// to the keyring and the files encrypted with the key derived from it get saved again
+    pub fn replace_rsa_keys(
+        &self,
+        public_key: RsaPublicKey,
+        private_key: RsaPrivateKey,
+        old_key_id: u64,
+    ) {
+        let Some((_, old_private_key)) = self.get_chatting_from().rsa_keys() else {
+            return;
+        };
+
+        // Both must be read with the old cache key before it changes
+        let mut keyring = self.keyring();
+        self.ratchet_store();
+
+        keyring.add_old_key(old_key_id, &old_private_key);
+        self.imp().keyring.replace(Some(keyring));
+
+        for user in self.get_users_liststore().iter::<UserObject>() {
+            user.unwrap()
+                .set_rsa_keys(public_key.clone(), private_key.clone());
+        }
+
+        self.save_rsa_keys();
+        self.save_keyring();
+        self.save_ratchet_store();
+        self.save_message_caches();
+    }
+
+    /// The word index used for searching the decrypted messages
+    pub fn search_index(&self) -> Arc<Mutex<SearchIndex>> {
+        self.imp().search_index.clone()
+    }
+
     /// Scroll to the bottom of the ListView if the given user is selected
     pub fn scroll_to_bottom(&self, current_user: UserObject, reveal_message: bool) {
         if current_user == self.get_chatting_with() {
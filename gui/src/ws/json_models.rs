@@ -1,7 +1,9 @@
-use gio::subclass::prelude::ObjectSubclassIsExt;
-use serde::{Deserialize, Serialize};
+use chirp_protocol::{
+    Attachment, ErrorResponse, MessageData, Reaction, ReceiptStatus, ServerResponse,
+};
+use gtk::glib::SourceId;
+use rsa::{RsaPrivateKey, RsaPublicKey};
 
-use crate::encryption::stringify_rsa_public;
 use crate::message::MessageObject;
 use crate::user::UserObject;
 
@@ -14,291 +16,73 @@ pub enum RequestType {
     NameUpdated(String),
     // Broadcast image update to the WS
     ImageUpdated(Option<String>),
-    // Try to reconnect with the WS again
+    // Try to reconnect with the WS again. Authenticates the owner or opens the chat of other users
     ReconnectUser,
     // Send a message to another user
     SendMessage(MessageData, MessageObject),
     // Ask the WS for a specific user info
     GetUserData(u64),
+    // Ask the WS for the info of a user that sent a message but was not added yet
+    GetSenderData(u64),
     // Broadcast new user selection to the WS
     GetLastMessageNumber(UserObject),
     // Ask the WS to send messages within a given range
     SyncMessage(u64, u64),
+    // Ask the WS for a replied message that is not loaded to show it as a quote
+    GetRepliedMessage(u64),
+    // React to the message with the given number
+    AddReaction(u64, String),
+    // Remove the reaction of the owner from the message with the given number
+    RemoveReaction(u64),
     // Ask the WS to delete a message
     DeleteMessage(u64, u64),
     // Ask the WS to send deleted messages within a given range
     SyncDeletedMessage(u64, u64),
-}
-
-/// Used for sending or receiving relevant data to create an UserObject
-/// An optional message field to receive messages along with the user data
-#[derive(Serialize, Deserialize)]
-pub struct FullUserData {
-    pub user_id: u64,
-    pub user_name: String,
-    pub image_link: Option<String>,
-    pub user_token: String,
-    pub rsa_public_key: String,
-}
-
-impl FullUserData {
-    pub fn new(user_object: &UserObject) -> Self {
-        let user_token = if user_object.imp().user_token.get().is_some() {
-            user_object.user_token()
-        } else {
-            String::new()
-        };
-
-        let rsa_public_key = user_object.imp().rsa_public.get().unwrap();
-
-        FullUserData {
-            user_id: user_object.user_id(),
-            user_name: user_object.name(),
-            image_link: user_object.image_link(),
-            user_token,
-            rsa_public_key: stringify_rsa_public(rsa_public_key),
-        }
-    }
-
-    pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
-    }
-
-    pub fn empty_token(self) -> Self {
-        FullUserData {
-            user_id: self.user_id,
-            user_name: self.user_name,
-            image_link: self.image_link,
-            user_token: String::new(),
-            rsa_public_key: self.rsa_public_key,
-        }
-    }
-
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct UserIDs {
-    pub user_id: u64,
-    pub user_token: String,
-}
-
-impl UserIDs {
-    pub fn new_json(user_id: u64, user_token: String) -> String {
-        let id_data = UserIDs {
-            user_id,
-            user_token,
-        };
-        serde_json::to_string(&id_data).unwrap()
-    }
-
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct MessageData {
-    pub created_at: String,
-    pub from_user: u64,
-    pub to_user: u64,
-    pub sender_message: Option<Vec<u8>>,
-    pub receiver_message: Option<Vec<u8>>,
-    pub sender_key: Option<Vec<u8>>,
-    pub receiver_key: Option<Vec<u8>>,
-    pub sender_nonce: Option<Vec<u8>>,
-    pub receiver_nonce: Option<Vec<u8>>,
-    pub message_number: u64,
-    #[serde(skip_deserializing)]
-    pub user_token: String,
-}
-
-impl MessageData {
-    pub fn new_incomplete(created_at: String, from_user: u64, to_user: u64) -> Self {
-        MessageData {
-            created_at,
-            from_user,
-            to_user,
-            sender_message: None,
-            receiver_message: None,
-            sender_key: None,
-            receiver_key: None,
-            sender_nonce: None,
-            receiver_nonce: None,
-            message_number: 0,
-            user_token: String::new(),
-        }
-    }
-
-    pub fn update_message(
-        self,
-        sender_message: Vec<u8>,
-        receiver_message: Vec<u8>,
-        sender_key: Vec<u8>,
-        receiver_key: Vec<u8>,
-        sender_nonce: Vec<u8>,
-        receiver_nonce: Vec<u8>,
-    ) -> Self {
-        MessageData {
-            created_at: self.created_at,
-            from_user: self.from_user,
-            to_user: self.to_user,
-            sender_message: Some(sender_message),
-            receiver_message: Some(receiver_message),
-            sender_key: Some(sender_key),
-            receiver_key: Some(receiver_key),
-            sender_nonce: Some(sender_nonce),
-            receiver_nonce: Some(receiver_nonce),
-            message_number: self.message_number,
-            user_token: self.user_token,
-        }
-    }
-
-    pub fn update_token(self, user_token: String) -> Self {
-        MessageData {
-            created_at: self.created_at,
-            from_user: self.from_user,
-            to_user: self.to_user,
-            sender_message: self.sender_message,
-            receiver_message: self.receiver_message,
-            sender_key: self.sender_key,
-            receiver_key: self.receiver_key,
-            sender_nonce: self.sender_nonce,
-            receiver_nonce: self.receiver_nonce,
-            message_number: self.message_number,
-            user_token,
-        }
-    }
-
-    pub fn update_message_number(self, message_number: u64) -> Self {
-        MessageData {
-            created_at: self.created_at,
-            from_user: self.from_user,
-            to_user: self.to_user,
-            sender_message: self.sender_message,
-            receiver_message: self.receiver_message,
-            sender_key: self.sender_key,
-            receiver_key: self.receiver_key,
-            sender_nonce: self.sender_nonce,
-            receiver_nonce: self.receiver_nonce,
-            message_number,
-            user_token: self.user_token,
-        }
-    }
-
-    pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
-    }
-
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct ImageUpdate {
-    pub image_link: Option<String>,
-    #[serde(skip_deserializing)]
-    user_token: String,
-}
-
-impl ImageUpdate {
-    pub fn new_json(image_link: Option<String>, user_token: String) -> String {
-        let data = ImageUpdate {
-            image_link,
-            user_token,
-        };
-        serde_json::to_string(&data).unwrap()
-    }
-
-    pub fn new_from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Serialize)]
-pub struct NameUpdate {
-    new_name: String,
-    user_token: String,
-}
-
-impl NameUpdate {
-    pub fn new_json(new_name: String, user_token: String) -> String {
-        let data = NameUpdate {
-            new_name,
-            user_token,
-        };
-        serde_json::to_string(&data).unwrap()
-    }
-}
-
-#[derive(Serialize)]
-pub struct MessageSyncRequest {
-    user_id: u64,
-    start_at: u64,
-    end_at: u64,
-    user_token: String,
-}
-
-impl MessageSyncRequest {
-    pub fn new_json(user_id: u64, start_at: u64, end_at: u64, user_token: String) -> String {
-        let data = MessageSyncRequest {
-            user_id,
-            start_at,
-            end_at,
-            user_token,
-        };
-        serde_json::to_string(&data).unwrap()
-    }
-}
-
-#[derive(Deserialize)]
-pub struct MessageSyncData {
-    pub message_data: Vec<MessageData>,
-    pub last_message_number: u64,
-    pub start_at: u64,
-    pub ends_at: u64,
-}
-
-impl MessageSyncData {
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Deserialize)]
-pub struct DeletedMessageData {
-    pub message_numbers: Vec<u64>,
-}
-
-impl DeletedMessageData {
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct DeleteMessage {
-    user_id: u64,
-    pub message_number: u64,
-    #[serde(skip_deserializing)]
-    user_token: String,
-}
-
-impl DeleteMessage {
-    pub fn new_json(user_id: u64, message_number: u64, user_token: String) -> String {
-        let data = DeleteMessage {
-            user_id,
-            message_number,
-            user_token,
-        };
-        serde_json::to_string(&data).unwrap()
-    }
-    pub fn from_json(data: &str) -> Self {
-        serde_json::from_str(data).unwrap()
-    }
+    // Replace the text of a sent message with the given number
+    EditMessage(u64, String, MessageObject),
+    // Ask the WS to send edited messages within a given range
+    SyncEditedMessage(u64, u64),
+    // Ask the WS for the earlier versions of a message
+    GetEditHistory(MessageObject),
+    // Ask the WS for a new token and invalidate the old one
+    RotateToken,
+    // Replace the RSA key of this device with the given newly generated pair
+    RotateKey(RsaPublicKey, RsaPrivateKey),
+    // Register the RSA key of this device with the owner on the WS
+    RegisterDevice,
+    // Publish the prekeys of this device along with the given number of new one-time prekeys
+    PublishPreKeys(usize),
+    // Ask the WS for the prekey bundles of this user to start ratchet sessions with the devices
+    GetPreKeyBundles,
+    // Create a new group with the given name
+    CreateGroup(String),
+    // Get every group the owner is a member of
+    GetGroups,
+    // Add a user to this group
+    AddGroupMember(u64),
+    // Remove a user from this group. Removing the owner leaves the group
+    RemoveGroupMember(u64),
+    // Tell the WS every message from this user up to the number reached the status
+    MessageReceipt(ReceiptStatus, u64),
+}
+
+/// Reasons a request to the WS did not get the expected reply
+#[derive(Debug)]
+pub enum RequestFailure {
+    // The server replied with an error
+    Server(ErrorResponse),
+    // The server replied with a different response than expected
+    UnexpectedResponse(ServerResponse),
+    // No reply was received in time
+    TimedOut,
+    // The connection closed before a reply was received
+    ConnectionLost,
+}
+
+/// A sent request that is waiting for its reply
+pub struct PendingRequest {
+    pub callback: Box<dyn FnOnce(Result<ServerResponse, RequestFailure>)>,
+    pub timeout_id: SourceId,
 }
 
 pub struct DecryptedMessageData {
@@ -308,24 +92,27 @@ pub struct DecryptedMessageData {
     pub message: Option<String>,
     pub message_number: u64,
     pub used_aes_key: Vec<u8>,
+    pub receipt: Option<ReceiptStatus>,
+    pub edited_at: Option<String>,
+    pub reply_to: Option<u64>,
+    pub reactions: Vec<Reaction>,
+    pub attachment: Option<Attachment>,
 }
 
 impl DecryptedMessageData {
-    pub fn new(
-        created_at: String,
-        from_user: u64,
-        to_user: u64,
-        message: String,
-        message_number: u64,
-        used_aes_key: Vec<u8>,
-    ) -> Self {
+    pub fn new(message_data: MessageData, message: String, used_aes_key: Vec<u8>) -> Self {
         DecryptedMessageData {
-            created_at,
-            from_user,
-            to_user,
+            created_at: message_data.created_at,
+            from_user: message_data.from_user,
+            to_user: message_data.to_user,
             message: Some(message),
-            message_number,
+            message_number: message_data.message_number,
             used_aes_key,
+            receipt: message_data.receipt,
+            edited_at: message_data.edited_at,
+            reply_to: message_data.reply_to,
+            reactions: message_data.reactions,
+            attachment: message_data.attachment,
         }
     }
 
@@ -337,6 +124,11 @@ impl DecryptedMessageData {
             message: None,
             message_number: message_data.message_number,
             used_aes_key: Vec::new(),
+            receipt: message_data.receipt,
+            edited_at: message_data.edited_at,
+            reply_to: message_data.reply_to,
+            reactions: message_data.reactions,
+            attachment: message_data.attachment,
         }
     }
 
@@ -348,6 +140,11 @@ impl DecryptedMessageData {
             message: self.message,
             message_number: 0,
             used_aes_key: self.used_aes_key,
+            receipt: self.receipt,
+            edited_at: self.edited_at,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            attachment: self.attachment,
         }
     }
 }
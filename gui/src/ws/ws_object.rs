@@ -7,6 +7,9 @@ mod imp {
     use gtk::glib;
     use soup::WebsocketConnection;
     use std::cell::{Cell, OnceCell, RefCell};
+    use std::collections::HashMap;
+
+    use crate::ws::PendingRequest;
 
     #[derive(Properties, Default)]
     #[properties(wrapper_type = super::WSObject)]
@@ -24,6 +27,11 @@ mod imp {
         pub manually_reloaded: Cell<bool>,
         #[property(get, set)]
         pub stop_processing: Cell<bool>,
+        // Whether the owner was authenticated on the current connection
+        #[property(get, set)]
+        pub authenticated: Cell<bool>,
+        pub last_request_id: Cell<u64>,
+        pub pending_requests: RefCell<HashMap<u64, PendingRequest>>,
         pub signal_ids: RefCell<Vec<SignalHandlerId>>,
     }
 
@@ -52,10 +60,17 @@ mod imp {
 }
 
 use adw::subclass::prelude::*;
+use chirp_protocol::{
+    Attachment, AttachmentChunk, ClientRequest, DeleteMessage, DeletedMessageData, DeviceKey,
+    EditHistoryRequest, FullUserData, GroupData, GroupMember, Handshake, ImageUpdate, MessageAck,
+    MessageData, MessageReaction, MessageReceipt, MessageSyncData, MessageSyncRequest, NameUpdate,
+    NewGroup, PreKeyBundle, PreKeyUpload, RequestFrame, ResponseFrame, ServerResponse, UserIDs,
+    PROTOCOL_VERSION,
+};
 use gio::Cancellable;
 use glib::{
-    clone, closure_local, timeout_add_seconds_local, wrapper, ControlFlow, MainContext, Object,
-    Priority, SignalHandlerId,
+    clone, closure_local, timeout_add_seconds_local, timeout_add_seconds_local_once, wrapper,
+    ControlFlow, MainContext, Object, Priority, SignalHandlerId,
 };
 use gtk::glib;
 use gtk::prelude::*;
@@ -64,6 +79,11 @@ use soup::{Message, Session, WebsocketConnection};
 use std::env;
 use tracing::{debug, error, info};
 
+use crate::ws::{PendingRequest, RequestFailure};
+
+/// Seconds to wait for a reply before a request is considered failed
+const REQUEST_TIMEOUT: u32 = 30;
+
 wrapper! {
     pub struct WSObject(ObjectSubclass<imp::WSObject>);
 }
@@ -143,8 +163,11 @@ impl WSObject {
                     return ControlFlow::Break
                 }
                 if conn.is_some() {
+                    ws_object.set_authenticated(false);
                     ws_object.set_ws_conn(Some(conn.unwrap()));
                     info!("WebSocket connection success");
+                    // Every other request gets discarded by the server before the handshake
+                    ws_object.handshake();
                     ws_object.emit_by_name::<()>("ws-success", &[&true]);
                     ws_object.start_pinging();
                     ws_object.set_last_timer(10);
@@ -217,6 +240,8 @@ impl WSObject {
                     info!("disconnecting ping connection");
                     conn.disconnect(id);
                 };
+                ws.set_authenticated(false);
+                ws.fail_pending_requests();
                 ws.connect_to_ws();
                 ws.set_ws_conn(None::<WebsocketConnection>);
 
@@ -238,81 +263,527 @@ impl WSObject {
         self.connect_to_ws();
     }
 
-    /// Sends a message
-    pub fn send_text_message(&self, message: &str) {
+    /// Sends a request to the WS wrapped in a frame with a new request ID
+    fn send_request(&self, request: ClientRequest) -> u64 {
+        let request_id = self.imp().last_request_id.get() + 1;
+        self.imp().last_request_id.set(request_id);
+
+        let frame = RequestFrame::new(request_id, request);
+        self.ws_conn().unwrap().send_text(&frame.to_json());
+        request_id
+    }
+
+    /// Sends a request and saves the callback to call once the reply with the same request ID arrives.
+    /// `extract` returns the expected data out of the reply or gives back the response if it was unexpected
+    fn send_request_with_reply<T: 'static>(
+        &self,
+        request: ClientRequest,
+        extract: fn(ServerResponse) -> Result<T, ServerResponse>,
+        callback: impl FnOnce(Result<T, RequestFailure>) + 'static,
+    ) {
+        let request_id = self.send_request(request);
+
+        let callback = Box::new(move |result: Result<ServerResponse, RequestFailure>| {
+            let result = result.and_then(|response| match response {
+                ServerResponse::Error(error_data) => Err(RequestFailure::Server(error_data)),
+                response => extract(response).map_err(RequestFailure::UnexpectedResponse),
+            });
+            callback(result);
+        });
+
+        let timeout_id = timeout_add_seconds_local_once(
+            REQUEST_TIMEOUT,
+            clone!(@weak self as ws => move || {
+                let pending = ws.imp().pending_requests.borrow_mut().remove(&request_id);
+                if let Some(pending) = pending {
+                    error!("No reply received for request {request_id}");
+                    (pending.callback)(Err(RequestFailure::TimedOut));
+                }
+            }),
+        );
+
+        self.imp().pending_requests.borrow_mut().insert(
+            request_id,
+            PendingRequest {
+                callback,
+                timeout_id,
+            },
+        );
+    }
+
+    /// Passes the response to the callback of the request it replies to.
+    /// Returns the response if no request is waiting for it
+    pub fn resolve_response(&self, frame: ResponseFrame) -> Option<ServerResponse> {
+        let pending = frame
+            .request_id
+            .and_then(|request_id| self.imp().pending_requests.borrow_mut().remove(&request_id));
+
+        if let Some(pending) = pending {
+            pending.timeout_id.remove();
+            (pending.callback)(Ok(frame.response));
+            None
+        } else {
+            Some(frame.response)
+        }
+    }
+
+    /// Fails every request that is waiting for a reply
+    fn fail_pending_requests(&self) {
+        let pending_requests: Vec<PendingRequest> = self
+            .imp()
+            .pending_requests
+            .borrow_mut()
+            .drain()
+            .map(|(_, pending)| pending)
+            .collect();
+
+        for pending in pending_requests {
+            pending.timeout_id.remove();
+            (pending.callback)(Err(RequestFailure::ConnectionLost));
+        }
+    }
+
+    /// Sends the protocol version to the WS. Must be the first request of every connection
+    pub fn handshake(&self) {
+        info!("Sending handshake request to WS");
+        self.send_request_with_reply(
+            ClientRequest::Handshake(Handshake::new(PROTOCOL_VERSION)),
+            |response| match response {
+                ServerResponse::HandshakeAccepted => Ok(()),
+                response => Err(response),
+            },
+            clone!(@weak self as ws => move |result| {
+                match result {
+                Ok(()) => info!("Handshake accepted by the server"),
+                Err(RequestFailure::UnexpectedResponse(ServerResponse::VersionMismatch(mismatch))) => {
+                    error!(
+                        "Server protocol version {} does not match client version {}. Stopping the connection",
+                        mismatch.server_version, mismatch.client_version
+                    );
+                    ws.emit_by_name::<()>("stop-processing", &[&true]);
+                }
+                Err(e) => error!("Handshake failed. Reason: {e:?}"),
+                }
+            }),
+        );
+    }
+
+    /// Sends a message. The reply has the number the server gave to the message
+    pub fn send_text_message(
+        &self,
+        message: MessageData,
+        callback: impl FnOnce(Result<MessageAck, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS to process message");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/message {}", message));
+        self.send_request_with_reply(
+            ClientRequest::SendMessage(message),
+            |response| match response {
+                ServerResponse::MessageSent(ack) => Ok(ack),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Sends a message to every member of a group. The reply has the number the server gave to the message
+    pub fn send_group_message(
+        &self,
+        message: MessageData,
+        callback: impl FnOnce(Result<MessageAck, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to process group message");
+        self.send_request_with_reply(
+            ClientRequest::SendGroupMessage(message),
+            |response| match response {
+                ServerResponse::MessageSent(ack) => Ok(ack),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to create a new group with the owner as a member
+    pub fn create_group(
+        &self,
+        group_data: NewGroup,
+        callback: impl FnOnce(Result<GroupData, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to create a new group");
+        self.send_request_with_reply(
+            ClientRequest::CreateGroup(group_data),
+            |response| match response {
+                ServerResponse::GroupCreated(group_data) => Ok(group_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to get every group the owner is a member of
+    pub fn get_groups(
+        &self,
+        callback: impl FnOnce(Result<Vec<GroupData>, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to get the groups");
+        self.send_request_with_reply(
+            ClientRequest::GetGroups,
+            |response| match response {
+                ServerResponse::Groups(groups) => Ok(groups),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to add a new member to a group
+    pub fn add_group_member(&self, data: GroupMember) {
+        info!("Sending request to WS to add a group member");
+        self.send_request(ClientRequest::AddGroupMember(data));
+    }
+
+    /// Calls the server to remove a member of a group
+    pub fn remove_group_member(&self, data: GroupMember) {
+        info!("Sending request to WS to remove a group member");
+        self.send_request(ClientRequest::RemoveGroupMember(data));
     }
 
     /// Calls the server to create a new user with the given data
-    pub fn create_new_user(&self, user_data: String) {
+    pub fn create_new_user(
+        &self,
+        user_data: FullUserData,
+        callback: impl FnOnce(Result<UserIDs, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS to create a new user");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/create-new-user {}", user_data));
+        self.send_request_with_reply(
+            ClientRequest::CreateNewUser(user_data),
+            |response| match response {
+                ServerResponse::UpdateUserId(id_data) => Ok(id_data),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
     /// Calls the server to get profile data of a user
-    pub fn get_user_data(&self, data: &str) {
+    pub fn get_user_data(
+        &self,
+        user_id: u64,
+        callback: impl FnOnce(Result<FullUserData, RequestFailure>) + 'static,
+    ) {
         info!("Sending request for getting UserObject Data");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/get-user-data {}", data))
+        self.send_request_with_reply(
+            ClientRequest::GetUserData(user_id),
+            |response| match response {
+                ServerResponse::GetUserData(user_data) => Ok(user_data),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
     /// Calls the server to update the user image link
-    pub fn image_link_updated(&self, link: &str) {
+    pub fn image_link_updated(&self, data: ImageUpdate) {
         info!("Sending request to WS to update image link");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/image-updated {}", link))
+        self.send_request(ClientRequest::ImageUpdated(data));
     }
 
     /// Calls the server to update the user name
-    pub fn name_updated(&self, name: &str) {
+    pub fn name_updated(&self, data: NameUpdate) {
         info!("Sending request to WS update name");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/name-updated {}", name))
+        self.send_request(ClientRequest::NameUpdated(data));
     }
 
     /// Connects to the WS to reconnect with previously server deleted user data
-    pub fn reconnect_user(&self, id_data: String) {
+    pub fn reconnect_user(
+        &self,
+        id_data: UserIDs,
+        callback: impl FnOnce(Result<FullUserData, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS to reconnect");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/reconnect-user {}", id_data))
+        self.send_request_with_reply(
+            ClientRequest::ReconnectUser(id_data),
+            |response| match response {
+                ServerResponse::ReconnectSuccess(user_data) => Ok(user_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to issue a new token for the owner
+    pub fn rotate_token(&self, callback: impl FnOnce(Result<UserIDs, RequestFailure>) + 'static) {
+        info!("Sending request to WS to rotate the token");
+        self.send_request_with_reply(
+            ClientRequest::RotateToken,
+            |response| match response {
+                ServerResponse::TokenRotated(id_data) => Ok(id_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to register the RSA public key of this device
+    pub fn register_device(
+        &self,
+        device_key: DeviceKey,
+        callback: impl FnOnce(Result<u64, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to register this device");
+        self.send_request_with_reply(
+            ClientRequest::RegisterDevice(device_key),
+            |response| match response {
+                ServerResponse::DeviceRegistered(device_id) => Ok(device_id),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to replace the RSA public key of this device. Replies with the new key ID
+    pub fn rotate_key(
+        &self,
+        device_key: DeviceKey,
+        callback: impl FnOnce(Result<DeviceKey, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to rotate the key of this device");
+        self.send_request_with_reply(
+            ClientRequest::RotateKey(device_key),
+            |response| match response {
+                ServerResponse::KeyRotated(device_key) => Ok(device_key),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to save the prekeys of this device. Replies with the unused one-time prekey count
+    pub fn publish_prekeys(
+        &self,
+        prekey_data: PreKeyUpload,
+        callback: impl FnOnce(Result<u64, RequestFailure>) + 'static,
+    ) {
+        info!(
+            "Sending request to WS to publish {} one-time prekeys",
+            prekey_data.one_time_prekeys.len()
+        );
+        self.send_request_with_reply(
+            ClientRequest::PublishPreKeys(prekey_data),
+            |response| match response {
+                ServerResponse::PreKeysPublished(remaining) => Ok(remaining),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server for the prekey bundles of every device of the user
+    pub fn get_prekey_bundles(
+        &self,
+        user_id: u64,
+        callback: impl FnOnce(Result<Vec<PreKeyBundle>, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to get the prekey bundles of {user_id}");
+        self.send_request_with_reply(
+            ClientRequest::GetPreKeyBundles(user_id),
+            |response| match response {
+                ServerResponse::PreKeyBundles(bundles) => Ok(bundles),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Calls the server to start sending updates of a chat and get the user profile
+    pub fn open_chat(
+        &self,
+        user_id: u64,
+        callback: impl FnOnce(Result<FullUserData, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to open chat with {user_id}");
+        self.send_request_with_reply(
+            ClientRequest::OpenChat(user_id),
+            |response| match response {
+                ServerResponse::ChatOpened(user_data) => Ok(user_data),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
     /// Calls the server to send last chat message number of a user
-    pub fn selection_update(&self, data: String) {
+    pub fn selection_update(
+        &self,
+        user_id: u64,
+        callback: impl FnOnce(Result<u64, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS get the last message number");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/message-number {}", data))
+        self.send_request_with_reply(
+            ClientRequest::MessageNumber(user_id),
+            |response| match response {
+                ServerResponse::MessageNumber(message_number) => Ok(message_number),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
-    pub fn sync_message(&self, data: String) {
+    pub fn sync_message(
+        &self,
+        data: MessageSyncRequest,
+        callback: impl FnOnce(Result<MessageSyncData, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS sync messages");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/sync-message {}", data))
+        self.send_request_with_reply(
+            ClientRequest::SyncMessage(data),
+            |response| match response {
+                ServerResponse::SyncMessage(sync_data) => Ok(sync_data),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
-    pub fn delete_message(&self, data: String) {
+    pub fn message_receipt(&self, receipt: MessageReceipt) {
+        info!(
+            "Sending request to WS to mark messages as {:?}",
+            receipt.status
+        );
+        self.send_request(ClientRequest::MessageReceipt(receipt));
+    }
+
+    pub fn delete_message(&self, data: DeleteMessage) {
         info!("Sending request to WS delete a message");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/delete-message {}", data))
+        self.send_request(ClientRequest::DeleteMessage(data));
+    }
+
+    pub fn typing(&self, user_id: u64) {
+        debug!("Sending request to WS that the owner is typing to {user_id}");
+        self.send_request(ClientRequest::Typing(user_id));
     }
 
-    pub fn sync_deleted_message(&self, data: String) {
+    pub fn add_reaction(&self, data: MessageReaction) {
+        info!("Sending request to WS to react to a message");
+        self.send_request(ClientRequest::AddReaction(data));
+    }
+
+    pub fn remove_reaction(&self, data: MessageReaction) {
+        info!("Sending request to WS to remove a reaction");
+        self.send_request(ClientRequest::RemoveReaction(data));
+    }
+
+    pub fn sync_deleted_message(
+        &self,
+        data: MessageSyncRequest,
+        callback: impl FnOnce(Result<DeletedMessageData, RequestFailure>) + 'static,
+    ) {
         info!("Sending request to WS sync deleted messages");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/sync-deleted-message {}", data))
+        self.send_request_with_reply(
+            ClientRequest::SyncDeletedMessage(data),
+            |response| match response {
+                ServerResponse::SyncDeletedMessage(deleted_data) => Ok(deleted_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    pub fn edit_message(
+        &self,
+        data: MessageData,
+        callback: impl FnOnce(Result<MessageData, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to edit a message");
+        self.send_request_with_reply(
+            ClientRequest::EditMessage(data),
+            |response| match response {
+                ServerResponse::EditMessage(message_data) => Ok(message_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    pub fn sync_edited_message(
+        &self,
+        data: MessageSyncRequest,
+        callback: impl FnOnce(Result<MessageSyncData, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS sync edited messages");
+        self.send_request_with_reply(
+            ClientRequest::SyncEditedMessage(data),
+            |response| match response {
+                ServerResponse::SyncEditedMessage(sync_data) => Ok(sync_data),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    pub fn get_edit_history(
+        &self,
+        data: EditHistoryRequest,
+        callback: impl FnOnce(Result<Vec<MessageData>, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS for the edit history of a message");
+        self.send_request_with_reply(
+            ClientRequest::GetEditHistory(data),
+            |response| match response {
+                ServerResponse::EditHistory(history) => Ok(history),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Starts uploading an encrypted attachment. The reply has the ID the chunks must be sent with
+    pub fn start_upload(
+        &self,
+        data: Attachment,
+        callback: impl FnOnce(Result<String, RequestFailure>) + 'static,
+    ) {
+        info!("Sending request to WS to start uploading an attachment");
+        self.send_request_with_reply(
+            ClientRequest::StartUpload(data),
+            |response| match response {
+                ServerResponse::UploadStarted(attachment_id) => Ok(attachment_id),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    /// Uploads the next chunk of an attachment. The reply has the total bytes saved so far
+    pub fn upload_chunk(
+        &self,
+        data: AttachmentChunk,
+        callback: impl FnOnce(Result<u64, RequestFailure>) + 'static,
+    ) {
+        debug!("Sending request to WS to upload an attachment chunk");
+        self.send_request_with_reply(
+            ClientRequest::UploadChunk(data),
+            |response| match response {
+                ServerResponse::ChunkUploaded(uploaded_size) => Ok(uploaded_size),
+                response => Err(response),
+            },
+            callback,
+        );
+    }
+
+    pub fn get_attachment_chunk(
+        &self,
+        data: AttachmentChunk,
+        callback: impl FnOnce(Result<AttachmentChunk, RequestFailure>) + 'static,
+    ) {
+        debug!("Sending request to WS for an attachment chunk");
+        self.send_request_with_reply(
+            ClientRequest::GetAttachmentChunk(data),
+            |response| match response {
+                ServerResponse::AttachmentChunk(chunk) => Ok(chunk),
+                response => Err(response),
+            },
+            callback,
+        );
     }
 
     /// Saves the signal ID of the Websocket Message Signal
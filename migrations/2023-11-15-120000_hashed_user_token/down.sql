@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+-- Plaintext tokens cannot be recovered from the hashes so every user has to register again
+ALTER TABLE users
+ADD COLUMN user_token VARCHAR(70) UNIQUE;
+
+ALTER TABLE users
+DROP COLUMN token_id,
+DROP COLUMN token_salt,
+DROP COLUMN token_hash;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE users
+ADD COLUMN token_id VARCHAR(16),
+ADD COLUMN token_salt VARCHAR(32),
+ADD COLUMN token_hash VARCHAR(64);
+
+UPDATE users
+SET token_id = LEFT(user_token, 16),
+    token_salt = md5(random()::text);
+
+UPDATE users
+SET token_hash = encode(sha256(convert_to(token_salt || SUBSTRING(user_token FROM 17), 'UTF8')), 'hex');
+
+ALTER TABLE users
+ALTER COLUMN token_id SET NOT NULL,
+ALTER COLUMN token_salt SET NOT NULL,
+ALTER COLUMN token_hash SET NOT NULL,
+ADD CONSTRAINT users_token_id_key UNIQUE (token_id),
+DROP COLUMN user_token;
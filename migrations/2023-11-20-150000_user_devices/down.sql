@@ -0,0 +1,44 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+ADD COLUMN rsa_public_key TEXT;
+ALTER TABLE messages
+ADD COLUMN sender_key BYTEA,
+    ADD COLUMN receiver_key BYTEA;
+
+-- Only the first device of every user is kept
+UPDATE users
+SET rsa_public_key = (
+        SELECT rsa_public_key
+        FROM devices
+        WHERE devices.user_id = users.user_id
+        ORDER BY device_id
+        LIMIT 1
+    );
+UPDATE users
+SET rsa_public_key = ''
+WHERE rsa_public_key IS NULL;
+ALTER TABLE users
+ALTER COLUMN rsa_public_key SET NOT NULL;
+
+UPDATE messages
+SET sender_key = (
+        SELECT encrypted_key
+        FROM message_keys
+            JOIN devices ON devices.device_id = message_keys.device_id
+        WHERE message_keys.message_id = messages.message_id
+            AND devices.user_id = messages.message_sender
+        ORDER BY devices.device_id
+        LIMIT 1
+    ),
+    receiver_key = (
+        SELECT encrypted_key
+        FROM message_keys
+            JOIN devices ON devices.device_id = message_keys.device_id
+        WHERE message_keys.message_id = messages.message_id
+            AND devices.user_id = messages.message_receiver
+        ORDER BY devices.device_id
+        LIMIT 1
+    );
+
+DROP TABLE message_keys;
+DROP TABLE devices;
@@ -0,0 +1,39 @@
+-- Your SQL goes here
+CREATE TABLE devices (
+    device_id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    rsa_public_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE
+);
+CREATE INDEX devices_user_id_idx ON devices (user_id);
+
+INSERT INTO devices (user_id, rsa_public_key)
+SELECT user_id, rsa_public_key FROM users;
+
+CREATE TABLE message_keys (
+    message_id INT NOT NULL,
+    device_id INT NOT NULL,
+    encrypted_key BYTEA NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages (message_id) ON DELETE CASCADE,
+    FOREIGN KEY (device_id) REFERENCES devices (device_id) ON DELETE CASCADE,
+    PRIMARY KEY (message_id, device_id)
+);
+
+INSERT INTO message_keys (message_id, device_id, encrypted_key)
+SELECT messages.message_id, devices.device_id, messages.sender_key
+FROM messages
+    JOIN devices ON devices.user_id = messages.message_sender
+WHERE messages.sender_key IS NOT NULL;
+
+-- Messages sent to self already got the key of the sender
+INSERT INTO message_keys (message_id, device_id, encrypted_key)
+SELECT messages.message_id, devices.device_id, messages.receiver_key
+FROM messages
+    JOIN devices ON devices.user_id = messages.message_receiver
+WHERE messages.receiver_key IS NOT NULL
+    AND messages.message_sender <> messages.message_receiver;
+
+ALTER TABLE messages DROP COLUMN sender_key,
+    DROP COLUMN receiver_key;
+ALTER TABLE users DROP COLUMN rsa_public_key;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM messages
+WHERE message_receiver IS NULL;
+ALTER TABLE messages
+ALTER COLUMN message_receiver SET NOT NULL;
+
+DROP TABLE conversation_members;
+DROP TABLE conversations;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- Group IDs are picked from the same range as user IDs and never overlap with them
+CREATE TABLE conversations (
+    conversation_id INT PRIMARY KEY,
+    conversation_name VARCHAR(250) NOT NULL,
+    created_by INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (created_by) REFERENCES users (user_id)
+);
+
+CREATE TABLE conversation_members (
+    conversation_id INT NOT NULL,
+    user_id INT NOT NULL,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (conversation_id) REFERENCES conversations (conversation_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE,
+    PRIMARY KEY (conversation_id, user_id)
+);
+CREATE INDEX conversation_members_user_id_idx ON conversation_members (user_id);
+
+-- Group messages have no single receiver
+ALTER TABLE messages
+ALTER COLUMN message_receiver DROP NOT NULL;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- Chats with messages that arrived while every session of the user was offline
+CREATE TABLE pending_notifications (
+    user_id INT NOT NULL,
+    sender_id INT NOT NULL,
+    unread_count INT NOT NULL DEFAULT 1,
+    last_message_number INT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE,
+    FOREIGN KEY (sender_id) REFERENCES users (user_id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, sender_id)
+);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Only direct messages get receipts
+ALTER TABLE messages
+ADD COLUMN delivered_at TIMESTAMPTZ,
+    ADD COLUMN read_at TIMESTAMPTZ;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE revision_keys;
+DROP TABLE message_revisions;
+
+ALTER TABLE messages DROP COLUMN edited_at;
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN edited_at TIMESTAMPTZ;
+
+-- Earlier versions of edited messages. Each version keeps the keys it was encrypted with
+CREATE TABLE message_revisions (
+    revision_id SERIAL PRIMARY KEY,
+    message_id INT NOT NULL,
+    sender_message BYTEA NOT NULL,
+    receiver_message BYTEA,
+    sender_nonce BYTEA NOT NULL,
+    receiver_nonce BYTEA,
+    written_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages (message_id) ON DELETE CASCADE
+);
+CREATE INDEX message_revisions_message_id_idx ON message_revisions (message_id);
+
+CREATE TABLE revision_keys (
+    revision_id INT NOT NULL,
+    device_id INT NOT NULL,
+    encrypted_key BYTEA NOT NULL,
+    FOREIGN KEY (revision_id) REFERENCES message_revisions (revision_id) ON DELETE CASCADE,
+    FOREIGN KEY (device_id) REFERENCES devices (device_id) ON DELETE CASCADE,
+    PRIMARY KEY (revision_id, device_id)
+);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- The number of the replied message within the same message group
+ALTER TABLE messages
+ADD COLUMN reply_to INT;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- Each user can have a single reaction on a message
+CREATE TABLE message_reactions (
+    message_group VARCHAR(40) NOT NULL,
+    message_number INT NOT NULL,
+    user_id INT NOT NULL,
+    emoji VARCHAR(32) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (message_group, message_number) REFERENCES messages (message_group, message_number) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE,
+    PRIMARY KEY (message_group, message_number, user_id)
+);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- When the last session of the user disconnected
+ALTER TABLE users
+ADD COLUMN last_seen_at TIMESTAMPTZ;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Encrypted files uploaded by users. The file itself is saved on the disk of the server
+CREATE TABLE attachments (
+    attachment_id VARCHAR(32) PRIMARY KEY,
+    uploaded_by INT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    size BIGINT NOT NULL,
+    uploaded_size BIGINT NOT NULL DEFAULT 0,
+    file_name BYTEA NOT NULL,
+    name_nonce BYTEA NOT NULL,
+    file_nonce BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- A message can carry a single attachment
+ALTER TABLE messages
+ADD COLUMN attachment_id VARCHAR(32) REFERENCES attachments (attachment_id) ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE revision_keys DROP COLUMN ratchet_header;
+ALTER TABLE message_keys DROP COLUMN ratchet_header;
+DROP TABLE one_time_prekeys;
+DROP TABLE device_prekeys;
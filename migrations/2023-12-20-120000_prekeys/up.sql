@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- X3DH keys of a device. Replaced whenever the device publishes new ones
+CREATE TABLE device_prekeys (
+    device_id INT PRIMARY KEY REFERENCES devices (device_id) ON DELETE CASCADE,
+    identity_key BYTEA NOT NULL,
+    signing_key BYTEA NOT NULL,
+    signed_prekey_id BIGINT NOT NULL,
+    signed_prekey BYTEA NOT NULL,
+    signature BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Each one-time prekey is handed out once and then deleted
+CREATE TABLE one_time_prekeys (
+    device_id INT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    key_id BIGINT NOT NULL,
+    public_key BYTEA NOT NULL,
+    PRIMARY KEY (device_id, key_id)
+);
+
+-- Message keys encrypted with a double ratchet carry the ratchet header as JSON
+ALTER TABLE message_keys ADD COLUMN ratchet_header TEXT;
+ALTER TABLE revision_keys ADD COLUMN ratchet_header TEXT;
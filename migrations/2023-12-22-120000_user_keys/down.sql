@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE revision_keys DROP COLUMN key_id;
+ALTER TABLE message_keys DROP COLUMN key_id;
+ALTER TABLE devices DROP COLUMN key_id;
+DROP TABLE user_keys;
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+-- Every RSA public key a device ever had. Old messages stay encrypted with the key of the time
+CREATE TABLE user_keys (
+    key_id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    device_id INT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    rsa_public_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX user_keys_device_id_idx ON user_keys (device_id);
+
+-- The key the device currently uses
+ALTER TABLE devices ADD COLUMN key_id INT NOT NULL DEFAULT 0;
+
+INSERT INTO user_keys (user_id, device_id, rsa_public_key, created_at)
+SELECT user_id, device_id, rsa_public_key, created_at FROM devices;
+
+UPDATE devices SET key_id = user_keys.key_id
+FROM user_keys WHERE user_keys.device_id = devices.device_id;
+
+-- The key of the receiving device a message key was encrypted with. NULL for ratchet keys
+ALTER TABLE message_keys ADD COLUMN key_id INT;
+ALTER TABLE revision_keys ADD COLUMN key_id INT;
+
+-- Every RSA encrypted key so far used the only key the device had
+UPDATE message_keys SET key_id = user_keys.key_id
+FROM user_keys
+WHERE user_keys.device_id = message_keys.device_id AND message_keys.ratchet_header IS NULL;
+
+UPDATE revision_keys SET key_id = user_keys.key_id
+FROM user_keys
+WHERE user_keys.device_id = revision_keys.device_id AND revision_keys.ratchet_header IS NULL;
@@ -0,0 +1,17 @@
+mod models;
+mod request;
+mod response;
+
+pub use models::*;
+pub use request::*;
+pub use response::*;
+
+/// The wire protocol version. Must be bumped every time a request or a response changes shape
+/// so a mismatched client gets rejected during the handshake instead of being misparsed
+pub const PROTOCOL_VERSION: u32 = 20;
+
+/// Largest encrypted attachment the server accepts in bytes
+pub const MAX_ATTACHMENT_SIZE: u64 = 20 * 1024 * 1024;
+
+/// Attachments are uploaded and downloaded in pieces of at most this many bytes
+pub const ATTACHMENT_CHUNK_SIZE: usize = 8 * 1024;
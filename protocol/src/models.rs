@@ -0,0 +1,776 @@
+use serde::{Deserialize, Serialize};
+
+/// The first request every client must send after the WS connection is established
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Handshake {
+    pub protocol_version: u32,
+}
+
+impl Handshake {
+    pub fn new(protocol_version: u32) -> Self {
+        Handshake { protocol_version }
+    }
+}
+
+/// Sent by the server right before closing the connection of a client with a different protocol version
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VersionMismatch {
+    pub server_version: u32,
+    pub client_version: u32,
+}
+
+/// The types of errors the server can reply with
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    // The frame could not be parsed or has missing data
+    MalformedRequest,
+    // A request was sent before a successful handshake
+    HandshakeRequired,
+    // The given user token does not belong to any user
+    InvalidToken,
+    // The request needs a session that was authenticated by reconnecting or creating a user first
+    NotAuthenticated,
+    // The targeted user does not exist
+    UnknownUser,
+    // The device is not registered with the user
+    UnknownDevice,
+    // The targeted group does not exist or the owner is not a member of it
+    UnknownGroup,
+    // The owner is not allowed to do the requested action
+    NotAllowed,
+    // The server failed to process the request with the DB
+    DatabaseError,
+    // The attachment does not exist or the owner cannot read it
+    UnknownAttachment,
+    // The server failed to read or write an attachment file
+    StorageError,
+}
+
+/// Sent back to the session a failed request came from
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        ErrorResponse { code, message }
+    }
+}
+
+/// The RSA public key of one of the devices of a user. Device ID 0 means the device is not registered yet
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeviceKey {
+    pub user_id: u64,
+    pub device_id: u64,
+    pub rsa_public_key: String,
+    // Assigned by the server every time the device gets a new key. The client value is ignored
+    #[serde(default)]
+    pub key_id: u64,
+}
+
+impl DeviceKey {
+    pub fn new(user_id: u64, device_id: u64, rsa_public_key: String) -> Self {
+        DeviceKey {
+            user_id,
+            device_id,
+            rsa_public_key,
+            key_id: 0,
+        }
+    }
+
+    pub fn update_key_id(mut self, key_id: u64) -> Self {
+        self.key_id = key_id;
+        self
+    }
+}
+
+/// The AES key of a message encrypted for a single device. Encrypted with the RSA public key of the
+/// device unless a ratchet header is given
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EncryptedKey {
+    pub device_id: u64,
+    pub encrypted_key: Vec<u8>,
+    #[serde(default)]
+    pub ratchet_header: Option<RatchetHeader>,
+    // ID of the RSA key of the device the key was encrypted with. 0 for ratchet keys
+    #[serde(default)]
+    pub key_id: u64,
+}
+
+impl EncryptedKey {
+    pub fn new(device_id: u64, encrypted_key: Vec<u8>) -> Self {
+        EncryptedKey {
+            device_id,
+            encrypted_key,
+            ratchet_header: None,
+            key_id: 0,
+        }
+    }
+
+    pub fn update_key_id(mut self, key_id: u64) -> Self {
+        self.key_id = key_id;
+        self
+    }
+
+    pub fn update_ratchet_header(mut self, ratchet_header: Option<RatchetHeader>) -> Self {
+        self.ratchet_header = ratchet_header;
+        self
+    }
+}
+
+/// The double ratchet state the sending device encrypted a message key with
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RatchetHeader {
+    pub sender_device: u64,
+    // Current ratchet public key of the sending device
+    pub ratchet_key: Vec<u8>,
+    // Number of keys sent with the previous ratchet key
+    pub previous_count: u32,
+    pub message_index: u32,
+    // Sent until the receiving device replies so it can create the session without being online
+    #[serde(default)]
+    pub initial: Option<InitialHeader>,
+}
+
+impl RatchetHeader {
+    pub fn new(
+        sender_device: u64,
+        ratchet_key: Vec<u8>,
+        previous_count: u32,
+        message_index: u32,
+        initial: Option<InitialHeader>,
+    ) -> Self {
+        RatchetHeader {
+            sender_device,
+            ratchet_key,
+            previous_count,
+            message_index,
+            initial,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
+
+/// The X3DH data a session was started with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct InitialHeader {
+    pub identity_key: Vec<u8>,
+    pub ephemeral_key: Vec<u8>,
+    pub signed_prekey_id: u64,
+    pub one_time_prekey_id: Option<u64>,
+    // Signing key of the starting device. Part of the safety number along with the identity key
+    #[serde(default)]
+    pub signing_key: Vec<u8>,
+}
+
+impl InitialHeader {
+    pub fn new(
+        identity_key: Vec<u8>,
+        ephemeral_key: Vec<u8>,
+        signed_prekey_id: u64,
+        one_time_prekey_id: Option<u64>,
+    ) -> Self {
+        InitialHeader {
+            identity_key,
+            ephemeral_key,
+            signed_prekey_id,
+            one_time_prekey_id,
+            signing_key: Vec::new(),
+        }
+    }
+
+    pub fn update_signing_key(mut self, signing_key: Vec<u8>) -> Self {
+        self.signing_key = signing_key;
+        self
+    }
+}
+
+/// A prekey that is handed out to a single device starting a session
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OneTimePreKey {
+    pub key_id: u64,
+    pub public_key: Vec<u8>,
+}
+
+impl OneTimePreKey {
+    pub fn new(key_id: u64, public_key: Vec<u8>) -> Self {
+        OneTimePreKey { key_id, public_key }
+    }
+}
+
+/// The X25519 keys of a device. The signed prekey is signed with the Ed25519 signing key
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PreKeyUpload {
+    pub identity_key: Vec<u8>,
+    pub signing_key: Vec<u8>,
+    pub signed_prekey_id: u64,
+    pub signed_prekey: Vec<u8>,
+    pub signature: Vec<u8>,
+    // Added to the unused prekeys of the device. Can be empty
+    pub one_time_prekeys: Vec<OneTimePreKey>,
+}
+
+impl PreKeyUpload {
+    pub fn new(
+        identity_key: Vec<u8>,
+        signing_key: Vec<u8>,
+        signed_prekey_id: u64,
+        signed_prekey: Vec<u8>,
+        signature: Vec<u8>,
+        one_time_prekeys: Vec<OneTimePreKey>,
+    ) -> Self {
+        PreKeyUpload {
+            identity_key,
+            signing_key,
+            signed_prekey_id,
+            signed_prekey,
+            signature,
+            one_time_prekeys,
+        }
+    }
+}
+
+/// Everything needed to start a session with a device. The one-time prekey is None once they run out
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PreKeyBundle {
+    pub user_id: u64,
+    pub device_id: u64,
+    pub identity_key: Vec<u8>,
+    pub signing_key: Vec<u8>,
+    pub signed_prekey_id: u64,
+    pub signed_prekey: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub one_time_prekey: Option<OneTimePreKey>,
+}
+
+/// Used for sending or receiving relevant data to create an UserObject
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FullUserData {
+    pub user_id: u64,
+    pub user_name: String,
+    pub image_link: Option<String>,
+    // Every registered device of the user. Messages must be encrypted for all of them
+    #[serde(default)]
+    pub devices: Vec<DeviceKey>,
+}
+
+impl FullUserData {
+    pub fn new(
+        user_id: u64,
+        user_name: String,
+        image_link: Option<String>,
+        devices: Vec<DeviceKey>,
+    ) -> Self {
+        FullUserData {
+            user_id,
+            user_name,
+            image_link,
+            devices,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UserIDs {
+    pub user_id: u64,
+    pub user_token: String,
+    // 0 if this device was not registered with the user yet
+    #[serde(default)]
+    pub device_id: u64,
+}
+
+impl UserIDs {
+    pub fn new(user_id: u64, user_token: String, device_id: u64) -> Self {
+        UserIDs {
+            user_id,
+            user_token,
+            device_id,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageData {
+    // Set by the server once the message is saved. The client value is ignored
+    pub created_at: String,
+    pub from_user: u64,
+    // The receiving user or the group ID in case of group messages
+    pub to_user: u64,
+    pub sender_message: Option<Vec<u8>>,
+    // Group messages only have the sender copy which every member reads
+    pub receiver_message: Option<Vec<u8>>,
+    // The AES key encrypted for every device of the sender and the receiver or every group member.
+    // Each carries the ID of the RSA key it was encrypted with
+    pub device_keys: Option<Vec<EncryptedKey>>,
+    pub sender_nonce: Option<Vec<u8>>,
+    pub receiver_nonce: Option<Vec<u8>>,
+    // Assigned by the server per chat. The client sends 0
+    pub message_number: u64,
+    // How far a direct message got on the receiver side. Only set when syncing
+    #[serde(default)]
+    pub receipt: Option<ReceiptStatus>,
+    // Set by the server if the message was edited
+    #[serde(default)]
+    pub edited_at: Option<String>,
+    // Number of the message of the same chat this one replies to
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+    // Reactions of the chat members. Only set when syncing
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    // An uploaded file sent with the message
+    #[serde(default)]
+    pub attachment: Option<Attachment>,
+}
+
+impl MessageData {
+    pub fn new_incomplete(created_at: String, from_user: u64, to_user: u64) -> Self {
+        MessageData {
+            created_at,
+            from_user,
+            to_user,
+            sender_message: None,
+            receiver_message: None,
+            device_keys: None,
+            sender_nonce: None,
+            receiver_nonce: None,
+            message_number: 0,
+            receipt: None,
+            edited_at: None,
+            reply_to: None,
+            reactions: Vec::new(),
+            attachment: None,
+        }
+    }
+
+    /// Marks the message as a reply to another message of the chat
+    pub fn update_reply_to(self, reply_to: Option<u64>) -> Self {
+        MessageData { reply_to, ..self }
+    }
+
+    /// Sends the uploaded attachment with the message
+    pub fn update_attachment(self, attachment: Option<Attachment>) -> Self {
+        MessageData { attachment, ..self }
+    }
+
+    pub fn update_message(
+        self,
+        sender_message: Vec<u8>,
+        receiver_message: Vec<u8>,
+        device_keys: Vec<EncryptedKey>,
+        sender_nonce: Vec<u8>,
+        receiver_nonce: Vec<u8>,
+    ) -> Self {
+        MessageData {
+            created_at: self.created_at,
+            from_user: self.from_user,
+            to_user: self.to_user,
+            sender_message: Some(sender_message),
+            receiver_message: Some(receiver_message),
+            device_keys: Some(device_keys),
+            sender_nonce: Some(sender_nonce),
+            receiver_nonce: Some(receiver_nonce),
+            message_number: self.message_number,
+            receipt: self.receipt,
+            edited_at: self.edited_at,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            attachment: self.attachment,
+        }
+    }
+
+    /// Used for editing the message with the given number
+    pub fn update_message_number(self, message_number: u64) -> Self {
+        MessageData {
+            created_at: self.created_at,
+            from_user: self.from_user,
+            to_user: self.to_user,
+            sender_message: self.sender_message,
+            receiver_message: self.receiver_message,
+            device_keys: self.device_keys,
+            sender_nonce: self.sender_nonce,
+            receiver_nonce: self.receiver_nonce,
+            message_number,
+            receipt: self.receipt,
+            edited_at: self.edited_at,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            attachment: self.attachment,
+        }
+    }
+
+    pub fn update_group_message(
+        self,
+        message: Vec<u8>,
+        device_keys: Vec<EncryptedKey>,
+        nonce: Vec<u8>,
+    ) -> Self {
+        MessageData {
+            created_at: self.created_at,
+            from_user: self.from_user,
+            to_user: self.to_user,
+            sender_message: Some(message),
+            receiver_message: None,
+            device_keys: Some(device_keys),
+            sender_nonce: Some(nonce),
+            receiver_nonce: None,
+            message_number: self.message_number,
+            receipt: self.receipt,
+            edited_at: self.edited_at,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            attachment: self.attachment,
+        }
+    }
+}
+
+/// The receipt statuses in order. A read message was always delivered
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// Every direct message of the chat up to the message number reached the status.
+/// The user ID is the other user of the chat
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageReceipt {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub status: ReceiptStatus,
+}
+
+impl MessageReceipt {
+    pub fn new(user_id: u64, message_number: u64, status: ReceiptStatus) -> Self {
+        MessageReceipt {
+            user_id,
+            message_number,
+            status,
+        }
+    }
+}
+
+/// A file sent with a message. The file and its name are encrypted with the same AES key as the message
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Attachment {
+    // Given by the server once the upload starts. The client sends an empty ID to start an upload
+    pub attachment_id: String,
+    // Size of the encrypted file
+    pub size: u64,
+    pub file_name: Vec<u8>,
+    pub name_nonce: Vec<u8>,
+    pub file_nonce: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(size: u64, file_name: Vec<u8>, name_nonce: Vec<u8>, file_nonce: Vec<u8>) -> Self {
+        Attachment {
+            attachment_id: String::new(),
+            size,
+            file_name,
+            name_nonce,
+            file_nonce,
+        }
+    }
+
+    pub fn update_id(self, attachment_id: String) -> Self {
+        Attachment {
+            attachment_id,
+            ..self
+        }
+    }
+}
+
+/// A piece of an encrypted attachment file starting at the offset. Download requests send no data
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AttachmentChunk {
+    pub attachment_id: String,
+    pub offset: u64,
+    #[serde(default)]
+    pub data: Vec<u8>,
+}
+
+impl AttachmentChunk {
+    pub fn new(attachment_id: String, offset: u64, data: Vec<u8>) -> Self {
+        AttachmentChunk {
+            attachment_id,
+            offset,
+            data,
+        }
+    }
+}
+
+/// Whether a user has any connected session. The last seen time is when the last session of the user
+/// disconnected, None if the user was never seen offline
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Presence {
+    pub user_id: u64,
+    pub online: bool,
+    pub last_seen_at: Option<String>,
+}
+
+impl Presence {
+    pub fn new(user_id: u64, online: bool, last_seen_at: Option<String>) -> Self {
+        Presence {
+            user_id,
+            online,
+            last_seen_at,
+        }
+    }
+}
+
+/// Sent to the sender once a message is saved with the number and the time the server gave it
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageAck {
+    pub to_user: u64,
+    pub message_number: u64,
+    pub created_at: String,
+}
+
+impl MessageAck {
+    pub fn new(to_user: u64, message_number: u64, created_at: String) -> Self {
+        MessageAck {
+            to_user,
+            message_number,
+            created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImageUpdate {
+    pub user_id: u64,
+    pub image_link: Option<String>,
+}
+
+impl ImageUpdate {
+    pub fn new(user_id: u64, image_link: Option<String>) -> Self {
+        ImageUpdate {
+            user_id,
+            image_link,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NameUpdate {
+    pub user_id: u64,
+    pub new_name: String,
+}
+
+impl NameUpdate {
+    pub fn new(user_id: u64, new_name: String) -> Self {
+        NameUpdate { user_id, new_name }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageSyncRequest {
+    pub user_id: u64,
+    pub start_at: u64,
+    pub end_at: u64,
+}
+
+impl MessageSyncRequest {
+    pub fn new(user_id: u64, start_at: u64, end_at: u64) -> Self {
+        MessageSyncRequest {
+            user_id,
+            start_at,
+            end_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageSyncData {
+    pub message_data: Vec<MessageData>,
+    pub last_message_number: u64,
+    pub start_at: u64,
+    pub ends_at: u64,
+}
+
+impl MessageSyncData {
+    pub fn new(
+        message_data: Vec<MessageData>,
+        last_message_number: u64,
+        start_at: u64,
+        ends_at: u64,
+    ) -> Self {
+        MessageSyncData {
+            message_data,
+            last_message_number,
+            start_at,
+            ends_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeleteMessage {
+    pub user_id: u64,
+    pub message_number: u64,
+}
+
+impl DeleteMessage {
+    pub fn new(user_id: u64, message_number: u64) -> Self {
+        DeleteMessage {
+            user_id,
+            message_number,
+        }
+    }
+}
+
+/// Asks for every earlier version of an edited message
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EditHistoryRequest {
+    pub user_id: u64,
+    pub message_number: u64,
+}
+
+impl EditHistoryRequest {
+    pub fn new(user_id: u64, message_number: u64) -> Self {
+        EditHistoryRequest {
+            user_id,
+            message_number,
+        }
+    }
+}
+
+/// The emoji a user reacted to a message with
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Reaction {
+    pub user_id: u64,
+    pub emoji: String,
+}
+
+impl Reaction {
+    pub fn new(user_id: u64, emoji: String) -> Self {
+        Reaction { user_id, emoji }
+    }
+}
+
+/// Used for adding or removing a reaction. Each user can have one reaction on a message
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MessageReaction {
+    // The other user or the group ID of the chat
+    pub user_id: u64,
+    pub message_number: u64,
+    // Set by the server to the user that reacted
+    pub reacted_by: u64,
+    pub emoji: String,
+}
+
+impl MessageReaction {
+    pub fn new(user_id: u64, message_number: u64, emoji: String) -> Self {
+        MessageReaction {
+            user_id,
+            message_number,
+            reacted_by: 0,
+            emoji,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeletedMessageData {
+    pub message_numbers: Vec<u64>,
+}
+
+impl DeletedMessageData {
+    pub fn new(message_numbers: Vec<u64>) -> Self {
+        DeletedMessageData { message_numbers }
+    }
+}
+
+/// A group chat with every member of it. Group IDs share the ID space of users
+/// so any chat can be found with a single ID
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GroupData {
+    pub group_id: u64,
+    pub group_name: String,
+    pub created_by: u64,
+    pub members: Vec<FullUserData>,
+}
+
+impl GroupData {
+    pub fn new(
+        group_id: u64,
+        group_name: String,
+        created_by: u64,
+        members: Vec<FullUserData>,
+    ) -> Self {
+        GroupData {
+            group_id,
+            group_name,
+            created_by,
+            members,
+        }
+    }
+}
+
+/// Used for creating a new group. The creator is always added as a member
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NewGroup {
+    pub group_name: String,
+    pub members: Vec<u64>,
+}
+
+impl NewGroup {
+    pub fn new(group_name: String, members: Vec<u64>) -> Self {
+        NewGroup {
+            group_name,
+            members,
+        }
+    }
+}
+
+/// Used for adding or removing a member of a group
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GroupMember {
+    pub group_id: u64,
+    pub user_id: u64,
+}
+
+impl GroupMember {
+    pub fn new(group_id: u64, user_id: u64) -> Self {
+        GroupMember { group_id, user_id }
+    }
+}
+
+/// A chat that received messages while every session of the owner was offline.
+/// The sender might not be added by the client yet
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UnreadChat {
+    pub user_data: FullUserData,
+    pub unread_count: u64,
+    pub last_message_number: u64,
+}
+
+impl UnreadChat {
+    pub fn new(user_data: FullUserData, unread_count: u64, last_message_number: u64) -> Self {
+        UnreadChat {
+            user_data,
+            unread_count,
+            last_message_number,
+        }
+    }
+}
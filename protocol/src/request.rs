@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Attachment, AttachmentChunk, DeleteMessage, DeviceKey, EditHistoryRequest, FullUserData,
+    GroupMember, Handshake, ImageUpdate, MessageData, MessageReaction, MessageReceipt,
+    MessageSyncRequest, NameUpdate, NewGroup, PreKeyUpload, UserIDs,
+};
+
+/// Every request a client can send to the server. Serialized as `{"type": "...", "data": {...}}`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum ClientRequest {
+    // Must be the very first request on every new connection
+    Handshake(Handshake),
+    // Create a new user. Authenticates the session as the new user
+    CreateNewUser(FullUserData),
+    // Reconnect with an existing user. Authenticates the session as the owner of the token
+    ReconnectUser(UserIDs),
+    // Start receiving updates of a chat with the given user ID on this session
+    OpenChat(u64),
+    // Get the profile data of a specific user
+    GetUserData(u64),
+    // Send a message to another user. Replies with the number the message got
+    SendMessage(MessageData),
+    // Broadcast name updates to relevant sessions
+    NameUpdated(NameUpdate),
+    // Broadcast image updates to relevant sessions
+    ImageUpdated(ImageUpdate),
+    // Get the last message number of a chat with a user or a group
+    MessageNumber(u64),
+    // Get message data of a user or a group chat within a given range to sync messages
+    SyncMessage(MessageSyncRequest),
+    // Broadcast message deletion. In groups only the sender can delete a message
+    DeleteMessage(DeleteMessage),
+    // Get deleted message data of a user or a group chat within a given range to sync messages
+    SyncDeletedMessage(MessageSyncRequest),
+    // Replace the content of a message the owner sent. The earlier content is kept as a revision
+    EditMessage(MessageData),
+    // Get edited message data of a user or a group chat within a given range to sync messages
+    SyncEditedMessage(MessageSyncRequest),
+    // Get every earlier version of an edited message
+    GetEditHistory(EditHistoryRequest),
+    // React to a message. Replaces the earlier reaction of the owner
+    AddReaction(MessageReaction),
+    // Remove the reaction of the owner from a message
+    RemoveReaction(MessageReaction),
+    // Issue a new token for the owner and invalidate the old one
+    RotateToken,
+    // Register the RSA public key of a new device of the owner
+    RegisterDevice(DeviceKey),
+    // Replace the RSA public key of this device. The earlier keys are kept in the key history
+    RotateKey(DeviceKey),
+    // Replace the identity and signed prekey of this device and add the one-time prekeys.
+    // Replies with the number of unused one-time prekeys
+    PublishPreKeys(PreKeyUpload),
+    // Get a prekey bundle for every device of the user that published one
+    GetPreKeyBundles(u64),
+    // Create a new group with the owner as a member
+    CreateGroup(NewGroup),
+    // Get every group the owner is a member of
+    GetGroups,
+    // Add a new member to a group the owner is a member of
+    AddGroupMember(GroupMember),
+    // Remove a member of a group. Members can remove themselves, the creator can remove anyone
+    RemoveGroupMember(GroupMember),
+    // Every message from the user up to the number was delivered to or read by the owner
+    MessageReceipt(MessageReceipt),
+    // Send a message to every member of a group. Replies with the number the message got
+    SendGroupMessage(MessageData),
+    // The owner is typing a message to the user. Only relayed to the sessions online right now
+    Typing(u64),
+    // Start uploading an encrypted attachment. Replies with the ID the chunks must be sent with
+    StartUpload(Attachment),
+    // The next piece of an attachment being uploaded. Must start where the last one ended
+    UploadChunk(AttachmentChunk),
+    // Get a piece of an attachment starting at the offset. The data of the request is ignored
+    GetAttachmentChunk(AttachmentChunk),
+}
+
+/// Every request is sent wrapped in a frame so the server can refer back to it in the response
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RequestFrame {
+    pub request_id: u64,
+    pub request: ClientRequest,
+}
+
+impl RequestFrame {
+    pub fn new(request_id: u64, request: ClientRequest) -> Self {
+        RequestFrame {
+            request_id,
+            request,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+
+    /// Try to get the request ID out of a frame that failed to parse as a whole
+    pub fn request_id_from_json(data: &str) -> Option<u64> {
+        #[derive(Deserialize)]
+        struct PartialFrame {
+            request_id: u64,
+        }
+
+        serde_json::from_str::<PartialFrame>(data)
+            .ok()
+            .map(|frame| frame.request_id)
+    }
+}
+
+impl ClientRequest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AttachmentChunk, DeleteMessage, DeletedMessageData, DeviceKey, ErrorResponse, FullUserData,
+    GroupData, ImageUpdate, MessageAck, MessageData, MessageReaction, MessageReceipt,
+    MessageSyncData, NameUpdate, PreKeyBundle, Presence, UnreadChat, UserIDs, VersionMismatch,
+};
+
+/// Every response or broadcast the server can send to a client. Serialized as `{"type": "...", "data": {...}}`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum ServerResponse {
+    // The handshake was successful, other requests can be sent now
+    HandshakeAccepted,
+    // The client uses a different protocol version. The connection gets closed after this
+    VersionMismatch(VersionMismatch),
+    // A new user was created with the given ID and token
+    UpdateUserId(UserIDs),
+    // Reconnected with an existing user
+    ReconnectSuccess(FullUserData),
+    // Chats that received messages while the owner was offline. Sent after reconnecting
+    UnreadChats(Vec<UnreadChat>),
+    // A chat was opened with the given user
+    ChatOpened(FullUserData),
+    // Profile data of a user. User ID 0 means the user does not exist
+    GetUserData(FullUserData),
+    // A new message was received from a user or in a group
+    Message(MessageData),
+    // The message of the owner was saved with the given number
+    MessageSent(MessageAck),
+    // The user received or read the messages of the owner up to the number
+    MessageReceipt(MessageReceipt),
+    // The user with the ID is typing a message to the owner
+    Typing(u64),
+    // A user came online or went offline. Also sent after opening a chat with the user
+    Presence(Presence),
+    // A user updated their name
+    NameUpdated(NameUpdate),
+    // A user updated their image link
+    ImageUpdated(ImageUpdate),
+    // The last message number of a chat
+    MessageNumber(u64),
+    // Message data within the requested range
+    SyncMessage(MessageSyncData),
+    // A message was deleted
+    DeleteMessage(DeleteMessage),
+    // Deleted message numbers within the requested range
+    SyncDeletedMessage(DeletedMessageData),
+    // A message was edited. Also the reply to the sender with the saved edit
+    EditMessage(MessageData),
+    // Edited message data within the requested range
+    SyncEditedMessage(MessageSyncData),
+    // A user reacted to a message
+    ReactionAdded(MessageReaction),
+    // A user removed the reaction from a message
+    ReactionRemoved(MessageReaction),
+    // Earlier versions of a message, the oldest first. The creation time is when the version was written
+    EditHistory(Vec<MessageData>),
+    // The upload was started with the given attachment ID
+    UploadStarted(String),
+    // Total bytes of the attachment saved so far
+    ChunkUploaded(u64),
+    // A piece of an attachment. Empty data means the offset reached the end of the file
+    AttachmentChunk(AttachmentChunk),
+    // The owner token was replaced with a new one
+    TokenRotated(UserIDs),
+    // The device was registered with the given device ID
+    DeviceRegistered(u64),
+    // A user registered a new device
+    DeviceAdded(DeviceKey),
+    // A device got a new RSA public key. Also the reply to the device that rotated its key
+    KeyRotated(DeviceKey),
+    // Number of unused one-time prekeys of this device
+    PreKeysPublished(u64),
+    // A bundle for every device of the user that published prekeys
+    PreKeyBundles(Vec<PreKeyBundle>),
+    // The group was created
+    GroupCreated(GroupData),
+    // Every group the owner is a member of
+    Groups(Vec<GroupData>),
+    // The owner was added to a group or the members of a group changed
+    GroupUpdated(GroupData),
+    // The owner was removed from the group with the given ID
+    GroupRemoved(u64),
+    // A request from this session failed
+    Error(ErrorResponse),
+}
+
+/// Every response is sent wrapped in a frame. Replies carry the ID of the request they answer,
+/// anything the server sends on its own has no request ID
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ResponseFrame {
+    pub request_id: Option<u64>,
+    pub response: ServerResponse,
+}
+
+impl ResponseFrame {
+    pub fn reply(request_id: u64, response: ServerResponse) -> Self {
+        ResponseFrame {
+            request_id: Some(request_id),
+            response,
+        }
+    }
+
+    pub fn broadcast(response: ServerResponse) -> Self {
+        ResponseFrame {
+            request_id: None,
+            response,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
+
+impl ServerResponse {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
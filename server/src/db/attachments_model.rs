@@ -0,0 +1,57 @@
+use chirp_protocol::Attachment;
+use diesel::prelude::*;
+
+use crate::db::schema::attachments;
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = attachments)]
+pub struct SavedAttachment {
+    pub attachment_id: String,
+    pub uploaded_by: i32,
+    pub size: i64,
+    pub uploaded_size: i64,
+    pub file_name: Vec<u8>,
+    pub name_nonce: Vec<u8>,
+    pub file_nonce: Vec<u8>,
+}
+
+impl SavedAttachment {
+    /// Whether every byte of the file was uploaded
+    pub fn is_complete(&self) -> bool {
+        self.uploaded_size == self.size
+    }
+
+    pub fn into_attachment(self) -> Attachment {
+        Attachment::new(
+            self.size as u64,
+            self.file_name,
+            self.name_nonce,
+            self.file_nonce,
+        )
+        .update_id(self.attachment_id)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = attachments)]
+pub struct NewAttachment {
+    pub attachment_id: String,
+    pub uploaded_by: i32,
+    pub size: i64,
+    pub file_name: Vec<u8>,
+    pub name_nonce: Vec<u8>,
+    pub file_nonce: Vec<u8>,
+}
+
+impl NewAttachment {
+    pub fn new(attachment_id: String, uploaded_by: usize, attachment: Attachment) -> Self {
+        NewAttachment {
+            attachment_id,
+            uploaded_by: uploaded_by as i32,
+            size: attachment.size as i64,
+            file_name: attachment.file_name,
+            name_nonce: attachment.name_nonce,
+            file_nonce: attachment.file_nonce,
+        }
+    }
+}
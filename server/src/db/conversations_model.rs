@@ -0,0 +1,47 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::{conversation_members, conversations};
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(primary_key(conversation_id))]
+pub struct Conversation {
+    pub conversation_id: i32,
+    pub conversation_name: String,
+    pub created_by: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = conversations)]
+pub struct NewConversation {
+    pub conversation_id: i32,
+    pub conversation_name: String,
+    pub created_by: i32,
+}
+
+impl NewConversation {
+    pub fn new(conversation_id: usize, conversation_name: String, created_by: usize) -> Self {
+        NewConversation {
+            conversation_id: conversation_id as i32,
+            conversation_name,
+            created_by: created_by as i32,
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = conversation_members)]
+pub struct NewConversationMember {
+    pub conversation_id: i32,
+    pub user_id: i32,
+}
+
+impl NewConversationMember {
+    pub fn new(conversation_id: usize, user_id: usize) -> Self {
+        NewConversationMember {
+            conversation_id: conversation_id as i32,
+            user_id: user_id as i32,
+        }
+    }
+}
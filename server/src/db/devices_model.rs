@@ -0,0 +1,43 @@
+use chirp_protocol::DeviceKey;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::devices;
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(primary_key(device_id))]
+pub struct Device {
+    pub device_id: i32,
+    pub user_id: i32,
+    pub rsa_public_key: String,
+    pub created_at: NaiveDateTime,
+    // The latest key of the device in the key history
+    pub key_id: i32,
+}
+
+impl Device {
+    pub fn into_device_key(self) -> DeviceKey {
+        DeviceKey::new(
+            self.user_id as u64,
+            self.device_id as u64,
+            self.rsa_public_key,
+        )
+        .update_key_id(self.key_id as u64)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = devices)]
+pub struct NewDevice {
+    pub user_id: i32,
+    pub rsa_public_key: String,
+}
+
+impl NewDevice {
+    pub fn new(user_id: usize, rsa_public_key: String) -> Self {
+        NewDevice {
+            user_id: user_id as i32,
+            rsa_public_key,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use actix::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::env;
+use std::marker::PhantomData;
+use tracing::info;
+
+use crate::server::RequestError;
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+/// Runs DB work on its own threads so the chat server never waits for a query
+pub struct DbExecutor(pub DbPool);
+
+impl Actor for DbExecutor {
+    type Context = SyncContext<Self>;
+}
+
+/// Creates the connection pool shared by every DB executor thread
+pub fn create_db_pool() -> DbPool {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+
+    info!("Creating DB connection pool");
+    let manager = ConnectionManager::<PgConnection>::new(&database_url);
+    Pool::builder()
+        .build(manager)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+}
+
+/// A closure to run with a pooled connection on the DB executor
+pub struct Execute<F, R> {
+    query: F,
+    result: PhantomData<R>,
+}
+
+impl<F, R> Execute<F, R>
+where
+    F: FnOnce(&mut PgConnection) -> Result<R, RequestError> + Send + 'static,
+    R: Send + 'static,
+{
+    pub fn new(query: F) -> Self {
+        Execute {
+            query,
+            result: PhantomData,
+        }
+    }
+}
+
+impl<F, R> Message for Execute<F, R>
+where
+    F: FnOnce(&mut PgConnection) -> Result<R, RequestError> + Send + 'static,
+    R: Send + 'static,
+{
+    type Result = Result<R, RequestError>;
+}
+
+impl<F, R> Handler<Execute<F, R>> for DbExecutor
+where
+    F: FnOnce(&mut PgConnection) -> Result<R, RequestError> + Send + 'static,
+    R: Send + 'static,
+{
+    type Result = Result<R, RequestError>;
+
+    fn handle(&mut self, msg: Execute<F, R>, _: &mut Self::Context) -> Self::Result {
+        let mut conn = self.0.get()?;
+        (msg.query)(&mut conn)
+    }
+}
@@ -1,7 +1,8 @@
+use chirp_protocol::{EncryptedKey, RatchetHeader, ReceiptStatus};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 
-use crate::db::schema::messages;
+use crate::db::schema::{message_keys, message_revisions, messages, revision_keys};
 
 #[derive(Queryable, Selectable, Identifiable)]
 #[diesel(primary_key(message_group, message_number))]
@@ -11,13 +12,28 @@ pub struct Message {
     pub message_number: i32,
     pub sender_message: Option<Vec<u8>>,
     pub receiver_message: Option<Vec<u8>>,
-    pub sender_key: Option<Vec<u8>>,
-    pub receiver_key: Option<Vec<u8>>,
     pub sender_nonce: Option<Vec<u8>>,
     pub receiver_nonce: Option<Vec<u8>>,
     pub message_sender: i32,
-    pub message_receiver: i32,
+    pub message_receiver: Option<i32>,
     pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub read_at: Option<NaiveDateTime>,
+    pub edited_at: Option<NaiveDateTime>,
+    pub reply_to: Option<i32>,
+    pub attachment_id: Option<String>,
+}
+
+impl Message {
+    pub fn receipt(&self) -> Option<ReceiptStatus> {
+        if self.read_at.is_some() {
+            Some(ReceiptStatus::Read)
+        } else if self.delivered_at.is_some() {
+            Some(ReceiptStatus::Delivered)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -27,41 +43,181 @@ pub struct NewMessage {
     pub message_number: i32,
     pub sender_message: Option<Vec<u8>>,
     pub receiver_message: Option<Vec<u8>>,
-    pub sender_key: Option<Vec<u8>>,
-    pub receiver_key: Option<Vec<u8>>,
     pub sender_nonce: Option<Vec<u8>>,
     pub receiver_nonce: Option<Vec<u8>>,
     pub message_sender: i32,
-    pub message_receiver: i32,
+    pub message_receiver: Option<i32>,
     pub created_at: NaiveDateTime,
+    pub reply_to: Option<i32>,
+    pub attachment_id: Option<String>,
 }
 
 impl NewMessage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         message_group: String,
-        message_number: usize,
         sender_message: Vec<u8>,
         receiver_message: Vec<u8>,
-        sender_key: Vec<u8>,
-        receiver_key: Vec<u8>,
         sender_nonce: Vec<u8>,
         receiver_nonce: Vec<u8>,
         message_sender: usize,
         message_receiver: usize,
         created_at: NaiveDateTime,
+        reply_to: Option<u64>,
+        attachment_id: Option<String>,
     ) -> Self {
         NewMessage {
             message_group,
-            message_number: message_number as i32,
+            message_number: 0,
             sender_message: Some(sender_message),
             receiver_message: Some(receiver_message),
-            sender_key: Some(sender_key),
-            receiver_key: Some(receiver_key),
             sender_nonce: Some(sender_nonce),
             receiver_nonce: Some(receiver_nonce),
             message_sender: message_sender as i32,
-            message_receiver: message_receiver as i32,
+            message_receiver: Some(message_receiver as i32),
             created_at,
+            reply_to: reply_to.map(|number| number as i32),
+            attachment_id,
         }
     }
+
+    /// A group message only has a single copy that every member reads
+    pub fn new_group(
+        message_group: String,
+        message: Vec<u8>,
+        nonce: Vec<u8>,
+        message_sender: usize,
+        created_at: NaiveDateTime,
+        reply_to: Option<u64>,
+        attachment_id: Option<String>,
+    ) -> Self {
+        NewMessage {
+            message_group,
+            message_number: 0,
+            sender_message: Some(message),
+            receiver_message: None,
+            sender_nonce: Some(nonce),
+            receiver_nonce: None,
+            message_sender: message_sender as i32,
+            message_receiver: None,
+            created_at,
+            reply_to: reply_to.map(|number| number as i32),
+            attachment_id,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = message_keys)]
+pub struct MessageKey {
+    pub message_id: i32,
+    pub device_id: i32,
+    pub encrypted_key: Vec<u8>,
+    pub ratchet_header: Option<String>,
+    // None for ratchet keys
+    pub key_id: Option<i32>,
+}
+
+impl MessageKey {
+    pub fn new(message_id: i32, key: EncryptedKey) -> Self {
+        let key_id = key.ratchet_header.is_none().then_some(key.key_id as i32);
+        MessageKey {
+            message_id,
+            device_id: key.device_id as i32,
+            encrypted_key: key.encrypted_key,
+            ratchet_header: key.ratchet_header.map(|header| header.to_json()),
+            key_id,
+        }
+    }
+
+    pub fn into_encrypted_key(self) -> EncryptedKey {
+        to_encrypted_key(
+            self.device_id,
+            self.encrypted_key,
+            self.ratchet_header,
+            self.key_id,
+        )
+    }
+}
+
+/// An earlier version of an edited message
+#[derive(Queryable, Selectable, Identifiable)]
+#[diesel(primary_key(revision_id))]
+pub struct MessageRevision {
+    pub revision_id: i32,
+    pub message_id: i32,
+    pub sender_message: Vec<u8>,
+    pub receiver_message: Option<Vec<u8>>,
+    pub sender_nonce: Vec<u8>,
+    pub receiver_nonce: Option<Vec<u8>>,
+    pub written_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = message_revisions)]
+pub struct NewMessageRevision {
+    pub message_id: i32,
+    pub sender_message: Vec<u8>,
+    pub receiver_message: Option<Vec<u8>>,
+    pub sender_nonce: Vec<u8>,
+    pub receiver_nonce: Option<Vec<u8>>,
+    pub written_at: NaiveDateTime,
+}
+
+impl NewMessageRevision {
+    /// Takes the current content of the message. Returns None if the message was deleted
+    pub fn from_message(message: Message) -> Option<Self> {
+        Some(NewMessageRevision {
+            message_id: message.message_id,
+            sender_message: message.sender_message?,
+            receiver_message: message.receiver_message,
+            sender_nonce: message.sender_nonce?,
+            receiver_nonce: message.receiver_nonce,
+            written_at: message.edited_at.unwrap_or(message.created_at),
+        })
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = revision_keys)]
+pub struct RevisionKey {
+    pub revision_id: i32,
+    pub device_id: i32,
+    pub encrypted_key: Vec<u8>,
+    pub ratchet_header: Option<String>,
+    // None for ratchet keys
+    pub key_id: Option<i32>,
+}
+
+impl RevisionKey {
+    pub fn from_message_key(revision_id: i32, key: MessageKey) -> Self {
+        RevisionKey {
+            revision_id,
+            device_id: key.device_id,
+            encrypted_key: key.encrypted_key,
+            ratchet_header: key.ratchet_header,
+            key_id: key.key_id,
+        }
+    }
+
+    pub fn into_encrypted_key(self) -> EncryptedKey {
+        to_encrypted_key(
+            self.device_id,
+            self.encrypted_key,
+            self.ratchet_header,
+            self.key_id,
+        )
+    }
+}
+
+fn to_encrypted_key(
+    device_id: i32,
+    encrypted_key: Vec<u8>,
+    ratchet_header: Option<String>,
+    key_id: Option<i32>,
+) -> EncryptedKey {
+    let ratchet_header = ratchet_header.and_then(|header| RatchetHeader::from_json(&header).ok());
+    EncryptedKey::new(device_id as u64, encrypted_key)
+        .update_ratchet_header(ratchet_header)
+        .update_key_id(key_id.unwrap_or_default() as u64)
 }
@@ -1,9 +1,24 @@
+mod attachments_model;
+mod conversations_model;
+mod devices_model;
+mod executor;
 mod messages_model;
+mod notifications_model;
 mod operations;
+mod prekeys_model;
+mod reactions_model;
 mod schema;
+mod user_keys_model;
 mod users_model;
 
+pub use attachments_model::*;
+pub use conversations_model::*;
+pub use devices_model::*;
+pub use executor::*;
 pub use messages_model::*;
+pub use notifications_model::*;
 pub use operations::*;
-pub use schema::*;
+pub use prekeys_model::*;
+pub use reactions_model::*;
+pub use user_keys_model::*;
 pub use users_model::*;
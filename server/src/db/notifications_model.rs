@@ -0,0 +1,30 @@
+use diesel::prelude::*;
+
+use crate::db::schema::pending_notifications;
+
+/// The receiver is already known by whoever loads the notification
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = pending_notifications)]
+pub struct PendingNotification {
+    pub sender_id: i32,
+    pub unread_count: i32,
+    pub last_message_number: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = pending_notifications)]
+pub struct NewPendingNotification {
+    pub user_id: i32,
+    pub sender_id: i32,
+    pub last_message_number: i32,
+}
+
+impl NewPendingNotification {
+    pub fn new(user_id: usize, sender_id: usize, last_message_number: usize) -> Self {
+        NewPendingNotification {
+            user_id: user_id as i32,
+            sender_id: sender_id as i32,
+            last_message_number: last_message_number as i32,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    update, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::attachments_model::{NewAttachment, SavedAttachment};
+use crate::db::messages_model::Message;
+use crate::db::schema::{attachments, messages};
+
+pub fn create_new_attachment(
+    conn: &mut PgConnection,
+    attachment_data: NewAttachment,
+) -> QueryResult<usize> {
+    diesel::insert_into(attachments::table)
+        .values(attachment_data)
+        .execute(conn)
+}
+
+pub fn get_attachment_with_id(
+    conn: &mut PgConnection,
+    id: &str,
+) -> QueryResult<Option<SavedAttachment>> {
+    use crate::db::schema::attachments::dsl::*;
+
+    attachments
+        .find(id)
+        .select(SavedAttachment::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Locks the row of the attachment till the transaction ends so its chunks are written one at a time
+pub fn lock_attachment_with_id(
+    conn: &mut PgConnection,
+    id: &str,
+) -> QueryResult<Option<SavedAttachment>> {
+    use crate::db::schema::attachments::dsl::*;
+
+    attachments
+        .find(id)
+        .select(SavedAttachment::as_select())
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+pub fn get_attachments_with_ids(
+    conn: &mut PgConnection,
+    ids: Vec<String>,
+) -> QueryResult<Vec<SavedAttachment>> {
+    use crate::db::schema::attachments::dsl::*;
+
+    attachments
+        .filter(attachment_id.eq_any(ids))
+        .select(SavedAttachment::as_select())
+        .load(conn)
+}
+
+pub fn update_uploaded_size(
+    conn: &mut PgConnection,
+    id: &str,
+    new_size: u64,
+) -> QueryResult<usize> {
+    use crate::db::schema::attachments::dsl::*;
+
+    update(attachments.find(id))
+        .set(uploaded_size.eq(new_size as i64))
+        .execute(conn)
+}
+
+/// The message the attachment was sent with. None if it was not sent yet
+pub fn get_message_with_attachment(
+    conn: &mut PgConnection,
+    id: &str,
+) -> QueryResult<Option<Message>> {
+    messages::table
+        .filter(messages::attachment_id.eq(id))
+        .select(Message::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Removes the attachments whose upload was started before the given time and never completed.
+/// Returns the IDs of the removed attachments
+pub fn remove_stale_uploads(
+    conn: &mut PgConnection,
+    started_before: NaiveDateTime,
+) -> QueryResult<Vec<String>> {
+    use crate::db::schema::attachments::dsl::*;
+
+    diesel::delete(
+        attachments
+            .filter(uploaded_size.lt(size))
+            .filter(created_at.lt(started_before)),
+    )
+    .returning(attachment_id)
+    .get_results(conn)
+}
+
+pub fn remove_attachment(conn: &mut PgConnection, id: &str) -> QueryResult<usize> {
+    use crate::db::schema::attachments::dsl::*;
+
+    diesel::delete(attachments.find(id)).execute(conn)
+}
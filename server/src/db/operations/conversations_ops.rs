@@ -0,0 +1,81 @@
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::conversations_model::{Conversation, NewConversation, NewConversationMember};
+use crate::db::schema::{conversation_members, conversations};
+
+pub fn create_new_conversation(
+    conn: &mut PgConnection,
+    conversation_data: NewConversation,
+) -> QueryResult<Conversation> {
+    diesel::insert_into(conversations::table)
+        .values(conversation_data)
+        .returning(Conversation::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_conversation_with_id(
+    conn: &mut PgConnection,
+    id: usize,
+) -> QueryResult<Option<Conversation>> {
+    use crate::db::schema::conversations::dsl::*;
+
+    conversations
+        .filter(conversation_id.eq(id as i32))
+        .limit(1)
+        .select(Conversation::as_select())
+        .first(conn)
+        .optional()
+}
+
+pub fn get_user_conversations(
+    conn: &mut PgConnection,
+    id: usize,
+) -> QueryResult<Vec<Conversation>> {
+    conversations::table
+        .inner_join(conversation_members::table)
+        .filter(conversation_members::user_id.eq(id as i32))
+        .order(conversations::conversation_id.asc())
+        .select(Conversation::as_select())
+        .load(conn)
+}
+
+pub fn get_conversation_members(conn: &mut PgConnection, id: usize) -> QueryResult<Vec<usize>> {
+    use crate::db::schema::conversation_members::dsl::*;
+
+    let members: Vec<i32> = conversation_members
+        .filter(conversation_id.eq(id as i32))
+        .order(joined_at.asc())
+        .select(user_id)
+        .load(conn)?;
+
+    Ok(members.into_iter().map(|member| member as usize).collect())
+}
+
+/// Adds the members to the conversation. Existing members are ignored
+pub fn add_conversation_members(
+    conn: &mut PgConnection,
+    members: Vec<NewConversationMember>,
+) -> QueryResult<usize> {
+    diesel::insert_into(conversation_members::table)
+        .values(members)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn remove_conversation_member(
+    conn: &mut PgConnection,
+    id: usize,
+    member_id: usize,
+) -> QueryResult<usize> {
+    use crate::db::schema::conversation_members::dsl::*;
+
+    diesel::delete(
+        conversation_members
+            .filter(conversation_id.eq(id as i32))
+            .filter(user_id.eq(member_id as i32)),
+    )
+    .execute(conn)
+}
@@ -0,0 +1,35 @@
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::devices_model::{Device, NewDevice};
+use crate::db::schema::devices;
+
+pub fn create_new_device(conn: &mut PgConnection, device_data: NewDevice) -> QueryResult<Device> {
+    diesel::insert_into(devices::table)
+        .values(device_data)
+        .returning(Device::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_device_with_id(conn: &mut PgConnection, id: usize) -> QueryResult<Option<Device>> {
+    use crate::db::schema::devices::dsl::*;
+
+    devices
+        .filter(device_id.eq(id as i32))
+        .limit(1)
+        .select(Device::as_select())
+        .first(conn)
+        .optional()
+}
+
+pub fn get_user_devices(conn: &mut PgConnection, id: usize) -> QueryResult<Vec<Device>> {
+    use crate::db::schema::devices::dsl::*;
+
+    devices
+        .filter(user_id.eq(id as i32))
+        .order(device_id.asc())
+        .select(Device::as_select())
+        .load(conn)
+}
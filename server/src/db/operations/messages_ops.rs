@@ -1,31 +1,175 @@
-use diesel::{update, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use chirp_protocol::ReceiptStatus;
+use diesel::dsl::now;
+use diesel::sql_types::Text;
+use diesel::{
+    sql_query, update, Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    QueryResult, RunQueryDsl, SelectableHelper,
+};
 
-use crate::db::messages_model::Message;
-use crate::db::schema::messages;
+use crate::db::messages_model::{
+    Message, MessageKey, MessageRevision, NewMessageRevision, RevisionKey,
+};
+use crate::db::schema::{message_keys, message_revisions, messages, revision_keys};
 use crate::db::NewMessage;
 
-pub fn create_new_message(conn: &mut PgConnection, message_data: NewMessage) {
-    diesel::insert_into(messages::table)
-        .values(message_data)
+/// Saves the message with the next message number of its group
+pub fn create_new_message(
+    conn: &mut PgConnection,
+    mut message_data: NewMessage,
+) -> QueryResult<Message> {
+    conn.transaction(|conn| {
+        // Messages of the same group wait for each other so no number is given out twice
+        sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<Text, _>(&message_data.message_group)
+            .execute(conn)?;
+
+        let last_number = get_last_message_number(conn, message_data.message_group.clone())?;
+        message_data.message_number = last_number as i32 + 1;
+
+        diesel::insert_into(messages::table)
+            .values(message_data)
+            .returning(Message::as_returning())
+            .get_result(conn)
+    })
+}
+
+pub fn create_message_keys(conn: &mut PgConnection, keys: Vec<MessageKey>) -> QueryResult<usize> {
+    diesel::insert_into(message_keys::table)
+        .values(keys)
+        .execute(conn)
+}
+
+pub fn get_message_keys(
+    conn: &mut PgConnection,
+    message_ids: Vec<i32>,
+    target_device: usize,
+) -> QueryResult<Vec<MessageKey>> {
+    use crate::db::schema::message_keys::dsl::*;
+
+    message_keys
+        .filter(message_id.eq_any(message_ids))
+        .filter(device_id.eq(target_device as i32))
+        .select(MessageKey::as_select())
+        .load(conn)
+}
+
+/// Keys of every device the message was encrypted for
+pub fn get_all_message_keys(conn: &mut PgConnection, id: i32) -> QueryResult<Vec<MessageKey>> {
+    use crate::db::schema::message_keys::dsl::*;
+
+    message_keys
+        .filter(message_id.eq(id))
+        .select(MessageKey::as_select())
+        .load(conn)
+}
+
+/// Saves an earlier version of a message with the keys it was encrypted with
+pub fn create_message_revision(
+    conn: &mut PgConnection,
+    revision_data: NewMessageRevision,
+    keys: Vec<MessageKey>,
+) -> QueryResult<i32> {
+    let revision_id: i32 = diesel::insert_into(message_revisions::table)
+        .values(revision_data)
+        .returning(message_revisions::revision_id)
+        .get_result(conn)?;
+
+    let keys: Vec<RevisionKey> = keys
+        .into_iter()
+        .map(|key| RevisionKey::from_message_key(revision_id, key))
+        .collect();
+
+    diesel::insert_into(revision_keys::table)
+        .values(keys)
+        .execute(conn)?;
+
+    Ok(revision_id)
+}
+
+/// Replaces the content and the keys of a message and marks it as edited
+pub fn replace_message_content(
+    conn: &mut PgConnection,
+    id: i32,
+    new_sender_message: Vec<u8>,
+    new_receiver_message: Option<Vec<u8>>,
+    new_sender_nonce: Vec<u8>,
+    new_receiver_nonce: Option<Vec<u8>>,
+    keys: Vec<MessageKey>,
+) -> QueryResult<Message> {
+    use crate::db::schema::messages::dsl::*;
+
+    diesel::delete(message_keys::table.filter(message_keys::message_id.eq(id))).execute(conn)?;
+    diesel::insert_into(message_keys::table)
+        .values(keys)
+        .execute(conn)?;
+
+    update(messages)
+        .filter(message_id.eq(id))
+        .set((
+            sender_message.eq(Some(new_sender_message)),
+            receiver_message.eq(new_receiver_message),
+            sender_nonce.eq(Some(new_sender_nonce)),
+            receiver_nonce.eq(new_receiver_nonce),
+            edited_at.eq(now),
+        ))
         .returning(Message::as_returning())
         .get_result(conn)
-        .unwrap();
 }
 
-pub fn get_last_message_number(conn: &mut PgConnection, group: String) -> usize {
+pub fn get_message_revisions(
+    conn: &mut PgConnection,
+    id: i32,
+) -> QueryResult<Vec<MessageRevision>> {
+    use crate::db::schema::message_revisions::dsl::*;
+
+    message_revisions
+        .filter(message_id.eq(id))
+        .order(revision_id.asc())
+        .select(MessageRevision::as_select())
+        .load(conn)
+}
+
+pub fn get_revision_keys(
+    conn: &mut PgConnection,
+    revision_ids: Vec<i32>,
+    target_device: usize,
+) -> QueryResult<Vec<RevisionKey>> {
+    use crate::db::schema::revision_keys::dsl::*;
+
+    revision_keys
+        .filter(revision_id.eq_any(revision_ids))
+        .filter(device_id.eq(target_device as i32))
+        .select(RevisionKey::as_select())
+        .load(conn)
+}
+
+pub fn get_last_message_number(conn: &mut PgConnection, group: String) -> QueryResult<usize> {
     use crate::db::schema::messages::dsl::*;
 
-    let result: Result<Message, diesel::result::Error> = messages
+    let result = messages
         .filter(message_group.eq(group))
         .order(message_number.desc())
         .limit(1)
         .select(Message::as_select())
-        .first(conn);
+        .first(conn)
+        .optional()?;
 
-    match result {
-        Ok(data) => data.message_number as usize,
-        Err(_) => 0,
-    }
+    Ok(result.map_or(0, |data| data.message_number as usize))
+}
+
+pub fn get_message_with_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: usize,
+) -> QueryResult<Option<Message>> {
+    use crate::db::schema::messages::dsl::*;
+
+    messages
+        .filter(message_group.eq(group))
+        .filter(message_number.eq(number as i32))
+        .select(Message::as_select())
+        .first(conn)
+        .optional()
 }
 
 pub fn get_messages_from_number(
@@ -33,7 +177,7 @@ pub fn get_messages_from_number(
     group: String,
     start_at: usize,
     end_at: usize,
-) -> Vec<Message> {
+) -> QueryResult<Vec<Message>> {
     use crate::db::schema::messages::dsl::*;
 
     messages
@@ -44,7 +188,6 @@ pub fn get_messages_from_number(
         .order(message_number.desc())
         .select(Message::as_select())
         .load(conn)
-        .unwrap()
 }
 
 pub fn get_deleted_messages_from_number(
@@ -52,7 +195,7 @@ pub fn get_deleted_messages_from_number(
     group: String,
     start_at: usize,
     end_at: usize,
-) -> Vec<Message> {
+) -> QueryResult<Vec<Message>> {
     use crate::db::schema::messages::dsl::*;
 
     messages
@@ -63,23 +206,86 @@ pub fn get_deleted_messages_from_number(
         .order(message_number.desc())
         .select(Message::as_select())
         .load(conn)
-        .unwrap()
 }
 
-pub fn delete_message_with_number(conn: &mut PgConnection, group: String, number: usize) {
+/// Marks every message the receiver got in the group up to the number.
+/// A read message gets marked as delivered too. Returns the number of updated messages
+pub fn update_message_receipts(
+    conn: &mut PgConnection,
+    group: String,
+    receiver: usize,
+    number: usize,
+    status: ReceiptStatus,
+) -> QueryResult<usize> {
     use crate::db::schema::messages::dsl::*;
 
-    update(messages)
+    let mut updated = update(messages)
+        .filter(message_group.eq(&group))
+        .filter(message_receiver.eq(receiver as i32))
+        .filter(message_number.le(number as i32))
+        .filter(delivered_at.is_null())
+        .set(delivered_at.eq(now))
+        .execute(conn)?;
+
+    if status == ReceiptStatus::Read {
+        updated += update(messages)
+            .filter(message_group.eq(&group))
+            .filter(message_receiver.eq(receiver as i32))
+            .filter(message_number.le(number as i32))
+            .filter(read_at.is_null())
+            .set(read_at.eq(now))
+            .execute(conn)?;
+    }
+
+    Ok(updated)
+}
+
+pub fn get_edited_messages_from_number(
+    conn: &mut PgConnection,
+    group: String,
+    start_at: usize,
+    end_at: usize,
+) -> QueryResult<Vec<Message>> {
+    use crate::db::schema::messages::dsl::*;
+
+    messages
+        .filter(message_group.eq(group))
+        .filter(message_number.gt(start_at as i32))
+        .filter(message_number.le(end_at as i32))
+        .filter(sender_message.is_not_null())
+        .filter(edited_at.is_not_null())
+        .order(message_number.desc())
+        .select(Message::as_select())
+        .load(conn)
+}
+
+pub fn delete_message_with_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: usize,
+) -> QueryResult<usize> {
+    use crate::db::schema::messages::dsl::*;
+
+    let deleted_ids: Vec<i32> = update(messages)
         .filter(message_group.eq(group))
         .filter(message_number.eq(number as i32))
         .set((
             sender_message.eq(None::<Vec<u8>>),
             receiver_message.eq(None::<Vec<u8>>),
-            sender_key.eq(None::<Vec<u8>>),
-            receiver_key.eq(None::<Vec<u8>>),
             sender_nonce.eq(None::<Vec<u8>>),
             receiver_nonce.eq(None::<Vec<u8>>),
         ))
-        .execute(conn)
-        .unwrap();
+        .returning(message_id)
+        .get_results(conn)?;
+
+    diesel::delete(message_keys::table.filter(message_keys::message_id.eq_any(&deleted_ids)))
+        .execute(conn)?;
+
+    // Earlier versions must not outlive the deleted message
+    diesel::delete(
+        message_revisions::table.filter(message_revisions::message_id.eq_any(&deleted_ids)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted_ids.len())
 }
@@ -1,5 +1,19 @@
+mod attachments_ops;
+mod conversations_ops;
+mod devices_ops;
 mod messages_ops;
+mod notifications_ops;
+mod prekeys_ops;
+mod reactions_ops;
+mod user_keys_ops;
 mod users_ops;
 
+pub use attachments_ops::*;
+pub use conversations_ops::*;
+pub use devices_ops::*;
 pub use messages_ops::*;
+pub use notifications_ops::*;
+pub use prekeys_ops::*;
+pub use reactions_ops::*;
+pub use user_keys_ops::*;
 pub use users_ops::*;
@@ -0,0 +1,54 @@
+use diesel::dsl::now;
+use diesel::upsert::excluded;
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+
+use crate::db::notifications_model::{NewPendingNotification, PendingNotification};
+
+/// Saves the notification or bumps the unread count if the chat already has one
+pub fn add_pending_notification(
+    conn: &mut PgConnection,
+    notification_data: NewPendingNotification,
+) -> QueryResult<usize> {
+    use crate::db::schema::pending_notifications::dsl::*;
+
+    diesel::insert_into(pending_notifications)
+        .values(notification_data)
+        .on_conflict((user_id, sender_id))
+        .do_update()
+        .set((
+            unread_count.eq(unread_count + 1),
+            last_message_number.eq(excluded(last_message_number)),
+            updated_at.eq(now),
+        ))
+        .execute(conn)
+}
+
+pub fn get_pending_notifications(
+    conn: &mut PgConnection,
+    id: usize,
+) -> QueryResult<Vec<PendingNotification>> {
+    use crate::db::schema::pending_notifications::dsl::*;
+
+    pending_notifications
+        .filter(user_id.eq(id as i32))
+        .order(updated_at.asc())
+        .select(PendingNotification::as_select())
+        .load(conn)
+}
+
+pub fn remove_pending_notification(
+    conn: &mut PgConnection,
+    id: usize,
+    sender: usize,
+) -> QueryResult<usize> {
+    use crate::db::schema::pending_notifications::dsl::*;
+
+    diesel::delete(
+        pending_notifications
+            .filter(user_id.eq(id as i32))
+            .filter(sender_id.eq(sender as i32)),
+    )
+    .execute(conn)
+}
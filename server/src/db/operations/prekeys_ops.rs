@@ -0,0 +1,77 @@
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::prekeys_model::{DevicePreKeys, NewDevicePreKeys, SavedPreKey};
+use crate::db::schema::{device_prekeys, one_time_prekeys};
+
+/// Saves the prekeys of a device, replacing the ones it published before
+pub fn upsert_device_prekeys(
+    conn: &mut PgConnection,
+    prekey_data: NewDevicePreKeys,
+) -> QueryResult<usize> {
+    diesel::insert_into(device_prekeys::table)
+        .values(&prekey_data)
+        .on_conflict(device_prekeys::device_id)
+        .do_update()
+        .set(&prekey_data)
+        .execute(conn)
+}
+
+pub fn get_device_prekeys(
+    conn: &mut PgConnection,
+    ids: Vec<i32>,
+) -> QueryResult<Vec<DevicePreKeys>> {
+    use crate::db::schema::device_prekeys::dsl::*;
+
+    device_prekeys
+        .filter(device_id.eq_any(ids))
+        .order(device_id.asc())
+        .select(DevicePreKeys::as_select())
+        .load(conn)
+}
+
+/// Adds one-time prekeys of a device. Keys with an ID that already exists are ignored
+pub fn add_one_time_prekeys(
+    conn: &mut PgConnection,
+    prekeys: Vec<SavedPreKey>,
+) -> QueryResult<usize> {
+    diesel::insert_into(one_time_prekeys::table)
+        .values(prekeys)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn count_one_time_prekeys(conn: &mut PgConnection, id: usize) -> QueryResult<i64> {
+    use crate::db::schema::one_time_prekeys::dsl::*;
+
+    one_time_prekeys
+        .filter(device_id.eq(id as i32))
+        .count()
+        .get_result(conn)
+}
+
+/// Removes the oldest one-time prekey of a device and returns it
+pub fn take_one_time_prekey(conn: &mut PgConnection, id: i32) -> QueryResult<Option<SavedPreKey>> {
+    use crate::db::schema::one_time_prekeys::dsl::*;
+
+    let Some(prekey) = one_time_prekeys
+        .filter(device_id.eq(id))
+        .order(key_id.asc())
+        .select(SavedPreKey::as_select())
+        .first(conn)
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    diesel::delete(
+        one_time_prekeys
+            .filter(device_id.eq(id))
+            .filter(key_id.eq(prekey.key_id)),
+    )
+    .execute(conn)?;
+
+    Ok(Some(prekey))
+}
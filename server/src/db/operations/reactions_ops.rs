@@ -0,0 +1,71 @@
+use diesel::dsl::now;
+use diesel::upsert::excluded;
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+
+use crate::db::reactions_model::{NewMessageReaction, SavedReaction};
+
+/// Saves the reaction or replaces the earlier reaction of the user on the message
+pub fn add_message_reaction(
+    conn: &mut PgConnection,
+    reaction_data: NewMessageReaction,
+) -> QueryResult<usize> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    diesel::insert_into(message_reactions)
+        .values(reaction_data)
+        .on_conflict((message_group, message_number, user_id))
+        .do_update()
+        .set((emoji.eq(excluded(emoji)), created_at.eq(now)))
+        .execute(conn)
+}
+
+pub fn remove_message_reaction(
+    conn: &mut PgConnection,
+    group: String,
+    number: usize,
+    id: usize,
+) -> QueryResult<usize> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    diesel::delete(
+        message_reactions
+            .filter(message_group.eq(group))
+            .filter(message_number.eq(number as i32))
+            .filter(user_id.eq(id as i32)),
+    )
+    .execute(conn)
+}
+
+/// Reactions of the given messages of a group, the oldest first
+pub fn get_message_reactions(
+    conn: &mut PgConnection,
+    group: String,
+    numbers: Vec<i32>,
+) -> QueryResult<Vec<SavedReaction>> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    message_reactions
+        .filter(message_group.eq(group))
+        .filter(message_number.eq_any(numbers))
+        .order(created_at.asc())
+        .select(SavedReaction::as_select())
+        .load(conn)
+}
+
+/// Removes every reaction of a message
+pub fn remove_all_message_reactions(
+    conn: &mut PgConnection,
+    group: String,
+    number: usize,
+) -> QueryResult<usize> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    diesel::delete(
+        message_reactions
+            .filter(message_group.eq(group))
+            .filter(message_number.eq(number as i32)),
+    )
+    .execute(conn)
+}
@@ -0,0 +1,43 @@
+use diesel::{
+    Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::devices_model::{Device, NewDevice};
+use crate::db::operations::create_new_device;
+use crate::db::schema::{devices, user_keys};
+use crate::db::user_keys_model::NewUserKey;
+
+/// Adds the key to the key history and makes it the current key of the device
+pub fn add_device_key(conn: &mut PgConnection, key_data: NewUserKey) -> QueryResult<Device> {
+    let new_key_id: i32 = diesel::insert_into(user_keys::table)
+        .values(&key_data)
+        .returning(user_keys::key_id)
+        .get_result(conn)?;
+
+    diesel::update(devices::table.filter(devices::device_id.eq(key_data.device_id)))
+        .set((
+            devices::rsa_public_key.eq(key_data.rsa_public_key),
+            devices::key_id.eq(new_key_id),
+        ))
+        .returning(Device::as_returning())
+        .get_result(conn)
+}
+
+/// Creates a device with its key as the first key in its key history
+pub fn create_device_with_key(
+    conn: &mut PgConnection,
+    device_data: NewDevice,
+) -> QueryResult<Device> {
+    conn.transaction(|conn| {
+        let device = create_new_device(conn, device_data)?;
+        add_device_key(
+            conn,
+            NewUserKey::new(
+                device.user_id as usize,
+                device.device_id as usize,
+                device.rsa_public_key,
+            ),
+        )
+    })
+}
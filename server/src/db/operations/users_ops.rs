@@ -1,60 +1,89 @@
-use diesel::{update, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use chrono::NaiveDateTime;
+use diesel::{
+    update, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
 
 use crate::db::schema::users;
 use crate::db::users_model::User;
+use crate::utils::HashedToken;
 
-pub fn create_new_user(conn: &mut PgConnection, user_data: User) {
+pub fn create_new_user(conn: &mut PgConnection, user_data: User) -> QueryResult<User> {
     diesel::insert_into(users::table)
         .values(user_data)
         .returning(User::as_returning())
         .get_result(conn)
-        .unwrap();
 }
 
-pub fn get_user_with_id(conn: &mut PgConnection, id: usize) -> Option<User> {
+pub fn get_user_with_id(conn: &mut PgConnection, id: usize) -> QueryResult<Option<User>> {
     use crate::db::schema::users::dsl::*;
 
-    let result = users
+    users
         .filter(user_id.eq(id as i32))
         .limit(1)
         .select(User::as_select())
-        .first(conn);
-
-    match result {
-        Ok(user) => Some(user),
-        Err(_) => None,
-    }
+        .first(conn)
+        .optional()
 }
 
-pub fn get_user_with_token(conn: &mut PgConnection, token: String) -> Option<User> {
+pub fn get_user_with_token_id(
+    conn: &mut PgConnection,
+    id_prefix: &str,
+) -> QueryResult<Option<User>> {
     use crate::db::schema::users::dsl::*;
 
-    let result = users
-        .filter(user_token.eq(token))
+    users
+        .filter(token_id.eq(id_prefix))
         .limit(1)
         .select(User::as_select())
-        .first(conn);
-
-    match result {
-        Ok(user) => Some(user),
-        Err(_) => None,
-    }
+        .first(conn)
+        .optional()
 }
 
-pub fn update_user_name(conn: &mut PgConnection, id: usize, new_name: &str) {
+pub fn update_user_name(conn: &mut PgConnection, id: usize, new_name: &str) -> QueryResult<usize> {
     use crate::db::schema::users::dsl::*;
 
     update(users.find(id as i32))
         .set(user_name.eq(new_name))
         .execute(conn)
-        .unwrap();
 }
 
-pub fn update_user_image_link(conn: &mut PgConnection, id: usize, new_image_link: Option<String>) {
+pub fn update_user_image_link(
+    conn: &mut PgConnection,
+    id: usize,
+    new_image_link: Option<String>,
+) -> QueryResult<usize> {
     use crate::db::schema::users::dsl::*;
 
     update(users.find(id as i32))
         .set(image_link.eq(new_image_link))
         .execute(conn)
-        .unwrap();
+}
+
+pub fn update_user_token(
+    conn: &mut PgConnection,
+    id: usize,
+    new_token: HashedToken,
+) -> QueryResult<usize> {
+    use crate::db::schema::users::dsl::*;
+
+    update(users.find(id as i32))
+        .set((
+            token_id.eq(new_token.token_id),
+            token_salt.eq(new_token.token_salt),
+            token_hash.eq(new_token.token_hash),
+        ))
+        .execute(conn)
+}
+
+pub fn update_user_last_seen(
+    conn: &mut PgConnection,
+    id: usize,
+    seen_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    use crate::db::schema::users::dsl::*;
+
+    update(users.find(id as i32))
+        .set(last_seen_at.eq(seen_at))
+        .execute(conn)
 }
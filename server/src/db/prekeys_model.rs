@@ -0,0 +1,80 @@
+use chirp_protocol::{OneTimePreKey, PreKeyBundle, PreKeyUpload};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::{device_prekeys, one_time_prekeys};
+
+#[derive(Queryable, Selectable, Identifiable)]
+#[diesel(table_name = device_prekeys, primary_key(device_id))]
+pub struct DevicePreKeys {
+    pub device_id: i32,
+    pub identity_key: Vec<u8>,
+    pub signing_key: Vec<u8>,
+    pub signed_prekey_id: i64,
+    pub signed_prekey: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl DevicePreKeys {
+    pub fn into_bundle(self, user_id: usize, one_time_prekey: Option<SavedPreKey>) -> PreKeyBundle {
+        PreKeyBundle {
+            user_id: user_id as u64,
+            device_id: self.device_id as u64,
+            identity_key: self.identity_key,
+            signing_key: self.signing_key,
+            signed_prekey_id: self.signed_prekey_id as u64,
+            signed_prekey: self.signed_prekey,
+            signature: self.signature,
+            one_time_prekey: one_time_prekey.map(|key| key.into_one_time_prekey()),
+        }
+    }
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = device_prekeys)]
+pub struct NewDevicePreKeys {
+    pub device_id: i32,
+    pub identity_key: Vec<u8>,
+    pub signing_key: Vec<u8>,
+    pub signed_prekey_id: i64,
+    pub signed_prekey: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl NewDevicePreKeys {
+    pub fn new(device_id: usize, upload: &PreKeyUpload, updated_at: NaiveDateTime) -> Self {
+        NewDevicePreKeys {
+            device_id: device_id as i32,
+            identity_key: upload.identity_key.to_owned(),
+            signing_key: upload.signing_key.to_owned(),
+            signed_prekey_id: upload.signed_prekey_id as i64,
+            signed_prekey: upload.signed_prekey.to_owned(),
+            signature: upload.signature.to_owned(),
+            updated_at,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = one_time_prekeys)]
+pub struct SavedPreKey {
+    pub device_id: i32,
+    pub key_id: i64,
+    pub public_key: Vec<u8>,
+}
+
+impl SavedPreKey {
+    pub fn new(device_id: usize, prekey: OneTimePreKey) -> Self {
+        SavedPreKey {
+            device_id: device_id as i32,
+            key_id: prekey.key_id as i64,
+            public_key: prekey.public_key,
+        }
+    }
+
+    pub fn into_one_time_prekey(self) -> OneTimePreKey {
+        OneTimePreKey::new(self.key_id as u64, self.public_key)
+    }
+}
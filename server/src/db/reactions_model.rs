@@ -0,0 +1,36 @@
+use diesel::prelude::*;
+
+use crate::db::schema::message_reactions;
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = message_reactions)]
+pub struct SavedReaction {
+    pub message_number: i32,
+    pub user_id: i32,
+    pub emoji: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = message_reactions)]
+pub struct NewMessageReaction {
+    pub message_group: String,
+    pub message_number: i32,
+    pub user_id: i32,
+    pub emoji: String,
+}
+
+impl NewMessageReaction {
+    pub fn new(
+        message_group: String,
+        message_number: usize,
+        user_id: usize,
+        emoji: String,
+    ) -> Self {
+        NewMessageReaction {
+            message_group,
+            message_number: message_number as i32,
+            user_id: user_id as i32,
+            emoji,
+        }
+    }
+}
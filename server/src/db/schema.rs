@@ -1,5 +1,69 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    attachments (attachment_id) {
+        #[max_length = 32]
+        attachment_id -> Varchar,
+        uploaded_by -> Int4,
+        size -> Int8,
+        uploaded_size -> Int8,
+        file_name -> Bytea,
+        name_nonce -> Bytea,
+        file_nonce -> Bytea,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    conversation_members (conversation_id, user_id) {
+        conversation_id -> Int4,
+        user_id -> Int4,
+        joined_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    conversations (conversation_id) {
+        conversation_id -> Int4,
+        #[max_length = 250]
+        conversation_name -> Varchar,
+        created_by -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    device_prekeys (device_id) {
+        device_id -> Int4,
+        identity_key -> Bytea,
+        signing_key -> Bytea,
+        signed_prekey_id -> Int8,
+        signed_prekey -> Bytea,
+        signature -> Bytea,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    devices (device_id) {
+        device_id -> Int4,
+        user_id -> Int4,
+        rsa_public_key -> Text,
+        created_at -> Timestamptz,
+        key_id -> Int4,
+    }
+}
+
+diesel::table! {
+    message_keys (message_id, device_id) {
+        message_id -> Int4,
+        device_id -> Int4,
+        encrypted_key -> Bytea,
+        ratchet_header -> Nullable<Text>,
+        key_id -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     messages (message_group, message_number) {
         message_id -> Int4,
@@ -7,14 +71,80 @@ diesel::table! {
         message_group -> Varchar,
         message_number -> Int4,
         message_sender -> Int4,
-        message_receiver -> Int4,
+        message_receiver -> Nullable<Int4>,
         created_at -> Timestamptz,
         sender_message -> Nullable<Bytea>,
         receiver_message -> Nullable<Bytea>,
-        sender_key -> Nullable<Bytea>,
-        receiver_key -> Nullable<Bytea>,
         sender_nonce -> Nullable<Bytea>,
         receiver_nonce -> Nullable<Bytea>,
+        delivered_at -> Nullable<Timestamptz>,
+        read_at -> Nullable<Timestamptz>,
+        edited_at -> Nullable<Timestamptz>,
+        reply_to -> Nullable<Int4>,
+        #[max_length = 32]
+        attachment_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    message_reactions (message_group, message_number, user_id) {
+        #[max_length = 40]
+        message_group -> Varchar,
+        message_number -> Int4,
+        user_id -> Int4,
+        #[max_length = 32]
+        emoji -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    message_revisions (revision_id) {
+        revision_id -> Int4,
+        message_id -> Int4,
+        sender_message -> Bytea,
+        receiver_message -> Nullable<Bytea>,
+        sender_nonce -> Bytea,
+        receiver_nonce -> Nullable<Bytea>,
+        written_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    one_time_prekeys (device_id, key_id) {
+        device_id -> Int4,
+        key_id -> Int8,
+        public_key -> Bytea,
+    }
+}
+
+diesel::table! {
+    pending_notifications (user_id, sender_id) {
+        user_id -> Int4,
+        sender_id -> Int4,
+        unread_count -> Int4,
+        last_message_number -> Int4,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    revision_keys (revision_id, device_id) {
+        revision_id -> Int4,
+        device_id -> Int4,
+        encrypted_key -> Bytea,
+        ratchet_header -> Nullable<Text>,
+        key_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    user_keys (key_id) {
+        key_id -> Int4,
+        user_id -> Int4,
+        device_id -> Int4,
+        rsa_public_key -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -24,10 +154,44 @@ diesel::table! {
         #[max_length = 250]
         user_name -> Varchar,
         image_link -> Nullable<Text>,
-        #[max_length = 70]
-        user_token -> Varchar,
-        rsa_public_key -> Text,
+        #[max_length = 16]
+        token_id -> Varchar,
+        #[max_length = 32]
+        token_salt -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        last_seen_at -> Nullable<Timestamptz>,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(messages, users,);
+diesel::joinable!(attachments -> users (uploaded_by));
+diesel::joinable!(conversation_members -> conversations (conversation_id));
+diesel::joinable!(conversation_members -> users (user_id));
+diesel::joinable!(conversations -> users (created_by));
+diesel::joinable!(device_prekeys -> devices (device_id));
+diesel::joinable!(devices -> users (user_id));
+diesel::joinable!(message_keys -> devices (device_id));
+diesel::joinable!(message_reactions -> users (user_id));
+diesel::joinable!(messages -> attachments (attachment_id));
+diesel::joinable!(one_time_prekeys -> devices (device_id));
+diesel::joinable!(revision_keys -> devices (device_id));
+diesel::joinable!(revision_keys -> message_revisions (revision_id));
+diesel::joinable!(user_keys -> devices (device_id));
+diesel::joinable!(user_keys -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    attachments,
+    conversation_members,
+    conversations,
+    device_prekeys,
+    devices,
+    message_keys,
+    message_reactions,
+    message_revisions,
+    messages,
+    one_time_prekeys,
+    pending_notifications,
+    revision_keys,
+    user_keys,
+    users,
+);
@@ -0,0 +1,21 @@
+use diesel::prelude::*;
+
+use crate::db::schema::user_keys;
+
+#[derive(Insertable)]
+#[diesel(table_name = user_keys)]
+pub struct NewUserKey {
+    pub user_id: i32,
+    pub device_id: i32,
+    pub rsa_public_key: String,
+}
+
+impl NewUserKey {
+    pub fn new(user_id: usize, device_id: usize, rsa_public_key: String) -> Self {
+        NewUserKey {
+            user_id: user_id as i32,
+            device_id: device_id as i32,
+            rsa_public_key,
+        }
+    }
+}
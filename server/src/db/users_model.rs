@@ -1,16 +1,21 @@
+use chirp_protocol::{DeviceKey, FullUserData};
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
 
 use crate::db::schema::users;
+use crate::utils::HashedToken;
 
-#[derive(Queryable, Selectable, Insertable, Identifiable, Clone, Serialize, Deserialize)]
+#[derive(Queryable, Selectable, Insertable, Identifiable, Clone)]
 #[diesel(primary_key(user_id))]
 pub struct User {
     pub user_id: i32,
     pub user_name: String,
     pub image_link: Option<String>,
-    pub user_token: String,
-    pub rsa_public_key: String,
+    pub token_id: String,
+    pub token_salt: String,
+    pub token_hash: String,
+    // When the last session of the user disconnected
+    pub last_seen_at: Option<NaiveDateTime>,
 }
 
 impl User {
@@ -19,13 +24,23 @@ impl User {
             user_id: 0,
             user_name: String::new(),
             image_link: None,
-            user_token: String::new(),
-            rsa_public_key: String::new(),
+            token_id: String::new(),
+            token_salt: String::new(),
+            token_hash: String::new(),
+            last_seen_at: None,
         }
     }
 
-    pub fn from_json(data: String) -> Self {
-        serde_json::from_str(&data).unwrap()
+    pub fn from_user_data(data: FullUserData) -> Self {
+        User {
+            user_id: data.user_id as i32,
+            user_name: data.user_name,
+            image_link: data.image_link,
+            token_id: String::new(),
+            token_salt: String::new(),
+            token_hash: String::new(),
+            last_seen_at: None,
+        }
     }
 
     pub fn update_id(self, id: usize) -> Self {
@@ -33,22 +48,31 @@ impl User {
             user_id: id as i32,
             user_name: self.user_name,
             image_link: self.image_link,
-            user_token: self.user_token,
-            rsa_public_key: self.rsa_public_key,
+            token_id: self.token_id,
+            token_salt: self.token_salt,
+            token_hash: self.token_hash,
+            last_seen_at: self.last_seen_at,
         }
     }
 
-    pub fn update_token(self, token: String) -> Self {
+    pub fn update_token(self, token: HashedToken) -> Self {
         User {
             user_id: self.user_id,
             user_name: self.user_name,
             image_link: self.image_link,
-            user_token: token,
-            rsa_public_key: self.rsa_public_key,
+            token_id: token.token_id,
+            token_salt: token.token_salt,
+            token_hash: token.token_hash,
+            last_seen_at: self.last_seen_at,
         }
     }
 
-    pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    pub fn into_user_data(self, devices: Vec<DeviceKey>) -> FullUserData {
+        FullUserData::new(
+            self.user_id as u64,
+            self.user_name,
+            self.image_link,
+            devices,
+        )
     }
 }
@@ -1,11 +1,13 @@
 mod db;
 mod server;
 mod session;
+mod storage;
 mod utils;
 
 use actix::*;
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
+use db::{create_db_pool, DbExecutor};
 use dotenvy::dotenv;
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
@@ -15,6 +17,9 @@ use std::io::BufReader;
 use std::time::Instant;
 use tracing::error;
 
+/// Number of threads running DB queries
+const DB_THREADS: usize = 4;
+
 async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
@@ -23,8 +28,8 @@ async fn chat_route(
     ws::start(
         session::WsChatSession {
             id: 0,
-            user_id: 0,
             hb: Instant::now(),
+            handshake_done: false,
             addr: srv.get_ref().clone(),
         },
         &req,
@@ -64,7 +69,9 @@ async fn main() -> std::io::Result<()> {
     tracing_subscriber::fmt::init();
     dotenv().ok();
 
-    let server = ChatServer::new().start();
+    let pool = create_db_pool();
+    let db = SyncArbiter::start(DB_THREADS, move || DbExecutor(pool.clone()));
+    let server = ChatServer::new(db).start();
     let config = load_rustls_config();
 
     HttpServer::new(move || {
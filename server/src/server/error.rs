@@ -0,0 +1,113 @@
+use actix::MailboxError;
+use chirp_protocol::{ErrorCode, ErrorResponse};
+use tracing::error;
+
+/// A failed request that gets sent back to the WS session it came from
+pub struct RequestError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+pub type RequestResult = Result<(), RequestError>;
+
+impl RequestError {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        error!("{message}. Discarding request");
+        RequestError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_token() -> Self {
+        RequestError::new(ErrorCode::InvalidToken, "Invalid user token received")
+    }
+
+    pub fn not_authenticated() -> Self {
+        RequestError::new(
+            ErrorCode::NotAuthenticated,
+            "Request received on a session that is not authenticated",
+        )
+    }
+
+    pub fn unknown_user(user_id: usize) -> Self {
+        RequestError::new(
+            ErrorCode::UnknownUser,
+            &format!("User with ID {user_id} does not exist"),
+        )
+    }
+
+    pub fn unknown_device(device_id: usize) -> Self {
+        RequestError::new(
+            ErrorCode::UnknownDevice,
+            &format!("Device with ID {device_id} is not registered with this user"),
+        )
+    }
+
+    pub fn unknown_group(group_id: usize) -> Self {
+        RequestError::new(
+            ErrorCode::UnknownGroup,
+            &format!("Group with ID {group_id} does not exist or the user is not a member of it"),
+        )
+    }
+
+    pub fn unknown_attachment(attachment_id: &str) -> Self {
+        RequestError::new(
+            ErrorCode::UnknownAttachment,
+            &format!("Attachment {attachment_id} does not exist or the user cannot access it"),
+        )
+    }
+
+    pub fn not_allowed(message: &str) -> Self {
+        RequestError::new(ErrorCode::NotAllowed, message)
+    }
+
+    pub fn malformed(message: &str) -> Self {
+        RequestError::new(ErrorCode::MalformedRequest, message)
+    }
+
+    pub fn into_response(self) -> ErrorResponse {
+        ErrorResponse::new(self.code, self.message)
+    }
+}
+
+impl From<diesel::result::Error> for RequestError {
+    fn from(e: diesel::result::Error) -> Self {
+        // The DB error itself is only logged, the client gets a generic message
+        error!("DB operation failed. Reason: {e}");
+        RequestError {
+            code: ErrorCode::DatabaseError,
+            message: String::from("The server failed to process the request"),
+        }
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for RequestError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        error!("Failed to get a DB connection from the pool. Reason: {e}");
+        RequestError {
+            code: ErrorCode::DatabaseError,
+            message: String::from("The server failed to process the request"),
+        }
+    }
+}
+
+impl From<std::io::Error> for RequestError {
+    fn from(e: std::io::Error) -> Self {
+        error!("Attachment file operation failed. Reason: {e}");
+        RequestError {
+            code: ErrorCode::StorageError,
+            message: String::from("The server failed to process the attachment"),
+        }
+    }
+}
+
+impl From<MailboxError> for RequestError {
+    fn from(e: MailboxError) -> Self {
+        error!("Failed to reach the DB executor. Reason: {e}");
+        RequestError {
+            code: ErrorCode::DatabaseError,
+            message: String::from("The server failed to process the request"),
+        }
+    }
+}
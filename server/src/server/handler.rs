@@ -1,465 +1,1972 @@
 use actix::prelude::*;
-use chrono::NaiveDateTime;
+use chirp_protocol::{
+    Attachment, AttachmentChunk, DeleteMessage, DeletedMessageData, DeviceKey, EditHistoryRequest,
+    EncryptedKey, ErrorCode, ErrorResponse, FullUserData, GroupData, GroupMember, ImageUpdate,
+    MessageAck, MessageData, MessageReaction, MessageReceipt, MessageSyncData, MessageSyncRequest,
+    NameUpdate, NewGroup, PreKeyUpload, Presence, Reaction, ResponseFrame, ServerResponse,
+    UnreadChat, UserIDs, ATTACHMENT_CHUNK_SIZE, MAX_ATTACHMENT_SIZE,
+};
+use chrono::{Duration, NaiveDateTime, SubsecRound, Utc};
 use diesel::pg::PgConnection;
-use diesel::prelude::*;
+use diesel::Connection;
 use rand::rngs::ThreadRng;
 use rand::Rng;
-use std::collections::HashMap;
-use std::env;
+use std::collections::{HashMap, HashSet};
+use subtle::ConstantTimeEq;
 use tracing::{error, info};
 
 use crate::db::{
-    create_new_message, create_new_user, delete_message_with_number,
-    get_deleted_messages_from_number, get_last_message_number, get_messages_from_number,
-    get_user_with_id, get_user_with_token, update_user_image_link, update_user_name, NewMessage,
-    User,
+    add_conversation_members, add_device_key, add_message_reaction, add_one_time_prekeys,
+    add_pending_notification, count_one_time_prekeys, create_device_with_key, create_message_keys,
+    create_message_revision, create_new_attachment, create_new_conversation, create_new_message,
+    create_new_user, delete_message_with_number, get_all_message_keys, get_attachment_with_id,
+    get_attachments_with_ids, get_conversation_members, get_conversation_with_id,
+    get_deleted_messages_from_number, get_device_prekeys, get_device_with_id,
+    get_edited_messages_from_number, get_last_message_number, get_message_keys,
+    get_message_reactions, get_message_revisions, get_message_with_attachment,
+    get_message_with_number, get_messages_from_number, get_pending_notifications,
+    get_revision_keys, get_user_conversations, get_user_devices, get_user_with_id,
+    get_user_with_token_id, lock_attachment_with_id, remove_all_message_reactions,
+    remove_attachment, remove_conversation_member, remove_message_reaction,
+    remove_pending_notification, remove_stale_uploads, replace_message_content,
+    take_one_time_prekey, update_message_receipts, update_uploaded_size, update_user_image_link,
+    update_user_last_seen, update_user_name, update_user_token, upsert_device_prekeys,
+    Conversation, DbExecutor, Execute, MessageKey, NewAttachment, NewConversation,
+    NewConversationMember, NewDevice, NewDevicePreKeys, NewMessage, NewMessageReaction,
+    NewMessageRevision, NewPendingNotification, NewUserKey, SavedAttachment, SavedPreKey, User,
+};
+use crate::server::{IDInfo, Message, RequestError, RequestResult};
+use crate::storage::{
+    create_attachment_file, read_attachment_chunk, remove_attachment_file, write_attachment_chunk,
 };
-use crate::server::{
-    DeleteMessage, DeletedMessageData, IDInfo, ImageUpdate, Message, MessageData, NameUpdate,
-    SendUserData, SyncMessage, SyncMessageData, WSData,
+use crate::utils::{
+    create_group_message_group, create_message_group, generate_attachment_id, generate_user_token,
+    hash_token_secret, split_token,
 };
-use crate::utils::{create_message_group, generate_user_token};
+
+/// Uploads not completed within this time get removed along with their file
+const STALE_UPLOAD_AGE: Duration = Duration::days(1);
+
+/// The future of a request that first runs on the DB executor and then finishes on the chat server
+pub type RequestFuture = ResponseActFuture<ChatServer, RequestResult>;
 
 pub struct ChatServer {
     // {WS session ID: (IDInfo, WS Receiver)}
     pub sessions: HashMap<usize, (IDInfo, Recipient<Message>)>,
-    // The gui side has 1 WS session per user added
-    // 1 owner session + 1 for every single users added for chatting
-    // If there are 2 users chatting with each other, there will be total 4 sessions
-    // user 1: [user 1/owner session, a WS session containing user 2 ID]
-    // user 2: [user 2/owner session, a WS session containing user 1 ID]
-    // {User ID: [All the sessions this user added including owner session]}
-    pub user_session: HashMap<usize, Vec<WSData>>,
+    // Every chat of a client goes through a single WS session. Each frame names the chat it belongs to
+    // {User ID: [All the WS session IDs authenticated as this user]}
+    pub user_session: HashMap<usize, Vec<usize>>,
     pub rng: ThreadRng,
-    conn: PgConnection,
+    db: Addr<DbExecutor>,
 }
 
-impl ChatServer {
-    pub fn new() -> ChatServer {
-        info!("New Chat Server getting created");
+/// Get the user the token belongs to
+fn user_from_token(conn: &mut PgConnection, token: String) -> Result<User, RequestError> {
+    let (token_id, secret) = split_token(&token).ok_or_else(RequestError::invalid_token)?;
+    let user = get_user_with_token_id(conn, token_id)?.ok_or_else(RequestError::invalid_token)?;
+
+    let token_hash = hash_token_secret(&user.token_salt, secret);
+    if bool::from(token_hash.as_bytes().ct_eq(user.token_hash.as_bytes())) {
+        Ok(user)
+    } else {
+        Err(RequestError::invalid_token())
+    }
+}
+
+/// Get a user that is expected to exist
+fn existing_user(conn: &mut PgConnection, user_id: usize) -> Result<User, RequestError> {
+    get_user_with_id(conn, user_id)?.ok_or_else(|| RequestError::unknown_user(user_id))
+}
+
+/// Get the profile data of a user along with the keys of every device of the user
+fn profile_with_devices(conn: &mut PgConnection, user: User) -> Result<FullUserData, RequestError> {
+    let devices = get_user_devices(conn, user.user_id as usize)?
+        .into_iter()
+        .map(|device| device.into_device_key())
+        .collect();
 
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    Ok(user.into_user_data(devices))
+}
+
+/// The chat a request targets. Group IDs never overlap with user IDs
+enum ChatTarget {
+    User(usize),
+    // The group ID and every member of the group
+    Group(usize, Vec<usize>),
+}
+
+impl ChatTarget {
+    /// The message group the messages of this chat are saved with
+    fn message_group(&self, owner_id: usize) -> String {
+        match self {
+            ChatTarget::User(user_id) => create_message_group(owner_id, *user_id),
+            ChatTarget::Group(group_id, _) => create_group_message_group(*group_id),
+        }
+    }
+}
+
+/// Find whether the chat ID belongs to a user or a group the owner is a member of
+fn resolve_chat(
+    conn: &mut PgConnection,
+    owner_id: usize,
+    chat_id: usize,
+) -> Result<ChatTarget, RequestError> {
+    if get_conversation_with_id(conn, chat_id)?.is_some() {
+        let members = group_members(conn, owner_id, chat_id)?;
+        return Ok(ChatTarget::Group(chat_id, members));
+    }
+    Ok(ChatTarget::User(chat_id))
+}
 
-        let conn = PgConnection::establish(&database_url)
-            .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+/// Get every member of a group the owner is a member of
+fn group_members(
+    conn: &mut PgConnection,
+    owner_id: usize,
+    group_id: usize,
+) -> Result<Vec<usize>, RequestError> {
+    let members = get_conversation_members(conn, group_id)?;
+
+    if !members.contains(&owner_id) {
+        return Err(RequestError::unknown_group(group_id));
+    }
+    Ok(members)
+}
+
+/// Get the group data along with the profile and device keys of every member
+fn group_with_members(
+    conn: &mut PgConnection,
+    conversation: Conversation,
+) -> Result<GroupData, RequestError> {
+    let group_id = conversation.conversation_id as usize;
+
+    let mut members = Vec::new();
+    for member_id in get_conversation_members(conn, group_id)? {
+        let user = existing_user(conn, member_id)?;
+        members.push(profile_with_devices(conn, user)?);
+    }
+
+    Ok(GroupData::new(
+        group_id as u64,
+        conversation.conversation_name,
+        conversation.created_by as u64,
+        members,
+    ))
+}
+
+/// Generate a random ID that is not used by any user or group
+fn generate_chat_id(conn: &mut PgConnection) -> Result<usize, RequestError> {
+    let mut rng = rand::thread_rng();
+
+    loop {
+        let chat_id = rng.gen_range(1..=2_147_483_647) as usize;
+
+        if get_user_with_id(conn, chat_id)?.is_none()
+            && get_conversation_with_id(conn, chat_id)?.is_none()
+        {
+            return Ok(chat_id);
+        }
+        info!("Generated ID already exist. Creating a new ID");
+    }
+}
+
+/// Every user that shares a group with the given user
+fn group_co_members(conn: &mut PgConnection, user_id: usize) -> Result<Vec<usize>, RequestError> {
+    let mut co_members = HashSet::new();
+    for conversation in get_user_conversations(conn, user_id)? {
+        let members = get_conversation_members(conn, conversation.conversation_id as usize)?;
+        co_members.extend(members.into_iter().filter(|id| *id != user_id));
+    }
+    Ok(co_members.into_iter().collect())
+}
+
+/// Converts the saved messages of a chat to sendable data.
+/// Only the key of the requesting device is sent, no other device can use the rest
+fn messages_with_keys(
+    conn: &mut PgConnection,
+    messages: Vec<crate::db::Message>,
+    device_id: usize,
+    chat_id: u64,
+) -> Result<Vec<MessageData>, RequestError> {
+    let message_ids = messages.iter().map(|msg| msg.message_id).collect();
+    let message_numbers = messages.iter().map(|msg| msg.message_number).collect();
+
+    // Every synced message belongs to the same group
+    let mut reactions: HashMap<i32, Vec<Reaction>> = HashMap::new();
+    if let Some(message) = messages.first() {
+        let group = message.message_group.to_owned();
+        for reaction in get_message_reactions(conn, group, message_numbers)? {
+            reactions
+                .entry(reaction.message_number)
+                .or_default()
+                .push(Reaction::new(reaction.user_id as u64, reaction.emoji));
+        }
+    }
+
+    let attachment_ids = messages
+        .iter()
+        .filter_map(|msg| msg.attachment_id.to_owned())
+        .collect();
+    let mut attachments: HashMap<String, Attachment> =
+        get_attachments_with_ids(conn, attachment_ids)?
+            .into_iter()
+            .map(|attachment| {
+                (
+                    attachment.attachment_id.to_owned(),
+                    attachment.into_attachment(),
+                )
+            })
+            .collect();
+
+    let mut device_keys: HashMap<i32, Vec<EncryptedKey>> = HashMap::new();
+    for key in get_message_keys(conn, message_ids, device_id)? {
+        device_keys
+            .entry(key.message_id)
+            .or_default()
+            .push(key.into_encrypted_key());
+    }
+
+    let message_data = messages
+        .into_iter()
+        .map(|msg| {
+            let receipt = msg.receipt();
+            MessageData {
+                created_at: msg.created_at.to_string(),
+                from_user: msg.message_sender as u64,
+                // Group messages have no receiver, they belong to the synced group
+                to_user: msg
+                    .message_receiver
+                    .map_or(chat_id, |receiver| receiver as u64),
+                sender_message: msg.sender_message,
+                receiver_message: msg.receiver_message,
+                device_keys: Some(device_keys.remove(&msg.message_id).unwrap_or_default()),
+                sender_nonce: msg.sender_nonce,
+                receiver_nonce: msg.receiver_nonce,
+                message_number: msg.message_number as u64,
+                receipt,
+                edited_at: msg.edited_at.map(|time| time.to_string()),
+                reply_to: msg.reply_to.map(|number| number as u64),
+                reactions: reactions.remove(&msg.message_number).unwrap_or_default(),
+                attachment: msg.attachment_id.and_then(|id| attachments.remove(&id)),
+            }
+        })
+        .collect();
+
+    Ok(message_data)
+}
+
+/// Messages are timed by the server in UTC. Clients parse the time with millisecond precision
+fn message_created_at() -> NaiveDateTime {
+    Utc::now().naive_utc().trunc_subsecs(3)
+}
+
+/// Check that every key was encrypted for one of the allowed users' devices and no device got two
+fn validate_device_keys(
+    conn: &mut PgConnection,
+    device_keys: &[EncryptedKey],
+    allowed_users: &[usize],
+) -> Result<(), RequestError> {
+    let mut allowed_devices: HashSet<u64> = HashSet::new();
+    for user_id in allowed_users {
+        for device in get_user_devices(conn, *user_id)? {
+            allowed_devices.insert(device.device_id as u64);
+        }
+    }
+
+    if device_keys.is_empty()
+        || device_keys
+            .iter()
+            .any(|key| !allowed_devices.contains(&key.device_id))
+    {
+        return Err(RequestError::malformed(
+            "Message received with keys for unknown devices",
+        ));
+    }
+
+    // Only one key per device can be saved for a message
+    let mut seen_devices = HashSet::new();
+    if !device_keys
+        .iter()
+        .all(|key| seen_devices.insert(key.device_id))
+    {
+        return Err(RequestError::malformed(
+            "Message received with multiple keys for a device",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a replied message exists in the chat the reply is sent to
+fn validate_reply_to(
+    conn: &mut PgConnection,
+    message_group: &str,
+    reply_to: Option<u64>,
+) -> Result<(), RequestError> {
+    let Some(reply_to) = reply_to else {
+        return Ok(());
+    };
+
+    if get_message_with_number(conn, message_group.to_owned(), reply_to as usize)?.is_none() {
+        return Err(RequestError::malformed(
+            "Message replies to a message that does not exist",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the attachment was fully uploaded by the owner and was not sent with another message.
+/// Returns the saved attachment so the chat gets what the server has
+fn validate_attachment(
+    conn: &mut PgConnection,
+    owner_id: usize,
+    attachment: &Option<Attachment>,
+) -> Result<Option<Attachment>, RequestError> {
+    let Some(attachment) = attachment else {
+        return Ok(None);
+    };
+    let attachment_id = &attachment.attachment_id;
+
+    let saved_attachment = get_attachment_with_id(conn, attachment_id)?
+        .filter(|saved| saved.uploaded_by as usize == owner_id)
+        .ok_or_else(|| RequestError::unknown_attachment(attachment_id))?;
+
+    if !saved_attachment.is_complete() {
+        return Err(RequestError::malformed(
+            "Message received with an attachment that was not fully uploaded",
+        ));
+    }
+
+    if get_message_with_attachment(conn, attachment_id)?.is_some() {
+        return Err(RequestError::not_allowed(
+            "The attachment was already sent with another message",
+        ));
+    }
+    Ok(Some(saved_attachment.into_attachment()))
+}
+
+/// Only the uploader and the members of the chat the attachment was sent to can read it
+fn can_read_attachment(
+    conn: &mut PgConnection,
+    owner_id: usize,
+    attachment: &SavedAttachment,
+) -> Result<bool, RequestError> {
+    if attachment.uploaded_by as usize == owner_id {
+        return Ok(true);
+    }
+
+    let Some(message) = get_message_with_attachment(conn, &attachment.attachment_id)? else {
+        return Ok(false);
+    };
+
+    // Group messages have no receiver
+    if let Some(receiver) = message.message_receiver {
+        return Ok(receiver as usize == owner_id || message.message_sender as usize == owner_id);
+    }
+
+    let is_member = get_user_conversations(conn, owner_id)?.iter().any(|group| {
+        create_group_message_group(group.conversation_id as usize) == message.message_group
+    });
+    Ok(is_member)
+}
+
+impl ChatServer {
+    pub fn new(db: Addr<DbExecutor>) -> ChatServer {
+        info!("New Chat Server getting created");
 
         ChatServer {
             sessions: HashMap::new(),
             user_session: HashMap::new(),
             rng: rand::thread_rng(),
-            conn,
+            db,
         }
     }
 
-    /// Send a message to another WS session
-    pub fn send_message(&mut self, message_data: MessageData) {
-        let from_user_id;
+    /// Runs the query on the DB executor and passes the result to `then` back on the chat server
+    fn run_query<F, R, C>(&self, query: F, then: C) -> RequestFuture
+    where
+        F: FnOnce(&mut PgConnection) -> Result<R, RequestError> + Send + 'static,
+        R: Send + 'static,
+        C: FnOnce(&mut ChatServer, R) -> RequestResult + 'static,
+    {
+        Box::pin(
+            self.db
+                .send(Execute::new(query))
+                .into_actor(self)
+                .map(move |result, act, _| then(act, result??)),
+        )
+    }
 
-        if let Some(from_user) =
-            get_user_with_token(&mut self.conn, message_data.user_token.to_owned())
-        {
-            from_user_id = from_user.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+    /// Reply to a request of a WS session
+    fn reply(&self, ws_id: usize, request_id: u64, response: ServerResponse) {
+        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
+            receiver_ws.do_send(Message(
+                ResponseFrame::reply(request_id, response).to_json(),
+            ))
         }
+    }
 
-        let send_message_data = message_data.to_json();
-
-        let sender_message = message_data.sender_message.to_owned().unwrap();
-        let receiver_message = message_data.receiver_message.to_owned().unwrap();
-        let sender_key = message_data.sender_key.to_owned().unwrap();
-        let receiver_key = message_data.receiver_key.to_owned().unwrap();
-        let sender_nonce = message_data.sender_nonce.to_owned().unwrap();
-        let receiver_nonce = message_data.receiver_nonce.to_owned().unwrap();
-
-        let to_user_id = message_data.to_user;
-        let mut conn_found = false;
-        let message_group = create_message_group(from_user_id, to_user_id);
-        let message_number = message_data.message_number;
-
-        let created_at =
-            NaiveDateTime::parse_from_str(&message_data.created_at, "%Y-%m-%d %H:%M:%S%.6f")
-                .unwrap();
-
-        let new_message_data = NewMessage::new(
-            message_group,
-            message_number,
-            sender_message,
-            receiver_message,
-            sender_key,
-            receiver_key,
-            sender_nonce,
-            receiver_nonce,
-            from_user_id,
-            to_user_id,
-            created_at,
+    /// Reply to a WS session with the reason its request failed
+    pub fn send_error(&self, ws_id: usize, request_id: u64, error_data: ErrorResponse) {
+        self.reply(ws_id, request_id, ServerResponse::Error(error_data));
+    }
+
+    /// Send a message to every device of the receiver and the other devices of the sender
+    pub fn send_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        mut message_data: MessageData,
+    ) -> RequestFuture {
+        let from_user_id = owner_id;
+        let to_user_id = message_data.to_user as usize;
+        message_data.from_user = from_user_id as u64;
+
+        // The receiver learns about the chat after reconnecting if no session can get the message now
+        let receiver_offline =
+            from_user_id != to_user_id && !self.user_session.contains_key(&to_user_id);
+
+        let query = move |conn: &mut PgConnection| {
+            let created_at = message_created_at();
+            message_data.created_at = created_at.to_string();
+
+            let (
+                Some(sender_message),
+                Some(receiver_message),
+                Some(device_keys),
+                Some(sender_nonce),
+                Some(receiver_nonce),
+            ) = (
+                message_data.sender_message.to_owned(),
+                message_data.receiver_message.to_owned(),
+                message_data.device_keys.to_owned(),
+                message_data.sender_nonce.to_owned(),
+                message_data.receiver_nonce.to_owned(),
+            )
+            else {
+                return Err(RequestError::malformed(
+                    "Message received with missing encrypted data",
+                ));
+            };
+
+            existing_user(conn, to_user_id)?;
+
+            // Keys can only be given to the devices of the two users of the chat
+            validate_device_keys(conn, &device_keys, &[from_user_id, to_user_id])?;
+
+            let message_group = create_message_group(from_user_id, to_user_id);
+            validate_reply_to(conn, &message_group, message_data.reply_to)?;
+            message_data.attachment =
+                validate_attachment(conn, from_user_id, &message_data.attachment)?;
+            let attachment_id = message_data
+                .attachment
+                .as_ref()
+                .map(|attachment| attachment.attachment_id.to_owned());
+
+            let new_message_data = NewMessage::new(
+                message_group,
+                sender_message,
+                receiver_message,
+                sender_nonce,
+                receiver_nonce,
+                from_user_id,
+                to_user_id,
+                created_at,
+                message_data.reply_to,
+                attachment_id,
+            );
+
+            info!("Saving message from {} to {}", from_user_id, to_user_id);
+
+            // A message without its keys can not be read by anyone
+            let message_number = conn.transaction::<_, RequestError, _>(|conn| {
+                let saved_message = create_new_message(conn, new_message_data)?;
+                let (message_id, message_number) = (
+                    saved_message.message_id,
+                    saved_message.message_number as usize,
+                );
+
+                let message_keys = device_keys
+                    .into_iter()
+                    .map(|key| MessageKey::new(message_id, key))
+                    .collect();
+                create_message_keys(conn, message_keys)?;
+
+                if receiver_offline {
+                    info!("Saving a pending notification for User ID {to_user_id}");
+                    let notification =
+                        NewPendingNotification::new(to_user_id, from_user_id, message_number);
+                    add_pending_notification(conn, notification)?;
+                }
+                Ok(message_number)
+            })?;
+            message_data.message_number = message_number as u64;
+
+            Ok(message_data)
+        };
+
+        self.run_query(query, move |act, message_data| {
+            act.reply_message_ack(ws_id, request_id, &message_data);
+            act.relay_message(ws_id, message_data);
+            Ok(())
+        })
+    }
+
+    /// Lets the sender know the number and the time the message was saved with
+    fn reply_message_ack(&self, ws_id: usize, request_id: u64, message_data: &MessageData) {
+        let ack = MessageAck::new(
+            message_data.to_user,
+            message_data.message_number,
+            message_data.created_at.to_owned(),
         );
+        self.reply(ws_id, request_id, ServerResponse::MessageSent(ack));
+    }
+
+    /// Sends a saved message to every session of the receiver and every other session of the sender
+    fn relay_message(&self, sender_ws_id: usize, message_data: MessageData) {
+        let from_user_id = message_data.from_user as usize;
+        let to_user_id = message_data.to_user as usize;
 
         info!("Sending message from {} to {}", from_user_id, to_user_id);
 
-        create_new_message(&mut self.conn, new_message_data);
+        // The client finds the chat by the other user's ID. If the sender was not added yet,
+        // the client gets the sender profile by itself
+        let send_message_data =
+            ResponseFrame::broadcast(ServerResponse::Message(message_data)).to_json();
+
+        if from_user_id != to_user_id && !self.send_to_user(to_user_id, &send_message_data, None) {
+            info!("No active session id found with the User ID {to_user_id}");
+        }
+
+        self.send_to_user(from_user_id, &send_message_data, Some(sender_ws_id));
+    }
+
+    /// Send a message to every device of every member of a group
+    pub fn send_group_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        mut message_data: MessageData,
+    ) -> RequestFuture {
+        let group_id = message_data.to_user as usize;
+        message_data.from_user = owner_id as u64;
+
+        let query = move |conn: &mut PgConnection| {
+            let created_at = message_created_at();
+            message_data.created_at = created_at.to_string();
+
+            let (Some(message), Some(device_keys), Some(nonce)) = (
+                message_data.sender_message.to_owned(),
+                message_data.device_keys.to_owned(),
+                message_data.sender_nonce.to_owned(),
+            ) else {
+                return Err(RequestError::malformed(
+                    "Message received with missing encrypted data",
+                ));
+            };
+
+            let members = group_members(conn, owner_id, group_id)?;
+
+            // Keys can only be given to the devices of the group members
+            validate_device_keys(conn, &device_keys, &members)?;
+
+            let message_group = create_group_message_group(group_id);
+            validate_reply_to(conn, &message_group, message_data.reply_to)?;
+            message_data.attachment =
+                validate_attachment(conn, owner_id, &message_data.attachment)?;
+            let attachment_id = message_data
+                .attachment
+                .as_ref()
+                .map(|attachment| attachment.attachment_id.to_owned());
+
+            let new_message_data = NewMessage::new_group(
+                message_group,
+                message,
+                nonce,
+                owner_id,
+                created_at,
+                message_data.reply_to,
+                attachment_id,
+            );
+
+            info!("Saving message from {} to group {}", owner_id, group_id);
+
+            // A message without its keys can not be read by anyone
+            let saved_message = conn.transaction::<_, RequestError, _>(|conn| {
+                let saved_message = create_new_message(conn, new_message_data)?;
+
+                let message_keys = device_keys
+                    .into_iter()
+                    .map(|key| MessageKey::new(saved_message.message_id, key))
+                    .collect();
+                create_message_keys(conn, message_keys)?;
+                Ok(saved_message)
+            })?;
+            message_data.message_number = saved_message.message_number as u64;
+
+            Ok((message_data, members))
+        };
+
+        self.run_query(query, move |act, (message_data, members)| {
+            info!("Sending message from {} to group {}", owner_id, group_id);
+            act.reply_message_ack(ws_id, request_id, &message_data);
+
+            let send_message_data =
+                ResponseFrame::broadcast(ServerResponse::Message(message_data)).to_json();
+            act.send_to_members(&members, &send_message_data, Some(ws_id));
+            Ok(())
+        })
+    }
+
+    /// Sends the data to every session of every given member except the skipped one
+    fn send_to_members(&self, members: &[usize], data: &str, skip_ws_id: Option<usize>) {
+        for member_id in members {
+            self.send_to_user(*member_id, data, skip_ws_id);
+        }
+    }
+
+    /// Sends the data to a single WS session
+    fn send_to_session(&self, ws_id: usize, data: &str) {
+        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
+            receiver_ws.do_send(Message(data.to_owned()));
+        }
+    }
+
+    /// Sends the data to every session authenticated as the user except the skipped one.
+    /// Returns false if there was no session
+    fn send_to_user(&self, user_id: usize, data: &str, skip_ws_id: Option<usize>) -> bool {
+        let Some(ws_ids) = self.user_session.get(&user_id) else {
+            return false;
+        };
+
+        for ws_id in ws_ids {
+            if Some(*ws_id) == skip_ws_id {
+                continue;
+            }
+            if let Some((_, receiver_ws)) = self.sessions.get(ws_id) {
+                receiver_ws.do_send(Message(data.to_owned()));
+            }
+        }
+        true
+    }
 
-        if from_user_id == to_user_id {
-            info!("From and to users are the same. Stopping sending.");
+    /// Saves the session as authenticated with the given owner
+    fn authenticate_session(&mut self, ws_id: usize, owner_id: usize, device_id: usize) {
+        let Some((id_info, _)) = self.sessions.get_mut(&ws_id) else {
+            return;
+        };
+
+        if id_info.owner_id == owner_id {
+            id_info.device_id = device_id;
             return;
         }
+        let old_owner_id = id_info.owner_id;
+        *id_info = IDInfo::new_with_owner(owner_id, device_id);
+
+        // The session might have been authenticated as a different user before
+        if let Some(sessions) = self.user_session.get_mut(&old_owner_id) {
+            sessions.retain(|id| *id != ws_id);
+            if sessions.is_empty() {
+                self.user_session.remove(&old_owner_id);
+                self.user_went_offline(old_owner_id);
+            }
+        }
 
-        // If a Gui Client adds 10 users for chatting, there will be 10 + owner = 11 WS sessions
-        // We store every single session of an owner in a vec. So it goes like this to find the proper
-        // session and the receiver
-        // Get all the sessions of the to_user => Out of all of them find the sessions
-        // that has the user id of the from_user. Here from_user = a WS session with from_user id that
-        // was added on the to_user's side => Get the receiver => Send message
-        //
-        // If there is no from_user session inside the list of to_user, to_user hasn't
-        // added from_user as a new chat yet. So in this case, get all the sessions of the to_user
-        // => Find the session that has the user id to_user which will always be the owner session
-        // => send a request to the owner
-        if let Some(receiver_ws_data) = self.user_session.get(&to_user_id) {
-            for i in receiver_ws_data {
-                if i.user_id == from_user_id {
-                    conn_found = true;
-                    let ws_id = i.ws_id;
-                    if let Some(receiver_data) = self.sessions.get(&ws_id) {
-                        receiver_data
-                            .1
-                            .do_send(Message(format!("/message {}", send_message_data)))
-                    }
-                    break;
+        // Only the first session of the owner changes what others see
+        if !self.user_session.contains_key(&owner_id) {
+            self.send_presence(Presence::new(owner_id as u64, true, None));
+        }
+        self.user_session.entry(owner_id).or_default().push(ws_id);
+    }
+
+    /// Sends the presence to every session that has the user's chat opened
+    fn send_presence(&self, presence: Presence) {
+        let user_id = presence.user_id as usize;
+        let presence_data = ResponseFrame::broadcast(ServerResponse::Presence(presence)).to_json();
+
+        for (id_info, receiver_ws) in self.sessions.values() {
+            if id_info.owner_id != user_id && id_info.chats.contains(&user_id) {
+                receiver_ws.do_send(Message(presence_data.to_owned()));
+            }
+        }
+    }
+
+    /// Saves the time the user was last seen and lets the sessions that have the user's chat opened know.
+    /// Called once the last session of the user is gone
+    pub fn user_went_offline(&mut self, user_id: usize) {
+        let last_seen_at = message_created_at();
+        info!("User ID {user_id} went offline");
+
+        // Nothing waits for the result, a failed update only gets logged
+        let update = self.db.send(Execute::new(move |conn: &mut PgConnection| {
+            update_user_last_seen(conn, user_id, last_seen_at).map_err(RequestError::from)
+        }));
+        actix::spawn(async move {
+            match update.await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!(
+                    "Failed to save the last seen time of User ID {user_id}. Reason: {}",
+                    e.message
+                ),
+                Err(e) => {
+                    error!("Failed to save the last seen time of User ID {user_id}. Reason: {e}")
                 }
             }
+        });
 
-            if !conn_found {
-                info!("Client session exists but the user was not added. Sending request to add the user");
-                for i in receiver_ws_data {
-                    if i.user_id == to_user_id {
-                        let ws_id = i.ws_id;
-                        if let Some(receiver_data) = self.sessions.get(&ws_id) {
-                            let user_data = get_user_with_id(&mut self.conn, from_user_id)
-                                .unwrap()
-                                .update_token(String::new())
-                                .to_json();
-
-                            receiver_data
-                                .1
-                                .do_send(Message(format!("/new-user-message {}", user_data)))
-                        }
-                        break;
-                    }
+        self.send_presence(Presence::new(
+            user_id as u64,
+            false,
+            Some(last_seen_at.to_string()),
+        ));
+    }
+
+    /// Removes the attachments whose upload was abandoned so their rows and files do not stay forever
+    pub fn clean_stale_uploads(&self) {
+        let started_before = message_created_at() - STALE_UPLOAD_AGE;
+
+        let removal = self.db.send(Execute::new(move |conn: &mut PgConnection| {
+            let removed_ids = remove_stale_uploads(conn, started_before)?;
+
+            // The rows are gone so a file that failed to be removed can only be logged
+            for attachment_id in removed_ids.iter() {
+                if let Err(e) = remove_attachment_file(attachment_id) {
+                    error!("Failed to remove the file of attachment {attachment_id}. Reason: {e}");
                 }
             }
-        } else {
-            info!("No active session id found with the User ID {to_user_id}");
-        }
+            Ok(removed_ids.len())
+        }));
+        actix::spawn(async move {
+            match removal.await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(total)) => info!("Removed {total} incomplete uploads"),
+                Ok(Err(e)) => error!("Failed to remove incomplete uploads. Reason: {}", e.message),
+                Err(e) => error!("Failed to remove incomplete uploads. Reason: {e}"),
+            }
+        });
     }
 
     /// Creates, saves and broadcasts the new user to the relevant session
-    pub fn create_new_user(&mut self, ws_id: usize, other_data: String) {
-        let mut user_id = self.rng.gen_range(1..=2_147_483_647) as usize;
-        let user_token = generate_user_token();
+    pub fn create_new_user(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        other_data: FullUserData,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            // The user is created with the device that sent the request
+            let Some(device) = other_data.devices.first() else {
+                return Err(RequestError::malformed(
+                    "New user received without a device key",
+                ));
+            };
+            let rsa_public_key = device.rsa_public_key.to_owned();
 
-        while get_user_with_id(&mut self.conn, user_id).is_some() {
-            info!("Generated user ID already exist. Creating a new ID");
-            user_id = self.rng.gen_range(1..=2_147_483_647) as usize;
-        }
+            // User IDs must not overlap with group IDs either
+            let user_id = generate_chat_id(conn)?;
+            let (user_token, hashed_token) = generate_user_token();
+
+            info!("Creating new user with User ID {user_id}");
+
+            let user_data = User::from_user_data(other_data)
+                .update_id(user_id)
+                .update_token(hashed_token);
+
+            create_new_user(conn, user_data)?;
+            let device = create_device_with_key(conn, NewDevice::new(user_id, rsa_public_key))?;
+            Ok((user_id, user_token, device.device_id as usize))
+        };
 
-        info!("Creating new user with User ID {user_id}");
+        self.run_query(query, move |act, (user_id, user_token, device_id)| {
+            // The session is authenticated as the newly created user
+            act.authenticate_session(ws_id, user_id, device_id);
+
+            let id_data = UserIDs::new(user_id as u64, user_token, device_id as u64);
+            act.reply(ws_id, request_id, ServerResponse::UpdateUserId(id_data));
+            Ok(())
+        })
+    }
+
+    /// Reconnect with an existing user and save necessary session information
+    pub fn reconnect_user(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        id_data: UserIDs,
+    ) -> RequestFuture {
+        let user_id = id_data.user_id as usize;
+        let device_id = id_data.device_id as usize;
+        let token = id_data.user_token;
+
+        let query = move |conn: &mut PgConnection| {
+            let user_data = user_from_token(conn, token)?;
+
+            // The token must belong to the user that is reconnecting
+            if user_data.user_id as usize != user_id {
+                return Err(RequestError::invalid_token());
+            }
 
-        let user_data = User::from_json(other_data)
-            .update_id(user_id)
-            .update_token(user_token.to_owned());
+            // Device ID 0 is a new device that registers itself after reconnecting
+            if device_id != 0 {
+                let device = get_device_with_id(conn, device_id)?;
+                if device.is_none_or(|device| device.user_id as usize != user_id) {
+                    return Err(RequestError::unknown_device(device_id));
+                }
+            }
 
-        create_new_user(&mut self.conn, user_data);
+            info!(
+                "Reconnecting with User ID {} with device {}",
+                user_id, device_id
+            );
+
+            // Notifications stay until the chat is opened so every device of the owner gets them
+            let mut unread_chats = Vec::new();
+            for notification in get_pending_notifications(conn, user_id)? {
+                let sender = existing_user(conn, notification.sender_id as usize)?;
+                unread_chats.push(UnreadChat::new(
+                    profile_with_devices(conn, sender)?,
+                    notification.unread_count as u64,
+                    notification.last_message_number as u64,
+                ));
+            }
 
-        let id_data = IDInfo {
-            user_id,
-            owner_id: user_id,
-            user_token,
+            Ok((profile_with_devices(conn, user_data)?, unread_chats))
         };
 
-        let ws_data = WSData::new(user_id, ws_id);
+        self.run_query(query, move |act, (user_data, unread_chats)| {
+            // The session is authenticated as the owner of the token from now on
+            act.authenticate_session(ws_id, user_id, device_id);
 
-        self.user_session.entry(user_id).or_default().push(ws_data);
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::ReconnectSuccess(user_data),
+            );
 
-        if let Some(entry) = self.sessions.get_mut(&ws_id) {
-            let (id_info, receiver_ws) = entry;
-            *id_info = id_data.clone();
-            receiver_ws.do_send(Message(format!("/update-user-id {}", id_data.to_json())))
-        }
+            if !unread_chats.is_empty() {
+                let unread_data =
+                    ResponseFrame::broadcast(ServerResponse::UnreadChats(unread_chats)).to_json();
+                act.send_to_session(ws_id, &unread_data);
+            }
+            Ok(())
+        })
     }
 
-    /// Reconnect with an existing user and save necessary session information
-    pub fn reconnect_user(&mut self, ws_id: usize, mut id_data: IDInfo) {
-        let owner_id;
+    /// Marks the messages the owner got from the user and lets the user know
+    pub fn message_receipt(&mut self, owner_id: usize, receipt: MessageReceipt) -> RequestFuture {
+        let user_id = receipt.user_id as usize;
+        let message_number = receipt.message_number as usize;
+        let status = receipt.status;
 
-        if let Some(owner_data) = get_user_with_token(&mut self.conn, id_data.user_token.clone()) {
-            owner_id = owner_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+        let query = move |conn: &mut PgConnection| {
+            // Group messages have no receiver so nothing gets marked for them
+            let message_group = create_message_group(owner_id, user_id);
+
+            update_message_receipts(conn, message_group, owner_id, message_number, status)
+                .map_err(RequestError::from)
         };
 
-        let user_id = id_data.user_id;
-        id_data.update_owner_id(owner_id);
+        self.run_query(query, move |act, updated| {
+            // Nothing to tell if every message was already marked
+            if updated > 0 && user_id != owner_id {
+                info!("Sending {status:?} receipt of User ID {owner_id} to {user_id}");
+                let receipt = MessageReceipt::new(owner_id as u64, message_number as u64, status);
+                let receipt_data =
+                    ResponseFrame::broadcast(ServerResponse::MessageReceipt(receipt)).to_json();
+                act.send_to_user(user_id, &receipt_data, None);
+            }
+            Ok(())
+        })
+    }
 
-        info!(
-            "Reconnecting with User ID {} with owner ID {}",
-            user_id, owner_id
-        );
+    /// Starts sending updates of the given user to the session and replies with the user profile
+    pub fn open_chat(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        user_id: u64,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let id = user_id as usize;
+
+            info!("Opening chat with User ID {} for owner {}", id, owner_id);
+            let user_data = existing_user(conn, id)?;
+            let last_seen_at = user_data.last_seen_at.map(|time| time.to_string());
+
+            // The client syncs every message of an opened chat, nothing is pending anymore
+            remove_pending_notification(conn, owner_id, id)?;
+            Ok((profile_with_devices(conn, user_data)?, last_seen_at))
+        };
+
+        self.run_query(query, move |act, (user_data, last_seen_at)| {
+            if let Some((id_info, _)) = act.sessions.get_mut(&ws_id) {
+                id_info.chats.insert(user_id as usize);
+            }
+
+            act.reply(ws_id, request_id, ServerResponse::ChatOpened(user_data));
+
+            // Later changes are sent as they happen
+            let online = act.user_session.contains_key(&(user_id as usize));
+            let presence = Presence::new(user_id, online, last_seen_at);
+            let presence_data =
+                ResponseFrame::broadcast(ServerResponse::Presence(presence)).to_json();
+            act.send_to_session(ws_id, &presence_data);
+            Ok(())
+        })
+    }
+
+    /// Issues a new token for the owner and invalidates the old one
+    pub fn rotate_token(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            info!("Rotating token of User ID {owner_id}");
+
+            let (user_token, hashed_token) = generate_user_token();
+            update_user_token(conn, owner_id, hashed_token)?;
+            Ok(user_token)
+        };
+
+        self.run_query(query, move |act, user_token| {
+            let id_data = UserIDs::new(owner_id as u64, user_token, device_id as u64);
+            act.reply(ws_id, request_id, ServerResponse::TokenRotated(id_data));
+            Ok(())
+        })
+    }
+
+    /// Registers a new device of the owner and lets everyone who needs the device key know
+    pub fn register_device(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_key: DeviceKey,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            info!("Registering a new device for User ID {owner_id}");
+
+            let device =
+                create_device_with_key(conn, NewDevice::new(owner_id, device_key.rsa_public_key))?;
+
+            // Group members encrypt for every device of the owner as well
+            let co_members = group_co_members(conn, owner_id)?;
+            Ok((device, co_members))
+        };
 
-        if let Some(user_data) = get_user_with_id(&mut self.conn, user_id) {
-            let ws_data = WSData::new(user_id, ws_id);
+        self.run_query(query, move |act, (device, co_members)| {
+            let device_id = device.device_id as usize;
+            act.authenticate_session(ws_id, owner_id, device_id);
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::DeviceRegistered(device_id as u64),
+            );
+
+            let device_data =
+                ResponseFrame::broadcast(ServerResponse::DeviceAdded(device.into_device_key()))
+                    .to_json();
+
+            // Other devices of the owner must encrypt for the new device too
+            act.send_to_user(owner_id, &device_data, Some(ws_id));
+            act.broadcast_user_update(owner_id, &device_data);
+            act.send_to_members(&co_members, &device_data, None);
+            Ok(())
+        })
+    }
 
-            let session_data = self.user_session.entry(owner_id).or_default();
-            if !session_data.contains(&ws_data) {
-                session_data.push(ws_data);
+    /// Replaces the RSA key of the device of the session and lets everyone who encrypts for the
+    /// device know. The earlier key stays in the key history
+    pub fn rotate_key(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+        device_key: DeviceKey,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            if device_id == 0 {
+                return Err(RequestError::not_allowed(
+                    "Keys can only be rotated by a registered device",
+                ));
             }
 
-            if let Some(entry) = self.sessions.get_mut(&ws_id) {
-                let (id_info, receiver_ws) = entry;
-                *id_info = id_data.clone();
+            info!("Rotating the key of Device ID {device_id} of User ID {owner_id}");
 
-                receiver_ws.do_send(Message(format!(
-                    "/reconnect-success {}",
-                    user_data.update_token(String::new()).to_json()
-                )));
+            let device = add_device_key(
+                conn,
+                NewUserKey::new(owner_id, device_id, device_key.rsa_public_key),
+            )?;
+            let co_members = group_co_members(conn, owner_id)?;
+            Ok((device, co_members))
+        };
+
+        self.run_query(query, move |act, (device, co_members)| {
+            let device_key = device.into_device_key();
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::KeyRotated(device_key.clone()),
+            );
+
+            let key_data =
+                ResponseFrame::broadcast(ServerResponse::KeyRotated(device_key)).to_json();
+
+            act.send_to_user(owner_id, &key_data, Some(ws_id));
+            act.broadcast_user_update(owner_id, &key_data);
+            act.send_to_members(&co_members, &key_data, None);
+            Ok(())
+        })
+    }
+
+    /// Saves the X3DH keys of the device of the session and replies with its unused one-time prekey count
+    pub fn publish_prekeys(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+        prekey_data: PreKeyUpload,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            if device_id == 0 {
+                return Err(RequestError::not_allowed(
+                    "Prekeys can only be published by a registered device",
+                ));
             }
-        } else {
-            error!("Unable to reconnect with a non-existing user")
-        }
+
+            if prekey_data.identity_key.len() != 32
+                || prekey_data.signing_key.len() != 32
+                || prekey_data.signed_prekey.len() != 32
+                || prekey_data.signature.len() != 64
+                || prekey_data
+                    .one_time_prekeys
+                    .iter()
+                    .any(|prekey| prekey.public_key.len() != 32)
+            {
+                return Err(RequestError::malformed("Prekeys have an invalid length"));
+            }
+
+            info!(
+                "Publishing {} one-time prekeys for device {device_id} of User ID {owner_id}",
+                prekey_data.one_time_prekeys.len()
+            );
+
+            conn.transaction::<_, RequestError, _>(|conn| {
+                upsert_device_prekeys(
+                    conn,
+                    NewDevicePreKeys::new(device_id, &prekey_data, message_created_at()),
+                )?;
+
+                let one_time_prekeys: Vec<SavedPreKey> = prekey_data
+                    .one_time_prekeys
+                    .into_iter()
+                    .map(|prekey| SavedPreKey::new(device_id, prekey))
+                    .collect();
+                if !one_time_prekeys.is_empty() {
+                    add_one_time_prekeys(conn, one_time_prekeys)?;
+                }
+
+                Ok(count_one_time_prekeys(conn, device_id)? as u64)
+            })
+        };
+
+        self.run_query(query, move |act, remaining| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::PreKeysPublished(remaining),
+            );
+            Ok(())
+        })
     }
 
-    /// Sends a user profile data to a client
-    pub fn send_user_data(&mut self, ws_id: usize, user_data: SendUserData) {
-        if get_user_with_token(&mut self.conn, user_data.user_token).is_none() {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+    /// Sends a prekey bundle for every device of the user. Each bundle uses up one one-time prekey
+    pub fn get_prekey_bundles(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        user_id: u64,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let user_id = user_id as usize;
+            existing_user(conn, user_id)?;
+
+            info!("User ID {owner_id} is fetching prekey bundles of User ID {user_id}");
+
+            conn.transaction::<_, RequestError, _>(|conn| {
+                let device_ids = get_user_devices(conn, user_id)?
+                    .into_iter()
+                    .map(|device| device.device_id)
+                    .collect();
+
+                let mut bundles = Vec::new();
+                for prekeys in get_device_prekeys(conn, device_ids)? {
+                    let one_time_prekey = take_one_time_prekey(conn, prekeys.device_id)?;
+                    bundles.push(prekeys.into_bundle(user_id, one_time_prekey));
+                }
+                Ok(bundles)
+            })
+        };
 
-        let id = user_data.user_id;
+        self.run_query(query, move |act, bundles| {
+            act.reply(ws_id, request_id, ServerResponse::PreKeyBundles(bundles));
+            Ok(())
+        })
+    }
 
-        info!("Sending User ID {} profile data", id);
-        if let Some(user_data) = get_user_with_id(&mut self.conn, id) {
-            let user_data = user_data.update_token(String::new()).to_json();
-            if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-                receiver_ws.do_send(Message(format!("/get-user-data {}", user_data)))
-            };
-        } else {
-            let empty_user = User::new().to_json();
-            if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-                receiver_ws.do_send(Message(format!("/get-user-data {}", empty_user)))
+    /// Creates a new group with the owner and the given users as members
+    pub fn create_group(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        group_data: NewGroup,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let group_name = group_data.group_name.trim().to_string();
+            if group_name.is_empty() || group_name.len() > 250 {
+                return Err(RequestError::malformed(
+                    "Group name must be between 1 and 250 letters",
+                ));
+            }
+
+            let mut member_ids = vec![owner_id];
+            for member_id in group_data.members {
+                let member_id = member_id as usize;
+                existing_user(conn, member_id)?;
+                if !member_ids.contains(&member_id) {
+                    member_ids.push(member_id);
+                }
+            }
+
+            let group_id = generate_chat_id(conn)?;
+            info!("Creating new group {} by User ID {}", group_id, owner_id);
+
+            let conversation = create_new_conversation(
+                conn,
+                NewConversation::new(group_id, group_name, owner_id),
+            )?;
+            let new_members = member_ids
+                .iter()
+                .map(|member_id| NewConversationMember::new(group_id, *member_id))
+                .collect();
+            add_conversation_members(conn, new_members)?;
+
+            let group = group_with_members(conn, conversation)?;
+            Ok((group, member_ids))
+        };
+
+        self.run_query(query, move |act, (group, members)| {
+            let group_update =
+                ResponseFrame::broadcast(ServerResponse::GroupUpdated(group.clone())).to_json();
+
+            act.reply(ws_id, request_id, ServerResponse::GroupCreated(group));
+            act.send_to_members(&members, &group_update, Some(ws_id));
+            Ok(())
+        })
+    }
+
+    /// Sends every group the owner is a member of
+    pub fn send_groups(&mut self, ws_id: usize, request_id: u64, owner_id: usize) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            info!("Sending groups of User ID {owner_id}");
+
+            let mut groups = Vec::new();
+            for conversation in get_user_conversations(conn, owner_id)? {
+                groups.push(group_with_members(conn, conversation)?);
+            }
+            Ok(groups)
+        };
+
+        self.run_query(query, move |act, groups| {
+            act.reply(ws_id, request_id, ServerResponse::Groups(groups));
+            Ok(())
+        })
+    }
+
+    /// Adds a new member to a group and sends the updated group to every member
+    pub fn add_group_member(&mut self, owner_id: usize, member_data: GroupMember) -> RequestFuture {
+        let group_id = member_data.group_id as usize;
+        let user_id = member_data.user_id as usize;
+
+        let query = move |conn: &mut PgConnection| {
+            let conversation = get_conversation_with_id(conn, group_id)?
+                .ok_or_else(|| RequestError::unknown_group(group_id))?;
+            group_members(conn, owner_id, group_id)?;
+            existing_user(conn, user_id)?;
+
+            info!("Adding User ID {} to group {}", user_id, group_id);
+            add_conversation_members(conn, vec![NewConversationMember::new(group_id, user_id)])?;
+
+            let group = group_with_members(conn, conversation)?;
+            let members = get_conversation_members(conn, group_id)?;
+            Ok((group, members))
+        };
+
+        self.run_query(query, move |act, (group, members)| {
+            let group_update =
+                ResponseFrame::broadcast(ServerResponse::GroupUpdated(group)).to_json();
+            act.send_to_members(&members, &group_update, None);
+            Ok(())
+        })
+    }
+
+    /// Removes a member of a group. Members can leave by themselves, only the creator can remove others
+    pub fn remove_group_member(
+        &mut self,
+        owner_id: usize,
+        member_data: GroupMember,
+    ) -> RequestFuture {
+        let group_id = member_data.group_id as usize;
+        let user_id = member_data.user_id as usize;
+
+        let query = move |conn: &mut PgConnection| {
+            let conversation = get_conversation_with_id(conn, group_id)?
+                .ok_or_else(|| RequestError::unknown_group(group_id))?;
+            let members = group_members(conn, owner_id, group_id)?;
+
+            if user_id != owner_id && conversation.created_by as usize != owner_id {
+                return Err(RequestError::not_allowed(
+                    "Only the creator of a group can remove other members",
+                ));
+            }
+
+            if !members.contains(&user_id) {
+                return Err(RequestError::new(
+                    ErrorCode::UnknownUser,
+                    &format!("User with ID {user_id} is not a member of group {group_id}"),
+                ));
+            }
+
+            info!("Removing User ID {} from group {}", user_id, group_id);
+            remove_conversation_member(conn, group_id, user_id)?;
+
+            let group = group_with_members(conn, conversation)?;
+            let members = get_conversation_members(conn, group_id)?;
+            Ok((group, members))
+        };
+
+        self.run_query(query, move |act, (group, members)| {
+            let group_update =
+                ResponseFrame::broadcast(ServerResponse::GroupUpdated(group)).to_json();
+            act.send_to_members(&members, &group_update, None);
+
+            let removed_data =
+                ResponseFrame::broadcast(ServerResponse::GroupRemoved(group_id as u64)).to_json();
+            act.send_to_user(user_id, &removed_data, None);
+            Ok(())
+        })
+    }
+
+    /// Sends a user profile data to a client
+    pub fn send_user_data(&mut self, ws_id: usize, request_id: u64, user_id: u64) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let id = user_id as usize;
+
+            info!("Sending User ID {} profile data", id);
+            // A non-existing user gets an empty profile so the client can tell the user apart from a failure
+            let user_data = if let Some(user_data) = get_user_with_id(conn, id)? {
+                profile_with_devices(conn, user_data)?
+            } else {
+                User::new().into_user_data(Vec::new())
             };
-        }
+            Ok(user_data)
+        };
+
+        self.run_query(query, move |act, user_data| {
+            act.reply(ws_id, request_id, ServerResponse::GetUserData(user_data));
+            Ok(())
+        })
     }
 
     /// Updates user name of a user
-    pub fn user_name_update(&mut self, update_data: NameUpdate) {
-        let user_id;
+    pub fn user_name_update(&mut self, owner_id: usize, update_data: NameUpdate) -> RequestFuture {
+        let user_id = owner_id;
+        let new_name = update_data.new_name;
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, update_data.user_token) {
-            user_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+        let query = move |conn: &mut PgConnection| {
+            info!("Updating name of user {} to {new_name}", user_id);
 
-        let new_name = update_data.new_name;
+            update_user_name(conn, user_id, &new_name)?;
+            Ok(new_name)
+        };
 
-        info!("Updating name of user {} to {new_name}", user_id);
+        self.run_query(query, move |act, new_name| {
+            let name_data = NameUpdate::new(user_id as u64, new_name);
+            let name_update_data =
+                ResponseFrame::broadcast(ServerResponse::NameUpdated(name_data)).to_json();
 
-        update_user_name(&mut self.conn, user_id, &new_name);
+            act.broadcast_user_update(user_id, &name_update_data);
+            Ok(())
+        })
+    }
 
-        // broadcast the name update to every active session that has added this user id
-        for (id, session_data) in self.user_session.iter() {
-            if id != &user_id {
-                for session in session_data {
-                    if session.user_id == user_id {
-                        if let Some(data) = self.sessions.get(&session.ws_id) {
-                            let receiver = &data.1;
-                            receiver.do_send(Message(format!("/name-updated {new_name}")));
-                        }
-                    }
-                }
+    /// Updates image link of a user
+    pub fn image_link_update(
+        &mut self,
+        owner_id: usize,
+        update_data: ImageUpdate,
+    ) -> RequestFuture {
+        let user_id = owner_id;
+        let new_link = update_data.image_link;
+
+        let query = move |conn: &mut PgConnection| {
+            info!("Updating image link of user {} to {new_link:?}", user_id);
+            update_user_image_link(conn, user_id, new_link.to_owned())?;
+            Ok(new_link)
+        };
+
+        self.run_query(query, move |act, new_link| {
+            let image_data = ImageUpdate::new(user_id as u64, new_link);
+            let image_update_data =
+                ResponseFrame::broadcast(ServerResponse::ImageUpdated(image_data)).to_json();
+
+            act.broadcast_user_update(user_id, &image_update_data);
+            Ok(())
+        })
+    }
+
+    /// Broadcast a profile update to every active session that has opened a chat with this user id
+    fn broadcast_user_update(&self, user_id: usize, update_data: &str) {
+        for (id_info, receiver) in self.sessions.values() {
+            if id_info.owner_id != user_id && id_info.chats.contains(&user_id) {
+                receiver.do_send(Message(update_data.to_owned()));
             }
         }
     }
 
-    /// Updates image link of a user
-    pub fn image_link_update(&mut self, update_data: ImageUpdate) {
-        let user_id;
+    pub fn send_message_number(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        user_id: u64,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let message_group =
+                resolve_chat(conn, owner_id, user_id as usize)?.message_group(owner_id);
 
-        if let Some(user_data) =
-            get_user_with_token(&mut self.conn, update_data.user_token.to_owned())
-        {
-            user_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+            info!("Sending message number of group {}", message_group);
 
-        let image_update_data = update_data.to_json();
-        let new_link = update_data.image_link;
+            get_last_message_number(conn, message_group).map_err(RequestError::from)
+        };
+
+        self.run_query(query, move |act, last_message_number| {
+            let response = ServerResponse::MessageNumber(last_message_number as u64);
+            act.reply(ws_id, request_id, response);
+            Ok(())
+        })
+    }
+
+    pub fn sync_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+        sync_data: MessageSyncRequest,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let group_name =
+                resolve_chat(conn, owner_id, sync_data.user_id as usize)?.message_group(owner_id);
+            let last_message_number = get_last_message_number(conn, group_name.to_owned())?;
+
+            info!("Sending sync message data of group {}", group_name);
+
+            let gathered_message_data = get_messages_from_number(
+                conn,
+                group_name,
+                sync_data.start_at as usize,
+                sync_data.end_at as usize,
+            )?;
+
+            let message_data =
+                messages_with_keys(conn, gathered_message_data, device_id, sync_data.user_id)?;
+
+            Ok(MessageSyncData::new(
+                message_data,
+                last_message_number as u64,
+                sync_data.start_at,
+                sync_data.end_at,
+            ))
+        };
+
+        self.run_query(query, move |act, to_send| {
+            act.reply(ws_id, request_id, ServerResponse::SyncMessage(to_send));
+            Ok(())
+        })
+    }
+
+    pub fn sync_deleted_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        sync_data: MessageSyncRequest,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let group_name =
+                resolve_chat(conn, owner_id, sync_data.user_id as usize)?.message_group(owner_id);
+
+            info!("Sending deleted sync message data of group {}", group_name);
+
+            let gathered_message_data = get_deleted_messages_from_number(
+                conn,
+                group_name,
+                sync_data.start_at as usize,
+                sync_data.end_at as usize,
+            )?;
+
+            let message_numbers: Vec<u64> = gathered_message_data
+                .into_iter()
+                .map(|msg| msg.message_number as u64)
+                .collect();
+
+            Ok(DeletedMessageData::new(message_numbers))
+        };
+
+        self.run_query(query, move |act, to_send| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::SyncDeletedMessage(to_send),
+            );
+            Ok(())
+        })
+    }
 
-        info!("Updating image link of user {} to {new_link:?}", user_id);
-        update_user_image_link(&mut self.conn, user_id, new_link);
-
-        // broadcast the image update update to every active session that has added this user id
-        for (id, session_data) in self.user_session.iter() {
-            if id != &user_id {
-                for session in session_data {
-                    if session.user_id == user_id {
-                        if let Some(data) = self.sessions.get(&session.ws_id) {
-                            let receiver = &data.1;
-                            receiver
-                                .do_send(Message(format!("/image-updated {image_update_data}")));
+    pub fn delete_message(
+        &mut self,
+        ws_id: usize,
+        owner_id: usize,
+        deletion_data: DeleteMessage,
+    ) -> RequestFuture {
+        let user_id = deletion_data.user_id as usize;
+        let message_number = deletion_data.message_number as usize;
+
+        let query = move |conn: &mut PgConnection| {
+            let chat = resolve_chat(conn, owner_id, user_id)?;
+            let group_name = chat.message_group(owner_id);
+
+            info!(
+                "Processing a delete message request for group {}",
+                group_name
+            );
+
+            let message = get_message_with_number(conn, group_name.to_owned(), message_number)?;
+
+            // Group members can only delete their own messages
+            if let ChatTarget::Group(..) = chat {
+                if message
+                    .as_ref()
+                    .is_none_or(|message| message.message_sender as usize != owner_id)
+                {
+                    return Err(RequestError::not_allowed(
+                        "Only the sender can delete a group message",
+                    ));
+                }
+            }
+
+            delete_message_with_number(conn, group_name.to_owned(), message_number)?;
+            remove_all_message_reactions(conn, group_name, message_number)?;
+
+            // The attachment must not outlive the deleted message
+            if let Some(attachment_id) = message.and_then(|message| message.attachment_id) {
+                remove_attachment(conn, &attachment_id)?;
+                remove_attachment_file(&attachment_id)?;
+            }
+            Ok(chat)
+        };
+
+        self.run_query(query, move |act, chat| {
+            // Every member knows the group by the same group ID
+            if let ChatTarget::Group(_, members) = chat {
+                let to_send =
+                    ResponseFrame::broadcast(ServerResponse::DeleteMessage(deletion_data))
+                        .to_json();
+                act.send_to_members(&members, &to_send, Some(ws_id));
+                return Ok(());
+            }
+
+            // Other devices of the owner know the chat by the same user ID
+            let own_data =
+                ResponseFrame::broadcast(ServerResponse::DeleteMessage(deletion_data.clone()))
+                    .to_json();
+            act.send_to_user(owner_id, &own_data, Some(ws_id));
+
+            if owner_id == user_id {
+                return Ok(());
+            }
+
+            // The receiver side knows the chat by the ID of the deleting user
+            let deletion_data = DeleteMessage::new(owner_id as u64, deletion_data.message_number);
+            let to_send =
+                ResponseFrame::broadcast(ServerResponse::DeleteMessage(deletion_data)).to_json();
+
+            act.send_to_user(user_id, &to_send, None);
+            Ok(())
+        })
+    }
+
+    /// Lets every session of the other user that has a chat open with the owner know the owner
+    /// is typing. Nothing gets saved so the signal is lost if the user is offline
+    pub fn typing(&mut self, owner_id: usize, user_id: u64) -> RequestFuture {
+        let to_user_id = user_id as usize;
+
+        if to_user_id != owner_id {
+            let to_send =
+                ResponseFrame::broadcast(ServerResponse::Typing(owner_id as u64)).to_json();
+
+            // Anyone could be named as the receiver so only the sessions that opened the chat get it
+            if let Some(ws_ids) = self.user_session.get(&to_user_id) {
+                for ws_id in ws_ids {
+                    if let Some((id_info, receiver_ws)) = self.sessions.get(ws_id) {
+                        if id_info.chats.contains(&owner_id) {
+                            receiver_ws.do_send(Message(to_send.to_owned()));
                         }
                     }
                 }
             }
         }
+        Box::pin(fut::ready(Ok(())))
     }
 
-    pub fn send_message_number(&mut self, ws_id: usize, id_data: IDInfo) {
-        let owner_id;
+    /// Saves or removes the reaction of the owner on a message and sends it to everyone in the chat
+    pub fn update_reaction(
+        &mut self,
+        ws_id: usize,
+        owner_id: usize,
+        mut reaction: MessageReaction,
+        added: bool,
+    ) -> RequestFuture {
+        let user_id = reaction.user_id as usize;
+        let message_number = reaction.message_number as usize;
+        reaction.reacted_by = owner_id as u64;
+        let emoji = reaction.emoji.to_owned();
+
+        let query = move |conn: &mut PgConnection| {
+            if added && (emoji.is_empty() || emoji.chars().count() > 32) {
+                return Err(RequestError::malformed(
+                    "Reaction must be between 1 and 32 letters",
+                ));
+            }
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, id_data.user_token) {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+            let chat = resolve_chat(conn, owner_id, user_id)?;
+            let group_name = chat.message_group(owner_id);
 
-        let message_group = create_message_group(owner_id, id_data.user_id);
+            // Deleted messages can not get reactions
+            let message = get_message_with_number(conn, group_name.to_owned(), message_number)?;
+            if message.is_none_or(|message| message.sender_message.is_none()) {
+                return Err(RequestError::malformed(
+                    "Reaction received for a message that does not exist",
+                ));
+            }
 
-        info!("Sending message number of group {}", message_group);
+            info!(
+                "Updating the reaction of {} on message {} of group {}",
+                owner_id, message_number, group_name
+            );
+
+            if added {
+                let reaction_data =
+                    NewMessageReaction::new(group_name, message_number, owner_id, emoji);
+                add_message_reaction(conn, reaction_data)?;
+            } else {
+                remove_message_reaction(conn, group_name, message_number, owner_id)?;
+            }
+            Ok(chat)
+        };
 
-        let last_message_number = get_last_message_number(&mut self.conn, message_group);
+        self.run_query(query, move |act, chat| {
+            let response = |reaction: MessageReaction| {
+                let response = if added {
+                    ServerResponse::ReactionAdded(reaction)
+                } else {
+                    ServerResponse::ReactionRemoved(reaction)
+                };
+                ResponseFrame::broadcast(response).to_json()
+            };
 
-        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-            receiver_ws.do_send(Message(format!("/message-number {last_message_number}")));
-        };
+            // Every member knows the group by the same group ID
+            if let ChatTarget::Group(_, members) = chat {
+                act.send_to_members(&members, &response(reaction), Some(ws_id));
+                return Ok(());
+            }
+
+            // Other devices of the owner know the chat by the same user ID
+            act.send_to_user(owner_id, &response(reaction.clone()), Some(ws_id));
+
+            if owner_id == user_id {
+                return Ok(());
+            }
+
+            // The receiver side knows the chat by the ID of the reacting user
+            reaction.user_id = owner_id as u64;
+            act.send_to_user(user_id, &response(reaction), None);
+            Ok(())
+        })
     }
 
-    pub fn sync_message(&mut self, ws_id: usize, sync_data: SyncMessage) {
-        let owner_id;
+    /// Replaces the content of a message the owner sent and sends the edit to everyone in the chat
+    pub fn edit_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        mut message_data: MessageData,
+    ) -> RequestFuture {
+        let chat_id = message_data.to_user as usize;
+        let message_number = message_data.message_number as usize;
+        message_data.from_user = owner_id as u64;
+
+        let query = move |conn: &mut PgConnection| {
+            let chat = resolve_chat(conn, owner_id, chat_id)?;
+            let group_name = chat.message_group(owner_id);
+
+            info!("Editing message {} of group {}", message_number, group_name);
+
+            let (Some(sender_message), Some(device_keys), Some(sender_nonce)) = (
+                message_data.sender_message.to_owned(),
+                message_data.device_keys.to_owned(),
+                message_data.sender_nonce.to_owned(),
+            ) else {
+                return Err(RequestError::malformed(
+                    "Edit received with missing encrypted data",
+                ));
+            };
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, sync_data.user_token) {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+            // Group messages only have the sender copy
+            let allowed_users = match &chat {
+                ChatTarget::User(user_id) => {
+                    if message_data.receiver_message.is_none()
+                        || message_data.receiver_nonce.is_none()
+                    {
+                        return Err(RequestError::malformed(
+                            "Edit received with missing encrypted data",
+                        ));
+                    }
+                    vec![owner_id, *user_id]
+                }
+                ChatTarget::Group(_, members) => {
+                    message_data.receiver_message = None;
+                    message_data.receiver_nonce = None;
+                    members.to_owned()
+                }
+            };
+            validate_device_keys(conn, &device_keys, &allowed_users)?;
 
-        let group_name = create_message_group(owner_id, sync_data.user_id);
-        let last_message_number = get_last_message_number(&mut self.conn, group_name.to_owned());
+            let Some(message) = get_message_with_number(conn, group_name, message_number)? else {
+                return Err(RequestError::malformed(
+                    "The message to edit does not exist",
+                ));
+            };
 
-        info!("Sending sync message data of group {}", group_name);
+            if message.message_sender as usize != owner_id {
+                return Err(RequestError::not_allowed(
+                    "Only the sender can edit a message",
+                ));
+            }
 
-        let gathered_message_data = get_messages_from_number(
-            &mut self.conn,
-            group_name,
-            sync_data.start_at,
-            sync_data.end_at,
-        );
+            let message_id = message.message_id;
+            let created_at = message.created_at;
+            let receipt = message.receipt();
+            let Some(revision) = NewMessageRevision::from_message(message) else {
+                return Err(RequestError::malformed(
+                    "A deleted message cannot be edited",
+                ));
+            };
 
-        let message_data: Vec<MessageData> = gathered_message_data
-            .into_iter()
-            .map(|msg| MessageData {
-                created_at: msg.created_at.to_string(),
-                from_user: msg.message_sender as usize,
-                to_user: msg.message_receiver as usize,
-                sender_message: msg.sender_message,
-                receiver_message: msg.receiver_message,
-                sender_key: msg.sender_key,
-                receiver_key: msg.receiver_key,
-                sender_nonce: msg.sender_nonce,
-                receiver_nonce: msg.receiver_nonce,
-                message_number: msg.message_number as usize,
-                user_token: String::new(),
-            })
-            .collect();
+            let message_keys = device_keys
+                .into_iter()
+                .map(|key| MessageKey::new(message_id, key))
+                .collect();
+
+            let edited_message = conn.transaction::<_, RequestError, _>(|conn| {
+                let old_keys = get_all_message_keys(conn, message_id)?;
+                create_message_revision(conn, revision, old_keys)?;
+
+                let edited_message = replace_message_content(
+                    conn,
+                    message_id,
+                    sender_message,
+                    message_data.receiver_message.to_owned(),
+                    sender_nonce,
+                    message_data.receiver_nonce.to_owned(),
+                    message_keys,
+                )?;
+                Ok(edited_message)
+            })?;
+
+            message_data.created_at = created_at.to_string();
+            message_data.receipt = receipt;
+            message_data.edited_at = edited_message.edited_at.map(|time| time.to_string());
+            message_data.reply_to = edited_message.reply_to.map(|number| number as u64);
+
+            Ok((chat, message_data))
+        };
 
-        let to_send = SyncMessageData::new_json(
-            message_data,
-            last_message_number,
-            sync_data.start_at,
-            sync_data.end_at,
-        );
+        self.run_query(query, move |act, (chat, message_data)| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::EditMessage(message_data.clone()),
+            );
 
-        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-            receiver_ws.do_send(Message(format!("/sync-message {}", to_send)))
+            let to_send =
+                ResponseFrame::broadcast(ServerResponse::EditMessage(message_data)).to_json();
+
+            match chat {
+                ChatTarget::Group(_, members) => {
+                    act.send_to_members(&members, &to_send, Some(ws_id));
+                }
+                ChatTarget::User(user_id) => {
+                    act.send_to_user(owner_id, &to_send, Some(ws_id));
+                    if user_id != owner_id {
+                        act.send_to_user(user_id, &to_send, None);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn sync_edited_message(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+        sync_data: MessageSyncRequest,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let group_name =
+                resolve_chat(conn, owner_id, sync_data.user_id as usize)?.message_group(owner_id);
+            let last_message_number = get_last_message_number(conn, group_name.to_owned())?;
+
+            info!("Sending sync edited message data of group {}", group_name);
+
+            let gathered_message_data = get_edited_messages_from_number(
+                conn,
+                group_name,
+                sync_data.start_at as usize,
+                sync_data.end_at as usize,
+            )?;
+
+            let message_data =
+                messages_with_keys(conn, gathered_message_data, device_id, sync_data.user_id)?;
+
+            Ok(MessageSyncData::new(
+                message_data,
+                last_message_number as u64,
+                sync_data.start_at,
+                sync_data.end_at,
+            ))
         };
+
+        self.run_query(query, move |act, to_send| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::SyncEditedMessage(to_send),
+            );
+            Ok(())
+        })
     }
 
-    pub fn sync_deleted_message(&mut self, ws_id: usize, sync_data: SyncMessage) {
-        let owner_id;
+    /// Sends every earlier version of a message with the keys of the requesting device
+    pub fn send_edit_history(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        device_id: usize,
+        history_request: EditHistoryRequest,
+    ) -> RequestFuture {
+        let chat_id = history_request.user_id;
+        let message_number = history_request.message_number as usize;
+
+        let query = move |conn: &mut PgConnection| {
+            let group_name =
+                resolve_chat(conn, owner_id, chat_id as usize)?.message_group(owner_id);
+
+            info!(
+                "Sending edit history of message {} of group {}",
+                message_number, group_name
+            );
+
+            let Some(message) = get_message_with_number(conn, group_name, message_number)? else {
+                return Ok(Vec::new());
+            };
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, sync_data.user_token) {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+            let revisions = get_message_revisions(conn, message.message_id)?;
+            let revision_ids = revisions.iter().map(|rev| rev.revision_id).collect();
 
-        let group_name = create_message_group(owner_id, sync_data.user_id);
+            let mut device_keys: HashMap<i32, Vec<EncryptedKey>> = HashMap::new();
+            for key in get_revision_keys(conn, revision_ids, device_id)? {
+                device_keys
+                    .entry(key.revision_id)
+                    .or_default()
+                    .push(key.into_encrypted_key());
+            }
 
-        info!("Sending deleted sync message data of group {}", group_name);
+            let history = revisions
+                .into_iter()
+                .map(|rev| MessageData {
+                    created_at: rev.written_at.to_string(),
+                    from_user: message.message_sender as u64,
+                    to_user: message
+                        .message_receiver
+                        .map_or(chat_id, |receiver| receiver as u64),
+                    sender_message: Some(rev.sender_message),
+                    receiver_message: rev.receiver_message,
+                    device_keys: Some(device_keys.remove(&rev.revision_id).unwrap_or_default()),
+                    sender_nonce: Some(rev.sender_nonce),
+                    receiver_nonce: rev.receiver_nonce,
+                    message_number: message.message_number as u64,
+                    receipt: None,
+                    edited_at: None,
+                    reply_to: message.reply_to.map(|number| number as u64),
+                    reactions: Vec::new(),
+                    attachment: None,
+                })
+                .collect();
+
+            Ok(history)
+        };
 
-        let gathered_message_data = get_deleted_messages_from_number(
-            &mut self.conn,
-            group_name,
-            sync_data.start_at,
-            sync_data.end_at,
-        );
+        self.run_query(query, move |act, history| {
+            act.reply(ws_id, request_id, ServerResponse::EditHistory(history));
+            Ok(())
+        })
+    }
 
-        let message_numbers: Vec<usize> = gathered_message_data
-            .into_iter()
-            .map(|msg| msg.message_number as usize)
-            .collect();
+    /// Saves the data of a new attachment and creates the file its chunks get written to
+    pub fn start_upload(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        attachment: Attachment,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            if attachment.size == 0 || attachment.size > MAX_ATTACHMENT_SIZE {
+                return Err(RequestError::malformed(&format!(
+                    "Attachment size must be between 1 and {MAX_ATTACHMENT_SIZE} bytes"
+                )));
+            }
 
-        let to_send = DeletedMessageData::new_json(message_numbers);
+            let attachment_id = generate_attachment_id();
+            info!("Starting upload of attachment {attachment_id} for User ID {owner_id}");
 
-        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-            receiver_ws.do_send(Message(format!("/sync-deleted-message {}", to_send)))
+            create_new_attachment(
+                conn,
+                NewAttachment::new(attachment_id.to_owned(), owner_id, attachment),
+            )?;
+            create_attachment_file(&attachment_id)?;
+            Ok(attachment_id)
         };
+
+        self.run_query(query, move |act, attachment_id| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::UploadStarted(attachment_id),
+            );
+            Ok(())
+        })
     }
 
-    pub fn delete_message(&mut self, deletion_data: DeleteMessage) {
-        let owner_id;
+    /// Writes the next chunk of an attachment the owner is uploading
+    pub fn upload_chunk(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        chunk: AttachmentChunk,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let attachment_id = chunk.attachment_id;
+
+            if chunk.data.is_empty() || chunk.data.len() > ATTACHMENT_CHUNK_SIZE {
+                return Err(RequestError::malformed(&format!(
+                    "Attachment chunks must be between 1 and {ATTACHMENT_CHUNK_SIZE} bytes"
+                )));
+            }
 
-        if let Some(user_data) =
-            get_user_with_token(&mut self.conn, deletion_data.user_token.to_owned())
-        {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+            // The row stays locked till the size is saved so a retried chunk waits for the first one
+            conn.transaction::<_, RequestError, _>(|conn| {
+                let attachment = lock_attachment_with_id(conn, &attachment_id)?
+                    .filter(|attachment| attachment.uploaded_by as usize == owner_id)
+                    .ok_or_else(|| RequestError::unknown_attachment(&attachment_id))?;
+
+                // Chunks must arrive in order
+                if chunk.offset != attachment.uploaded_size as u64 {
+                    return Err(RequestError::malformed(
+                        "Attachment chunk does not start where the last one ended",
+                    ));
+                }
 
-        let to_send = deletion_data.to_json();
-        let group_name = create_message_group(owner_id, deletion_data.user_id);
+                let uploaded_size = chunk.offset + chunk.data.len() as u64;
+                if uploaded_size > attachment.size as u64 {
+                    return Err(RequestError::malformed(
+                        "Attachment chunk goes past the size of the attachment",
+                    ));
+                }
 
-        info!(
-            "Processing a delete message request for group {}",
-            group_name
-        );
+                write_attachment_chunk(&attachment_id, chunk.offset, &chunk.data)?;
+                update_uploaded_size(conn, &attachment_id, uploaded_size)?;
+                Ok(uploaded_size)
+            })
+        };
 
-        delete_message_with_number(&mut self.conn, group_name, deletion_data.message_number);
+        self.run_query(query, move |act, uploaded_size| {
+            act.reply(
+                ws_id,
+                request_id,
+                ServerResponse::ChunkUploaded(uploaded_size),
+            );
+            Ok(())
+        })
+    }
 
-        if owner_id == deletion_data.user_id {
-            return;
-        }
+    /// Sends a chunk of an uploaded attachment starting at the requested offset
+    pub fn send_attachment_chunk(
+        &mut self,
+        ws_id: usize,
+        request_id: u64,
+        owner_id: usize,
+        chunk_request: AttachmentChunk,
+    ) -> RequestFuture {
+        let query = move |conn: &mut PgConnection| {
+            let attachment_id = chunk_request.attachment_id;
+
+            let Some(attachment) = get_attachment_with_id(conn, &attachment_id)? else {
+                return Err(RequestError::unknown_attachment(&attachment_id));
+            };
 
-        if let Some(user_sessions) = self.user_session.get(&deletion_data.user_id) {
-            for session in user_sessions {
-                if session.user_id == owner_id {
-                    if let Some((_, receiver_ws)) = self.sessions.get(&session.ws_id) {
-                        receiver_ws.do_send(Message(format!("/delete-message {}", to_send)));
-                        break;
-                    }
-                }
+            if !attachment.is_complete() || !can_read_attachment(conn, owner_id, &attachment)? {
+                return Err(RequestError::unknown_attachment(&attachment_id));
             }
-        }
+
+            let data =
+                read_attachment_chunk(&attachment_id, chunk_request.offset, ATTACHMENT_CHUNK_SIZE)?;
+            Ok(AttachmentChunk::new(
+                attachment_id,
+                chunk_request.offset,
+                data,
+            ))
+        };
+
+        self.run_query(query, move |act, chunk| {
+            act.reply(ws_id, request_id, ServerResponse::AttachmentChunk(chunk));
+            Ok(())
+        })
     }
 }
@@ -1,7 +1,9 @@
+mod error;
 mod handler;
 mod json_models;
 mod websocket;
 
+pub use error::*;
 pub use handler::ChatServer;
 pub use json_models::*;
 pub use websocket::{Connect, Disconnect, HandleRequest, Message};
@@ -1,11 +1,10 @@
 use actix::prelude::*;
+use chirp_protocol::ClientRequest;
 use rand::Rng;
+use std::time::Duration;
 use tracing::info;
 
-use crate::server::{
-    ChatServer, CommunicationType, DeleteMessage, IDInfo, ImageUpdate, MessageData, NameUpdate,
-    SendUserData, SyncMessage,
-};
+use crate::server::{ChatServer, IDInfo, RequestError};
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -27,12 +26,19 @@ pub struct Disconnect {
 #[rtype(result = "()")]
 pub struct HandleRequest {
     pub ws_id: usize,
-    pub data: String,
-    pub comm_type: CommunicationType,
+    pub request_id: u64,
+    pub request: ClientRequest,
 }
 
+/// How often abandoned uploads are looked for
+const UPLOAD_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 impl Actor for ChatServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(UPLOAD_CLEANUP_INTERVAL, |act, _| act.clean_stale_uploads());
+    }
 }
 
 impl Handler<Connect> for ChatServer {
@@ -57,63 +63,132 @@ impl Handler<Disconnect> for ChatServer {
         if let Some(id_data) = self.sessions.get(&msg.id) {
             let id_data = &id_data.0;
             info!(
-                "WS Session {} disconnected. Removing session data related to user {} belonging to owner {}",
-                msg.id, id_data.user_id, id_data.owner_id
+                "WS Session {} disconnected. Removing session data belonging to owner {}",
+                msg.id, id_data.owner_id
             );
 
-            if let Some(sessions) = self.user_session.get_mut(&id_data.owner_id) {
-                sessions.retain(|i| i.ws_id != msg.id);
-                if self.user_session[&id_data.owner_id].is_empty() {
-                    self.user_session.remove(&id_data.owner_id);
+            let owner_id = id_data.owner_id;
+            self.sessions.remove(&msg.id);
+
+            if let Some(sessions) = self.user_session.get_mut(&owner_id) {
+                sessions.retain(|ws_id| *ws_id != msg.id);
+                if sessions.is_empty() {
+                    self.user_session.remove(&owner_id);
+                    self.user_went_offline(owner_id);
                 }
             }
-            self.sessions.remove(&msg.id);
         }
     }
 }
 
 impl Handler<HandleRequest> for ChatServer {
-    type Result = ();
+    type Result = ResponseActFuture<Self, ()>;
 
-    fn handle(&mut self, msg: HandleRequest, _: &mut Context<Self>) {
-        match msg.comm_type {
-            CommunicationType::SendMessage => {
-                let message_data = MessageData::new_from_json(&msg.data);
-                self.send_message(message_data);
+    fn handle(&mut self, msg: HandleRequest, _: &mut Context<Self>) -> Self::Result {
+        let (ws_id, request_id) = (msg.ws_id, msg.request_id);
+
+        // Set once the session gets authenticated by reconnecting or creating a user
+        let (is_authenticated, owner_id, device_id) =
+            self.sessions
+                .get(&ws_id)
+                .map_or((false, 0, 0), |(id_info, _)| {
+                    (
+                        id_info.is_authenticated(),
+                        id_info.owner_id,
+                        id_info.device_id,
+                    )
+                });
+
+        let request_future = match msg.request {
+            // Handshakes are handled by the WS session itself
+            ClientRequest::Handshake(_) => Box::pin(fut::ready(Ok(()))),
+            ClientRequest::CreateNewUser(user_data) => {
+                self.create_new_user(ws_id, request_id, user_data)
             }
-            CommunicationType::SendUserData => {
-                let user_data = SendUserData::new_from_json(&msg.data);
-                self.send_user_data(msg.ws_id, user_data)
+            ClientRequest::ReconnectUser(id_data) => {
+                self.reconnect_user(ws_id, request_id, id_data)
             }
-            CommunicationType::CreateNewUser => self.create_new_user(msg.ws_id, msg.data),
-            CommunicationType::UpdateName => {
-                let update_data = NameUpdate::new_from_json(&msg.data);
-                self.user_name_update(update_data)
+            // Every other request is authorized by the session state
+            _ if !is_authenticated => Box::pin(fut::ready(Err(RequestError::not_authenticated()))),
+            ClientRequest::SendMessage(message_data) => {
+                self.send_message(ws_id, request_id, owner_id, message_data)
             }
-            CommunicationType::UpdateImageLink => {
-                let update_data = ImageUpdate::new_from_json(&msg.data);
-                self.image_link_update(update_data)
+            ClientRequest::OpenChat(user_id) => {
+                self.open_chat(ws_id, request_id, owner_id, user_id)
             }
-            CommunicationType::ReconnectUser => {
-                let id_data = IDInfo::new_from_json(msg.data);
-                self.reconnect_user(msg.ws_id, id_data);
+            ClientRequest::GetUserData(user_id) => self.send_user_data(ws_id, request_id, user_id),
+            ClientRequest::NameUpdated(update_data) => self.user_name_update(owner_id, update_data),
+            ClientRequest::ImageUpdated(update_data) => {
+                self.image_link_update(owner_id, update_data)
             }
-            CommunicationType::SendMessageNumber => {
-                let id_data = IDInfo::new_from_json(msg.data);
-                self.send_message_number(msg.ws_id, id_data)
+            ClientRequest::MessageNumber(user_id) => {
+                self.send_message_number(ws_id, request_id, owner_id, user_id)
             }
-            CommunicationType::SyncMessage => {
-                let sync_data = SyncMessage::new_from_json(&msg.data);
-                self.sync_message(msg.ws_id, sync_data);
+            ClientRequest::SyncMessage(sync_data) => {
+                self.sync_message(ws_id, request_id, owner_id, device_id, sync_data)
             }
-            CommunicationType::DeleteMessage => {
-                let data = DeleteMessage::from_json(&msg.data);
-                self.delete_message(data);
+            ClientRequest::DeleteMessage(data) => self.delete_message(ws_id, owner_id, data),
+            ClientRequest::SyncDeletedMessage(sync_data) => {
+                self.sync_deleted_message(ws_id, request_id, owner_id, sync_data)
             }
-            CommunicationType::SyncDeletedMessage => {
-                let sync_data = SyncMessage::new_from_json(&msg.data);
-                self.sync_deleted_message(msg.ws_id, sync_data)
+            ClientRequest::EditMessage(message_data) => {
+                self.edit_message(ws_id, request_id, owner_id, message_data)
             }
-        }
+            ClientRequest::SyncEditedMessage(sync_data) => {
+                self.sync_edited_message(ws_id, request_id, owner_id, device_id, sync_data)
+            }
+            ClientRequest::GetEditHistory(history_request) => {
+                self.send_edit_history(ws_id, request_id, owner_id, device_id, history_request)
+            }
+            ClientRequest::Typing(user_id) => self.typing(owner_id, user_id),
+            ClientRequest::AddReaction(reaction) => {
+                self.update_reaction(ws_id, owner_id, reaction, true)
+            }
+            ClientRequest::RemoveReaction(reaction) => {
+                self.update_reaction(ws_id, owner_id, reaction, false)
+            }
+            ClientRequest::RotateToken => self.rotate_token(ws_id, request_id, owner_id, device_id),
+            ClientRequest::RegisterDevice(device_key) => {
+                self.register_device(ws_id, request_id, owner_id, device_key)
+            }
+            ClientRequest::RotateKey(device_key) => {
+                self.rotate_key(ws_id, request_id, owner_id, device_id, device_key)
+            }
+            ClientRequest::PublishPreKeys(prekey_data) => {
+                self.publish_prekeys(ws_id, request_id, owner_id, device_id, prekey_data)
+            }
+            ClientRequest::GetPreKeyBundles(user_id) => {
+                self.get_prekey_bundles(ws_id, request_id, owner_id, user_id)
+            }
+            ClientRequest::CreateGroup(group_data) => {
+                self.create_group(ws_id, request_id, owner_id, group_data)
+            }
+            ClientRequest::GetGroups => self.send_groups(ws_id, request_id, owner_id),
+            ClientRequest::AddGroupMember(member_data) => {
+                self.add_group_member(owner_id, member_data)
+            }
+            ClientRequest::RemoveGroupMember(member_data) => {
+                self.remove_group_member(owner_id, member_data)
+            }
+            ClientRequest::MessageReceipt(receipt) => self.message_receipt(owner_id, receipt),
+            ClientRequest::SendGroupMessage(message_data) => {
+                self.send_group_message(ws_id, request_id, owner_id, message_data)
+            }
+            ClientRequest::StartUpload(attachment) => {
+                self.start_upload(ws_id, request_id, owner_id, attachment)
+            }
+            ClientRequest::UploadChunk(chunk) => {
+                self.upload_chunk(ws_id, request_id, owner_id, chunk)
+            }
+            ClientRequest::GetAttachmentChunk(chunk_request) => {
+                self.send_attachment_chunk(ws_id, request_id, owner_id, chunk_request)
+            }
+        };
+
+        Box::pin(request_future.map(move |result, act, _| {
+            if let Err(e) = result {
+                act.send_error(ws_id, request_id, e.into_response());
+            }
+        }))
     }
 }
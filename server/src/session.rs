@@ -1,9 +1,13 @@
 use actix::prelude::*;
 use actix_web_actors::ws;
+use chirp_protocol::{
+    ClientRequest, ErrorCode, ErrorResponse, Handshake, RequestFrame, ResponseFrame,
+    ServerResponse, VersionMismatch, PROTOCOL_VERSION,
+};
 use std::time::{Duration, Instant};
-use tracing::info;
+use tracing::{error, info};
 
-use crate::server::{ChatServer, CommunicationType, Connect, Disconnect, HandleRequest, Message};
+use crate::server::{ChatServer, Connect, Disconnect, HandleRequest, Message};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 
@@ -11,8 +15,8 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct WsChatSession {
     pub id: usize,
-    pub user_id: usize,
     pub hb: Instant,
+    pub handshake_done: bool,
     pub addr: Addr<ChatServer>,
 }
 
@@ -29,6 +33,49 @@ impl WsChatSession {
             ctx.ping(b"");
         });
     }
+
+    /// Accept the connection if the client speaks the same protocol version, otherwise reject and close it
+    fn handshake(
+        &mut self,
+        request_id: u64,
+        handshake: Handshake,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if handshake.protocol_version == PROTOCOL_VERSION {
+            info!("Handshake successful for WS Session {}", self.id);
+            self.handshake_done = true;
+            ctx.text(ResponseFrame::reply(request_id, ServerResponse::HandshakeAccepted).to_json());
+        } else {
+            error!(
+                "Client protocol version {} does not match server version {}. Closing WS Session {}",
+                handshake.protocol_version, PROTOCOL_VERSION, self.id
+            );
+            let mismatch = VersionMismatch {
+                server_version: PROTOCOL_VERSION,
+                client_version: handshake.protocol_version,
+            };
+            let response = ServerResponse::VersionMismatch(mismatch);
+            ctx.text(ResponseFrame::reply(request_id, response).to_json());
+            ctx.close(Some(ws::CloseCode::Protocol.into()));
+            ctx.stop();
+        }
+    }
+
+    /// Reply with an error for a request that never reached the chat server
+    fn send_error(
+        &self,
+        code: ErrorCode,
+        message: &str,
+        request_id: Option<u64>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let response = ServerResponse::Error(ErrorResponse::new(code, message.to_string()));
+        let frame = ResponseFrame {
+            request_id,
+            response,
+        };
+        ctx.text(frame.to_json());
+    }
 }
 
 impl Actor for WsChatSession {
@@ -87,65 +134,53 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                 self.hb = Instant::now();
             }
             ws::Message::Text(text) => {
-                let m = text.trim();
-                if m.starts_with('/') {
-                    let v: Vec<&str> = m.splitn(2, ' ').collect();
-                    let json_text = v[1].to_string();
-
-                    match v[0] {
-                        "/create-new-user" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::CreateNewUser,
-                        }),
-                        "/reconnect-user" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::ReconnectUser,
-                        }),
-                        "/get-user-data" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::SendUserData,
-                        }),
-                        "/message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::SendMessage,
-                        }),
-                        "/name-updated" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::UpdateName,
-                        }),
-                        "/image-updated" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::UpdateImageLink,
-                        }),
-                        "/message-number" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::SendMessageNumber,
-                        }),
-                        "/sync-message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::SyncMessage,
-                        }),
-                        "/delete-message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::DeleteMessage,
-                        }),
-                        "/sync-deleted-message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: json_text,
-                            comm_type: CommunicationType::SyncDeletedMessage,
-                        }),
-                        _ => ctx.text(format!("!!! unknown command: {m:?}")),
+                let text = text.trim();
+                let frame = match RequestFrame::from_json(text) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        error!("Failed to parse the request. Discarding request. Reason: {e}");
+                        self.send_error(
+                            ErrorCode::MalformedRequest,
+                            &format!("Failed to parse the request: {e}"),
+                            RequestFrame::request_id_from_json(text),
+                            ctx,
+                        );
+                        return;
                     }
+                };
+
+                if let ClientRequest::Handshake(handshake) = frame.request {
+                    self.handshake(frame.request_id, handshake, ctx);
+                    return;
                 }
+
+                if !self.handshake_done {
+                    error!("Request received before the handshake. Discarding request");
+                    self.send_error(
+                        ErrorCode::HandshakeRequired,
+                        "A handshake is required before sending any request",
+                        Some(frame.request_id),
+                        ctx,
+                    );
+                    return;
+                }
+
+                // Wait for the request to finish so requests of a session are processed in order
+                self.addr
+                    .send(HandleRequest {
+                        ws_id: self.id,
+                        request_id: frame.request_id,
+                        request: frame.request,
+                    })
+                    .into_actor(self)
+                    .then(|res, _, ctx| {
+                        if res.is_err() {
+                            error!("Failed to reach the chat server. Stopping WS session");
+                            ctx.stop();
+                        }
+                        fut::ready(())
+                    })
+                    .wait(ctx);
             }
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(reason) => {
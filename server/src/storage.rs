@@ -0,0 +1,52 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+/// Folder the encrypted attachment files are saved in
+fn attachment_dir() -> PathBuf {
+    env::var("ATTACHMENT_DIR").map_or_else(|_| PathBuf::from("./attachments"), PathBuf::from)
+}
+
+/// Attachment IDs are generated by the server so they are always safe to use as file names
+fn attachment_path(attachment_id: &str) -> PathBuf {
+    attachment_dir().join(attachment_id)
+}
+
+/// Creates the empty file the chunks of a new attachment get written to
+pub fn create_attachment_file(attachment_id: &str) -> io::Result<()> {
+    fs::create_dir_all(attachment_dir())?;
+    File::create(attachment_path(attachment_id))?;
+    Ok(())
+}
+
+/// Writes the chunk at the offset. Bytes left from a chunk that was not recorded get overwritten
+pub fn write_attachment_chunk(attachment_id: &str, offset: u64, data: &[u8]) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(attachment_path(attachment_id))?;
+    file.write_all_at(data, offset)
+}
+
+/// Reads at most the given length of the file from the offset. Empty if the offset is past the end
+pub fn read_attachment_chunk(
+    attachment_id: &str,
+    offset: u64,
+    length: usize,
+) -> io::Result<Vec<u8>> {
+    let mut file = File::open(attachment_path(attachment_id))?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut data = Vec::with_capacity(length);
+    file.take(length as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Removes the file of an attachment. A missing file counts as removed
+pub fn remove_attachment_file(attachment_id: &str) -> io::Result<()> {
+    match fs::remove_file(attachment_path(attachment_id)) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -1,20 +1,82 @@
 use rand::rngs::OsRng;
 use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::fmt::Write;
 
-/// Generate a random hex string to be used as a token
-pub fn generate_user_token() -> String {
-    let mut random_bytes = [0u8; 32];
+/// Length of the token id prefix used to look up a token without knowing the secret
+pub const TOKEN_ID_LENGTH: usize = 16;
 
-    OsRng.fill_bytes(&mut random_bytes);
-    let hex_string = random_bytes
+/// The parts of a user token that are saved in the DB. The secret part is never stored
+pub struct HashedToken {
+    pub token_id: String,
+    pub token_salt: String,
+    pub token_hash: String,
+}
+
+/// Convert bytes to an uppercase hex string
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
         .iter()
-        .fold(String::with_capacity(64), |mut acc, byte| {
+        .fold(String::with_capacity(bytes.len() * 2), |mut acc, byte| {
             write!(&mut acc, "{:02X}", byte).expect("Failed to write to string");
             acc
-        });
+        })
+}
+
+/// Generate a random hex string of the given number of bytes
+fn random_hex(length: usize) -> String {
+    let mut random_bytes = vec![0u8; length];
+
+    OsRng.fill_bytes(&mut random_bytes);
+    to_hex(&random_bytes)
+}
+
+/// Generate a new token to give to the user along with the hashed version to save in the DB.
+/// The token is the token id followed by the secret
+pub fn generate_user_token() -> (String, HashedToken) {
+    let token_id = random_hex(TOKEN_ID_LENGTH / 2);
+    let secret = random_hex(32);
+    let token_salt = random_hex(16);
+    let token_hash = hash_token_secret(&token_salt, &secret);
+
+    let token = format!("{token_id}{secret}");
+
+    (
+        token,
+        HashedToken {
+            token_id,
+            token_salt,
+            token_hash,
+        },
+    )
+}
+
+/// Generate a random ID for a new attachment
+pub fn generate_attachment_id() -> String {
+    random_hex(16).to_lowercase()
+}
+
+/// Hash the secret part of a token with the given salt
+pub fn hash_token_secret(salt: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(secret.as_bytes());
+
+    to_hex(&hasher.finalize()).to_lowercase()
+}
+
+/// Split a token into the token id and the secret. Returns None if the token is too short
+pub fn split_token(token: &str) -> Option<(&str, &str)> {
+    if token.len() <= TOKEN_ID_LENGTH || !token.is_char_boundary(TOKEN_ID_LENGTH) {
+        return None;
+    }
 
-    hex_string
+    Some(token.split_at(TOKEN_ID_LENGTH))
+}
+
+/// Create the message group name of a group chat
+pub fn create_group_message_group(group_id: usize) -> String {
+    format!("group@{group_id}")
 }
 
 /// Create a message group name from 2 IDs. The smaller ID is always the first value
@@ -25,3 +87,63 @@ pub fn create_message_group(id_1: usize, id_2: usize) -> String {
         format!("{}@{}", id_1, id_2)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_token_splits_into_id_and_secret() {
+        let (token, hashed_token) = generate_user_token();
+        let (token_id, secret) = split_token(&token).unwrap();
+
+        assert_eq!(token_id.len(), TOKEN_ID_LENGTH);
+        assert!(token_id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(token_id, hashed_token.token_id);
+        assert_eq!(secret.len(), 64);
+
+        // Only the hash of the secret is saved
+        assert_ne!(hashed_token.token_hash, secret);
+        assert_eq!(
+            hash_token_secret(&hashed_token.token_salt, secret),
+            hashed_token.token_hash
+        );
+    }
+
+    #[test]
+    fn generated_tokens_are_unique() {
+        let (first_token, first_hashed) = generate_user_token();
+        let (second_token, second_hashed) = generate_user_token();
+
+        assert_ne!(first_token, second_token);
+        assert_ne!(first_hashed.token_id, second_hashed.token_id);
+        assert_ne!(first_hashed.token_salt, second_hashed.token_salt);
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        assert!(split_token("").is_none());
+        assert!(split_token("0123456789ABCDE").is_none());
+
+        // A token without a secret after the token id
+        assert!(split_token("0123456789ABCDEF").is_none());
+
+        // The token id must not end inside a character
+        assert!(split_token("0123456789ABCDEéSECRET").is_none());
+
+        assert_eq!(
+            split_token("0123456789ABCDEFS"),
+            Some(("0123456789ABCDEF", "S"))
+        );
+    }
+
+    #[test]
+    fn hashing_depends_on_the_salt_and_the_secret() {
+        let hash = hash_token_secret("salt", "secret");
+
+        assert_eq!(hash, hash_token_secret("salt", "secret"));
+        assert_ne!(hash, hash_token_secret("other salt", "secret"));
+        assert_ne!(hash, hash_token_secret("salt", "other secret"));
+        assert_eq!(hash.len(), 64);
+    }
+}